@@ -0,0 +1,67 @@
+-- Tables and views the application has always expected. Tables use IF NOT EXISTS so a
+-- database that was set up by hand before migrations existed can be adopted as-is.
+-- Migrations run unchanged on Postgres and SQLite, so stick to SQL both understand.
+
+CREATE TABLE IF NOT EXISTS parts (
+    partnumber TEXT PRIMARY KEY,
+    manufacturer TEXT,
+    description TEXT,
+    label TEXT,
+    package TEXT,
+    value TEXT,
+    tolerance TEXT
+);
+
+CREATE TABLE IF NOT EXISTS part_storage (
+    partnumber TEXT NOT NULL REFERENCES parts (partnumber) ON UPDATE CASCADE,
+    storage_loc_name TEXT NOT NULL,
+    quantity INTEGER NOT NULL DEFAULT 0 CHECK (quantity >= 0),
+    PRIMARY KEY (partnumber, storage_loc_name)
+);
+
+-- c_stock: everything we have or will have, c_available: on hand and not reserved for
+-- production, c_balance: what is left once orders arrive and production is done.
+CREATE TABLE IF NOT EXISTS stock (
+    partnumber TEXT PRIMARY KEY REFERENCES parts (partnumber) ON UPDATE CASCADE,
+    low_stock_threshold INTEGER NOT NULL DEFAULT 0,
+    on_hand INTEGER NOT NULL DEFAULT 0,
+    on_order INTEGER NOT NULL DEFAULT 0,
+    in_prod INTEGER NOT NULL DEFAULT 0,
+    c_stock INTEGER GENERATED ALWAYS AS (on_hand + on_order) STORED,
+    c_available INTEGER GENERATED ALWAYS AS (on_hand - in_prod) STORED,
+    c_balance INTEGER GENERATED ALWAYS AS (on_hand + on_order - in_prod) STORED
+);
+
+CREATE TABLE IF NOT EXISTS projects (
+    project_name TEXT PRIMARY KEY
+);
+
+CREATE TABLE IF NOT EXISTS project_components (
+    project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE ON DELETE CASCADE,
+    partnumber TEXT NOT NULL REFERENCES parts (partnumber) ON UPDATE CASCADE,
+    designators TEXT NOT NULL DEFAULT '',
+    qty INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (project_name, partnumber)
+);
+
+DROP VIEW IF EXISTS big_part_view;
+CREATE VIEW big_part_view AS
+SELECT p.partnumber, p.manufacturer, p.description, p.label, p.package, p.value, p.tolerance,
+       COALESCE(SUM(ps.quantity), 0) AS total_qty
+FROM parts p
+LEFT JOIN part_storage ps ON ps.partnumber = p.partnumber
+GROUP BY p.partnumber
+ORDER BY p.partnumber;
+
+DROP VIEW IF EXISTS part_storage_view;
+CREATE VIEW part_storage_view AS
+SELECT partnumber, storage_loc_name, quantity
+FROM part_storage
+ORDER BY partnumber, storage_loc_name;
+
+DROP VIEW IF EXISTS non_stocked_parts_view;
+CREATE VIEW non_stocked_parts_view AS
+SELECT p.partnumber
+FROM parts p
+WHERE NOT EXISTS (SELECT 1 FROM stock s WHERE s.partnumber = p.partnumber)
+ORDER BY p.partnumber;
@@ -0,0 +1,16 @@
+-- Every change to stock quantities is recorded here, starting with receiving.
+-- {{id}} is the one placeholder migrations may use, it becomes an auto-incrementing
+-- primary key on each backend.
+
+CREATE TABLE IF NOT EXISTS stock_transactions (
+    id {{id}},
+    partnumber TEXT NOT NULL REFERENCES parts (partnumber) ON UPDATE CASCADE,
+    kind TEXT NOT NULL,
+    delta INTEGER NOT NULL,
+    location TEXT,
+    reference TEXT,
+    note TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS stock_transactions_partnumber_idx ON stock_transactions (partnumber);
@@ -0,0 +1,3 @@
+-- Why stock was spent: build, scrap or sample. Only set on spend transactions.
+
+ALTER TABLE stock_transactions ADD COLUMN reason TEXT;
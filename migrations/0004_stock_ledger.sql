@@ -0,0 +1,11 @@
+-- Records who booked each transaction, and books an opening balance for stock that existed
+-- before the ledger so the ledger adds up to on_hand for every part.
+
+ALTER TABLE stock_transactions ADD COLUMN username TEXT;
+
+INSERT INTO stock_transactions (partnumber, kind, delta, note)
+SELECT s.partnumber, 'adjust',
+       s.on_hand - COALESCE((SELECT SUM(t.delta) FROM stock_transactions t WHERE t.partnumber = s.partnumber), 0),
+       'Opening balance'
+FROM stock s
+WHERE s.on_hand <> COALESCE((SELECT SUM(t.delta) FROM stock_transactions t WHERE t.partnumber = s.partnumber), 0);
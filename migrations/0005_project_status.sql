@@ -0,0 +1,3 @@
+-- Lifecycle status of a project: active or archived. Archived projects are hidden from the list by default.
+
+ALTER TABLE projects ADD COLUMN status TEXT NOT NULL DEFAULT 'active';
@@ -0,0 +1,19 @@
+-- Project builds. Starting one reserves qty x units of every BOM line into stock.in_prod,
+-- build_parts keeps what was reserved so later BOM edits can't unbalance in_prod.
+-- Projects with builds can't be deleted, they are archived instead.
+
+CREATE TABLE IF NOT EXISTS builds (
+    id {{id}},
+    project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE,
+    quantity INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'in-progress',
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS build_parts (
+    build_id BIGINT NOT NULL REFERENCES builds (id) ON DELETE CASCADE,
+    partnumber TEXT NOT NULL REFERENCES parts (partnumber) ON UPDATE CASCADE,
+    quantity INTEGER NOT NULL,
+    PRIMARY KEY (build_id, partnumber)
+);
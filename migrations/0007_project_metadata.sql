@@ -0,0 +1,12 @@
+-- Project metadata for telling board revisions apart. SQLite can't add a column with a
+-- CURRENT_TIMESTAMP default, so the timestamps are filled in here and set on insert.
+
+ALTER TABLE projects ADD COLUMN revision TEXT NOT NULL DEFAULT '';
+ALTER TABLE projects ADD COLUMN description TEXT NOT NULL DEFAULT '';
+ALTER TABLE projects ADD COLUMN pcb_fab_pn TEXT NOT NULL DEFAULT '';
+ALTER TABLE projects ADD COLUMN assembly_notes TEXT NOT NULL DEFAULT '';
+ALTER TABLE projects ADD COLUMN owner TEXT NOT NULL DEFAULT '';
+ALTER TABLE projects ADD COLUMN created_at TIMESTAMP;
+ALTER TABLE projects ADD COLUMN modified_at TIMESTAMP;
+
+UPDATE projects SET created_at = CURRENT_TIMESTAMP, modified_at = CURRENT_TIMESTAMP;
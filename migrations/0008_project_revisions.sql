@@ -0,0 +1,18 @@
+-- Frozen copies of a project's BOM, one per board revision, so revisions can be diffed.
+-- They go with the project when it is deleted.
+
+CREATE TABLE IF NOT EXISTS project_revisions (
+    id {{id}},
+    project_name TEXT NOT NULL REFERENCES projects (project_name) ON UPDATE CASCADE ON DELETE CASCADE,
+    revision TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (project_name, revision)
+);
+
+CREATE TABLE IF NOT EXISTS project_revision_parts (
+    revision_id BIGINT NOT NULL REFERENCES project_revisions (id) ON DELETE CASCADE,
+    partnumber TEXT NOT NULL REFERENCES parts (partnumber) ON UPDATE CASCADE,
+    designators TEXT NOT NULL,
+    qty INTEGER NOT NULL,
+    PRIMARY KEY (revision_id, partnumber)
+);
@@ -0,0 +1,7 @@
+-- Saved column mappings for the spreadsheet BOM import, one per BOM source (Altium, a customer).
+-- The mapping is JSON so new fields don't need a migration.
+
+CREATE TABLE IF NOT EXISTS import_profiles (
+    name TEXT PRIMARY KEY,
+    mapping TEXT NOT NULL
+);
@@ -0,0 +1,59 @@
+-- Part categories and the attributes parts in them have. Categories are keyed by their path,
+-- e.g. Passives/Resistors/Chip, and inherit the attributes of the categories above them.
+-- Attribute values are text checked against the kind in the schema: text, number or value
+-- (an engineering value like 50V, optionally in a fixed unit).
+
+CREATE TABLE IF NOT EXISTS categories (
+    path TEXT PRIMARY KEY,
+    parent TEXT REFERENCES categories (path) ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS category_attributes (
+    category TEXT NOT NULL REFERENCES categories (path) ON UPDATE CASCADE ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    unit TEXT,
+    position INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (category, name)
+);
+
+ALTER TABLE parts ADD COLUMN category TEXT REFERENCES categories (path) ON UPDATE CASCADE;
+
+CREATE TABLE IF NOT EXISTS part_attributes (
+    partnumber TEXT NOT NULL REFERENCES parts (partnumber) ON UPDATE CASCADE ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (partnumber, name)
+);
+
+DROP VIEW IF EXISTS big_part_view;
+CREATE VIEW big_part_view AS
+SELECT p.partnumber, p.manufacturer, p.description, p.label, p.package, p.value, p.tolerance, p.category,
+       COALESCE(SUM(ps.quantity), 0) AS total_qty
+FROM parts p
+LEFT JOIN part_storage ps ON ps.partnumber = p.partnumber
+GROUP BY p.partnumber
+ORDER BY p.partnumber;
+
+-- A starting set, keep in step with `categories::default_categories`
+INSERT INTO categories (path, parent) VALUES
+    ('Passives', NULL),
+    ('Passives/Resistors', 'Passives'),
+    ('Passives/Resistors/Chip', 'Passives/Resistors'),
+    ('Passives/Capacitors', 'Passives'),
+    ('Passives/Capacitors/Ceramic', 'Passives/Capacitors'),
+    ('Passives/Inductors', 'Passives'),
+    ('ICs', NULL),
+    ('Connectors', NULL),
+    ('Crystals', NULL);
+
+INSERT INTO category_attributes (category, name, kind, unit, position) VALUES
+    ('Passives/Resistors', 'power', 'value', 'W', 0),
+    ('Passives/Capacitors', 'voltage', 'value', 'V', 0),
+    ('Passives/Capacitors/Ceramic', 'dielectric', 'text', NULL, 0),
+    ('Passives/Inductors', 'current', 'value', 'A', 0),
+    ('ICs', 'pins', 'number', NULL, 0),
+    ('Connectors', 'pins', 'number', NULL, 0),
+    ('Connectors', 'pitch', 'text', NULL, 1),
+    ('Crystals', 'frequency', 'value', 'Hz', 0),
+    ('Crystals', 'load', 'value', 'F', 1);
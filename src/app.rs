@@ -9,11 +9,16 @@ use crossterm::{
         self, Event, KeyCode, KeyEventKind, KeyEvent
     }
 };
+use tracing::error;
+use crate::errors::{ShikaError, ShikaResult};
 use crate::parts_view::*;
 use crate::projects_view::{ProjectSubState, ProjectsView};
+use crate::repository::BomRepository;
 use crate::stock_view::*;
 use crate::ui::ui;
 
+//The screen names read better than Part, Project and Stock would
+#[allow(clippy::enum_variant_names)]
 pub enum CurrentScreen {
     PartScreen,
     ProjectScreen,
@@ -25,21 +30,29 @@ pub struct App {
     pub parts_view: PartsView,
     pub stock_view: StockView,
     pub projects_view: ProjectsView,
+    pub repo: Box<dyn BomRepository>,
+    //Set when the database can't be reached, the UI shows an error screen instead of the data
+    pub connection_error: Option<String>,
+    //The last error from the data layer, shown until the next key press
+    pub error_toast: Option<ShikaError>,
     pub exit: bool,
 }
 impl App {
-    pub fn new() -> App {
+    pub fn new(repo: Box<dyn BomRepository>) -> App {
         App {
             current_screen: CurrentScreen::PartScreen,
             parts_view: PartsView::new(),
             stock_view: StockView::new(),
             projects_view: ProjectsView::new(),
+            repo,
+            connection_error: None,
+            error_toast: None,
             exit: false,
         }
     }
 
     pub fn run<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()> {
-        app.parts_view.refresh_part_data();
+        app.connect();
         while !app.exit {
             terminal.draw(|f| ui(f, app))?;
             app.handle_events().wrap_err("handle events failed")?;
@@ -59,103 +72,214 @@ impl App {
             _ => Ok(())
         }
     }
+    /// Checks the database connection and loads the initial data if it is reachable.
+    pub fn connect(&mut self) {
+        match self.load_initial_data() {
+            Ok(()) => {
+                self.connection_error = None;
+            }
+            Err(ShikaError::Connection(message)) => {
+                error!("Database connection failed: {}", message);
+                self.connection_error = Some(message);
+            }
+            Err(e) => {
+                self.connection_error = None;
+                self.show_error(e);
+            }
+        }
+    }
+
+    fn load_initial_data(&mut self) -> ShikaResult<()> {
+        self.repo.check_connection()?;
+        self.parts_view.refresh_part_data(self.repo.as_mut())?;
+        self.projects_view.refresh_list(self.repo.as_mut())?;
+        Ok(())
+    }
+
+    pub fn show_error(&mut self, e: ShikaError) {
+        error!("{}", e);
+        self.error_toast = Some(e);
+    }
+
     fn handle_key_event(&mut self, key_event: KeyEvent) -> Result<()> {
-        match self.current_screen {
+        if self.connection_error.is_some() {
+            self.handle_connection_error_keys(key_event);
+            return Ok(());
+        }
+        if self.error_toast.is_some() {
+            //The key that dismisses the toast isn't passed on, so it can't edit a form by accident
+            self.error_toast = None;
+            return Ok(());
+        }
+        let result = match self.current_screen {
             CurrentScreen::PartScreen => {
-                self.handle_parts_keys(key_event);
+                self.handle_parts_keys(key_event)
             },
             CurrentScreen::StockScreen => {
-                self.handle_storage_keys(key_event);
+                self.handle_storage_keys(key_event)
             }
             CurrentScreen::ProjectScreen => {
-                self.handle_project_keys(key_event);
+                self.handle_project_keys(key_event)
             }
+        };
+        if let Err(e) = result {
+            self.show_error(e);
         }
         Ok(())
     }
 
-    fn handle_parts_keys(&mut self, key_event: KeyEvent) {
+    fn handle_parts_keys(&mut self, key_event: KeyEvent) -> ShikaResult<()> {
         match self.parts_view.parts_sub_state {
             PartsSubState::Main => {
-                if !self.handle_global_keys(key_event) {
-                    self.parts_view.handle_main_keys(key_event.code);
+                if !self.handle_global_keys(key_event)? {
+                    self.parts_view.handle_main_keys(self.repo.as_mut(), key_event.code)?;
                 }
             } //end of PartsSubState::Main
             PartsSubState::NewPart => {
-                self.parts_view.handle_new_part_keys(key_event.code);
+                self.parts_view.handle_new_part_keys(self.repo.as_mut(), key_event.code)?;
             }
             PartsSubState::EditPart => {
-                self.parts_view.handle_edit_part_keys(key_event.code);
+                self.parts_view.handle_edit_part_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            PartsSubState::Search => {
+                self.parts_view.handle_search_keys(self.repo.as_mut(), key_event.code)?;
             }
         }
-
+        Ok(())
     }
 
-    fn handle_storage_keys(&mut self, key_event: KeyEvent) {
+    fn handle_storage_keys(&mut self, key_event: KeyEvent) -> ShikaResult<()> {
         match self.stock_view.stock_sub_state {
             StockSubState::StockMain => {
-                if !self.handle_global_keys(key_event) {
-                    self.stock_view.handle_main_keys(key_event.code);
+                if !self.handle_global_keys(key_event)? {
+                    self.stock_view.handle_main_keys(self.repo.as_mut(), key_event.code)?;
                 }
             },
             StockSubState::CreateStock => {
-                self.stock_view.handle_create_stock_keys(key_event.code);
+                self.stock_view.handle_create_stock_keys(self.repo.as_mut(), key_event.code)?;
             },
             StockSubState::AddStock => {
-                self.stock_view.handle_add_stock_keys(key_event.code);
+                self.stock_view.handle_add_stock_keys(self.repo.as_mut(), key_event.code)?;
             },
             StockSubState::SpendStock => {
-                self.stock_view.handle_spend_stock_keys(key_event.code);
+                self.stock_view.handle_spend_stock_keys(self.repo.as_mut(), key_event.code)?;
             },
             StockSubState::EditStock => {
-                self.stock_view.handle_edit_stock_keys(key_event.code);
+                self.stock_view.handle_edit_stock_keys(self.repo.as_mut(), key_event.code)?;
+            },
+            StockSubState::TransferStock => {
+                self.stock_view.handle_transfer_stock_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            StockSubState::ReconcileStock => {
+                self.stock_view.handle_reconcile_stock_keys(self.repo.as_mut(), key_event.code)?;
             }
         }
+        Ok(())
     }
 
-    fn handle_project_keys(&mut self, key_event: KeyEvent) {
+    fn handle_project_keys(&mut self, key_event: KeyEvent) -> ShikaResult<()> {
         match self.projects_view.sub_state {
             ProjectSubState::Main => {
-                if !self.handle_global_keys(key_event) {
+                if !self.handle_global_keys(key_event)? {
                     self.projects_view.handle_main_keys(key_event.code);
                 }
             }
             ProjectSubState::ListMode => {
-                self.projects_view.handle_list_mode_keys(key_event.code);
+                self.projects_view.handle_list_mode_keys(self.repo.as_mut(), key_event.code)?;
             }
             ProjectSubState::BOMMode => {
-                self.projects_view.handle_bom_mode_keys(key_event.code);
+                self.projects_view.handle_bom_mode_keys(self.repo.as_mut(), key_event.code)?;
             }
-            ProjectSubState::CreateNewProject => {
-                self.projects_view.handle_create_project_keys(key_event.code);
+            ProjectSubState::CreateNewProject | ProjectSubState::RenameProject | ProjectSubState::DuplicateProject => {
+                self.projects_view.handle_project_name_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::DeleteProject => {
+                self.projects_view.handle_delete_project_keys(self.repo.as_mut(), key_event.code)?;
             }
             ProjectSubState::AddToBOM => {
-                self.projects_view.handle_add_to_bom_keys(key_event.code);
+                self.projects_view.handle_add_to_bom_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::EditBOMLine => {
+                self.projects_view.handle_edit_bom_line_keys(self.repo.as_mut(), key_event.code)?;
             }
+            ProjectSubState::RemoveBOMLine => {
+                self.projects_view.handle_remove_bom_line_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::SubstitutePN => {
+                self.projects_view.handle_substitute_pn_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::BuildPlanner => {
+                self.projects_view.handle_build_planner_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::BuildsMode => {
+                self.projects_view.handle_builds_mode_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::EditMetadata => {
+                self.projects_view.handle_edit_metadata_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::Revisions => {
+                self.projects_view.handle_revisions_keys(key_event.code)?;
+            }
+            ProjectSubState::SnapshotRevision => {
+                self.projects_view.handle_snapshot_revision_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::ImportPath => {
+                self.projects_view.handle_import_path_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::ImportReview => {
+                self.projects_view.handle_import_review_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::ImportPartNumber => {
+                self.projects_view.handle_import_part_number_keys(key_event.code)?;
+            }
+            ProjectSubState::WizardFile => {
+                self.projects_view.handle_wizard_file_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::WizardMapping => {
+                self.projects_view.handle_wizard_mapping_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::WizardProfileName => {
+                self.projects_view.handle_wizard_profile_name_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::WizardDryRun => {
+                self.projects_view.handle_wizard_dry_run_keys(self.repo.as_mut(), key_event.code)?;
+            }
+            ProjectSubState::ExportBOM => {
+                self.projects_view.handle_export_bom_keys(key_event.code)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection_error_keys(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.exit(),
+            KeyCode::Char('r') => self.connect(),
+            _ => {}
         }
     }
 
     // handles global key events when we don't want to override (e.g. quit)
-    pub fn handle_global_keys(&mut self, key_event: KeyEvent) -> bool {
+    pub fn handle_global_keys(&mut self, key_event: KeyEvent) -> ShikaResult<bool> {
         match key_event.code {
             KeyCode::Char('q') => {
                 self.exit();
-                true
+                Ok(true)
             },
             KeyCode::Char('S') => {
-                self.stock_view.fetch_stock_data();
                 self.current_screen = CurrentScreen::StockScreen;
-                true
+                self.stock_view.fetch_stock_data(self.repo.as_mut())?;
+                Ok(true)
             },
             KeyCode::Char('A') => {
                 self.current_screen = CurrentScreen::PartScreen;
-                true
+                Ok(true)
             },
             KeyCode::Char('P') => {
                 self.current_screen = CurrentScreen::ProjectScreen;
-                true
+                Ok(true)
             }
-            _ => {false}
+            _ => {Ok(false)}
         }
     }
 
@@ -0,0 +1,286 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use color_eyre::eyre::eyre;
+use serde::Serialize;
+use crate::errors::{ShikaError, ShikaResult};
+use crate::parts::Part;
+use crate::projects::{parse_designators, Project, ProjectPart};
+
+/// The layouts a project BOM can be exported in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportFormat {
+    //Every ShikaBOM column, for spreadsheets and re-importing
+    Csv,
+    Json,
+    //Comment, Designator, Footprint and part number columns as JLCPCB expects them
+    Jlcpcb,
+    //One numbered row per line with manufacturer and part number, as PCBWay expects them
+    Pcbway,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [ExportFormat::Csv, ExportFormat::Json, ExportFormat::Jlcpcb, ExportFormat::Pcbway];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            _ => "csv",
+        }
+    }
+
+    pub fn next(&self) -> ExportFormat {
+        let idx = ExportFormat::ALL.iter().position(|format| format == self).unwrap_or(0);
+        ExportFormat::ALL[(idx + 1) % ExportFormat::ALL.len()]
+    }
+
+    pub fn previous(&self) -> ExportFormat {
+        let idx = ExportFormat::ALL.iter().position(|format| format == self).unwrap_or(0);
+        ExportFormat::ALL[(idx + ExportFormat::ALL.len() - 1) % ExportFormat::ALL.len()]
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Json => write!(f, "json"),
+            ExportFormat::Jlcpcb => write!(f, "jlcpcb"),
+            ExportFormat::Pcbway => write!(f, "pcbway"),
+        }
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> color_eyre::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "jlcpcb" => Ok(ExportFormat::Jlcpcb),
+            "pcbway" => Ok(ExportFormat::Pcbway),
+            other => Err(eyre!("invalid export format '{other}', expected csv, json, jlcpcb or pcbway")),
+        }
+    }
+}
+
+/// One BOM line as it is written out, with the quantity already multiplied.
+#[derive(Serialize)]
+pub struct ExportLine<'a> {
+    pub partnumber: &'a str,
+    pub designators: String,
+    pub qty: i64,
+    pub part: &'a Part,
+}
+
+#[derive(Serialize)]
+struct ExportDocument<'a> {
+    project: &'a str,
+    revision: &'a str,
+    multiplier: i64,
+    lines: Vec<ExportLine<'a>>,
+}
+
+pub fn validate_multiplier(multiplier: i64) -> ShikaResult<()> {
+    if multiplier < 1 {
+        return Err(ShikaError::Validation("The build multiplier must be at least 1".to_string()));
+    }
+    Ok(())
+}
+
+/// The project's BOM lines with their quantity multiplied by `multiplier`.
+pub fn export_lines(project: &Project, multiplier: i64) -> ShikaResult<Vec<ExportLine<'_>>> {
+    project.parts.iter()
+        .map(|ppart| {
+            let qty = (ppart.qty as i64).checked_mul(multiplier)
+                .ok_or_else(|| ShikaError::Validation(
+                    format!("{} x {multiplier} of {} is too many to export", ppart.qty, ppart.partnumber)))?;
+            Ok(ExportLine {
+                partnumber: &ppart.partnumber,
+                designators: expanded_designators(ppart),
+                qty,
+                part: &ppart.part_info,
+            })
+        })
+        .collect()
+}
+
+//Assembly houses want every designator spelled out, ranges like R1-R4 are expanded.
+//Anything that doesn't parse is passed through as it was entered
+fn expanded_designators(ppart: &ProjectPart) -> String {
+    match parse_designators(&ppart.designators) {
+        Ok(designators) => designators.join(","),
+        Err(_) => ppart.designators.clone(),
+    }
+}
+
+//The "comment" column, the value when there is one. Postgres gives NULL columns as empty strings
+fn comment(part: &Part) -> String {
+    [&part.value, &part.label, &part.description].into_iter()
+        .flatten()
+        .find(|text| !text.is_empty())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Renders the project's BOM in `format`.
+pub fn export_bom(project: &Project, format: ExportFormat, multiplier: i64) -> ShikaResult<String> {
+    validate_multiplier(multiplier)?;
+    let lines = export_lines(project, multiplier)?;
+    if format == ExportFormat::Json {
+        let document = ExportDocument {
+            project: &project.name,
+            revision: &project.metadata.revision,
+            multiplier,
+            lines,
+        };
+        return serde_json::to_string_pretty(&document)
+            .map_err(|e| ShikaError::Validation(format!("Can't export {}: {e}", project.name)));
+    }
+    let (headers, rows): (Vec<&str>, Vec<Vec<String>>) = match format {
+        ExportFormat::Jlcpcb => (
+            vec!["Comment", "Designator", "Footprint", "Manufacturer Part Number", "Quantity"],
+            lines.iter().map(|line| vec![
+                comment(line.part),
+                line.designators.clone(),
+                line.part.package.clone().unwrap_or_default(),
+                line.partnumber.to_string(),
+                line.qty.to_string(),
+            ]).collect(),
+        ),
+        ExportFormat::Pcbway => (
+            vec!["Item #", "Designator", "Qty", "Manufacturer", "Mfg Part #", "Description / Value", "Package/Footprint"],
+            lines.iter().enumerate().map(|(idx, line)| vec![
+                (idx + 1).to_string(),
+                line.designators.clone(),
+                line.qty.to_string(),
+                line.part.manufacturer.clone().unwrap_or_default(),
+                line.partnumber.to_string(),
+                comment(line.part),
+                line.part.package.clone().unwrap_or_default(),
+            ]).collect(),
+        ),
+        _ => (
+            vec!["Part Number", "Designators", "Qty", "Manufacturer", "Description", "Label", "Package", "Value", "Tolerance"],
+            lines.iter().map(|line| vec![
+                line.partnumber.to_string(),
+                line.designators.clone(),
+                line.qty.to_string(),
+                line.part.manufacturer.clone().unwrap_or_default(),
+                line.part.description.clone().unwrap_or_default(),
+                line.part.label.clone().unwrap_or_default(),
+                line.part.package.clone().unwrap_or_default(),
+                line.part.value.clone().unwrap_or_default(),
+                line.part.tolerance.clone().unwrap_or_default(),
+            ]).collect(),
+        ),
+    };
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let write_error = |e: csv::Error| ShikaError::Validation(format!("Can't export {}: {e}", project.name));
+    writer.write_record(&headers).map_err(write_error)?;
+    for row in rows {
+        writer.write_record(&row).map_err(write_error)?;
+    }
+    let bytes = writer.into_inner()
+        .map_err(|e| ShikaError::Validation(format!("Can't export {}: {e}", project.name)))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Writes the project's BOM to `path`.
+pub fn write_bom(project: &Project, format: ExportFormat, multiplier: i64, path: &Path) -> ShikaResult<()> {
+    let text = export_bom(project, format, multiplier)?;
+    fs::write(path, text).map_err(|e| ShikaError::Validation(format!("Can't write {}: {e}", path.display())))
+}
+
+/// A file name for the export, e.g. `Widget_revB_x10_jlcpcb.csv`.
+pub fn default_file_name(project: &Project, format: ExportFormat, multiplier: i64) -> String {
+    let mut name = file_name_safe(&project.name);
+    if !project.metadata.revision.is_empty() {
+        name.push_str(&format!("_rev{}", file_name_safe(&project.metadata.revision)));
+    }
+    if multiplier > 1 {
+        name.push_str(&format!("_x{multiplier}"));
+    }
+    format!("{name}_{format}.{}", format.extension())
+}
+
+//Anything but letters, digits and dashes becomes an underscore
+fn file_name_safe(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+fn test_project() -> Project {
+    let mut project = Project::new();
+    project.name = "Widget".to_string();
+    project.metadata.revision = "B".to_string();
+    let mut resistor = Part::new();
+    resistor.part_number = "RC0603-10K".to_string();
+    resistor.manufacturer = Some("Yageo".to_string());
+    resistor.package = Some("0603".to_string());
+    resistor.value = Some("10k".to_string());
+    let mut regulator = Part::new();
+    regulator.part_number = "TPS62160DSGR".to_string();
+    regulator.description = Some("Buck, 1A".to_string());
+    project.parts = vec![
+        ProjectPart {
+            partnumber: resistor.part_number.clone(),
+            designators: "R1-R3, R7".to_string(),
+            qty: 4,
+            part_info: resistor,
+        },
+        ProjectPart {
+            partnumber: regulator.part_number.clone(),
+            designators: "U1".to_string(),
+            qty: 1,
+            part_info: regulator,
+        },
+    ];
+    project
+}
+
+#[test]
+fn test_export_bom() {
+    let project = test_project();
+    let jlcpcb = export_bom(&project, ExportFormat::Jlcpcb, 10).unwrap();
+    let mut lines = jlcpcb.lines();
+    assert_eq!(lines.next(), Some("Comment,Designator,Footprint,Manufacturer Part Number,Quantity"));
+    assert_eq!(lines.next(), Some("10k,\"R1,R2,R3,R7\",0603,RC0603-10K,40"));
+    assert_eq!(lines.next(), Some("\"Buck, 1A\",U1,,TPS62160DSGR,10"));
+
+    let pcbway = export_bom(&project, ExportFormat::Pcbway, 1).unwrap();
+    assert_eq!(pcbway.lines().nth(1), Some("1,\"R1,R2,R3,R7\",4,Yageo,RC0603-10K,10k,0603"));
+    let csv = export_bom(&project, ExportFormat::Csv, 2).unwrap();
+    assert!(csv.starts_with("Part Number,Designators,Qty,"));
+    assert_eq!(csv.lines().nth(2), Some("TPS62160DSGR,U1,2,,\"Buck, 1A\",,,,"));
+
+    let json: serde_json::Value = serde_json::from_str(&export_bom(&project, ExportFormat::Json, 3).unwrap()).unwrap();
+    assert_eq!(json["revision"], "B");
+    assert_eq!(json["lines"][0]["qty"], 12);
+    assert_eq!(json["lines"][0]["part"]["manufacturer"], "Yageo");
+
+    assert!(export_bom(&project, ExportFormat::Csv, 0).is_err());
+    assert!(matches!(export_bom(&project, ExportFormat::Csv, i64::MAX), Err(ShikaError::Validation(_))));
+    assert_eq!(default_file_name(&project, ExportFormat::Jlcpcb, 10), "Widget_revB_x10_jlcpcb.csv");
+    let mut revised = project.clone();
+    revised.metadata.revision = "B/2".to_string();
+    assert_eq!(default_file_name(&revised, ExportFormat::Csv, 1), "Widget_revB_2_csv.csv");
+    assert_eq!("PCBWay".parse::<ExportFormat>().unwrap(), ExportFormat::Pcbway);
+}
+
+#[test]
+fn test_comment_skips_empty_fields() {
+    let mut part = Part::new();
+    part.value = Some(String::new());
+    part.label = Some(String::new());
+    part.description = Some("Buck, 1A".to_string());
+    assert_eq!(comment(&part), "Buck, 1A");
+    part.label = Some("U_BUCK".to_string());
+    assert_eq!(comment(&part), "U_BUCK");
+    part.description = Some(String::new());
+    part.label = Some(String::new());
+    assert_eq!(comment(&part), "");
+}
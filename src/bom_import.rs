@@ -0,0 +1,406 @@
+use std::path::Path;
+use calamine::Reader;
+use serde::{Deserialize, Serialize};
+use crate::db::Db;
+use crate::errors::{ShikaError, ShikaResult};
+use crate::kicad_import::{csv_reader, normalize_name};
+use crate::parts::{validate_part, Part};
+use crate::projects::{compress_designators, parse_bom_qty, parse_designators, validate_bom_line, Project, ProjectPart};
+
+/// The BOM line and part fields a spreadsheet column can be mapped to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BomField {
+    PartNumber,
+    Designators,
+    Qty,
+    Manufacturer,
+    Value,
+    Package,
+    Tolerance,
+}
+
+impl BomField {
+    pub const ALL: [BomField; 7] = [
+        BomField::PartNumber,
+        BomField::Designators,
+        BomField::Qty,
+        BomField::Manufacturer,
+        BomField::Value,
+        BomField::Package,
+        BomField::Tolerance,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BomField::PartNumber => "Part number",
+            BomField::Designators => "Designators",
+            BomField::Qty => "Qty",
+            BomField::Manufacturer => "Manufacturer",
+            BomField::Value => "Value",
+            BomField::Package => "Package",
+            BomField::Tolerance => "Tolerance",
+        }
+    }
+
+    //Normalized header names guessed as this field
+    fn synonyms(&self) -> &'static [&'static str] {
+        match self {
+            BomField::PartNumber => &["partnumber", "mpn", "manufacturerpartnumber", "manufacturerpartnumber1", "mfrpartnumber",
+                                      "mfgpartnumber", "pn"],
+            BomField::Designators => &["designator", "designators", "reference", "references", "refdes", "ref", "refs"],
+            BomField::Qty => &["qty", "quantity", "count"],
+            BomField::Manufacturer => &["manufacturer", "manufacturer1", "mfr", "mfg"],
+            BomField::Value => &["value", "val", "comment"],
+            BomField::Package => &["package", "footprint", "case", "casepackage"],
+            BomField::Tolerance => &["tolerance", "tol"],
+        }
+    }
+}
+
+/// A spreadsheet read into memory. The first row with at least two filled cells is the header,
+/// anything above it (titles, dates) is dropped.
+pub struct ImportTable {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl ImportTable {
+    pub fn column(&self, header: &str) -> Option<usize> {
+        self.headers.iter().position(|existing| existing == header)
+    }
+
+    fn cell<'a>(&self, row: &'a [String], header: Option<&str>) -> &'a str {
+        header.and_then(|header| self.column(header))
+            .and_then(|idx| row.get(idx))
+            .map(|cell| cell.trim())
+            .unwrap_or("")
+    }
+}
+
+/// Reads `.xlsx`, `.xls` and `.ods` files from their first sheet, anything else as CSV.
+pub fn read_table(path: &Path) -> ShikaResult<ImportTable> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+    let rows = match extension.as_str() {
+        "xlsx" | "xlsm" | "xls" | "ods" => {
+            let mut workbook = calamine::open_workbook_auto(path)
+                .map_err(|e| ShikaError::Validation(format!("Can't open {}: {e}", path.display())))?;
+            let sheet = workbook.worksheet_range_at(0)
+                .ok_or_else(|| ShikaError::Validation(format!("{} has no sheets", path.display())))?
+                .map_err(|e| ShikaError::Validation(format!("Can't read {}: {e}", path.display())))?;
+            sheet.rows()
+                .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                .collect()
+        }
+        _ => {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| ShikaError::Validation(format!("Can't read {}: {e}", path.display())))?;
+            csv_rows(&text)?
+        }
+    };
+    table_from_rows(rows)
+}
+
+fn csv_rows(text: &str) -> ShikaResult<Vec<Vec<String>>> {
+    csv_reader(text).records()
+        .map(|record| {
+            record.map(|record| record.iter().map(str::to_string).collect())
+                .map_err(|e| ShikaError::Validation(format!("Not a valid CSV file: {e}")))
+        })
+        .collect()
+}
+
+pub fn table_from_rows(rows: Vec<Vec<String>>) -> ShikaResult<ImportTable> {
+    let filled = |row: &Vec<String>| row.iter().filter(|cell| !cell.trim().is_empty()).count();
+    let header_idx = rows.iter().position(|row| filled(row) >= 2)
+        .ok_or_else(|| ShikaError::Validation("No header row was found".to_string()))?;
+    let mut rows = rows;
+    let data = rows.split_off(header_idx + 1);
+    let headers = rows.pop().unwrap_or_default().iter().map(|header| header.trim().to_string()).collect();
+    Ok(ImportTable {
+        headers,
+        rows: data.into_iter().filter(|row| filled(row) > 0).collect(),
+    })
+}
+
+/// Which column feeds each field. Columns are kept by header name, so a saved profile still
+/// fits when the next file from the same source has its columns in another order.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct ColumnMapping {
+    pub part_number: Option<String>,
+    pub designators: Option<String>,
+    pub qty: Option<String>,
+    pub manufacturer: Option<String>,
+    pub value: Option<String>,
+    pub package: Option<String>,
+    pub tolerance: Option<String>,
+}
+
+impl ColumnMapping {
+    /// Guesses the mapping from the header names.
+    pub fn auto(headers: &[String]) -> ColumnMapping {
+        let mut mapping = ColumnMapping::default();
+        for field in BomField::ALL {
+            let header = headers.iter().find(|header| field.synonyms().contains(&normalize_name(header).as_str()));
+            mapping.set(field, header.cloned());
+        }
+        mapping
+    }
+
+    pub fn get(&self, field: BomField) -> Option<&str> {
+        let header = match field {
+            BomField::PartNumber => &self.part_number,
+            BomField::Designators => &self.designators,
+            BomField::Qty => &self.qty,
+            BomField::Manufacturer => &self.manufacturer,
+            BomField::Value => &self.value,
+            BomField::Package => &self.package,
+            BomField::Tolerance => &self.tolerance,
+        };
+        header.as_deref()
+    }
+
+    pub fn set(&mut self, field: BomField, header: Option<String>) {
+        let slot = match field {
+            BomField::PartNumber => &mut self.part_number,
+            BomField::Designators => &mut self.designators,
+            BomField::Qty => &mut self.qty,
+            BomField::Manufacturer => &mut self.manufacturer,
+            BomField::Value => &mut self.value,
+            BomField::Package => &mut self.package,
+            BomField::Tolerance => &mut self.tolerance,
+        };
+        *slot = header;
+    }
+
+    /// Whether every mapped column exists in `headers`.
+    pub fn fits(&self, headers: &[String]) -> bool {
+        BomField::ALL.iter()
+            .filter_map(|field| self.get(*field))
+            .all(|header| headers.iter().any(|existing| existing == header))
+    }
+}
+
+/// A column mapping saved under a name, one per BOM source.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImportProfile {
+    pub name: String,
+    pub mapping: ColumnMapping,
+}
+
+/// What an import would write, worked out before anything is.
+pub struct DryRun {
+    pub lines: Vec<ProjectPart>,
+    //Part numbers the parts table doesn't know yet, created with the line
+    pub new_parts: Vec<Part>,
+    //Rows that can't be imported, the import is refused while there are any
+    pub problems: Vec<String>,
+    //Rows without a part number, such as blank lines and totals
+    pub skipped_rows: usize,
+}
+
+impl DryRun {
+    pub fn can_commit(&self) -> bool {
+        self.problems.is_empty() && !self.lines.is_empty()
+    }
+}
+
+/// Works out the BOM lines the mapped table adds to `project`. Rows repeating a part number are
+/// merged, a qty column wins over counting the designators.
+pub fn dry_run(table: &ImportTable, mapping: &ColumnMapping, known_parts: &[Part], project: &Project) -> ShikaResult<DryRun> {
+    if mapping.part_number.is_none() {
+        return Err(ShikaError::Validation("Map a column to the part number".to_string()));
+    }
+    if mapping.qty.is_none() && mapping.designators.is_none() {
+        return Err(ShikaError::Validation("Map a column to the qty or the designators".to_string()));
+    }
+    let mut run = DryRun {
+        lines: Vec::new(),
+        new_parts: Vec::new(),
+        problems: Vec::new(),
+        skipped_rows: 0,
+    };
+    let mut designators: Vec<Vec<String>> = Vec::new();
+    for (idx, row) in table.rows.iter().enumerate() {
+        let pn = table.cell(row, mapping.get(BomField::PartNumber));
+        if pn.is_empty() {
+            run.skipped_rows += 1;
+            continue;
+        }
+        let row_designators = table.cell(row, mapping.get(BomField::Designators));
+        let count = parse_designators(row_designators).map(|parsed| parsed.len()).unwrap_or(0);
+        let qty_text = match table.cell(row, mapping.get(BomField::Qty)) {
+            "" => count.to_string(),
+            qty => qty.to_string(),
+        };
+        //Screws, the PCB and the like are often listed without designators
+        let checked = match row_designators.trim() {
+            "" => parse_bom_qty(&qty_text),
+            _ => validate_bom_line(&qty_text, row_designators),
+        };
+        let qty = match checked {
+            Ok(qty) => qty,
+            Err(e) => {
+                run.problems.push(format!("Row {} ({pn}): {e}", idx + 1));
+                continue;
+            }
+        };
+        let parsed = parse_designators(row_designators).unwrap_or_default();
+        match run.lines.iter().position(|line| line.partnumber == pn) {
+            Some(existing) => {
+                run.lines[existing].qty += qty;
+                designators[existing].extend(parsed);
+            }
+            None => {
+                run.lines.push(ProjectPart {
+                    partnumber: pn.to_string(),
+                    designators: "".to_string(),
+                    qty,
+                    part_info: Part::new(),
+                });
+                designators.push(parsed);
+            }
+        }
+        if !known_parts.iter().any(|part| part.part_number == pn) && !run.new_parts.iter().any(|part| part.part_number == pn) {
+            let optional = |field: BomField| Some(table.cell(row, mapping.get(field)).to_string()).filter(|text| !text.is_empty());
+            let mut part = Part::new();
+            part.part_number = pn.to_string();
+            part.manufacturer = optional(BomField::Manufacturer);
+            part.value = optional(BomField::Value);
+            part.package = optional(BomField::Package);
+            part.tolerance = optional(BomField::Tolerance);
+            //Committing validates the new parts as well, so a bad one is caught here first
+            if let Err(e) = validate_part(&part) {
+                run.problems.push(format!("Row {} ({pn}): {e}", idx + 1));
+            }
+            run.new_parts.push(part);
+        }
+    }
+    for (line, merged) in run.lines.iter_mut().zip(designators) {
+        line.designators = compress_designators(&merged);
+        if !merged.is_empty() && merged.len() != line.qty as usize {
+            run.problems.push(format!("{}: {} designators for a qty of {}", line.partnumber, merged.len(), line.qty));
+        }
+        if project.parts.iter().any(|ppart| ppart.partnumber == line.partnumber) {
+            run.problems.push(format!("{} is already in the BOM for {}", line.partnumber, project.name));
+        }
+    }
+    Ok(run)
+}
+
+/// Saved profiles, by name.
+pub fn fetch_import_profiles(db: &mut Db) -> ShikaResult<Vec<ImportProfile>> {
+    let rows = db.client()?.query("SELECT name, mapping FROM import_profiles ORDER BY name", &[])?;
+    rows.iter()
+        .map(|row| profile_from_json(row.get("name"), row.get("mapping")))
+        .collect()
+}
+
+/// Saves the profile, replacing one with the same name.
+pub fn save_import_profile(db: &mut Db, profile: &ImportProfile) -> ShikaResult<()> {
+    validate_profile_name(&profile.name)?;
+    db.client()?.execute("INSERT INTO import_profiles (name, mapping) VALUES ($1, $2)
+                    ON CONFLICT (name) DO UPDATE SET mapping = excluded.mapping",
+                         &[&profile.name, &profile_to_json(profile)?])?;
+    Ok(())
+}
+
+pub fn validate_profile_name(name: &str) -> ShikaResult<()> {
+    if name.trim().is_empty() {
+        return Err(ShikaError::Validation("Profile name cannot be empty".to_string()));
+    }
+    Ok(())
+}
+
+//Profiles are stored as JSON so new fields don't need a migration
+pub fn profile_to_json(profile: &ImportProfile) -> ShikaResult<String> {
+    serde_json::to_string(&profile.mapping)
+        .map_err(|e| ShikaError::Validation(format!("Can't save profile {}: {e}", profile.name)))
+}
+
+pub fn profile_from_json(name: String, mapping: String) -> ShikaResult<ImportProfile> {
+    let mapping = serde_json::from_str(&mapping)
+        .map_err(|e| ShikaError::Database(format!("Import profile {name} is damaged: {e}")))?;
+    Ok(ImportProfile { name, mapping })
+}
+
+#[cfg(test)]
+fn test_table() -> ImportTable {
+    let rows = "Customer BOM,,,\n\
+                Comment,Designator,Quantity,Manufacturer Part Number 1,Manufacturer 1\n\
+                10k,\"R1, R2\",2,RC0603FR-0710KL,Yageo\n\
+                10k,R3,1,RC0603FR-0710KL,Yageo\n\
+                100n,C1-C3,3,CL10B104KB8NNNC,Samsung\n\
+                ,,,,\n\
+                Total,,6,,\n";
+    table_from_rows(csv_rows(rows).unwrap()).unwrap()
+}
+
+#[test]
+fn test_read_table_and_auto_map() {
+    let table = test_table();
+    assert_eq!(table.headers[1], "Designator");
+    assert_eq!(table.rows.len(), 4);
+    let mapping = ColumnMapping::auto(&table.headers);
+    assert_eq!(mapping.part_number.as_deref(), Some("Manufacturer Part Number 1"));
+    assert_eq!(mapping.designators.as_deref(), Some("Designator"));
+    assert_eq!(mapping.qty.as_deref(), Some("Quantity"));
+    assert_eq!(mapping.value.as_deref(), Some("Comment"));
+    assert_eq!(mapping.package, None);
+    assert!(mapping.fits(&table.headers));
+    assert!(!mapping.fits(&["Part".to_string()]));
+}
+
+#[test]
+fn test_dry_run() {
+    let table = test_table();
+    let mapping = ColumnMapping::auto(&table.headers);
+    let mut known = Part::new();
+    known.part_number = "RC0603FR-0710KL".to_string();
+    let mut project = Project::new();
+    project.name = "Sample Board".to_string();
+
+    let run = dry_run(&table, &mapping, &[known.clone()], &project).unwrap();
+    assert!(run.can_commit());
+    assert_eq!(run.skipped_rows, 1);
+    assert_eq!(run.lines.len(), 2);
+    assert_eq!((run.lines[0].designators.as_str(), run.lines[0].qty), ("R1-R3", 3));
+    assert_eq!(run.new_parts.len(), 1);
+    assert_eq!(run.new_parts[0].manufacturer.as_deref(), Some("Samsung"));
+    assert_eq!(run.new_parts[0].value.as_deref(), Some("100n"));
+
+    project.parts.push(run.lines[0].clone());
+    let run = dry_run(&table, &mapping, &[known.clone()], &project).unwrap();
+    assert!(!run.can_commit());
+    assert_eq!(run.problems, vec!["RC0603FR-0710KL is already in the BOM for Sample Board"]);
+
+    //New parts are validated like the commit does, 100n is no tolerance
+    let mut bad_tolerance = mapping.clone();
+    bad_tolerance.set(BomField::Tolerance, Some("Comment".to_string()));
+    project.parts.clear();
+    let run = dry_run(&table, &bad_tolerance, &[known.clone()], &project).unwrap();
+    assert!(!run.can_commit());
+    assert_eq!(run.problems.len(), 1);
+    assert!(run.problems[0].starts_with("Row 3 (CL10B104KB8NNNC)"));
+
+    //Rows without designators only need a qty
+    let mut no_designators = mapping.clone();
+    no_designators.set(BomField::Designators, None);
+    let run = dry_run(&table, &no_designators, &[known.clone()], &project).unwrap();
+    assert!(run.can_commit());
+    assert_eq!((run.lines[0].designators.as_str(), run.lines[0].qty), ("", 3));
+
+    let mut no_pn = mapping.clone();
+    no_pn.set(BomField::PartNumber, None);
+    assert!(dry_run(&table, &no_pn, &[known], &project).is_err());
+}
+
+#[test]
+fn test_profile_json() {
+    let profile = ImportProfile {
+        name: "Altium".to_string(),
+        mapping: ColumnMapping::auto(&test_table().headers),
+    };
+    let json = profile_to_json(&profile).unwrap();
+    assert_eq!(profile_from_json("Altium".to_string(), json).unwrap(), profile);
+    assert!(profile_from_json("Broken".to_string(), "{".to_string()).is_err());
+}
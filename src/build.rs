@@ -0,0 +1,361 @@
+use std::fmt;
+use std::str::FromStr;
+use postgres::{Row, Transaction};
+use serde::Serialize;
+use crate::db::Db;
+use crate::errors::{ShikaError, ShikaResult};
+use crate::projects::{Project, ProjectPart};
+use crate::parts::Part;
+use crate::stock::{self, plan_spend, SpendReason, StockInfo, StockSpend, StockTransaction, TransactionKind};
+
+/// One BOM line of a planned build checked against the available stock.
+#[derive(Serialize)]
+pub struct ShortageLine {
+    pub partnumber: String,
+    pub designators: String,
+    pub per_unit: i32,
+    pub required: i64,
+    pub available: i32,
+    //0 when there is enough
+    pub shortfall: i64,
+}
+
+/// Whether `units` of a project can be built from the available stock.
+#[derive(Serialize)]
+pub struct BuildPlan {
+    pub units: i32,
+    pub lines: Vec<ShortageLine>,
+    //None for a BOM without any lines, nothing limits those
+    pub max_buildable: Option<i64>,
+}
+
+impl BuildPlan {
+    pub fn can_build(&self) -> bool {
+        self.lines.iter().all(|line| line.shortfall == 0)
+    }
+
+    pub fn short_lines(&self) -> usize {
+        self.lines.iter().filter(|line| line.shortfall > 0).count()
+    }
+}
+
+/// Checks qty × `units` of every BOM line against `StockInfo.available`. Parts without a stock
+/// row count as nothing available. The BOM lines have to be loaded with `fetch_project_details`.
+pub fn plan_build(project: &Project, stock: &[StockInfo], units: i32) -> BuildPlan {
+    let mut lines = Vec::new();
+    let mut max_buildable: Option<i64> = None;
+    for ppart in &project.parts {
+        let available = stock.iter()
+            .find(|info| info.partnumber == ppart.partnumber)
+            .map(|info| info.available)
+            .unwrap_or(0);
+        let required = ppart.qty as i64 * units as i64;
+        if ppart.qty > 0 {
+            let buildable = available.max(0) as i64 / ppart.qty as i64;
+            max_buildable = Some(max_buildable.map_or(buildable, |max| max.min(buildable)));
+        }
+        lines.push(ShortageLine {
+            partnumber: ppart.partnumber.clone(),
+            designators: ppart.designators.clone(),
+            per_unit: ppart.qty,
+            required,
+            available,
+            shortfall: (required - available as i64).max(0),
+        });
+    }
+    BuildPlan {
+        units,
+        lines,
+        max_buildable,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BuildStatus {
+    //Its parts are reserved in `in_prod`
+    InProgress,
+    Completed,
+    Cancelled,
+}
+
+impl BuildStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::InProgress => "in-progress",
+            BuildStatus::Completed => "completed",
+            BuildStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for BuildStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for BuildStatus {
+    type Err = ShikaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in-progress" => Ok(BuildStatus::InProgress),
+            "completed" => Ok(BuildStatus::Completed),
+            "cancelled" => Ok(BuildStatus::Cancelled),
+            _ => Err(ShikaError::Validation(format!("Unknown build status {s}"))),
+        }
+    }
+}
+
+/// A row of the `builds` table.
+#[derive(Clone)]
+pub struct Build {
+    pub id: i64,
+    pub project_name: String,
+    pub quantity: i32,
+    pub status: BuildStatus,
+    pub started_at: String,
+    //None while the build is in progress
+    pub finished_at: Option<String>,
+}
+
+/// What a build reserved of one part, stored in `build_parts`.
+#[derive(Clone)]
+pub struct BuildPart {
+    pub partnumber: String,
+    pub quantity: i32,
+}
+
+impl BuildPart {
+    /// Completing a build spends the reserved quantity from wherever it is stored.
+    pub fn to_spend(&self) -> StockSpend {
+        StockSpend {
+            partnumber: self.partnumber.clone(),
+            quantity: self.quantity,
+            location: None,
+            reason: SpendReason::Build,
+        }
+    }
+
+    /// The ledger entries for consuming this part, referencing the build.
+    pub fn to_transactions(&self, build_id: i64, plan: &[(String, i32)]) -> Vec<StockTransaction> {
+        let mut entries = self.to_spend().to_transactions_of(TransactionKind::BuildConsume, plan);
+        for entry in &mut entries {
+            entry.reference = Some(format!("Build #{build_id}"));
+        }
+        entries
+    }
+}
+
+/// Works out what starting `units` of a project reserves, refusing builds the available stock
+/// can't cover.
+pub fn reserve_build(project: &Project, stock: &[StockInfo], units: i32) -> ShikaResult<Vec<BuildPart>> {
+    if units <= 0 {
+        return Err(ShikaError::Validation("Build at least one unit".to_string()));
+    }
+    if project.parts.is_empty() {
+        return Err(ShikaError::Validation(format!("{} has no BOM lines to build", project.name)));
+    }
+    let plan = plan_build(project, stock, units);
+    if !plan.can_build() {
+        let short: Vec<String> = plan.lines.iter()
+            .filter(|line| line.shortfall > 0)
+            .map(|line| format!("{} short by {}", line.partnumber, line.shortfall))
+            .collect();
+        return Err(ShikaError::Validation(format!("Can't build {units}: {}", short.join(", "))));
+    }
+    Ok(plan.lines.iter()
+        .map(|line| BuildPart {
+            partnumber: line.partnumber.clone(),
+            quantity: line.required as i32,
+        })
+        .collect())
+}
+
+/// Only builds in progress can be completed or cancelled.
+pub fn ensure_in_progress(id: i64, status: BuildStatus) -> ShikaResult<()> {
+    if status != BuildStatus::InProgress {
+        return Err(ShikaError::Validation(format!("Build #{id} is already {status}")));
+    }
+    Ok(())
+}
+
+/// Reserves the parts for `units` of the project and returns the new build's id.
+pub fn start_build(db: &mut Db, project_name: &str, units: i32) -> ShikaResult<i64> {
+    let mut transaction = db.client()?.transaction()?;
+    let mut project = Project::new();
+    project.name = project_name.to_string();
+    for row in transaction.query("SELECT partnumber, designators, qty FROM project_components
+                    WHERE project_name = $1 ORDER BY partnumber", &[&project_name])? {
+        project.parts.push(ProjectPart {
+            partnumber: row.get("partnumber"),
+            designators: row.get("designators"),
+            qty: row.get("qty"),
+            part_info: Part::new(),
+        });
+    }
+    //Locked so the availability check can't go stale before the reservation is booked
+    let stock: Vec<StockInfo> = transaction.query("SELECT partnumber, on_hand, in_prod FROM stock
+                    WHERE partnumber IN (SELECT partnumber FROM project_components WHERE project_name = $1)
+                    FOR UPDATE", &[&project_name])?
+        .iter()
+        .map(|row| {
+            let mut info = StockInfo::new();
+            info.partnumber = row.get("partnumber");
+            info.on_hand = row.get("on_hand");
+            info.in_prod = row.get("in_prod");
+            info.recompute();
+            info
+        })
+        .collect();
+    let reserved = reserve_build(&project, &stock, units)?;
+    let id: i64 = transaction.query_one("INSERT INTO builds (project_name, quantity) VALUES ($1, $2) RETURNING id",
+                                        &[&project_name, &units])?.get("id");
+    for part in &reserved {
+        transaction.execute("INSERT INTO build_parts (build_id, partnumber, quantity) VALUES ($1, $2, $3)",
+                            &[&id, &part.partnumber, &part.quantity])?;
+        transaction.execute("UPDATE stock SET in_prod = in_prod + $2 WHERE partnumber = $1",
+                            &[&part.partnumber, &part.quantity])?;
+    }
+    transaction.commit()?;
+    Ok(id)
+}
+
+/// Takes the reserved parts out of `on_hand`, `in_prod` and their storage locations, booking
+/// them as build-consume transactions.
+pub fn complete_build(db: &mut Db, id: i64) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    let parts = lock_open_build(&mut transaction, id)?;
+    for part in &parts {
+        let (on_hand, in_prod) = stock::lock_stock_levels(&mut transaction, &part.partnumber)?;
+        let storage = stock::lock_part_storage(&mut transaction, &part.partnumber)?;
+        //The build spends its own reservation, not what other builds hold
+        let plan = plan_spend(&part.to_spend(), on_hand, in_prod - part.quantity, &storage)?;
+        transaction.execute("UPDATE stock SET on_hand = on_hand - $2, in_prod = in_prod - $2 WHERE partnumber = $1", &[&part.partnumber, &part.quantity])?;
+        for (location, quantity) in &plan {
+            transaction.execute("UPDATE part_storage SET quantity = quantity - $3 WHERE partnumber = $1 AND storage_loc_name = $2",
+                                &[&part.partnumber, location, quantity])?;
+        }
+        for entry in part.to_transactions(id, &plan) {
+            stock::insert_transaction(&mut transaction, &entry)?;
+        }
+    }
+    finish_build(&mut transaction, id, BuildStatus::Completed)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Releases the reservation of a build that won't happen.
+pub fn cancel_build(db: &mut Db, id: i64) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    let parts = lock_open_build(&mut transaction, id)?;
+    for part in &parts {
+        transaction.execute("UPDATE stock SET in_prod = in_prod - $2 WHERE partnumber = $1", &[&part.partnumber, &part.quantity])?;
+    }
+    finish_build(&mut transaction, id, BuildStatus::Cancelled)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The project's builds, newest first.
+pub fn fetch_builds(db: &mut Db, project_name: &str) -> ShikaResult<Vec<Build>> {
+    let rows = db.client()?.query("SELECT id, project_name, quantity, status,
+                    SUBSTR(CAST(started_at AS TEXT), 1, 19) AS started_at,
+                    SUBSTR(CAST(finished_at AS TEXT), 1, 19) AS finished_at
+                    FROM builds WHERE project_name = $1 ORDER BY id DESC", &[&project_name])?;
+    rows.iter().map(build_from_row).collect()
+}
+
+fn build_from_row(row: &Row) -> ShikaResult<Build> {
+    Ok(Build {
+        id: row.get("id"),
+        project_name: row.get("project_name"),
+        quantity: row.get("quantity"),
+        status: row.get::<_, String>("status").parse()?,
+        started_at: row.get("started_at"),
+        finished_at: row.get("finished_at"),
+    })
+}
+
+//Locks the build, checks it is still in progress and returns what it reserved
+fn lock_open_build(transaction: &mut Transaction, id: i64) -> ShikaResult<Vec<BuildPart>> {
+    let status: String = transaction.query_opt("SELECT status FROM builds WHERE id = $1 FOR UPDATE", &[&id])?
+        .ok_or_else(|| ShikaError::NotFound(format!("Build #{id} does not exist")))?
+        .get("status");
+    ensure_in_progress(id, status.parse()?)?;
+    let rows = transaction.query("SELECT partnumber, quantity FROM build_parts WHERE build_id = $1 ORDER BY partnumber", &[&id])?;
+    Ok(rows.iter()
+        .map(|row| BuildPart {
+            partnumber: row.get("partnumber"),
+            quantity: row.get("quantity"),
+        })
+        .collect())
+}
+
+fn finish_build(transaction: &mut Transaction, id: i64, status: BuildStatus) -> ShikaResult<()> {
+    transaction.execute("UPDATE builds SET status = $2, finished_at = CURRENT_TIMESTAMP WHERE id = $1",
+                        &[&id, &status.as_str()])?;
+    Ok(())
+}
+
+#[test]
+fn test_plan_build() {
+    let mut project = Project::new();
+    for (pn, qty) in [("R", 4), ("C", 1)] {
+        project.parts.push(ProjectPart {
+            partnumber: pn.to_string(),
+            designators: "".to_string(),
+            qty,
+            part_info: Part::new(),
+        });
+    }
+    let mut resistors = StockInfo::new();
+    resistors.partnumber = "R".to_string();
+    resistors.available = 10;
+    let mut capacitors = StockInfo::new();
+    capacitors.partnumber = "C".to_string();
+    capacitors.available = 5;
+    let stock = vec![resistors, capacitors];
+
+    let plan = plan_build(&project, &stock, 2);
+    assert!(plan.can_build());
+    assert_eq!(plan.max_buildable, Some(2));
+    let plan = plan_build(&project, &stock, 3);
+    assert!(!plan.can_build());
+    assert_eq!(plan.short_lines(), 1);
+    assert_eq!((plan.lines[0].required, plan.lines[0].shortfall), (12, 2));
+    assert_eq!(plan.lines[1].shortfall, 0);
+
+    //No stock row at all means nothing can be built
+    let plan = plan_build(&project, &stock[..1], 1);
+    assert_eq!(plan.max_buildable, Some(0));
+    assert_eq!(plan.lines[1].shortfall, 1);
+    assert_eq!(plan_build(&Project::new(), &stock, 1).max_buildable, None);
+}
+
+#[test]
+fn test_reserve_build() {
+    let mut project = Project::new();
+    project.name = "Sample Board".to_string();
+    assert!(matches!(reserve_build(&project, &[], 1), Err(ShikaError::Validation(_))));
+    project.parts.push(ProjectPart {
+        partnumber: "R".to_string(),
+        designators: "R1-R4".to_string(),
+        qty: 4,
+        part_info: Part::new(),
+    });
+    let mut resistors = StockInfo::new();
+    resistors.partnumber = "R".to_string();
+    resistors.on_hand = 10;
+    resistors.in_prod = 2;
+    resistors.recompute();
+    let reserved = reserve_build(&project, &[resistors.clone()], 2).unwrap();
+    assert_eq!((reserved[0].partnumber.as_str(), reserved[0].quantity), ("R", 8));
+    assert!(matches!(reserve_build(&project, &[resistors.clone()], 3), Err(ShikaError::Validation(_))));
+    assert!(matches!(reserve_build(&project, &[resistors], 0), Err(ShikaError::Validation(_))));
+
+    let entries = reserved[0].to_transactions(7, &[("Drawer A1".to_string(), 5), ("Reel rack".to_string(), 3)]);
+    assert_eq!(entries.iter().map(|entry| entry.delta).sum::<i32>(), -8);
+    assert!(entries.iter().all(|entry| entry.kind == "build-consume" && entry.reference.as_deref() == Some("Build #7")));
+    assert!(ensure_in_progress(7, BuildStatus::Completed).is_err());
+}
@@ -0,0 +1,389 @@
+use std::fmt;
+use std::str::FromStr;
+use postgres::Row;
+use serde::Serialize;
+use crate::db::Db;
+use crate::eng_value::{parse_value, Unit};
+use crate::errors::{ShikaError, ShikaResult};
+use crate::part_search::SearchField;
+use crate::parts::Part;
+
+/// How the values of an attribute are checked. They are all stored as text.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeKind {
+    Text,
+    Number,
+    //An engineering value like 50V or 16MHz, see `eng_value`
+    Value,
+}
+
+impl AttributeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeKind::Text => "text",
+            AttributeKind::Number => "number",
+            AttributeKind::Value => "value",
+        }
+    }
+}
+
+impl fmt::Display for AttributeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for AttributeKind {
+    type Err = ShikaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(AttributeKind::Text),
+            "number" => Ok(AttributeKind::Number),
+            "value" => Ok(AttributeKind::Value),
+            _ => Err(ShikaError::Validation(format!("Unknown attribute kind {s}, expected text, number or value"))),
+        }
+    }
+}
+
+/// One attribute in a category's schema, like a capacitor's `voltage`.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct AttributeDef {
+    pub name: String,
+    pub kind: AttributeKind,
+    //Only for values, a value typed without a unit gets this one
+    pub unit: Option<Unit>,
+}
+
+impl AttributeDef {
+    /// Reads `name:kind` or `name:value:unit`, e.g. `voltage:value:V`.
+    pub fn parse(spec: &str) -> ShikaResult<AttributeDef> {
+        let mut parts = spec.split(':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let kind: AttributeKind = parts.next().unwrap_or("text").trim().parse()?;
+        let unit = match parts.next().map(str::trim) {
+            Some(symbol) if kind == AttributeKind::Value => Some(Unit::from_symbol(symbol)
+                .ok_or_else(|| ShikaError::Validation(format!("Unknown unit {symbol}")))?),
+            Some(_) => return Err(ShikaError::Validation(format!("Only value attributes have a unit, not {name}"))),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return Err(ShikaError::Validation(format!("Expected name:kind or name:value:unit, not {spec}")));
+        }
+        Ok(AttributeDef { name, kind, unit })
+    }
+
+    pub fn validate(&self, text: &str) -> ShikaResult<()> {
+        let text = text.trim();
+        match self.kind {
+            AttributeKind::Text => Ok(()),
+            AttributeKind::Number => match text.parse::<f64>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(ShikaError::Validation(format!("{} must be a number, not {text}", self.name))),
+            },
+            AttributeKind::Value => match (parse_value(text), self.unit) {
+                (None, _) => Err(ShikaError::Validation(format!("{} must be a value like 50V or 100m, not {text}", self.name))),
+                (Some(value), Some(unit)) if value.unit.is_some_and(|value_unit| value_unit != unit) => {
+                    Err(ShikaError::Validation(format!("{} must be in {}, not {text}", self.name, unit.symbol())))
+                }
+                _ => Ok(()),
+            },
+        }
+    }
+
+    /// The stored form, values get the unit of the schema: `50` becomes `50V`.
+    pub fn normalize(&self, text: &str) -> String {
+        match (self.kind, parse_value(text)) {
+            (AttributeKind::Value, Some(mut value)) => {
+                value.unit = value.unit.or(self.unit);
+                value.to_string()
+            }
+            _ => text.trim().to_string(),
+        }
+    }
+}
+
+/// Written the way `parse` reads it.
+impl fmt::Display for AttributeDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.unit {
+            Some(unit) => write!(f, "{}:{}:{}", self.name, self.kind, unit.symbol()),
+            None => write!(f, "{}:{}", self.name, self.kind),
+        }
+    }
+}
+
+/// A part category like `Passives/Resistors/Chip`. Its parts have the attributes of the
+/// category and of every category above it.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Category {
+    pub path: String,
+    //Only the ones added at this level, `schema` includes the inherited ones
+    pub attributes: Vec<AttributeDef>,
+}
+
+impl Category {
+    pub fn parent(&self) -> Option<&str> {
+        self.path.rsplit_once('/').map(|(parent, _)| parent)
+    }
+}
+
+/// `Passives/Resistors/Chip` as `Passives > Resistors > Chip`.
+pub fn display_path(path: &str) -> String {
+    path.replace('/', " > ")
+}
+
+/// Whether `path` is `ancestor` or somewhere below it.
+pub fn in_category(path: &str, ancestor: &str) -> bool {
+    path == ancestor || path.strip_prefix(ancestor).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Every attribute a part in `path` has, the top category's first.
+pub fn schema(categories: &[Category], path: &str) -> Vec<AttributeDef> {
+    let mut attributes: Vec<AttributeDef> = Vec::new();
+    let mut ancestors: Vec<&Category> = categories.iter().filter(|category| in_category(path, &category.path)).collect();
+    ancestors.sort_by_key(|category| category.path.len());
+    for category in ancestors {
+        for attribute in &category.attributes {
+            attributes.retain(|existing| existing.name != attribute.name);
+            attributes.push(attribute.clone());
+        }
+    }
+    attributes
+}
+
+pub fn validate_category_path(path: &str) -> ShikaResult<()> {
+    if path.split('/').any(|segment| segment.trim().is_empty() || segment.trim() != segment) {
+        return Err(ShikaError::Validation(format!("{path} is not a valid category, use e.g. Passives/Resistors/Chip")));
+    }
+    Ok(())
+}
+
+/// Checks a new or changed category against the existing ones: its parent has to exist and its
+/// attribute names have to work as search filters.
+pub fn validate_category(category: &Category, categories: &[Category]) -> ShikaResult<()> {
+    validate_category_path(&category.path)?;
+    if let Some(parent) = category.parent() {
+        if !categories.iter().any(|existing| existing.path == parent) {
+            return Err(ShikaError::Validation(format!("Create {parent} before {}", category.path)));
+        }
+    }
+    for (idx, attribute) in category.attributes.iter().enumerate() {
+        let name = &attribute.name;
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == ':') {
+            return Err(ShikaError::Validation(format!("'{name}' is not a valid attribute name, use one word")));
+        }
+        //`value:` already filters the value column
+        if SearchField::from_name(name).is_some() {
+            return Err(ShikaError::Validation(format!("{name} is already a part column")));
+        }
+        if category.attributes[..idx].iter().any(|other| other.name == *name) {
+            return Err(ShikaError::Validation(format!("{name} is in {} twice", category.path)));
+        }
+    }
+    Ok(())
+}
+
+/// A category can only go once nothing is in it. `parts` is how many parts are.
+pub fn validate_category_removal(path: &str, categories: &[Category], parts: usize) -> ShikaResult<()> {
+    if !categories.iter().any(|category| category.path == path) {
+        return Err(ShikaError::NotFound(format!("Category {path} does not exist")));
+    }
+    if categories.iter().any(|category| category.parent() == Some(path)) {
+        return Err(ShikaError::ConstraintViolation(format!("{path} still has subcategories")));
+    }
+    if parts > 0 {
+        return Err(ShikaError::ConstraintViolation(format!("{parts} parts are still in {path}")));
+    }
+    Ok(())
+}
+
+/// Checks the category and attributes of a part before it is written.
+pub fn validate_part_category(part: &Part, categories: &[Category]) -> ShikaResult<()> {
+    let attributes = match part.category.as_deref() {
+        Some(path) if !categories.iter().any(|category| category.path == path) => {
+            return Err(ShikaError::Validation(format!("Category {path} does not exist")));
+        }
+        Some(path) => schema(categories, path),
+        None => Vec::new(),
+    };
+    for (name, text) in &part.attributes {
+        match attributes.iter().find(|attribute| attribute.name == *name) {
+            Some(attribute) => attribute.validate(text)?,
+            None => return Err(ShikaError::Validation(format!("{name} is not an attribute of {}",
+                                                              part.category.as_deref().unwrap_or("parts without a category")))),
+        }
+    }
+    Ok(())
+}
+
+/// Drops empty attributes and writes the rest the way `AttributeDef::normalize` does.
+pub fn normalize_part_attributes(part: &mut Part, categories: &[Category]) {
+    part.category = part.category.take().map(|path| path.trim().to_string()).filter(|path| !path.is_empty());
+    let attributes = part.category.as_deref().map(|path| schema(categories, path)).unwrap_or_default();
+    part.attributes.retain(|_, text| !text.trim().is_empty());
+    for (name, text) in part.attributes.iter_mut() {
+        *text = match attributes.iter().find(|attribute| attribute.name == *name) {
+            Some(attribute) => attribute.normalize(text),
+            None => text.trim().to_string(),
+        };
+    }
+}
+
+/// The attributes any of `parts` has, in schema order, for showing as table columns.
+pub fn attribute_columns(categories: &[Category], parts: &[Part]) -> Vec<String> {
+    let mut columns: Vec<String> = Vec::new();
+    let names = categories.iter()
+        .flat_map(|category| category.attributes.iter().map(|attribute| &attribute.name))
+        //Ones no schema lists anymore go last
+        .chain(parts.iter().flat_map(|part| part.attributes.keys()));
+    for name in names {
+        if !columns.contains(name) && parts.iter().any(|part| part.attributes.contains_key(name)) {
+            columns.push(name.clone());
+        }
+    }
+    columns
+}
+
+/// The categories a new database starts with, the same as migration 0010 adds.
+pub fn default_categories() -> Vec<Category> {
+    let attribute = |name: &str, kind: AttributeKind, unit: Option<Unit>| AttributeDef { name: name.to_string(), kind, unit };
+    let defaults = [
+        ("Passives", vec![]),
+        ("Passives/Resistors", vec![attribute("power", AttributeKind::Value, Some(Unit::Watt))]),
+        ("Passives/Resistors/Chip", vec![]),
+        ("Passives/Capacitors", vec![attribute("voltage", AttributeKind::Value, Some(Unit::Volt))]),
+        ("Passives/Capacitors/Ceramic", vec![attribute("dielectric", AttributeKind::Text, None)]),
+        ("Passives/Inductors", vec![attribute("current", AttributeKind::Value, Some(Unit::Amp))]),
+        ("ICs", vec![attribute("pins", AttributeKind::Number, None)]),
+        ("Connectors", vec![attribute("pins", AttributeKind::Number, None), attribute("pitch", AttributeKind::Text, None)]),
+        ("Crystals", vec![attribute("frequency", AttributeKind::Value, Some(Unit::Hertz)),
+                          attribute("load", AttributeKind::Value, Some(Unit::Farad))]),
+    ];
+    defaults.into_iter()
+        .map(|(path, attributes)| Category { path: path.to_string(), attributes })
+        .collect()
+}
+
+/// Every category sorted by path, so parents come before their children.
+pub fn fetch_categories(db: &mut Db) -> ShikaResult<Vec<Category>> {
+    let client = db.client()?;
+    let mut categories: Vec<Category> = client.query("SELECT path FROM categories ORDER BY path", &[])?
+        .iter()
+        .map(|row| Category { path: row.get("path"), attributes: Vec::new() })
+        .collect();
+    for row in client.query("SELECT * FROM category_attributes ORDER BY category, position", &[])? {
+        let path: String = row.get("category");
+        if let Some(category) = categories.iter_mut().find(|category| category.path == path) {
+            category.attributes.push(attribute_from_row(&row)?);
+        }
+    }
+    Ok(categories)
+}
+
+/// Creates the category or replaces its attributes.
+pub fn save_category(db: &mut Db, category: &Category) -> ShikaResult<()> {
+    validate_category(category, &fetch_categories(db)?)?;
+    let mut transaction = db.client()?.transaction()?;
+    transaction.execute("INSERT INTO categories (path, parent) VALUES ($1, $2) ON CONFLICT (path) DO NOTHING",
+                        &[&category.path, &category.parent()])?;
+    transaction.execute("DELETE FROM category_attributes WHERE category = $1", &[&category.path])?;
+    for (position, attribute) in category.attributes.iter().enumerate() {
+        transaction.execute("INSERT INTO category_attributes (category, name, kind, unit, position) VALUES ($1, $2, $3, $4, $5)",
+                            &[&category.path, &attribute.name, &attribute.kind.as_str(),
+                              &attribute.unit.map(|unit| unit.symbol()), &(position as i32)])?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+pub fn delete_category(db: &mut Db, path: &str) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    //Locking the category and its subcategories holds off parts and new subcategories being
+    //put in it until the delete is done
+    let categories: Vec<Category> = transaction.query("SELECT path FROM categories
+                    WHERE path = $1 OR parent = $1 FOR UPDATE", &[&path])?
+        .iter()
+        .map(|row| Category { path: row.get("path"), attributes: Vec::new() })
+        .collect();
+    let parts: i64 = transaction.query_one("SELECT COUNT(*) FROM parts WHERE category = $1", &[&path])?.get(0);
+    validate_category_removal(path, &categories, parts as usize)?;
+    transaction.execute("DELETE FROM categories WHERE path = $1", &[&path])?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn attribute_from_row(row: &Row) -> ShikaResult<AttributeDef> {
+    let unit: Option<String> = row.get("unit");
+    Ok(AttributeDef {
+        name: row.get("name"),
+        kind: row.get::<_, String>("kind").parse()?,
+        unit: unit.as_deref().and_then(Unit::from_symbol),
+    })
+}
+
+#[cfg(test)]
+fn categorized_part(category: &str, attributes: &[(&str, &str)]) -> Part {
+    let mut part = Part::new();
+    part.part_number = "GRM188R71H104KA93D".to_string();
+    part.category = Some(category.to_string());
+    part.attributes = attributes.iter().map(|(name, text)| (name.to_string(), text.to_string())).collect();
+    part
+}
+
+#[test]
+fn test_category_schema() {
+    let categories = default_categories();
+    let names: Vec<String> = schema(&categories, "Passives/Capacitors/Ceramic").iter().map(|attribute| attribute.to_string()).collect();
+    assert_eq!(names, vec!["voltage:value:V", "dielectric:text"]);
+    assert!(schema(&categories, "Passives/Capacitor").is_empty());
+    assert!(in_category("Passives/Resistors/Chip", "Passives"));
+    assert!(!in_category("Passives/Resistors", "Passives/Res"));
+    assert_eq!(display_path("Passives/Resistors/Chip"), "Passives > Resistors > Chip");
+    for (path, valid) in [("Passives/Resistors", true), ("Passives//Chip", false), ("/ICs", false), ("ICs ", false), ("", false)] {
+        assert_eq!(validate_category_path(path).is_ok(), valid, "{path}");
+    }
+
+    let mut category = Category { path: "Passives/Capacitors/Film".to_string(), attributes: vec![AttributeDef::parse("Ripple:value:A").unwrap()] };
+    assert_eq!(category.attributes[0].name, "ripple");
+    assert!(validate_category(&category, &categories).is_ok());
+    category.path = "Magnetics/Chokes".to_string();
+    assert!(validate_category(&category, &categories).is_err());
+    category.path = "Magnetics".to_string();
+    category.attributes.push(AttributeDef::parse("package:text").unwrap());
+    assert!(validate_category(&category, &categories).is_err());
+    assert!(AttributeDef::parse("pins:number:V").is_err());
+    assert!(AttributeDef::parse("voltage:value:furlong").is_err());
+    assert!(validate_category_removal("Passives", &categories, 0).is_err());
+    assert!(validate_category_removal("Crystals", &categories, 2).is_err());
+    assert!(validate_category_removal("Crystals", &categories, 0).is_ok());
+}
+
+#[test]
+fn test_part_attributes() {
+    let categories = default_categories();
+    let mut part = categorized_part("Passives/Capacitors/Ceramic", &[("voltage", "50"), ("dielectric", " X7R "), ("pins", "")]);
+    normalize_part_attributes(&mut part, &categories);
+    assert_eq!(part.attributes.get("voltage").map(String::as_str), Some("50V"));
+    assert_eq!(part.attributes.get("dielectric").map(String::as_str), Some("X7R"));
+    assert!(!part.attributes.contains_key("pins"));
+    assert!(validate_part_category(&part, &categories).is_ok());
+
+    for (category, attributes) in [
+        ("Passives/Capacitors/Ceramic", [("voltage", "50A")]),
+        ("Passives/Capacitors/Ceramic", [("voltage", "fifty")]),
+        ("Passives/Capacitors/Ceramic", [("pins", "2")]),
+        ("ICs", [("pins", "lots")]),
+        ("Widgets", [("pins", "2")]),
+    ] {
+        assert!(validate_part_category(&categorized_part(category, &attributes), &categories).is_err(), "{category} {attributes:?}");
+    }
+    let columns = attribute_columns(&categories, &[categorized_part("ICs", &[("pins", "8"), ("grade", "automotive")]), part]);
+    assert_eq!(columns, vec!["voltage", "dielectric", "pins", "grade"]);
+
+    let mut uncategorized = categorized_part("", &[("pins", "8")]);
+    normalize_part_attributes(&mut uncategorized, &categories);
+    assert_eq!(uncategorized.category, None);
+    assert!(validate_part_category(&uncategorized, &categories).is_err());
+}
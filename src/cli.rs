@@ -0,0 +1,229 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use crate::bom_export::ExportFormat;
+use crate::categories::AttributeDef;
+use crate::config::{Backend, TlsMode};
+use crate::stock::SpendReason;
+
+/// Command line flags. Database flags override the config file and environment variables.
+#[derive(Parser, Debug)]
+#[command(name = "shikabom", version, about = "A terminal BOM and stock manager")]
+pub struct Cli {
+    /// Path to the config file [default: $XDG_CONFIG_HOME/shikabom/config.toml]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Storage backend: postgres, sqlite or memory
+    #[arg(long, global = true)]
+    pub db_backend: Option<Backend>,
+    /// Database host
+    #[arg(long, global = true)]
+    pub db_host: Option<String>,
+    /// Database port
+    #[arg(long, global = true)]
+    pub db_port: Option<u16>,
+    /// Database user
+    #[arg(long, global = true)]
+    pub db_user: Option<String>,
+    /// File containing the database password
+    #[arg(long, global = true)]
+    pub db_password_file: Option<PathBuf>,
+    /// Database name
+    #[arg(long, global = true)]
+    pub db_name: Option<String>,
+    /// TLS mode: disable, prefer or require
+    #[arg(long, global = true)]
+    pub db_tls: Option<TlsMode>,
+    /// Database file for the sqlite backend
+    #[arg(long, global = true)]
+    pub db_path: Option<PathBuf>,
+    /// Print command output as JSON instead of tables
+    #[arg(long, global = true)]
+    pub json: bool,
+    /// Runs a command instead of the TUI
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Database schema management
+    Db {
+        #[command(subcommand)]
+        action: DbCommand,
+    },
+    /// Part information
+    Parts {
+        #[command(subcommand)]
+        action: PartsCommand,
+    },
+    /// Stock levels, receiving and spending
+    Stock {
+        #[command(subcommand)]
+        action: StockCommand,
+    },
+    /// Projects and their BOMs
+    Projects {
+        #[command(subcommand)]
+        action: ProjectsCommand,
+    },
+    /// Build planning
+    Build {
+        #[command(subcommand)]
+        action: BuildCommand,
+    },
+    /// Part categories and their attributes
+    Categories {
+        #[command(subcommand)]
+        action: CategoriesCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Creates or upgrades the schema by applying pending migrations
+    Migrate,
+    /// Shows which migrations have been applied
+    Status,
+}
+
+/// Part fields set by `parts add` and `parts edit`, edit leaves out the ones not given.
+#[derive(Args, Debug, Default)]
+pub struct PartFields {
+    #[arg(long)]
+    pub manufacturer: Option<String>,
+    #[arg(long)]
+    pub description: Option<String>,
+    #[arg(long)]
+    pub label: Option<String>,
+    #[arg(long)]
+    pub package: Option<String>,
+    #[arg(long)]
+    pub value: Option<String>,
+    #[arg(long)]
+    pub tolerance: Option<String>,
+    /// Category path like Passives/Resistors/Chip, empty to clear it. Attributes the new category
+    /// doesn't have are dropped
+    #[arg(long)]
+    pub category: Option<String>,
+    /// An attribute of the category as name=value, an empty value removes it
+    #[arg(long = "attribute", value_name = "NAME=VALUE", value_parser = parse_part_attribute)]
+    pub attributes: Vec<(String, String)>,
+}
+
+fn parse_part_attribute(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_lowercase(), value.to_string())),
+        _ => Err(format!("expected name=value, not {text}")),
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PartsCommand {
+    /// Lists every part
+    List,
+    /// Shows a part and where it is stored
+    Show {
+        partnumber: String,
+    },
+    /// Adds a new part
+    Add {
+        partnumber: String,
+        #[command(flatten)]
+        fields: PartFields,
+    },
+    /// Changes the given fields of a part
+    Edit {
+        partnumber: String,
+        #[command(flatten)]
+        fields: PartFields,
+    },
+    /// Lists parts with the same value and package and an equal or tighter tolerance
+    Equivalents {
+        partnumber: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StockCommand {
+    /// Lists the stock levels of every stocked part
+    List,
+    /// Books stock arriving at a storage location
+    Receive {
+        partnumber: String,
+        quantity: i32,
+        /// Storage location the stock went to
+        #[arg(long, short)]
+        location: String,
+        /// PO number, supplier invoice, etc.
+        #[arg(long, short)]
+        reference: Option<String>,
+        /// Takes the quantity off on order as well
+        #[arg(long)]
+        against_order: bool,
+    },
+    /// Takes stock out of storage
+    Spend {
+        partnumber: String,
+        quantity: i32,
+        /// Location to take it from, every location in turn when left out
+        #[arg(long, short)]
+        location: Option<String>,
+        /// build, scrap or sample
+        #[arg(long, default_value = "build")]
+        reason: SpendReason,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProjectsCommand {
+    /// Lists every project, archived ones included
+    List,
+    /// Shows a project's metadata and BOM
+    Show {
+        project: String,
+    },
+    /// Writes a project's BOM to a file or stdout
+    Export {
+        /// Project name
+        project: String,
+        /// Layout: csv, json, jlcpcb or pcbway
+        #[arg(long, short, default_value = "csv")]
+        format: ExportFormat,
+        /// Number of boards, every qty is multiplied by it
+        #[arg(long, short, default_value_t = 1)]
+        multiplier: i64,
+        /// File to write, stdout when left out
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BuildCommand {
+    /// Checks whether the stock covers a number of builds, fails when it doesn't
+    Check {
+        project: String,
+        units: i32,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CategoriesCommand {
+    /// Lists every category with the attributes its parts have
+    List,
+    /// Creates a category or replaces its attributes, its parent has to exist
+    Set {
+        path: String,
+        /// An attribute as name:kind or name:value:unit, kind is text, number or value
+        #[arg(long = "attribute", short, value_name = "SPEC", value_parser = parse_attribute_def)]
+        attributes: Vec<AttributeDef>,
+    },
+    /// Removes a category without parts or subcategories
+    Remove {
+        path: String,
+    },
+}
+
+fn parse_attribute_def(spec: &str) -> Result<AttributeDef, String> {
+    AttributeDef::parse(spec).map_err(|e| e.to_string())
+}
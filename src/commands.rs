@@ -0,0 +1,443 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::Serialize;
+use crate::bom_export;
+use crate::build;
+use crate::categories::{display_path, normalize_part_attributes, schema, Category};
+use crate::cli::{BuildCommand, CategoriesCommand, Command, DbCommand, PartFields, PartsCommand, ProjectsCommand, StockCommand};
+use crate::config::{Backend, Config};
+use crate::db::Db;
+use crate::migrate::{self, SchemaStore};
+use crate::part_search::{equivalent_parts, PartQuery};
+use crate::parts::{normalize_part, Part};
+use crate::projects::Project;
+use crate::stock::{StockInfo, StockReceipt, StockSpend};
+use crate::repository::{self, BomRepository};
+use crate::sqlite_repository::SqliteRepository;
+
+/// Runs a CLI subcommand without starting the TUI. With `json` the output is printed as JSON
+/// instead of tables, for scripts.
+pub fn run(command: &Command, json: bool, config: Config) -> Result<()> {
+    let database = config.database.clone();
+    let open_repository = || repository::open_repository(database.clone());
+    match command {
+        Command::Db { action } => run_db_command(action, config),
+        Command::Parts { action } => run_parts_command(action, json, open_repository().as_mut()),
+        Command::Stock { action } => run_stock_command(action, json, open_repository().as_mut()),
+        Command::Projects { action } => run_projects_command(action, json, open_repository().as_mut()),
+        Command::Build { action } => run_build_command(action, json, open_repository().as_mut()),
+        Command::Categories { action } => run_categories_command(action, json, open_repository().as_mut()),
+    }
+}
+
+fn run_db_command(action: &DbCommand, config: Config) -> Result<()> {
+    let mut postgres;
+    let mut sqlite;
+    let db: &mut dyn SchemaStore = match config.database.backend {
+        Backend::Postgres => {
+            postgres = Db::new(config.database.clone());
+            &mut postgres
+        }
+        Backend::Sqlite => {
+            sqlite = SqliteRepository::new(&config.database);
+            &mut sqlite
+        }
+        Backend::Memory => {
+            return Err(eyre!("the {} backend has no schema to manage", config.database.backend));
+        }
+    };
+    match action {
+        DbCommand::Migrate => {
+            let applied = migrate::migrate(db)?;
+            for migration in &applied {
+                println!("Applied {:04} {}", migration.version, migration.name);
+            }
+            let version = migrate::current_version(db)?;
+            if applied.is_empty() {
+                println!("{} is already up to date (version {version})", config.database.describe());
+            } else {
+                println!("{} is now at version {version}", config.database.describe());
+            }
+        }
+        DbCommand::Status => {
+            println!("Database: {}", config.database.describe());
+            println!("{:<8} {:<30} Applied", "Version", "Name");
+            for migration in migrate::status(db)? {
+                let applied_at = migration.applied_at.unwrap_or("pending".to_string());
+                println!("{:<8} {:<30} {}", format!("{:04}", migration.version), migration.name, applied_at);
+            }
+        }
+    }
+    Ok(())
+}
+
+
+fn run_parts_command(action: &PartsCommand, json: bool, repo: &mut dyn BomRepository) -> Result<()> {
+    match action {
+        PartsCommand::List => {
+            let parts = repo.fetch_all_parts()?;
+            if json {
+                return print_json(&parts);
+            }
+            print_parts(&parts);
+        }
+        PartsCommand::Show { partnumber } => {
+            let part = repo.fetch_single_part(partnumber)?;
+            let storage = repo.fetch_part_storage_data(partnumber)?;
+            if json {
+                return print_json(&serde_json::json!({ "part": part, "storage": storage }));
+            }
+            print_part(&part);
+            if !storage.is_empty() {
+                println!();
+                let rows = storage.iter().map(|store| vec![store.location.clone(), store.quantity.to_string()]).collect();
+                print!("{}", format_table(&["Location", "Qty"], rows));
+            }
+        }
+        PartsCommand::Add { partnumber, fields } => {
+            let mut part = Part::new();
+            part.part_number = partnumber.clone();
+            let categories = repo.fetch_categories()?;
+            apply_part_fields(&mut part, fields, &categories);
+            normalize_part(&mut part);
+            normalize_part_attributes(&mut part, &categories);
+            repo.add_new_part(&part)?;
+            let part = repo.fetch_single_part(partnumber)?;
+            if json {
+                return print_json(&part);
+            }
+            print_part(&part);
+        }
+        PartsCommand::Edit { partnumber, fields } => {
+            let mut part = repo.fetch_single_part(partnumber)?;
+            let categories = repo.fetch_categories()?;
+            apply_part_fields(&mut part, fields, &categories);
+            normalize_part(&mut part);
+            normalize_part_attributes(&mut part, &categories);
+            repo.modify_part(&part)?;
+            let part = repo.fetch_single_part(partnumber)?;
+            if json {
+                return print_json(&part);
+            }
+            print_part(&part);
+        }
+        PartsCommand::Equivalents { partnumber } => {
+            let part = repo.fetch_single_part(partnumber)?;
+            if PartQuery::equivalent_to(&part).is_none() {
+                return Err(eyre!("{partnumber} has no value to find equivalents for"));
+            }
+            let parts = equivalent_parts(&part, &repo.fetch_all_parts()?);
+            if json {
+                return print_json(&parts);
+            }
+            print_parts(&parts);
+        }
+    }
+    Ok(())
+}
+
+fn apply_part_fields(part: &mut Part, fields: &PartFields, categories: &[Category]) {
+    let updates = [
+        (&mut part.manufacturer, &fields.manufacturer),
+        (&mut part.description, &fields.description),
+        (&mut part.label, &fields.label),
+        (&mut part.package, &fields.package),
+        (&mut part.value, &fields.value),
+        (&mut part.tolerance, &fields.tolerance),
+    ];
+    for (current, update) in updates {
+        if update.is_some() {
+            current.clone_from(update);
+        }
+    }
+    if let Some(category) = &fields.category {
+        //Moving the part drops the attributes its new category doesn't have
+        let kept = schema(categories, category.trim());
+        part.attributes.retain(|name, _| kept.iter().any(|attribute| &attribute.name == name));
+        part.category = Some(category.clone());
+    }
+    for (name, value) in &fields.attributes {
+        part.attributes.insert(name.clone(), value.clone());
+    }
+}
+
+fn print_parts(parts: &[Part]) {
+    let rows = parts.iter()
+        .map(|part| vec![
+            part.part_number.clone(),
+            part.total_qty.unwrap_or(0).to_string(),
+            text(&part.manufacturer),
+            text(&part.description),
+            text(&part.package),
+            text(&part.value),
+            text(&part.tolerance),
+        ])
+        .collect();
+    print!("{}", format_table(&["Part Number", "Qty", "Manufacturer", "Description", "Package", "Value", "Tolerance"], rows));
+}
+
+fn print_part(part: &Part) {
+    println!("Part number:  {}", part.part_number);
+    println!("Total qty:    {}", part.total_qty.unwrap_or(0));
+    println!("Manufacturer: {}", text(&part.manufacturer));
+    println!("Description:  {}", text(&part.description));
+    println!("Label:        {}", text(&part.label));
+    println!("Package:      {}", text(&part.package));
+    println!("Value:        {}", text(&part.value));
+    println!("Tolerance:    {}", text(&part.tolerance));
+    println!("Category:     {}", part.category.as_deref().map(display_path).unwrap_or_default());
+    for (name, value) in &part.attributes {
+        println!("{:<13} {value}", format!("{name}:"));
+    }
+}
+
+fn run_categories_command(action: &CategoriesCommand, json: bool, repo: &mut dyn BomRepository) -> Result<()> {
+    match action {
+        CategoriesCommand::List => {
+            let categories = repo.fetch_categories()?;
+            if json {
+                return print_json(&categories);
+            }
+            let rows = categories.iter()
+                .map(|category| {
+                    let attributes: Vec<String> = schema(&categories, &category.path).iter().map(|attribute| attribute.to_string()).collect();
+                    vec![category.path.clone(), display_path(&category.path), attributes.join(", ")]
+                })
+                .collect();
+            print!("{}", format_table(&["Path", "Category", "Attributes"], rows));
+        }
+        CategoriesCommand::Set { path, attributes } => {
+            let category = Category { path: path.clone(), attributes: attributes.clone() };
+            repo.save_category(&category)?;
+            if json {
+                return print_json(&category);
+            }
+            println!("Saved {}", display_path(path));
+        }
+        CategoriesCommand::Remove { path } => {
+            repo.delete_category(path)?;
+            if !json {
+                println!("Removed {}", display_path(path));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_stock_command(action: &StockCommand, json: bool, repo: &mut dyn BomRepository) -> Result<()> {
+    let partnumber = match action {
+        StockCommand::List => {
+            let stock = repo.fetch_stock_info()?;
+            if json {
+                return print_json(&stock);
+            }
+            print_stock_table(&stock);
+            return Ok(());
+        }
+        StockCommand::Receive { partnumber, quantity, location, reference, against_order } => {
+            repo.receive_stock(&StockReceipt {
+                partnumber: partnumber.clone(),
+                quantity: *quantity,
+                location: location.clone(),
+                reference: reference.clone(),
+                against_order: *against_order,
+            })?;
+            partnumber
+        }
+        StockCommand::Spend { partnumber, quantity, location, reason } => {
+            repo.spend_stock(&StockSpend {
+                partnumber: partnumber.clone(),
+                quantity: *quantity,
+                location: location.clone(),
+                reason: *reason,
+            })?;
+            partnumber
+        }
+    };
+    //Receiving and spending print the new stock levels of the part
+    let stock: Vec<StockInfo> = repo.fetch_stock_info()?
+        .into_iter()
+        .filter(|info| &info.partnumber == partnumber)
+        .collect();
+    if json {
+        return print_json(&stock.first());
+    }
+    print_stock_table(&stock);
+    Ok(())
+}
+
+fn print_stock_table(stock: &[StockInfo]) {
+    let rows = stock.iter()
+        .map(|info| vec![
+            info.partnumber.clone(),
+            info.on_hand.to_string(),
+            info.on_order.to_string(),
+            info.in_prod.to_string(),
+            info.available.to_string(),
+            info.low_stock_threshold.to_string(),
+        ])
+        .collect();
+    print!("{}", format_table(&["Part Number", "On Hand", "On Order", "In Prod", "Available", "Low At"], rows));
+}
+
+fn run_projects_command(action: &ProjectsCommand, json: bool, repo: &mut dyn BomRepository) -> Result<()> {
+    match action {
+        ProjectsCommand::List => {
+            let projects = repo.fetch_project_list()?;
+            if json {
+                return print_json(&projects);
+            }
+            let rows = projects.iter()
+                .map(|project| vec![
+                    project.name.clone(),
+                    project.status.to_string(),
+                    project.metadata.revision.clone(),
+                    project.modified_at.clone(),
+                    project.metadata.description.clone(),
+                ])
+                .collect();
+            print!("{}", format_table(&["Project", "Status", "Revision", "Modified", "Description"], rows));
+        }
+        ProjectsCommand::Show { project } => {
+            let project = find_project(repo, project)?;
+            if json {
+                return print_json(&project);
+            }
+            let metadata = &project.metadata;
+            println!("Project:        {} ({})", project.name, project.status);
+            println!("Revision:       {}", metadata.revision);
+            println!("Description:    {}", metadata.description);
+            println!("PCB fab PN:     {}", metadata.pcb_fab_pn);
+            println!("Assembly notes: {}", metadata.assembly_notes);
+            println!("Owner:          {}", metadata.owner);
+            println!("Modified:       {}", project.modified_at);
+            println!();
+            let rows = project.parts.iter()
+                .map(|ppart| vec![
+                    ppart.partnumber.clone(),
+                    ppart.qty.to_string(),
+                    ppart.designators.clone(),
+                    text(&ppart.part_info.description),
+                ])
+                .collect();
+            print!("{}", format_table(&["Part Number", "Qty", "Designators", "Description"], rows));
+        }
+        ProjectsCommand::Export { project, format, multiplier, output } => {
+            let project = find_project(repo, project)?;
+            match output {
+                Some(path) => {
+                    bom_export::write_bom(&project, *format, *multiplier, path)?;
+                    eprintln!("Wrote {} line(s) to {}", project.parts.len(), path.display());
+                }
+                None => print!("{}", bom_export::export_bom(&project, *format, *multiplier)?),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_build_command(action: &BuildCommand, json: bool, repo: &mut dyn BomRepository) -> Result<()> {
+    match action {
+        BuildCommand::Check { project, units } => {
+            if *units < 1 {
+                return Err(eyre!("the number of builds must be at least 1"));
+            }
+            let project = find_project(repo, project)?;
+            let plan = build::plan_build(&project, &repo.fetch_stock_info()?, *units);
+            if json {
+                print_json(&plan)?;
+            } else {
+                let rows = plan.lines.iter()
+                    .map(|line| vec![
+                        line.partnumber.clone(),
+                        line.per_unit.to_string(),
+                        line.required.to_string(),
+                        line.available.to_string(),
+                        line.shortfall.to_string(),
+                    ])
+                    .collect();
+                print!("{}", format_table(&["Part Number", "Per Unit", "Required", "Available", "Short"], rows));
+                let max_buildable = plan.max_buildable.map_or("no limit".to_string(), |max| max.to_string());
+                println!("\nMax buildable: {max_buildable}");
+            }
+            //A failing exit status lets CI gate on it
+            if !plan.can_build() {
+                return Err(eyre!("can't build {units} x {}: {} line(s) short", project.name, plan.short_lines()));
+            }
+        }
+    }
+    Ok(())
+}
+
+//The project with its BOM lines loaded
+fn find_project(repo: &mut dyn BomRepository, name: &str) -> Result<Project> {
+    let mut project = repo.fetch_project_list()?
+        .into_iter()
+        .find(|candidate| candidate.name == name)
+        .ok_or_else(|| eyre!("project {name} does not exist"))?;
+    repo.fetch_project_details(&mut project)?;
+    Ok(project)
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn text(value: &Option<String>) -> String {
+    value.clone().unwrap_or_default()
+}
+
+/// Left aligned columns sized to their widest cell, two spaces apart.
+fn format_table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: Vec<String>| {
+        let line: Vec<String> = cells.iter().zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        format!("{}\n", line.join("  ").trim_end())
+    };
+    let mut table = format_row(headers.iter().map(|header| header.to_string()).collect());
+    for row in rows {
+        table.push_str(&format_row(row));
+    }
+    table
+}
+
+#[test]
+fn test_apply_part_fields() {
+    let categories = crate::categories::default_categories();
+    let mut part = Part::new();
+    part.category = Some("Passives/Capacitors/Ceramic".to_string());
+    part.attributes.insert("voltage".to_string(), "50V".to_string());
+    part.attributes.insert("dielectric".to_string(), "X7R".to_string());
+    let mut fields = PartFields {
+        category: Some("Passives/Capacitors".to_string()),
+        attributes: vec![("voltage".to_string(), "25V".to_string())],
+        ..PartFields::default()
+    };
+    apply_part_fields(&mut part, &fields, &categories);
+    assert_eq!(part.category.as_deref(), Some("Passives/Capacitors"));
+    assert_eq!(part.attributes.len(), 1);
+    assert_eq!(part.attributes["voltage"], "25V");
+    //Without a category change the attributes stay
+    fields.category = None;
+    fields.attributes.clear();
+    part.attributes.insert("dielectric".to_string(), "X7R".to_string());
+    apply_part_fields(&mut part, &fields, &categories);
+    assert_eq!(part.attributes.len(), 2);
+}
+
+#[test]
+fn test_format_table() {
+    let table = format_table(&["Part Number", "Qty"], vec![
+        vec!["RC0603FR-0710KL".to_string(), "400".to_string()],
+        vec!["C1".to_string(), "".to_string()],
+    ]);
+    assert_eq!(table, "Part Number      Qty\nRC0603FR-0710KL  400\nC1\n");
+}
@@ -0,0 +1,317 @@
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use serde::Deserialize;
+use crate::cli::Cli;
+
+const CONFIG_DIR_NAME: &str = "shikabom";
+const CONFIG_FILE_NAME: &str = "config.toml";
+const SQLITE_FILE_NAME: &str = "shikabom.db";
+
+/// Top level configuration, read from `$XDG_CONFIG_HOME/shikabom/config.toml`.
+/// Every value can be overridden by an environment variable and then by a CLI flag.
+#[derive(Deserialize, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseConfig,
+}
+
+/// Where parts, stock and projects are stored.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Postgres,
+    //A single database file, handy for a bench with no server
+    Sqlite,
+    //Sample data kept in memory, nothing is saved
+    Memory,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Postgres => write!(f, "postgres"),
+            Backend::Sqlite => write!(f, "sqlite"),
+            Backend::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "postgres" => Ok(Backend::Postgres),
+            "sqlite" => Ok(Backend::Sqlite),
+            "memory" => Ok(Backend::Memory),
+            other => Err(eyre!("invalid backend '{other}', expected postgres, sqlite or memory")),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    Disable,
+    Prefer,
+    Require,
+}
+
+impl fmt::Display for TlsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsMode::Disable => write!(f, "disable"),
+            TlsMode::Prefer => write!(f, "prefer"),
+            TlsMode::Require => write!(f, "require"),
+        }
+    }
+}
+
+impl std::str::FromStr for TlsMode {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "disable" => Ok(TlsMode::Disable),
+            "prefer" => Ok(TlsMode::Prefer),
+            "require" => Ok(TlsMode::Require),
+            other => Err(eyre!("invalid TLS mode '{other}', expected disable, prefer or require")),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub backend: Backend,
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: Option<String>,
+    //Takes priority over `password` so secrets don't have to live in the config file
+    pub password_file: Option<PathBuf>,
+    pub dbname: String,
+    pub tls: TlsMode,
+    //Database file for the sqlite backend, see `sqlite_path`
+    pub path: Option<PathBuf>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            backend: Backend::Postgres,
+            host: "localhost".to_string(),
+            port: 5432,
+            user: env::var("USER").unwrap_or("postgres".to_string()),
+            password: None,
+            password_file: None,
+            dbname: "shika".to_string(),
+            tls: TlsMode::Prefer,
+            path: None,
+        }
+    }
+}
+
+impl DatabaseConfig {
+    /// Resolves the password to use, reading `password_file` if one is configured.
+    pub fn resolve_password(&self) -> Result<Option<String>> {
+        match &self.password_file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .wrap_err_with(|| format!("could not read password file {}", path.display()))?;
+                //Only the first line is used, so a trailing newline doesn't end up in the password
+                Ok(Some(contents.lines().next().unwrap_or("").to_string()))
+            }
+            None => Ok(self.password.clone()),
+        }
+    }
+
+    /// The SQLite database file: `path` if set, otherwise `$XDG_DATA_HOME/shikabom/shikabom.db`.
+    pub fn sqlite_path(&self) -> Result<PathBuf> {
+        match &self.path {
+            Some(path) => Ok(path.clone()),
+            None => default_data_dir()
+                .map(|dir| dir.join(SQLITE_FILE_NAME))
+                .ok_or_else(|| eyre!("could not find a data directory, set database.path")),
+        }
+    }
+
+    /// A human readable description of where we are connecting to, never includes the password.
+    pub fn describe(&self) -> String {
+        match self.backend {
+            Backend::Memory => "in-memory sample data".to_string(),
+            Backend::Sqlite => match self.sqlite_path() {
+                Ok(path) => format!("sqlite:{}", path.display()),
+                Err(_) => "sqlite (no database path)".to_string(),
+            },
+            Backend::Postgres => {
+                format!("{}@{}:{}/{} (tls: {})", self.user, self.host, self.port, self.dbname, self.tls)
+            }
+        }
+    }
+
+    fn apply_env(&mut self) -> Result<()> {
+        if let Ok(backend) = env::var("SHIKABOM_DB_BACKEND") {
+            self.backend = backend.parse().wrap_err("SHIKABOM_DB_BACKEND is not valid")?;
+        }
+        if let Ok(host) = env::var("SHIKABOM_DB_HOST") {
+            self.host = host;
+        }
+        if let Ok(port) = env::var("SHIKABOM_DB_PORT") {
+            self.port = port.parse().wrap_err("SHIKABOM_DB_PORT is not a valid port")?;
+        }
+        if let Ok(user) = env::var("SHIKABOM_DB_USER") {
+            self.user = user;
+        }
+        if let Ok(password) = env::var("SHIKABOM_DB_PASSWORD") {
+            self.password = Some(password);
+        }
+        if let Ok(password_file) = env::var("SHIKABOM_DB_PASSWORD_FILE") {
+            self.password_file = Some(PathBuf::from(password_file));
+        }
+        if let Ok(dbname) = env::var("SHIKABOM_DB_NAME") {
+            self.dbname = dbname;
+        }
+        if let Ok(tls) = env::var("SHIKABOM_DB_TLS") {
+            self.tls = tls.parse().wrap_err("SHIKABOM_DB_TLS is not valid")?;
+        }
+        if let Ok(path) = env::var("SHIKABOM_DB_PATH") {
+            self.path = Some(PathBuf::from(path));
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(backend) = cli.db_backend {
+            self.backend = backend;
+        }
+        if let Some(host) = &cli.db_host {
+            self.host = host.clone();
+        }
+        if let Some(port) = cli.db_port {
+            self.port = port;
+        }
+        if let Some(user) = &cli.db_user {
+            self.user = user.clone();
+        }
+        if let Some(password_file) = &cli.db_password_file {
+            self.password_file = Some(password_file.clone());
+        }
+        if let Some(dbname) = &cli.db_name {
+            self.dbname = dbname.clone();
+        }
+        if let Some(tls) = cli.db_tls {
+            self.tls = tls;
+        }
+        if let Some(path) = &cli.db_path {
+            self.path = Some(path.clone());
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file (if there is one), then applies environment variables and CLI flags.
+    pub fn load(cli: &Cli) -> Result<Config> {
+        let path = match &cli.config {
+            Some(path) => Some(path.clone()),
+            None => env::var("SHIKABOM_CONFIG").ok().map(PathBuf::from).or_else(default_config_path),
+        };
+        let mut config = match path {
+            Some(path) if path.exists() => Config::from_file(&path)?,
+            //Asking for a specific file that isn't there is an error, the default location is optional
+            Some(path) if cli.config.is_some() => {
+                return Err(eyre!("config file {} does not exist", path.display()));
+            }
+            _ => Config::default(),
+        };
+        config.database.apply_env()?;
+        config.database.apply_cli(cli);
+        Ok(config)
+    }
+
+    fn from_file(path: &PathBuf) -> Result<Config> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("could not read config file {}", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("could not parse config file {}", path.display()))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/shikabom/config.toml`, falling back to `~/.config/shikabom/config.toml`.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME))
+}
+
+/// `$XDG_DATA_HOME/shikabom`, falling back to `~/.local/share/shikabom`.
+pub fn default_data_dir() -> Option<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(data_home.join(CONFIG_DIR_NAME))
+}
+
+#[test]
+fn test_parse_database_config() {
+    let config: Config = toml::from_str(r#"
+        [database]
+        host = "lab-db"
+        port = 5433
+        user = "bench"
+        dbname = "bom"
+        tls = "require"
+    "#).unwrap();
+    assert_eq!(config.database.host, "lab-db");
+    assert_eq!(config.database.port, 5433);
+    assert_eq!(config.database.user, "bench");
+    assert_eq!(config.database.dbname, "bom");
+    assert_eq!(config.database.tls, TlsMode::Require);
+    assert!(config.database.password_file.is_none());
+}
+
+#[test]
+fn test_partial_config_uses_defaults() {
+    let config: Config = toml::from_str("[database]\nhost = \"lab-db\"\n").unwrap();
+    assert_eq!(config.database.port, 5432);
+    assert_eq!(config.database.dbname, "shika");
+    assert_eq!(config.database.tls, TlsMode::Prefer);
+}
+
+#[test]
+fn test_cli_overrides_config() {
+    use clap::Parser;
+    let cli = Cli::parse_from(["shikabom", "--db-host", "other", "--db-tls", "disable"]);
+    let mut db = DatabaseConfig::default();
+    db.apply_cli(&cli);
+    assert_eq!(db.host, "other");
+    assert_eq!(db.tls, TlsMode::Disable);
+    assert_eq!(db.port, 5432);
+}
+
+#[test]
+fn test_password_file_takes_first_line() {
+    let path = env::temp_dir().join(format!("shikabom-test-pw-{}", std::process::id()));
+    std::fs::write(&path, "hunter2\n").unwrap();
+    let db = DatabaseConfig {
+        password: Some("ignored".to_string()),
+        password_file: Some(path.clone()),
+        ..DatabaseConfig::default()
+    };
+    assert_eq!(db.resolve_password().unwrap(), Some("hunter2".to_string()));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sqlite_backend_config() {
+    let config: Config = toml::from_str("[database]\nbackend = \"sqlite\"\npath = \"/tmp/bench.db\"\n").unwrap();
+    assert_eq!(config.database.backend, Backend::Sqlite);
+    assert_eq!(config.database.sqlite_path().unwrap(), PathBuf::from("/tmp/bench.db"));
+    assert_eq!(config.database.describe(), "sqlite:/tmp/bench.db");
+}
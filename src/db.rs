@@ -1,5 +1,75 @@
+use native_tls::TlsConnector;
+use postgres::config::SslMode;
 use postgres::{Client, NoTls};
+use postgres_native_tls::MakeTlsConnector;
+use tracing::{info, warn};
+use crate::config::{DatabaseConfig, TlsMode};
+use crate::errors::{ShikaError, ShikaResult};
 
-pub fn postgres_init() -> Client {
-    return Client::connect("host=localhost user=rootben password=password dbname=shika", NoTls).unwrap();
-}
\ No newline at end of file
+/// The shared database connection. `App` owns the only instance and lends it to the data layer,
+/// so a session keeps a single connection open instead of reconnecting for every query.
+pub struct Db {
+    config: DatabaseConfig,
+    client: Option<Client>,
+}
+
+impl Db {
+    pub fn new(config: DatabaseConfig) -> Db {
+        Db {
+            config,
+            client: None,
+        }
+    }
+
+    /// Returns the open connection, connecting first if there isn't one or the last one dropped.
+    pub fn client(&mut self) -> ShikaResult<&mut Client> {
+        if self.client.as_ref().is_some_and(|client| client.is_closed()) {
+            warn!("Database connection was closed, reconnecting");
+            self.client = None;
+        }
+        if self.client.is_none() {
+            self.client = Some(connect(&self.config)?);
+            info!("Connected to {}", self.config.describe());
+        }
+        Ok(self.client.as_mut().unwrap())
+    }
+
+    /// Checks that the database can be reached, used before the TUI loads any data.
+    pub fn check_connection(&mut self) -> ShikaResult<()> {
+        let client = self.client()?;
+        if let Err(e) = client.simple_query("SELECT 1") {
+            //Throw the connection away so the next call starts fresh
+            self.client = None;
+            return Err(ShikaError::Connection(format!("database did not respond: {e}")));
+        }
+        Ok(())
+    }
+}
+
+pub fn connect(config: &DatabaseConfig) -> ShikaResult<Client> {
+    let mut pg_config = postgres::Config::new();
+    pg_config
+        .host(&config.host)
+        .port(config.port)
+        .user(&config.user)
+        .dbname(&config.dbname)
+        .application_name("shikabom");
+    let password = config.resolve_password()
+        .map_err(|e| ShikaError::Connection(format!("{e}: {}", e.root_cause())))?;
+    if let Some(password) = password {
+        pg_config.password(password);
+    }
+    let client = match config.tls {
+        TlsMode::Disable => {
+            pg_config.ssl_mode(SslMode::Disable);
+            pg_config.connect(NoTls)
+        }
+        TlsMode::Prefer | TlsMode::Require => {
+            pg_config.ssl_mode(if config.tls == TlsMode::Require { SslMode::Require } else { SslMode::Prefer });
+            let connector = TlsConnector::new()
+                .map_err(|e| ShikaError::Connection(format!("could not set up TLS: {e}")))?;
+            pg_config.connect(MakeTlsConnector::new(connector))
+        }
+    };
+    client.map_err(|e| ShikaError::Connection(format!("could not connect to {}: {e}", config.describe())))
+}
@@ -0,0 +1,281 @@
+use std::fmt;
+use serde::{Serialize, Serializer};
+use crate::errors::{ShikaError, ShikaResult};
+
+/// The units a component value can be given in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Unit {
+    Ohm,
+    Farad,
+    Henry,
+    Volt,
+    Amp,
+    Watt,
+    Hertz,
+}
+
+impl Unit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Ohm => "Ω",
+            Unit::Farad => "F",
+            Unit::Henry => "H",
+            Unit::Volt => "V",
+            Unit::Amp => "A",
+            Unit::Watt => "W",
+            Unit::Hertz => "Hz",
+        }
+    }
+
+    /// The unit written as its symbol, `ohm` works for Ω.
+    pub fn from_symbol(symbol: &str) -> Option<Unit> {
+        match symbol {
+            "Ω" => Some(Unit::Ohm),
+            _ if symbol.eq_ignore_ascii_case("ohm") => Some(Unit::Ohm),
+            "F" => Some(Unit::Farad),
+            "H" => Some(Unit::Henry),
+            "V" => Some(Unit::Volt),
+            "A" => Some(Unit::Amp),
+            "W" => Some(Unit::Watt),
+            "Hz" => Some(Unit::Hertz),
+            _ => None,
+        }
+    }
+}
+
+//Written as its symbol, e.g. in the category schemas `--json` prints
+impl Serialize for Unit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.symbol())
+    }
+}
+
+/// A parsed component value. Values written without a unit, like `10k`, have none and are
+/// equal to the same magnitude in any unit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EngValue {
+    pub magnitude: f64,
+    pub unit: Option<Unit>,
+}
+
+impl EngValue {
+    /// Same magnitude and no conflicting units, so `10k`, `10K`, `10kΩ` and `10000` are all equal.
+    pub fn same_as(&self, other: &EngValue) -> bool {
+        self.comparable(other) && approx_eq(self.magnitude, other.magnitude)
+    }
+
+    pub fn comparable(&self, other: &EngValue) -> bool {
+        match (self.unit, other.unit) {
+            (Some(unit), Some(other_unit)) => unit == other_unit,
+            _ => true,
+        }
+    }
+}
+
+/// Written the way values are stored after normalizing, e.g. `4.7kΩ`, `2.2n` or `100nF`.
+impl fmt::Display for EngValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", format_magnitude(self.magnitude), self.unit.map_or("", |unit| unit.symbol()))
+    }
+}
+
+const PREFIXES: [(char, i32); 7] = [('p', -12), ('n', -9), ('u', -6), ('m', -3), ('k', 3), ('M', 6), ('G', 9)];
+
+/// The power of ten of an SI prefix, `u` and `µ` both mean micro.
+fn prefix_exponent(prefix: char) -> Option<i32> {
+    match prefix {
+        'µ' => Some(-6),
+        'K' => Some(3),
+        _ => PREFIXES.iter().find(|(symbol, _)| *symbol == prefix).map(|(_, exponent)| *exponent),
+    }
+}
+
+//Dividing for small prefixes keeps 4.7e-6 at 4.7 without rounding noise
+fn scale(number: f64, exponent: i32) -> f64 {
+    match exponent < 0 {
+        true => number / 10f64.powi(-exponent),
+        false => number * 10f64.powi(exponent),
+    }
+}
+
+pub fn approx_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+}
+
+//Three decimals at most with an engineering prefix, 4700 is 4.7k
+fn format_magnitude(magnitude: f64) -> String {
+    if magnitude == 0.0 {
+        return "0".to_string();
+    }
+    let mut exponent = ((magnitude.abs().log10() / 3.0).floor() as i32 * 3).clamp(-12, 9);
+    let mut mantissa = (scale(magnitude, -exponent) * 1000.0).round() / 1000.0;
+    //999.9996 rounds up into the next prefix
+    if mantissa.abs() >= 1000.0 && exponent < 9 {
+        exponent += 3;
+        mantissa /= 1000.0;
+    }
+    let text = format_decimals(mantissa);
+    let prefix = PREFIXES.iter().find(|(_, prefix_exponent)| *prefix_exponent == exponent).map(|(symbol, _)| *symbol);
+    format!("{text}{}", prefix.map(String::from).unwrap_or_default())
+}
+
+fn format_decimals(number: f64) -> String {
+    let mut text = format!("{number:.3}");
+    while text.ends_with('0') {
+        text.pop();
+    }
+    if text.ends_with('.') {
+        text.pop();
+    }
+    text
+}
+
+//Splits a trailing unit off, `10kohm` is ("10k", Ohm)
+fn split_unit(text: &str) -> (&str, Option<Unit>) {
+    let lowercase = text.to_ascii_lowercase();
+    for (word, unit) in [("ohms", Unit::Ohm), ("ohm", Unit::Ohm), ("hz", Unit::Hertz)] {
+        if lowercase.ends_with(word) {
+            return (&text[..text.len() - word.len()], Some(unit));
+        }
+    }
+    let unit = match text.chars().last() {
+        Some('Ω') => Unit::Ohm,
+        Some('F') => Unit::Farad,
+        Some('H') => Unit::Henry,
+        Some('V') => Unit::Volt,
+        Some('A') => Unit::Amp,
+        Some('W') => Unit::Watt,
+        _ => return (text, None),
+    };
+    let last = text.char_indices().last().map_or(0, |(idx, _)| idx);
+    (&text[..last], Some(unit))
+}
+
+/// Reads a value with an SI prefix and unit like `4.7k`, `100nF`, `10 kΩ`, `3.3V` or `16MHz`, or in RKM
+/// notation where the prefix is the decimal point, like `4R7`, `4k7` or `2n2`. None for anything
+/// else, e.g. part numbers or packages like `6.3x6`.
+pub fn parse_value(text: &str) -> Option<EngValue> {
+    let (body, unit) = split_unit(text.trim());
+    let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut letters = body.char_indices().filter(|(_, c)| !(c.is_ascii_digit() || *c == '.'));
+    let letter = letters.next();
+    if letters.next().is_some() {
+        return None;
+    }
+    let Some((idx, letter)) = letter else {
+        return Some(EngValue { magnitude: body.parse().ok()?, unit });
+    };
+    //R is the decimal point of an ohm value in RKM, 4R7 or 10R
+    let (exponent, unit) = match letter {
+        'R' if unit.is_none() || unit == Some(Unit::Ohm) => (0, Some(Unit::Ohm)),
+        _ => (prefix_exponent(letter)?, unit),
+    };
+    let (before, after) = (&body[..idx], &body[idx + letter.len_utf8()..]);
+    let number = match after.is_empty() {
+        true => before.to_string(),
+        //RKM takes whole digits on both sides, R47 is 0.47Ω
+        false if before.contains('.') || after.contains('.') => return None,
+        false => format!("{}.{after}", if before.is_empty() { "0" } else { before }),
+    };
+    //Parsing the exponent along with the digits keeps 2n2 equal to the literal 2.2e-9
+    let magnitude: f64 = number.parse().ok()?;
+    Some(EngValue { magnitude: format!("{number}e{exponent}").parse().unwrap_or(magnitude), unit })
+}
+
+/// Reads a percent tolerance like `5%`, `±1%`, `+/-10%` or a bare `5`.
+pub fn parse_tolerance(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let text = ["±", "+/-", "+-"].iter().find_map(|sign| text.strip_prefix(sign)).unwrap_or(text);
+    let text = text.trim().strip_suffix('%').unwrap_or(text).trim();
+    let percent: f64 = text.parse().ok()?;
+    (percent >= 0.0 && percent.is_finite()).then_some(percent)
+}
+
+pub fn format_tolerance(percent: f64) -> String {
+    format!("{}%", format_decimals(percent))
+}
+
+/// Whether `text` is meant as a quantity: it starts with a digit and only has prefix and unit
+/// letters after it. Names like `2N7002` or `DNP` are left alone by validation.
+pub fn looks_like_value(text: &str) -> bool {
+    let (body, _) = split_unit(text.trim());
+    body.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && body.chars().all(|c| c.is_ascii_digit() || c == '.' || c.is_whitespace() || c == 'R' || prefix_exponent(c).is_some())
+}
+
+/// Checks a part value, anything that looks like a quantity has to parse.
+pub fn validate_value(text: &str) -> ShikaResult<()> {
+    if looks_like_value(text) && parse_value(text).is_none() {
+        return Err(ShikaError::Validation(format!("{} is not a valid value, use e.g. 4.7k, 4k7, 100nF or 3.3V", text.trim())));
+    }
+    Ok(())
+}
+
+pub fn validate_tolerance(text: &str) -> ShikaResult<()> {
+    if !text.trim().is_empty() && parse_tolerance(text).is_none() {
+        return Err(ShikaError::Validation(format!("{} is not a valid tolerance, use e.g. 1% or ±5%", text.trim())));
+    }
+    Ok(())
+}
+
+/// The stored form of a value: quantities are rewritten like `4k7` to `4.7k`, names are kept.
+pub fn normalize_value(text: &str) -> String {
+    match parse_value(text) {
+        Some(value) if looks_like_value(text) => value.to_string(),
+        _ => text.trim().to_string(),
+    }
+}
+
+pub fn normalize_tolerance(text: &str) -> String {
+    match parse_tolerance(text) {
+        Some(percent) => format_tolerance(percent),
+        None => text.trim().to_string(),
+    }
+}
+
+#[test]
+fn test_parse_value() {
+    let value = |text: &str| parse_value(text).map(|value| (value.magnitude, value.unit));
+    assert_eq!(value("4.7k"), Some((4700.0, None)));
+    assert_eq!(value("10K"), Some((10000.0, None)));
+    assert_eq!(value("10 kΩ"), Some((10000.0, Some(Unit::Ohm))));
+    assert_eq!(value("10kohm"), Some((10000.0, Some(Unit::Ohm))));
+    assert_eq!(value("100nF"), Some((100e-9, Some(Unit::Farad))));
+    assert_eq!(value("4.7µH"), Some((4.7e-6, Some(Unit::Henry))));
+    assert_eq!(value("3.3V"), Some((3.3, Some(Unit::Volt))));
+    assert_eq!(value("500mA"), Some((0.5, Some(Unit::Amp))));
+    assert_eq!(value("100mW"), Some((0.1, Some(Unit::Watt))));
+    assert_eq!(value("16MHz"), Some((16e6, Some(Unit::Hertz))));
+    assert_eq!(value("1M"), Some((1e6, None)));
+    //RKM
+    assert_eq!(value("4R7"), Some((4.7, Some(Unit::Ohm))));
+    assert_eq!(value("R47"), Some((0.47, Some(Unit::Ohm))));
+    assert_eq!(value("10R"), Some((10.0, Some(Unit::Ohm))));
+    assert_eq!(value("4k7"), Some((4700.0, None)));
+    assert_eq!(value("2n2"), Some((2.2e-9, None)));
+    assert_eq!(value("4u7H"), Some((4.7e-6, Some(Unit::Henry))));
+    for text in ["6.3x6", "RC0603FR-0710KL", "25SVPF47M", "2N7002", "4R7F", "4.7k7", "10kk", "", "DNP"] {
+        assert_eq!(parse_value(text), None, "{text}");
+    }
+    assert!(parse_value("10k").unwrap().same_as(&parse_value("10000Ω").unwrap()));
+    assert!(!parse_value("10kΩ").unwrap().same_as(&parse_value("10kF").unwrap()));
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(normalize_value("4k7"), "4.7k");
+    assert_eq!(normalize_value("10000"), "10k");
+    assert_eq!(normalize_value("4R7"), "4.7Ω");
+    assert_eq!(normalize_value("100 nF"), "100nF");
+    assert_eq!(normalize_value("0.1uF"), "100nF");
+    assert_eq!(normalize_value("999.9999"), "1k");
+    assert_eq!(normalize_value("TPS62160 "), "TPS62160");
+    assert_eq!(normalize_tolerance("±5 %"), "5%");
+    assert_eq!(normalize_tolerance("+/-0.5"), "0.5%");
+
+    assert!(validate_value("2N7002").is_ok());
+    assert!(validate_value("10kk").is_err());
+    assert!(validate_value("4.7.1k").is_err());
+    assert!(validate_tolerance("").is_ok());
+    assert!(validate_tolerance("5%%").is_err());
+}
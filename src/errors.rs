@@ -1,6 +1,7 @@
-use std::panic;
+use std::{fmt, panic};
 
 use color_eyre::{config::HookBuilder, eyre};
+use postgres::error::SqlState;
 
 use crate::tui;
 
@@ -26,4 +27,97 @@ pub fn install_hooks() -> color_eyre::Result<()> {
     ))?;
 
     Ok(())
-}
\ No newline at end of file
+}
+/// Errors returned by the data layer. These are shown to the user in the TUI instead of panicking.
+#[derive(Debug)]
+pub enum ShikaError {
+    /// The database can't be reached or the connection dropped
+    Connection(String),
+    /// A unique, foreign key or check constraint rejected the change
+    ConstraintViolation(String),
+    NotFound(String),
+    /// The input was rejected before it reached the database
+    Validation(String),
+    /// Any other error reported by the database
+    Database(String),
+}
+
+pub type ShikaResult<T> = Result<T, ShikaError>;
+
+impl ShikaError {
+    /// Maps a unique violation to a friendlier message, any other error is converted as usual.
+    pub fn on_unique_violation(e: postgres::Error, message: impl FnOnce() -> String) -> ShikaError {
+        if e.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+            ShikaError::ConstraintViolation(message())
+        } else {
+            e.into()
+        }
+    }
+
+    /// The SQLite counterpart of `on_unique_violation`.
+    pub fn on_sqlite_unique_violation(e: rusqlite::Error, message: impl FnOnce() -> String) -> ShikaError {
+        let is_unique = matches!(e.sqlite_error(), Some(err) if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_UNIQUE
+            || err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_PRIMARYKEY);
+        if is_unique {
+            ShikaError::ConstraintViolation(message())
+        } else {
+            e.into()
+        }
+    }
+}
+
+impl fmt::Display for ShikaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShikaError::Connection(msg) => write!(f, "Connection error: {msg}"),
+            ShikaError::ConstraintViolation(msg) => write!(f, "{msg}"),
+            ShikaError::NotFound(msg) => write!(f, "{msg}"),
+            ShikaError::Validation(msg) => write!(f, "{msg}"),
+            ShikaError::Database(msg) => write!(f, "Database error: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for ShikaError {}
+
+impl From<postgres::Error> for ShikaError {
+    fn from(e: postgres::Error) -> Self {
+        if e.is_closed() {
+            return ShikaError::Connection(e.to_string());
+        }
+        match e.as_db_error() {
+            Some(db_error) => {
+                let code = db_error.code();
+                if *code == SqlState::UNIQUE_VIOLATION
+                    || *code == SqlState::FOREIGN_KEY_VIOLATION
+                    || *code == SqlState::NOT_NULL_VIOLATION
+                    || *code == SqlState::CHECK_VIOLATION {
+                    ShikaError::ConstraintViolation(db_error.message().to_string())
+                } else {
+                    ShikaError::Database(db_error.message().to_string())
+                }
+            }
+            None => {
+                let is_io = std::error::Error::source(&e)
+                    .is_some_and(|source| source.is::<std::io::Error>());
+                if is_io {
+                    ShikaError::Connection(e.to_string())
+                } else {
+                    ShikaError::Database(e.to_string())
+                }
+            }
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ShikaError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::ConstraintViolation) => ShikaError::ConstraintViolation(e.to_string()),
+            Some(rusqlite::ErrorCode::CannotOpen)
+            | Some(rusqlite::ErrorCode::PermissionDenied)
+            | Some(rusqlite::ErrorCode::NotADatabase) => ShikaError::Connection(e.to_string()),
+            _ => ShikaError::Database(e.to_string()),
+        }
+    }
+}
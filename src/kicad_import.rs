@@ -0,0 +1,439 @@
+use std::path::Path;
+use crate::errors::{ShikaError, ShikaResult};
+use crate::parts::{validate_part, Part};
+use crate::projects::{compress_designators, parse_designators, ProjectPart};
+
+/// One placed symbol from a KiCad BOM export.
+#[derive(Clone, PartialEq, Debug)]
+pub struct KicadComponent {
+    pub reference: String,
+    pub value: String,
+    pub footprint: String,
+    //From an MPN style field, when the schematic has one
+    pub partnumber: Option<String>,
+    pub manufacturer: Option<String>,
+}
+
+/// What an import line turns into when the import is committed.
+#[derive(Clone, PartialEq, Debug)]
+pub enum LineResolution {
+    //The KiCad part number is a known part
+    Exact(String),
+    //Value and package matched a known part
+    Fuzzy(String),
+    //Picked by hand on the review screen
+    Mapped(String),
+    //Created as a new part on commit
+    NewPart(String),
+    Skip,
+    Unmatched,
+}
+
+impl LineResolution {
+    pub fn partnumber(&self) -> Option<&str> {
+        match self {
+            LineResolution::Exact(pn) | LineResolution::Fuzzy(pn) | LineResolution::Mapped(pn)
+            | LineResolution::NewPart(pn) => Some(pn),
+            LineResolution::Skip | LineResolution::Unmatched => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LineResolution::Exact(_) => "exact",
+            LineResolution::Fuzzy(_) => "fuzzy",
+            LineResolution::Mapped(_) => "mapped",
+            LineResolution::NewPart(_) => "new part",
+            LineResolution::Skip => "skip",
+            LineResolution::Unmatched => "unmatched",
+        }
+    }
+}
+
+/// Components sharing a part number, value and footprint, the future BOM line.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ImportLine {
+    pub designators: String,
+    pub qty: i32,
+    pub value: String,
+    pub footprint: String,
+    pub partnumber: Option<String>,
+    pub manufacturer: Option<String>,
+    //Known parts with the same value, best match first
+    pub candidates: Vec<String>,
+    pub resolution: LineResolution,
+}
+
+impl ImportLine {
+    //The part created for a NewPart resolution
+    fn new_part(&self, partnumber: &str) -> Part {
+        let mut part = Part::new();
+        part.part_number = partnumber.to_string();
+        part.manufacturer = self.manufacturer.clone();
+        part.value = Some(self.value.clone()).filter(|value| !value.is_empty());
+        part.package = Some(footprint_package(&self.footprint)).filter(|package| !package.is_empty());
+        part
+    }
+}
+
+/// Reads a KiCad BOM export, `.xml` files are read as the netlist XML and anything else as CSV.
+pub fn read_kicad_bom(path: &Path) -> ShikaResult<Vec<KicadComponent>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| ShikaError::Validation(format!("Can't read {}: {e}", path.display())))?;
+    let is_xml = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("xml"));
+    if is_xml {
+        parse_kicad_xml(&text)
+    } else {
+        parse_kicad_csv(&text)
+    }
+}
+
+//Lowercase without spaces and punctuation, so "Part Number" and "part_number" compare equal
+pub(crate) fn normalize_name(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+//A header-less reader for CSV in whichever of comma, semicolon or tab the first lines use most
+pub(crate) fn csv_reader(text: &str) -> csv::Reader<&[u8]> {
+    let delimiter = [b',', b';', b'\t'].into_iter()
+        .max_by_key(|delimiter| text.lines().take(20).map(|line| line.bytes().filter(|c| c == delimiter).count()).sum::<usize>())
+        .unwrap_or(b',');
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(text.as_bytes())
+}
+
+const REFERENCE_NAMES: [&str; 6] = ["reference", "references", "ref", "refs", "designator", "designators"];
+const PARTNUMBER_NAMES: [&str; 6] = ["mpn", "partnumber", "manufacturerpartnumber", "mfrpartnumber", "mfgpartnumber", "pn"];
+const MANUFACTURER_NAMES: [&str; 3] = ["manufacturer", "mfr", "mfg"];
+
+/// Parses the CSV export of the KiCad BOM tool or one of the BOM scripts. Rows may list several
+/// references, lines above the header (the scripts write a preamble) are skipped.
+pub fn parse_kicad_csv(text: &str) -> ShikaResult<Vec<KicadComponent>> {
+    let mut reader = csv_reader(text);
+    let mut columns: Option<Vec<String>> = None;
+    let mut components = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| ShikaError::Validation(format!("Not a valid CSV file: {e}")))?;
+        let Some(columns) = &columns else {
+            let names: Vec<String> = record.iter().map(normalize_name).collect();
+            if names.iter().any(|name| REFERENCE_NAMES.contains(&name.as_str())) {
+                columns = Some(names);
+            }
+            continue;
+        };
+        let field = |names: &[&str]| -> String {
+            columns.iter()
+                .position(|column| names.contains(&column.as_str()))
+                .and_then(|idx| record.get(idx))
+                .unwrap_or("")
+                .trim()
+                .to_string()
+        };
+        let value = field(&["value", "val"]);
+        if is_dnp(&field(&["dnp", "donotpopulate"])) || value.eq_ignore_ascii_case("dnp") {
+            continue;
+        }
+        let references = field(&REFERENCE_NAMES);
+        let references = parse_designators(&references).unwrap_or_else(|_| {
+            references.split([',', ' ', ';']).filter(|reference| !reference.is_empty()).map(str::to_string).collect()
+        });
+        for reference in references {
+            components.push(KicadComponent {
+                reference,
+                value: value.clone(),
+                footprint: field(&["footprint", "package"]),
+                partnumber: Some(field(&PARTNUMBER_NAMES)).filter(|pn| !pn.is_empty()),
+                manufacturer: Some(field(&MANUFACTURER_NAMES)).filter(|mfg| !mfg.is_empty()),
+            });
+        }
+    }
+    if columns.is_none() {
+        return Err(ShikaError::Validation("No header with a Reference column was found".to_string()));
+    }
+    Ok(components)
+}
+
+fn is_dnp(flag: &str) -> bool {
+    !flag.is_empty() && !["0", "no", "false"].contains(&flag.to_lowercase().as_str())
+}
+
+/// Parses the netlist XML KiCad hands to BOM plugins (`<export><components><comp ref=..>`).
+/// Symbols marked DNP or excluded from the BOM are left out.
+pub fn parse_kicad_xml(text: &str) -> ShikaResult<Vec<KicadComponent>> {
+    let document = roxmltree::Document::parse(text)
+        .map_err(|e| ShikaError::Validation(format!("Not a valid XML file: {e}")))?;
+    let components = document.descendants()
+        .find(|node| node.has_tag_name("components"))
+        .ok_or_else(|| ShikaError::Validation("No <components> section was found".to_string()))?;
+    let mut parsed = Vec::new();
+    for comp in components.children().filter(|node| node.has_tag_name("comp")) {
+        let Some(reference) = comp.attribute("ref") else { continue };
+        let skipped = comp.children().any(|node| {
+            node.has_tag_name("property")
+                && matches!(node.attribute("name"), Some("dnp") | Some("exclude_from_bom"))
+        });
+        if skipped || reference.starts_with('#') {
+            continue;
+        }
+        let child_text = |name: &str| -> String {
+            comp.children()
+                .find(|node| node.has_tag_name(name))
+                .and_then(|node| node.text())
+                .unwrap_or("")
+                .trim()
+                .to_string()
+        };
+        let field = |names: &[&str]| -> Option<String> {
+            comp.descendants()
+                .filter(|node| node.has_tag_name("field"))
+                .find(|node| node.attribute("name").is_some_and(|name| names.contains(&normalize_name(name).as_str())))
+                .and_then(|node| node.text())
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty() && text != "~")
+        };
+        parsed.push(KicadComponent {
+            reference: reference.to_string(),
+            value: child_text("value"),
+            footprint: child_text("footprint"),
+            partnumber: field(&PARTNUMBER_NAMES),
+            manufacturer: field(&MANUFACTURER_NAMES),
+        });
+    }
+    Ok(parsed)
+}
+
+/// Groups components with the same part number, value and footprint into import lines with
+/// their designators merged. The lines still have to be matched, see `match_lines`.
+pub fn group_components(components: &[KicadComponent]) -> Vec<ImportLine> {
+    let mut groups: Vec<(&KicadComponent, Vec<String>)> = Vec::new();
+    for component in components {
+        let group = groups.iter_mut().find(|(first, _)| {
+            first.partnumber == component.partnumber && first.value == component.value && first.footprint == component.footprint
+        });
+        match group {
+            Some((_, references)) => {
+                if !references.contains(&component.reference) {
+                    references.push(component.reference.clone());
+                }
+            }
+            None => groups.push((component, vec![component.reference.clone()])),
+        }
+    }
+    let mut lines: Vec<ImportLine> = groups.into_iter()
+        .map(|(first, references)| ImportLine {
+            designators: compress_designators(&references),
+            qty: references.len() as i32,
+            value: first.value.clone(),
+            footprint: first.footprint.clone(),
+            partnumber: first.partnumber.clone(),
+            manufacturer: first.manufacturer.clone(),
+            candidates: Vec::new(),
+            resolution: LineResolution::Unmatched,
+        })
+        .collect();
+    lines.sort_by(|a, b| a.designators.cmp(&b.designators));
+    lines
+}
+
+/// The package part of a KiCad footprint, `Resistor_SMD:R_0603_1608Metric` gives `0603` and
+/// `Package_SO:SOIC-8_3.9x4.9mm_P1.27mm` gives `SOIC-8`.
+pub fn footprint_package(footprint: &str) -> String {
+    let name = footprint.rsplit(':').next().unwrap_or(footprint);
+    let mut segments = name.split('_');
+    let first = segments.next().unwrap_or("");
+    match segments.next() {
+        Some(size) if !size.is_empty() && size.chars().all(|c| c.is_ascii_digit()) => size.to_string(),
+        _ => first.to_string(),
+    }
+}
+
+//"10 kΩ", "10K" and "10k ohm" all become "10k"
+fn normalize_value(value: &str) -> String {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+    let value = value.trim_end_matches("ohms").trim_end_matches("ohm").trim_end_matches('ω').trim_end_matches('Ω');
+    value.to_string()
+}
+
+fn normalize_package(package: &str) -> String {
+    package.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase()
+}
+
+//2 for the same package, 1 when one contains the other (SOT-23 and SOT-23-5), 0 otherwise
+fn package_score(part_package: &str, import_package: &str) -> u8 {
+    let (part_package, import_package) = (normalize_package(part_package), normalize_package(import_package));
+    if part_package.is_empty() || import_package.is_empty() {
+        0
+    } else if part_package == import_package {
+        2
+    } else if part_package.contains(&import_package) || import_package.contains(&part_package) {
+        1
+    } else {
+        0
+    }
+}
+
+/// Resolves a line against the known parts. A KiCad part number (or a value that is one) is an
+/// exact match, otherwise parts with the same value become candidates and the best one is taken
+/// when its package matches too.
+pub fn match_line(line: &mut ImportLine, parts: &[Part]) {
+    let exact = [line.partnumber.as_deref(), Some(line.value.as_str())].into_iter()
+        .flatten()
+        .filter(|pn| !pn.is_empty())
+        .find_map(|pn| parts.iter().find(|part| part.part_number.eq_ignore_ascii_case(pn)));
+    let value = normalize_value(&line.value);
+    let package = footprint_package(&line.footprint);
+    let mut scored: Vec<(u8, &str)> = parts.iter()
+        .filter(|part| !value.is_empty() && part.value.as_deref().is_some_and(|part_value| normalize_value(part_value) == value))
+        .map(|part| (package_score(part.package.as_deref().unwrap_or(""), &package), part.part_number.as_str()))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    line.candidates = scored.iter().map(|(_, pn)| pn.to_string()).collect();
+    line.resolution = match (exact, scored.first()) {
+        (Some(part), _) => LineResolution::Exact(part.part_number.clone()),
+        (None, Some((score, pn))) if *score > 0 => LineResolution::Fuzzy(pn.to_string()),
+        _ => LineResolution::Unmatched,
+    };
+}
+
+pub fn match_lines(lines: &mut [ImportLine], parts: &[Part]) {
+    for line in lines.iter_mut() {
+        match_line(line, parts);
+    }
+}
+
+/// The new parts and BOM lines to commit. Every line has to be resolved, lines resolved to the
+/// same part number are merged into one BOM line.
+pub fn to_project_parts(lines: &[ImportLine]) -> ShikaResult<(Vec<Part>, Vec<ProjectPart>)> {
+    let unmatched = lines.iter().filter(|line| line.resolution == LineResolution::Unmatched).count();
+    if unmatched > 0 {
+        return Err(ShikaError::Validation(format!("{unmatched} line(s) still have to be mapped, created or skipped")));
+    }
+    let mut new_parts: Vec<Part> = Vec::new();
+    let mut designators: Vec<(String, Vec<String>, i32)> = Vec::new();
+    for line in lines {
+        let Some(pn) = line.resolution.partnumber() else { continue };
+        if let LineResolution::NewPart(pn) = &line.resolution {
+            if !new_parts.iter().any(|part| &part.part_number == pn) {
+                let part = line.new_part(pn);
+                validate_part(&part)?;
+                new_parts.push(part);
+            }
+        }
+        let references = parse_designators(&line.designators).unwrap_or_else(|_| vec![line.designators.clone()]);
+        match designators.iter_mut().find(|(existing, _, _)| existing == pn) {
+            Some((_, merged, qty)) => {
+                merged.extend(references);
+                *qty += line.qty;
+            }
+            None => designators.push((pn.to_string(), references, line.qty)),
+        }
+    }
+    let pparts = designators.into_iter()
+        .map(|(partnumber, references, qty)| ProjectPart {
+            partnumber,
+            designators: compress_designators(&references),
+            qty,
+            part_info: Part::new(),
+        })
+        .collect();
+    Ok((new_parts, pparts))
+}
+
+#[cfg(test)]
+fn test_parts() -> Vec<Part> {
+    let mut parts = Vec::new();
+    for (pn, value, package) in [("RC0603FR-0710KL", "10k", "0603"), ("RC0402FR-0710KL", "10K", "0402"),
+                                 ("TPS62160DSGR", "TPS62160", "WSON-8")] {
+        let mut part = Part::new();
+        part.part_number = pn.to_string();
+        part.value = Some(value.to_string());
+        part.package = Some(package.to_string());
+        parts.push(part);
+    }
+    parts
+}
+
+#[test]
+fn test_parse_kicad_csv() {
+    let csv = "\"Source:\",\"board.kicad_sch\"\n\
+               \"Reference\",\"Value\",\"Footprint\",\"Qty\",\"MPN\",\"DNP\"\n\
+               \"R1,R2,R3\",\"10k\",\"Resistor_SMD:R_0603_1608Metric\",\"3\",\"\",\"\"\n\
+               \"R4\",\"10k\",\"Resistor_SMD:R_0603_1608Metric\",\"1\",\"\",\"DNP\"\n\
+               \"U1\",\"TPS62160\",\"Package_SON:WSON-8-1EP_2x2mm\",\"1\",\"TPS62160DSGR\",\"\"\n";
+    let components = parse_kicad_csv(csv).unwrap();
+    assert_eq!(components.len(), 4);
+    assert_eq!(components[2].reference, "R3");
+    assert_eq!(components[3].partnumber.as_deref(), Some("TPS62160DSGR"));
+    assert!(parse_kicad_csv("a,b\n1,2\n").is_err());
+}
+
+#[test]
+fn test_parse_kicad_xml() {
+    let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+        <export version="E">
+          <components>
+            <comp ref="C1"><value>100n</value><footprint>Capacitor_SMD:C_0402_1005Metric</footprint></comp>
+            <comp ref="C2"><value>100n</value><footprint>Capacitor_SMD:C_0402_1005Metric</footprint>
+              <property name="dnp"/></comp>
+            <comp ref="U1"><value>TPS62160</value><footprint>Package_SON:WSON-8-1EP_2x2mm</footprint>
+              <fields><field name="Manufacturer Part Number">TPS62160DSGR</field></fields></comp>
+          </components>
+        </export>"#;
+    let components = parse_kicad_xml(xml).unwrap();
+    assert_eq!(components.len(), 2);
+    assert_eq!(components[1].partnumber.as_deref(), Some("TPS62160DSGR"));
+    assert!(parse_kicad_xml("<export/>").is_err());
+}
+
+#[test]
+fn test_group_and_match() {
+    let component = |reference: &str, value: &str, footprint: &str| KicadComponent {
+        reference: reference.to_string(),
+        value: value.to_string(),
+        footprint: footprint.to_string(),
+        partnumber: None,
+        manufacturer: None,
+    };
+    let mut components = vec![
+        component("R1", "10k", "Resistor_SMD:R_0603_1608Metric"),
+        component("R3", "10 k", "Resistor_SMD:R_0603_1608Metric"),
+        component("R2", "10k", "Resistor_SMD:R_0603_1608Metric"),
+        component("R5", "10k", "Resistor_SMD:R_1206_3216Metric"),
+        component("U1", "TPS62160", "Package_SON:WSON-8-1EP_2x2mm"),
+        component("J1", "Conn_01x02", "Connector:PinHeader_1x02"),
+    ];
+    components[4].partnumber = Some("tps62160dsgr".to_string());
+    let mut lines = group_components(&components);
+    assert_eq!(lines.iter().map(|line| line.designators.as_str()).collect::<Vec<_>>(),
+               vec!["J1", "R1, R2", "R3", "R5", "U1"]);
+    match_lines(&mut lines, &test_parts());
+    assert_eq!(lines[0].resolution, LineResolution::Unmatched);
+    assert_eq!(lines[1].resolution, LineResolution::Fuzzy("RC0603FR-0710KL".to_string()));
+    assert_eq!(lines[2].resolution, LineResolution::Fuzzy("RC0603FR-0710KL".to_string()));
+    //No 1206 part, both 10k parts are offered but nothing is picked
+    assert_eq!(lines[3].resolution, LineResolution::Unmatched);
+    assert_eq!(lines[3].candidates.len(), 2);
+    assert_eq!(lines[4].resolution, LineResolution::Exact("TPS62160DSGR".to_string()));
+
+    assert!(to_project_parts(&lines).is_err());
+    lines[0].resolution = LineResolution::NewPart("PH-1x02".to_string());
+    lines[3].resolution = LineResolution::Skip;
+    let (new_parts, pparts) = to_project_parts(&lines).unwrap();
+    assert_eq!(new_parts.len(), 1);
+    assert_eq!(new_parts[0].package.as_deref(), Some("PinHeader"));
+    assert_eq!(pparts.len(), 3);
+    assert_eq!(pparts[1].partnumber, "RC0603FR-0710KL");
+    assert_eq!(pparts[1].designators, "R1-R3");
+    assert_eq!(pparts[1].qty, 3);
+}
+
+#[test]
+fn test_footprint_package() {
+    assert_eq!(footprint_package("Resistor_SMD:R_0603_1608Metric"), "0603");
+    assert_eq!(footprint_package("Package_SO:SOIC-8_3.9x4.9mm_P1.27mm"), "SOIC-8");
+    assert_eq!(footprint_package("Package_TO_SOT_SMD:SOT-23-5"), "SOT-23-5");
+    assert_eq!(footprint_package(""), "");
+}
@@ -6,26 +6,50 @@ mod parts;
 mod db;
 mod parts_view;
 mod parts_ui;
+mod part_search;
+mod categories;
+mod eng_value;
+mod table_sort;
 mod stock;
 mod stock_ui;
 mod stock_view;
 mod projects;
 mod projects_view;
 mod projects_ui;
+mod build;
+mod revisions;
+mod kicad_import;
+mod bom_import;
+mod bom_export;
 mod logging;
 mod utils;
+mod config;
+mod cli;
+mod repository;
+mod memory_repository;
+mod sqlite_repository;
+mod migrate;
+mod commands;
 
 use app::App;
+use clap::Parser;
+use cli::Cli;
+use config::Config;
 
 use color_eyre::{
     Result,
 };
 
 fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = Config::load(&cli)?;
+    if let Some(command) = &cli.command {
+        return commands::run(command, cli.json, config);
+    }
     errors::install_hooks()?;
     logging::init()?;
     let mut terminal = tui::init()?;
-    let mut app = App::new();
+    let mut app = App::new(repository::open_repository(config.database));
     App::run(&mut terminal, &mut app)?;
     tui::restore()?;
     Ok(())
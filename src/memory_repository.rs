@@ -0,0 +1,718 @@
+use crate::bom_import::{validate_profile_name, ImportProfile};
+use crate::build::{ensure_in_progress, reserve_build, Build, BuildPart, BuildStatus};
+use crate::categories::{default_categories, validate_category, validate_category_removal, validate_part_category, Category};
+use crate::errors::{ShikaError, ShikaResult};
+use crate::parts::{validate_part, Part, PartStorage};
+use crate::projects::{project_has_builds, validate_project_name, Project, ProjectMetadata, ProjectPart, ProjectStatus};
+use crate::repository::BomRepository;
+use crate::revisions::{revision_exists, validate_revision_name, ProjectRevision, RevisionLine};
+use crate::stock::{
+    current_user, opening_balance, plan_spend, reconciliation, validate_new_stock, validate_receipt, validate_stock_edit,
+    validate_transfer, StockEdit, StockInfo, StockReceipt, StockSpend, StockTransaction, StockTransfer
+};
+use crate::utils;
+
+struct ComponentRow {
+    project_name: String,
+    partnumber: String,
+    designators: String,
+    qty: i32,
+}
+
+/// A `BomRepository` that keeps everything in memory. Nothing is persisted, it backs the unit
+/// tests and the `memory` backend for trying ShikaBOM out without a database.
+pub struct MemoryRepository {
+    parts: Vec<Part>,
+    storage: Vec<PartStorage>,
+    stock: Vec<StockInfo>,
+    transactions: Vec<StockTransaction>,
+    //BOM lines live in `components`, the parts of these are always empty
+    projects: Vec<Project>,
+    components: Vec<ComponentRow>,
+    builds: Vec<Build>,
+    //What each build reserved, by build id
+    build_parts: Vec<(i64, BuildPart)>,
+    revisions: Vec<ProjectRevision>,
+    import_profiles: Vec<ImportProfile>,
+    categories: Vec<Category>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> MemoryRepository {
+        MemoryRepository {
+            parts: Vec::new(),
+            storage: Vec::new(),
+            stock: Vec::new(),
+            transactions: Vec::new(),
+            projects: Vec::new(),
+            components: Vec::new(),
+            builds: Vec::new(),
+            build_parts: Vec::new(),
+            revisions: Vec::new(),
+            import_profiles: Vec::new(),
+            //What migration 0010 starts a database with
+            categories: default_categories(),
+        }
+    }
+
+    /// A handful of parts, stock and a project, enough to click around in the TUI.
+    pub fn with_sample_data() -> MemoryRepository {
+        let mut repo = MemoryRepository::new();
+        let samples = [
+            ("HFW1V2210H4R7K", "Panasonic", "Inductor 4.7uH", "L_4u7", "1210", "4.7u", "10%",
+             "Passives/Inductors", vec![("current", "1.5A")]),
+            ("25SVPF47M", "Panasonic", "Polymer cap 47uF 25V", "C_47u", "6.3x6", "47u", "20%",
+             "Passives/Capacitors", vec![("voltage", "25V")]),
+            ("RC0603FR-0710KL", "Yageo", "Resistor 10k 0603", "R_10k", "0603", "10k", "1%",
+             "Passives/Resistors/Chip", vec![("power", "100mW")]),
+            ("GRM188R71H104KA93D", "Murata", "MLCC 100nF 50V X7R", "C_100n", "0603", "100n", "10%",
+             "Passives/Capacitors/Ceramic", vec![("voltage", "50V"), ("dielectric", "X7R")]),
+        ];
+        for (pn, mfg, desc, label, pkg, val, tol, category, attributes) in samples {
+            repo.parts.push(Part {
+                part_number: pn.to_string(),
+                total_qty: None,
+                manufacturer: Some(mfg.to_string()),
+                description: Some(desc.to_string()),
+                label: Some(label.to_string()),
+                package: Some(pkg.to_string()),
+                value: Some(val.to_string()),
+                tolerance: Some(tol.to_string()),
+                category: Some(category.to_string()),
+                attributes: attributes.into_iter().map(|(name, text)| (name.to_string(), text.to_string())).collect(),
+            });
+        }
+        repo.add_storage("HFW1V2210H4R7K", "Drawer A1", 40);
+        repo.add_storage("RC0603FR-0710KL", "Reel rack", 4500);
+        repo.add_storage("GRM188R71H104KA93D", "Reel rack", 3800);
+        for (pn, threshold, on_hand, on_order) in [
+            ("HFW1V2210H4R7K", 10, 40, 0),
+            ("RC0603FR-0710KL", 500, 4500, 5000),
+        ] {
+            let mut stock = StockInfo::new();
+            stock.partnumber = pn.to_string();
+            stock.low_stock_threshold = threshold;
+            stock.on_hand = on_hand;
+            stock.on_order = on_order;
+            if let Some(entry) = opening_balance(&stock) {
+                repo.record(entry);
+            }
+            repo.stock.push(stock);
+        }
+        let mut project = Project::new();
+        project.name = "Sample Board".to_string();
+        project.metadata.revision = "A".to_string();
+        project.metadata.description = "Buck converter evaluation board".to_string();
+        project.metadata.owner = "hardware".to_string();
+        project.created_at = utils::utc_timestamp();
+        project.modified_at = project.created_at.clone();
+        repo.projects.push(project);
+        repo.components.push(ComponentRow {
+            project_name: "Sample Board".to_string(),
+            partnumber: "RC0603FR-0710KL".to_string(),
+            designators: "R1-R4".to_string(),
+            qty: 4,
+        });
+        repo
+    }
+
+    fn add_storage(&mut self, pn: &str, location: &str, quantity: i32) {
+        self.storage.push(PartStorage {
+            part_number: pn.to_string(),
+            location: location.to_string(),
+            quantity,
+        });
+    }
+
+    /// Appends to the ledger, filling in what the database would.
+    fn record(&mut self, mut entry: StockTransaction) {
+        entry.id = self.transactions.len() as i64 + 1;
+        entry.created_at = utils::utc_timestamp();
+        self.transactions.push(entry);
+    }
+
+    fn on_hand(&self, pn: &str) -> ShikaResult<i32> {
+        Ok(self.stock_levels(pn)?.0)
+    }
+
+    //on_hand and in_prod
+    fn stock_levels(&self, pn: &str) -> ShikaResult<(i32, i32)> {
+        self.stock.iter()
+            .find(|stock| stock.partnumber == pn)
+            .map(|stock| (stock.on_hand, stock.in_prod))
+            .ok_or_else(|| ShikaError::NotFound(format!("Part number {pn} is not stocked")))
+    }
+
+    //Checks the build is still in progress and returns what it reserved
+    fn open_build_parts(&self, id: i64) -> ShikaResult<Vec<BuildPart>> {
+        let build = self.builds.iter()
+            .find(|build| build.id == id)
+            .ok_or_else(|| ShikaError::NotFound(format!("Build #{id} does not exist")))?;
+        ensure_in_progress(id, build.status)?;
+        Ok(self.build_parts.iter()
+            .filter(|(build_id, _)| *build_id == id)
+            .map(|(_, part)| part.clone())
+            .collect())
+    }
+
+    fn finish_build(&mut self, id: i64, status: BuildStatus) {
+        if let Some(build) = self.builds.iter_mut().find(|build| build.id == id) {
+            build.status = status;
+            build.finished_at = Some(utils::utc_timestamp());
+        }
+    }
+
+    //Bumps modified_at after a change to the project's BOM
+    fn touch_project(&mut self, name: &str) {
+        if let Ok(project) = self.find_project(name) {
+            project.modified_at = utils::utc_timestamp();
+        }
+    }
+
+    fn release_in_prod(&mut self, pn: &str, quantity: i32) {
+        if let Some(stock) = self.stock.iter_mut().find(|stock| stock.partnumber == pn) {
+            stock.in_prod -= quantity;
+        }
+    }
+
+    fn part_exists(&self, pn: &str) -> bool {
+        self.parts.iter().any(|part| part.part_number == pn)
+    }
+
+    /// Returns the part the way `big_part_view` would: every field filled in and the total
+    /// quantity summed over its storage locations.
+    fn part_view(&self, part: &Part) -> Part {
+        let total_qty: i64 = self.storage.iter()
+            .filter(|store| store.part_number == part.part_number)
+            .map(|store| store.quantity as i64)
+            .sum();
+        Part {
+            part_number: part.part_number.clone(),
+            total_qty: Some(total_qty),
+            manufacturer: Some(part.manufacturer.clone().unwrap_or_default()),
+            description: Some(part.description.clone().unwrap_or_default()),
+            label: Some(part.label.clone().unwrap_or_default()),
+            package: Some(part.package.clone().unwrap_or_default()),
+            value: Some(part.value.clone().unwrap_or_default()),
+            tolerance: Some(part.tolerance.clone().unwrap_or_default()),
+            category: part.category.clone(),
+            attributes: part.attributes.clone(),
+        }
+    }
+
+    fn find_part(&self, pn: &str) -> ShikaResult<Part> {
+        self.parts.iter()
+            .find(|part| part.part_number == pn)
+            .map(|part| self.part_view(part))
+            .ok_or_else(|| ShikaError::NotFound(format!("Part number {pn} not found")))
+    }
+
+    fn project_exists(&self, name: &str) -> bool {
+        self.projects.iter().any(|project| project.name == name)
+    }
+
+    fn find_project(&mut self, name: &str) -> ShikaResult<&mut Project> {
+        self.projects.iter_mut()
+            .find(|project| project.name == name)
+            .ok_or_else(|| ShikaError::NotFound(format!("Project {name} does not exist")))
+    }
+}
+
+impl BomRepository for MemoryRepository {
+    fn check_connection(&mut self) -> ShikaResult<()> {
+        Ok(())
+    }
+
+    fn fetch_all_parts(&mut self) -> ShikaResult<Vec<Part>> {
+        Ok(self.parts.iter().map(|part| self.part_view(part)).collect())
+    }
+
+    fn fetch_single_part(&mut self, pn: &str) -> ShikaResult<Part> {
+        self.find_part(pn)
+    }
+
+    fn add_new_part(&mut self, new_part: &Part) -> ShikaResult<()> {
+        validate_part(new_part)?;
+        validate_part_category(new_part, &self.categories)?;
+        if self.part_exists(&new_part.part_number) {
+            return Err(ShikaError::ConstraintViolation(
+                format!("Part number {} already exists", new_part.part_number)));
+        }
+        let mut part = new_part.clone();
+        part.total_qty = None;
+        self.parts.push(part);
+        Ok(())
+    }
+
+    fn modify_part(&mut self, part: &Part) -> ShikaResult<()> {
+        validate_part(part)?;
+        validate_part_category(part, &self.categories)?;
+        match self.parts.iter_mut().find(|existing| existing.part_number == part.part_number) {
+            Some(existing) => {
+                existing.manufacturer = part.manufacturer.clone();
+                existing.description = part.description.clone();
+                existing.label = part.label.clone();
+                existing.package = part.package.clone();
+                existing.value = part.value.clone();
+                existing.tolerance = part.tolerance.clone();
+                existing.category = part.category.clone();
+                existing.attributes = part.attributes.clone();
+                Ok(())
+            }
+            None => Err(ShikaError::NotFound(format!("Part number {} not found", part.part_number))),
+        }
+    }
+
+    fn fetch_part_storage_data(&mut self, pn: &str) -> ShikaResult<Vec<PartStorage>> {
+        Ok(self.storage.iter().filter(|store| store.part_number == pn).cloned().collect())
+    }
+
+    fn fetch_stock_info(&mut self) -> ShikaResult<Vec<StockInfo>> {
+        let mut stock_data = self.stock.clone();
+        for stock in &mut stock_data {
+            stock.recompute();
+        }
+        Ok(stock_data)
+    }
+
+    fn fetch_nonstocked_partnumbers(&mut self) -> ShikaResult<Vec<String>> {
+        let mut partnumbers: Vec<String> = self.parts.iter()
+            .filter(|part| !self.stock.iter().any(|stock| stock.partnumber == part.part_number))
+            .map(|part| part.part_number.clone())
+            .collect();
+        partnumbers.sort();
+        Ok(partnumbers)
+    }
+
+    fn create_new_stock(&mut self, stock: StockInfo) -> ShikaResult<()> {
+        validate_new_stock(&stock)?;
+        if !self.part_exists(&stock.partnumber) {
+            return Err(ShikaError::ConstraintViolation(
+                format!("Part number {} does not exist", stock.partnumber)));
+        }
+        if self.stock.iter().any(|existing| existing.partnumber == stock.partnumber) {
+            return Err(ShikaError::ConstraintViolation(
+                format!("Part number {} is already stocked", stock.partnumber)));
+        }
+        if let Some(entry) = opening_balance(&stock) {
+            self.record(entry);
+        }
+        self.stock.push(stock);
+        Ok(())
+    }
+
+    fn receive_stock(&mut self, receipt: &StockReceipt) -> ShikaResult<()> {
+        validate_receipt(receipt)?;
+        let stock = self.stock.iter_mut()
+            .find(|stock| stock.partnumber == receipt.partnumber)
+            .ok_or_else(|| ShikaError::NotFound(format!("Part number {} is not stocked", receipt.partnumber)))?;
+        stock.on_hand += receipt.quantity;
+        if receipt.against_order {
+            stock.on_order = (stock.on_order - receipt.quantity).max(0);
+        }
+        let location = receipt.location.trim();
+        match self.storage.iter_mut().find(|store| {
+            store.part_number == receipt.partnumber && store.location == location
+        }) {
+            Some(store) => store.quantity += receipt.quantity,
+            None => self.add_storage(&receipt.partnumber, location, receipt.quantity),
+        }
+        self.record(receipt.to_transaction());
+        Ok(())
+    }
+
+    fn spend_stock(&mut self, spend: &StockSpend) -> ShikaResult<()> {
+        let (on_hand, in_prod) = self.stock_levels(&spend.partnumber)?;
+        let mut storage = self.fetch_part_storage_data(&spend.partnumber)?;
+        storage.sort_by(|a, b| a.location.cmp(&b.location));
+        let plan = plan_spend(spend, on_hand, in_prod, &storage)?;
+        for entry in spend.to_transactions(&plan) {
+            self.record(entry);
+        }
+        for (location, quantity) in plan {
+            if let Some(store) = self.storage.iter_mut().find(|store| {
+                store.part_number == spend.partnumber && store.location == location
+            }) {
+                store.quantity -= quantity;
+            }
+        }
+        if let Some(stock) = self.stock.iter_mut().find(|stock| stock.partnumber == spend.partnumber) {
+            stock.on_hand -= spend.quantity;
+        }
+        Ok(())
+    }
+
+    fn edit_stock(&mut self, edit: &StockEdit) -> ShikaResult<()> {
+        let storage = self.fetch_part_storage_data(&edit.partnumber)?;
+        let stock = self.stock.iter_mut()
+            .find(|stock| stock.partnumber == edit.partnumber)
+            .ok_or_else(|| ShikaError::NotFound(format!("Part number {} is not stocked", edit.partnumber)))?;
+        let delta = validate_stock_edit(edit, stock.on_hand, &storage)?;
+        stock.low_stock_threshold = edit.low_stock_threshold;
+        stock.on_hand = edit.on_hand;
+        stock.on_order = edit.on_order;
+        if let Some(location) = edit.adjusted_location().filter(|_| delta != 0) {
+            if let Some(store) = self.storage.iter_mut().find(|store| {
+                store.part_number == edit.partnumber && store.location == location
+            }) {
+                store.quantity += delta;
+            }
+        }
+        if delta != 0 {
+            self.record(edit.to_transaction(delta));
+        }
+        Ok(())
+    }
+
+    fn transfer_stock(&mut self, transfer: &StockTransfer) -> ShikaResult<()> {
+        let storage = self.fetch_part_storage_data(&transfer.partnumber)?;
+        validate_transfer(transfer, &storage)?;
+        for entry in transfer.to_transactions() {
+            let location = entry.location.clone().unwrap_or_default();
+            match self.storage.iter_mut().find(|store| {
+                store.part_number == transfer.partnumber && store.location == location
+            }) {
+                Some(store) => store.quantity += entry.delta,
+                None => self.add_storage(&transfer.partnumber, &location, entry.delta),
+            }
+            self.record(entry);
+        }
+        Ok(())
+    }
+
+    fn fetch_stock_history(&mut self, pn: &str) -> ShikaResult<Vec<StockTransaction>> {
+        Ok(self.transactions.iter().rev().filter(|entry| entry.partnumber == pn).cloned().collect())
+    }
+
+    fn reconcile_stock(&mut self, pn: &str, expected: i32) -> ShikaResult<()> {
+        let on_hand = self.on_hand(pn)?;
+        let ledger_total: i64 = self.transactions.iter()
+            .filter(|entry| entry.partnumber == pn)
+            .map(|entry| entry.delta as i64)
+            .sum();
+        if let Some(entry) = reconciliation(pn, on_hand, ledger_total, expected)? {
+            self.record(entry);
+        }
+        Ok(())
+    }
+
+    fn fetch_project_list(&mut self) -> ShikaResult<Vec<Project>> {
+        Ok(self.projects.clone())
+    }
+
+    fn fetch_project_details(&mut self, project: &mut Project) -> ShikaResult<()> {
+        let mut rows: Vec<&ComponentRow> = self.components.iter()
+            .filter(|row| row.project_name == project.name)
+            .collect();
+        rows.sort_by(|a, b| a.partnumber.cmp(&b.partnumber));
+        project.parts.clear();
+        for row in rows {
+            let part_info = self.find_part(&row.partnumber)?;
+            project.parts.push(ProjectPart {
+                partnumber: row.partnumber.clone(),
+                designators: row.designators.clone(),
+                qty: row.qty,
+                part_info,
+            });
+        }
+        Ok(())
+    }
+
+    fn create_new_project_name(&mut self, name: String) -> ShikaResult<()> {
+        validate_project_name(&name)?;
+        if self.project_exists(&name) {
+            return Err(ShikaError::ConstraintViolation(format!("Project {name} already exists")));
+        }
+        let mut project = Project::new();
+        project.name = name;
+        project.metadata.owner = current_user();
+        project.created_at = utils::utc_timestamp();
+        project.modified_at = project.created_at.clone();
+        self.projects.push(project);
+        Ok(())
+    }
+
+    fn rename_project(&mut self, old_name: &str, new_name: &str) -> ShikaResult<()> {
+        validate_project_name(new_name)?;
+        if self.project_exists(new_name) {
+            return Err(ShikaError::ConstraintViolation(format!("Project {new_name} already exists")));
+        }
+        let project = self.find_project(old_name)?;
+        project.name = new_name.to_string();
+        project.modified_at = utils::utc_timestamp();
+        for row in self.components.iter_mut().filter(|row| row.project_name == old_name) {
+            row.project_name = new_name.to_string();
+        }
+        for build in self.builds.iter_mut().filter(|build| build.project_name == old_name) {
+            build.project_name = new_name.to_string();
+        }
+        for revision in self.revisions.iter_mut().filter(|revision| revision.project_name == old_name) {
+            revision.project_name = new_name.to_string();
+        }
+        Ok(())
+    }
+
+    fn duplicate_project(&mut self, source: &str, new_name: &str) -> ShikaResult<()> {
+        let metadata = self.find_project(source)?.metadata.clone();
+        self.create_new_project_name(new_name.to_string())?;
+        self.find_project(new_name)?.metadata = metadata;
+        let copies: Vec<ComponentRow> = self.components.iter()
+            .filter(|row| row.project_name == source)
+            .map(|row| ComponentRow {
+                project_name: new_name.to_string(),
+                partnumber: row.partnumber.clone(),
+                designators: row.designators.clone(),
+                qty: row.qty,
+            })
+            .collect();
+        self.components.extend(copies);
+        Ok(())
+    }
+
+    fn set_project_status(&mut self, name: &str, status: ProjectStatus) -> ShikaResult<()> {
+        self.find_project(name)?.status = status;
+        Ok(())
+    }
+
+    fn update_project_metadata(&mut self, name: &str, metadata: &ProjectMetadata) -> ShikaResult<()> {
+        let project = self.find_project(name)?;
+        project.metadata = metadata.clone();
+        project.modified_at = utils::utc_timestamp();
+        Ok(())
+    }
+
+    fn delete_project(&mut self, name: &str) -> ShikaResult<()> {
+        self.find_project(name)?;
+        if self.builds.iter().any(|build| build.project_name == name) {
+            return Err(project_has_builds(name));
+        }
+        self.projects.retain(|project| project.name != name);
+        self.components.retain(|row| row.project_name != name);
+        self.revisions.retain(|revision| revision.project_name != name);
+        Ok(())
+    }
+
+    fn fetch_pns_not_in_project(&mut self, project: &Project) -> ShikaResult<Vec<String>> {
+        Ok(self.parts.iter()
+            .filter(|part| !self.components.iter().any(|row| {
+                row.project_name == project.name && row.partnumber == part.part_number
+            }))
+            .map(|part| part.part_number.clone())
+            .collect())
+    }
+
+    fn add_pn_to_project(&mut self, project: &Project, ppart: &ProjectPart) -> ShikaResult<()> {
+        if !self.project_exists(&project.name) || !self.part_exists(&ppart.partnumber) {
+            return Err(ShikaError::ConstraintViolation(
+                format!("{} or {} does not exist", project.name, ppart.partnumber)));
+        }
+        if self.components.iter().any(|row| row.project_name == project.name && row.partnumber == ppart.partnumber) {
+            return Err(ShikaError::ConstraintViolation(
+                format!("{} is already in the BOM for {}", ppart.partnumber, project.name)));
+        }
+        self.components.push(ComponentRow {
+            project_name: project.name.clone(),
+            partnumber: ppart.partnumber.clone(),
+            designators: ppart.designators.clone(),
+            qty: ppart.qty,
+        });
+        self.touch_project(&project.name);
+        Ok(())
+    }
+
+    fn update_project_part(&mut self, project: &Project, old_pn: &str, ppart: &ProjectPart) -> ShikaResult<()> {
+        if ppart.partnumber != old_pn {
+            if !self.part_exists(&ppart.partnumber) {
+                return Err(ShikaError::ConstraintViolation(format!("{} does not exist", ppart.partnumber)));
+            }
+            if self.components.iter().any(|row| row.project_name == project.name && row.partnumber == ppart.partnumber) {
+                return Err(ShikaError::ConstraintViolation(
+                    format!("{} is already in the BOM for {}", ppart.partnumber, project.name)));
+            }
+        }
+        let row = self.components.iter_mut()
+            .find(|row| row.project_name == project.name && row.partnumber == old_pn)
+            .ok_or_else(|| ShikaError::NotFound(format!("{old_pn} is not in the BOM for {}", project.name)))?;
+        row.partnumber = ppart.partnumber.clone();
+        row.designators = ppart.designators.clone();
+        row.qty = ppart.qty;
+        self.touch_project(&project.name);
+        Ok(())
+    }
+
+    fn remove_pn_from_project(&mut self, project: &Project, pn: &str) -> ShikaResult<()> {
+        let before = self.components.len();
+        self.components.retain(|row| !(row.project_name == project.name && row.partnumber == pn));
+        if self.components.len() == before {
+            return Err(ShikaError::NotFound(format!("{pn} is not in the BOM for {}", project.name)));
+        }
+        self.touch_project(&project.name);
+        Ok(())
+    }
+
+    fn import_project_parts(&mut self, project: &Project, new_parts: &[Part], pparts: &[ProjectPart]) -> ShikaResult<()> {
+        //Checked up front so a failure leaves nothing half imported
+        for (idx, part) in new_parts.iter().enumerate() {
+            validate_part(part)?;
+            if self.part_exists(&part.part_number) || new_parts[..idx].iter().any(|other| other.part_number == part.part_number) {
+                return Err(ShikaError::ConstraintViolation(format!("Part number {} already exists", part.part_number)));
+            }
+        }
+        for (idx, ppart) in pparts.iter().enumerate() {
+            let known = self.part_exists(&ppart.partnumber) || new_parts.iter().any(|part| part.part_number == ppart.partnumber);
+            if !self.project_exists(&project.name) || !known {
+                return Err(ShikaError::ConstraintViolation(
+                    format!("{} or {} does not exist", project.name, ppart.partnumber)));
+            }
+            let in_bom = self.components.iter().any(|row| row.project_name == project.name && row.partnumber == ppart.partnumber);
+            if in_bom || pparts[..idx].iter().any(|other| other.partnumber == ppart.partnumber) {
+                return Err(ShikaError::ConstraintViolation(
+                    format!("{} is already in the BOM for {}", ppart.partnumber, project.name)));
+            }
+        }
+        for part in new_parts {
+            self.add_new_part(part)?;
+        }
+        for ppart in pparts {
+            self.add_pn_to_project(project, ppart)?;
+        }
+        Ok(())
+    }
+
+    fn fetch_builds(&mut self, project_name: &str) -> ShikaResult<Vec<Build>> {
+        Ok(self.builds.iter()
+            .rev()
+            .filter(|build| build.project_name == project_name)
+            .cloned()
+            .collect())
+    }
+
+    fn start_build(&mut self, project_name: &str, units: i32) -> ShikaResult<i64> {
+        let mut project = Project::new();
+        project.name = project_name.to_string();
+        self.fetch_project_details(&mut project)?;
+        let reserved = reserve_build(&project, &self.fetch_stock_info()?, units)?;
+        let id = self.builds.len() as i64 + 1;
+        self.builds.push(Build {
+            id,
+            project_name: project_name.to_string(),
+            quantity: units,
+            status: BuildStatus::InProgress,
+            started_at: utils::utc_timestamp(),
+            finished_at: None,
+        });
+        for part in reserved {
+            if let Some(stock) = self.stock.iter_mut().find(|stock| stock.partnumber == part.partnumber) {
+                stock.in_prod += part.quantity;
+            }
+            self.build_parts.push((id, part));
+        }
+        Ok(id)
+    }
+
+    fn complete_build(&mut self, id: i64) -> ShikaResult<()> {
+        let parts = self.open_build_parts(id)?;
+        //Plan every part before touching anything, so a failed build changes nothing
+        let mut plans = Vec::new();
+        for part in &parts {
+            let (on_hand, in_prod) = self.stock_levels(&part.partnumber)?;
+            let mut storage = self.fetch_part_storage_data(&part.partnumber)?;
+            storage.sort_by(|a, b| a.location.cmp(&b.location));
+            plans.push(plan_spend(&part.to_spend(), on_hand, in_prod - part.quantity, &storage)?);
+        }
+        for (part, plan) in parts.iter().zip(plans) {
+            for entry in part.to_transactions(id, &plan) {
+                self.record(entry);
+            }
+            for (location, quantity) in plan {
+                if let Some(store) = self.storage.iter_mut().find(|store| {
+                    store.part_number == part.partnumber && store.location == location
+                }) {
+                    store.quantity -= quantity;
+                }
+            }
+            if let Some(stock) = self.stock.iter_mut().find(|stock| stock.partnumber == part.partnumber) {
+                stock.on_hand -= part.quantity;
+            }
+            self.release_in_prod(&part.partnumber, part.quantity);
+        }
+        self.finish_build(id, BuildStatus::Completed);
+        Ok(())
+    }
+
+    fn cancel_build(&mut self, id: i64) -> ShikaResult<()> {
+        for part in self.open_build_parts(id)? {
+            self.release_in_prod(&part.partnumber, part.quantity);
+        }
+        self.finish_build(id, BuildStatus::Cancelled);
+        Ok(())
+    }
+
+    fn fetch_revisions(&mut self, project_name: &str) -> ShikaResult<Vec<ProjectRevision>> {
+        Ok(self.revisions.iter()
+            .filter(|revision| revision.project_name == project_name)
+            .rev()
+            .cloned()
+            .collect())
+    }
+
+    fn create_revision(&mut self, project_name: &str, revision: &str) -> ShikaResult<()> {
+        validate_revision_name(revision)?;
+        self.find_project(project_name)?;
+        if self.revisions.iter().any(|existing| existing.project_name == project_name && existing.revision == revision) {
+            return Err(ShikaError::ConstraintViolation(revision_exists(project_name, revision)));
+        }
+        let mut lines: Vec<RevisionLine> = self.components.iter()
+            .filter(|row| row.project_name == project_name)
+            .map(|row| RevisionLine {
+                partnumber: row.partnumber.clone(),
+                designators: row.designators.clone(),
+                qty: row.qty,
+            })
+            .collect();
+        lines.sort_by(|a, b| a.partnumber.cmp(&b.partnumber));
+        self.revisions.push(ProjectRevision {
+            id: self.revisions.len() as i64 + 1,
+            project_name: project_name.to_string(),
+            revision: revision.to_string(),
+            created_at: utils::utc_timestamp(),
+            lines,
+        });
+        Ok(())
+    }
+
+    fn fetch_import_profiles(&mut self) -> ShikaResult<Vec<ImportProfile>> {
+        let mut profiles = self.import_profiles.clone();
+        profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(profiles)
+    }
+
+    fn save_import_profile(&mut self, profile: &ImportProfile) -> ShikaResult<()> {
+        validate_profile_name(&profile.name)?;
+        self.import_profiles.retain(|existing| existing.name != profile.name);
+        self.import_profiles.push(profile.clone());
+        Ok(())
+    }
+
+    fn fetch_categories(&mut self) -> ShikaResult<Vec<Category>> {
+        let mut categories = self.categories.clone();
+        categories.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(categories)
+    }
+
+    fn save_category(&mut self, category: &Category) -> ShikaResult<()> {
+        validate_category(category, &self.categories)?;
+        match self.categories.iter_mut().find(|existing| existing.path == category.path) {
+            Some(existing) => existing.attributes = category.attributes.clone(),
+            None => self.categories.push(category.clone()),
+        }
+        Ok(())
+    }
+
+    fn delete_category(&mut self, path: &str) -> ShikaResult<()> {
+        let parts = self.parts.iter().filter(|part| part.category.as_deref() == Some(path)).count();
+        validate_category_removal(path, &self.categories, parts)?;
+        self.categories.retain(|category| category.path != path);
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::db::Db;
+use crate::errors::ShikaResult;
+
+/// A versioned schema change, embedded in the binary so a fresh database can be set up
+/// with `shikabom db migrate`. The same SQL is run on every backend, see `sql_for`.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration in the order they are applied. Versions must only ever be appended.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "stock_transactions",
+        sql: include_str!("../migrations/0002_stock_transactions.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "stock_transaction_reason",
+        sql: include_str!("../migrations/0003_stock_transaction_reason.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "stock_ledger",
+        sql: include_str!("../migrations/0004_stock_ledger.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "project_status",
+        sql: include_str!("../migrations/0005_project_status.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "builds",
+        sql: include_str!("../migrations/0006_builds.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "project_metadata",
+        sql: include_str!("../migrations/0007_project_metadata.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "project_revisions",
+        sql: include_str!("../migrations/0008_project_revisions.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "import_profiles",
+        sql: include_str!("../migrations/0009_import_profiles.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "part_categories",
+        sql: include_str!("../migrations/0010_part_categories.sql"),
+    },
+];
+
+/// The SQL flavour a migration is run against.
+pub enum Dialect {
+    Postgres,
+    Sqlite,
+}
+
+impl Migration {
+    /// The migration SQL with its placeholders filled in for the backend.
+    pub fn sql_for(&self, dialect: Dialect) -> String {
+        let id = match dialect {
+            Dialect::Postgres => "BIGSERIAL PRIMARY KEY",
+            Dialect::Sqlite => "INTEGER PRIMARY KEY AUTOINCREMENT",
+        };
+        self.sql.replace("{{id}}", id)
+    }
+}
+
+pub struct MigrationStatus {
+    pub version: i32,
+    pub name: &'static str,
+    //None if the migration hasn't been applied yet
+    pub applied_at: Option<String>,
+}
+
+/// A database that migrations can be applied to.
+pub trait SchemaStore {
+    /// Creates the `schema_migrations` table if it doesn't exist yet.
+    fn ensure_migrations_table(&mut self) -> ShikaResult<()>;
+    /// The applied versions and when they were applied.
+    fn applied_migrations(&mut self) -> ShikaResult<Vec<(i32, String)>>;
+    /// Runs the migration and records it in one transaction. Returns false if another
+    /// process applied it first.
+    fn apply_migration(&mut self, migration: &Migration) -> ShikaResult<bool>;
+}
+
+//Any constant works, it only has to be the same for every shikabom process
+const MIGRATION_LOCK_ID: i64 = 0x5348_494B_4142_4F4D;
+
+impl SchemaStore for Db {
+    fn ensure_migrations_table(&mut self) -> ShikaResult<()> {
+        self.client()?.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )")?;
+        Ok(())
+    }
+
+    fn applied_migrations(&mut self) -> ShikaResult<Vec<(i32, String)>> {
+        let rows = self.client()?.query(
+            "SELECT version, to_char(applied_at, 'YYYY-MM-DD HH24:MI:SS') AS applied_at FROM schema_migrations", &[])?;
+        Ok(rows.iter().map(|row| (row.get("version"), row.get("applied_at"))).collect())
+    }
+
+    fn apply_migration(&mut self, migration: &Migration) -> ShikaResult<bool> {
+        let mut transaction = self.client()?.transaction()?;
+        //Serializes concurrent `db migrate` runs, released when the transaction ends
+        transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID])?;
+        let already_applied = transaction.query_opt(
+            "SELECT 1 FROM schema_migrations WHERE version = $1", &[&migration.version])?.is_some();
+        if already_applied {
+            return Ok(false);
+        }
+        transaction.batch_execute(&migration.sql_for(Dialect::Postgres))?;
+        transaction.execute("INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                            &[&migration.version, &migration.name])?;
+        transaction.commit()?;
+        Ok(true)
+    }
+}
+
+/// Lists every known migration and when it was applied.
+pub fn status(store: &mut dyn SchemaStore) -> ShikaResult<Vec<MigrationStatus>> {
+    store.ensure_migrations_table()?;
+    let applied = store.applied_migrations()?;
+    let mut statuses = Vec::new();
+    for migration in MIGRATIONS {
+        let applied_at = applied.iter()
+            .find(|(version, _)| *version == migration.version)
+            .map(|(_, applied_at)| applied_at.clone());
+        statuses.push(MigrationStatus {
+            version: migration.version,
+            name: migration.name,
+            applied_at,
+        });
+    }
+    Ok(statuses)
+}
+
+/// The highest applied version, 0 for an empty database.
+pub fn current_version(store: &mut dyn SchemaStore) -> ShikaResult<i32> {
+    Ok(status(store)?.iter()
+        .filter(|migration| migration.applied_at.is_some())
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0))
+}
+
+/// Applies every pending migration, each one in its own transaction, and returns the ones applied.
+pub fn migrate(store: &mut dyn SchemaStore) -> ShikaResult<Vec<&'static Migration>> {
+    store.ensure_migrations_table()?;
+    let applied_versions: Vec<i32> = store.applied_migrations()?.iter().map(|(version, _)| *version).collect();
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS {
+        if applied_versions.contains(&migration.version) {
+            continue;
+        }
+        if store.apply_migration(migration)? {
+            applied.push(migration);
+        }
+    }
+    Ok(applied)
+}
+
+#[test]
+fn test_migration_versions_are_sequential() {
+    for (idx, migration) in MIGRATIONS.iter().enumerate() {
+        assert_eq!(migration.version, idx as i32 + 1, "migration {} is out of order", migration.name);
+        assert!(!migration.sql.trim().is_empty());
+    }
+}
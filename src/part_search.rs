@@ -0,0 +1,381 @@
+use crate::eng_value::{approx_eq, format_tolerance, parse_tolerance, parse_value};
+use crate::parts::Part;
+
+/// The part columns a search can look at.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SearchField {
+    PartNumber,
+    Manufacturer,
+    Value,
+    Package,
+    Label,
+    Description,
+    Tolerance,
+    Category,
+}
+
+impl SearchField {
+    //Searched by a term without a `field:` prefix, tolerance only matches through a filter
+    pub const ANY: [SearchField; 7] = [
+        SearchField::PartNumber,
+        SearchField::Manufacturer,
+        SearchField::Value,
+        SearchField::Package,
+        SearchField::Label,
+        SearchField::Description,
+        SearchField::Category,
+    ];
+
+    /// The field a `name:` filter prefix refers to.
+    pub fn from_name(name: &str) -> Option<SearchField> {
+        match name.to_ascii_lowercase().as_str() {
+            "pn" | "partnumber" | "part" => Some(SearchField::PartNumber),
+            "mfg" | "manufacturer" => Some(SearchField::Manufacturer),
+            "val" | "value" => Some(SearchField::Value),
+            "pkg" | "package" => Some(SearchField::Package),
+            "label" => Some(SearchField::Label),
+            "desc" | "description" => Some(SearchField::Description),
+            "tol" | "tolerance" => Some(SearchField::Tolerance),
+            "cat" | "category" => Some(SearchField::Category),
+            _ => None,
+        }
+    }
+
+    pub fn text(self, part: &Part) -> &str {
+        let text = match self {
+            SearchField::PartNumber => return &part.part_number,
+            SearchField::Manufacturer => &part.manufacturer,
+            SearchField::Value => &part.value,
+            SearchField::Package => &part.package,
+            SearchField::Label => &part.label,
+            SearchField::Description => &part.description,
+            SearchField::Tolerance => &part.tolerance,
+            //The whole path, so `cat:passives` finds everything below Passives
+            SearchField::Category => &part.category,
+        };
+        text.as_deref().unwrap_or("")
+    }
+}
+
+/// How a `value:` or `tol:` filter compares, `value:>=1k` finds everything from 1k up.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Equal,
+    Below,
+    AtMost,
+    Above,
+    AtLeast,
+}
+
+impl Comparison {
+    //Longer prefixes first so `<=` isn't read as `<`
+    const PREFIXES: [(&'static str, Comparison); 5] = [
+        ("<=", Comparison::AtMost),
+        (">=", Comparison::AtLeast),
+        ("<", Comparison::Below),
+        (">", Comparison::Above),
+        ("=", Comparison::Equal),
+    ];
+
+    fn symbol(self) -> &'static str {
+        Comparison::PREFIXES.iter().find(|(_, comparison)| *comparison == self).map_or("", |(prefix, _)| prefix)
+    }
+
+    fn split(text: &str) -> (Option<Comparison>, &str) {
+        Comparison::PREFIXES.iter()
+            .find_map(|(prefix, comparison)| text.strip_prefix(prefix).map(|rest| (Some(*comparison), rest)))
+            .unwrap_or((None, text))
+    }
+
+    fn holds(self, quantity: f64, limit: f64) -> bool {
+        let equal = approx_eq(quantity, limit);
+        match self {
+            Comparison::Equal => equal,
+            Comparison::Below => quantity < limit && !equal,
+            Comparison::AtMost => quantity < limit || equal,
+            Comparison::Above => quantity > limit && !equal,
+            Comparison::AtLeast => quantity > limit || equal,
+        }
+    }
+}
+
+/// One word of a search, matched case-insensitively as a substring. Values and tolerances that
+/// parse are also compared as quantities, so `10k` finds `10000` and `10kΩ`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SearchTerm {
+    //None searches every field in `SearchField::ANY`
+    pub field: Option<SearchField>,
+    //Set for `name:text` when name isn't a column, it filters the part attribute of that name
+    pub attribute: Option<String>,
+    //Kept in its typed case since `m` and `M` are different prefixes
+    pub text: String,
+    //Only set on value, tolerance and attribute filters, which then match by quantity alone
+    pub comparison: Option<Comparison>,
+}
+
+impl SearchTerm {
+    fn matches(&self, part: &Part) -> bool {
+        if let Some(name) = &self.attribute {
+            let matches_attribute = part.attributes.get(name).is_some_and(|text| self.matches_text(text, Self::compare_values));
+            //Part numbers with a colon in them are still found as plain text
+            let word = format!("{name}:{}{}", self.comparison.map_or("", Comparison::symbol), self.text).to_ascii_lowercase();
+            return matches_attribute || SearchField::ANY.iter().any(|field| contains(field.text(part), &word));
+        }
+        match self.field {
+            Some(field) => self.matches_field(field, field.text(part)),
+            None => SearchField::ANY.iter().any(|field| self.matches_field(*field, field.text(part))),
+        }
+    }
+
+    fn matches_field(&self, field: SearchField, text: &str) -> bool {
+        self.matches_text(text, |term, text| term.compare_quantity(field, text))
+    }
+
+    fn matches_text(&self, text: &str, compare: impl Fn(&Self, &str) -> Option<bool>) -> bool {
+        compare(self, text) == Some(true)
+            || (self.comparison.is_none() && contains(text, &self.text.to_ascii_lowercase()))
+    }
+
+    //Some when both the term and the cell are quantities of `field`
+    fn compare_quantity(&self, field: SearchField, text: &str) -> Option<bool> {
+        match field {
+            SearchField::Value => self.compare_values(text),
+            SearchField::Tolerance => {
+                let comparison = self.comparison.unwrap_or(Comparison::Equal);
+                Some(comparison.holds(parse_tolerance(text)?, parse_tolerance(&self.text)?))
+            }
+            _ => None,
+        }
+    }
+
+    fn compare_values(&self, text: &str) -> Option<bool> {
+        let (limit, value) = (parse_value(&self.text)?, parse_value(text)?);
+        Some(match self.comparison.unwrap_or(Comparison::Equal) {
+            Comparison::Equal => value.same_as(&limit),
+            comparison => value.comparable(&limit) && comparison.holds(value.magnitude, limit.magnitude),
+        })
+    }
+
+    fn applies_to(&self, field: SearchField) -> bool {
+        self.attribute.is_none() && self.field.map_or(SearchField::ANY.contains(&field), |term_field| term_field == field)
+    }
+}
+
+/// A parts table search like `10k package:0603`. A part has to match every term.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PartQuery {
+    pub terms: Vec<SearchTerm>,
+}
+
+impl PartQuery {
+    /// Splits the search on whitespace. `field:text` filters one column and any other
+    /// `name:text` filters the part attribute `name`, like `voltage:>=50V`.
+    pub fn parse(text: &str) -> PartQuery {
+        let terms = text.split_whitespace()
+            .filter_map(|word| {
+                let (field, attribute, text) = match word.split_once(':') {
+                    Some((name, text)) => match SearchField::from_name(name) {
+                        Some(field) => (Some(field), None, text),
+                        None if !name.is_empty() => (None, Some(name.to_lowercase()), text),
+                        None => (None, None, word),
+                    },
+                    None => (None, None, word),
+                };
+                //A bare `package:` or `value:<=` while typing doesn't filter anything yet
+                let (comparison, text) = match field {
+                    Some(SearchField::Value | SearchField::Tolerance) => Comparison::split(text),
+                    _ if attribute.is_some() => Comparison::split(text),
+                    _ => (None, text),
+                };
+                (!text.is_empty()).then(|| SearchTerm {
+                    field,
+                    attribute,
+                    text: text.to_string(),
+                    comparison,
+                })
+            })
+            .collect();
+        PartQuery { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn matches(&self, part: &Part) -> bool {
+        self.terms.iter().all(|term| term.matches(part))
+    }
+
+    /// The search for parts that can stand in for `part`: the same value and package and a
+    /// tolerance at least as tight. None when the part has no value to compare.
+    pub fn equivalent_to(part: &Part) -> Option<String> {
+        let value = parse_value(part.value.as_deref()?)?;
+        let mut terms = vec![format!("value:={value}")];
+        if let Some(package) = part.package.as_deref().map(str::trim).filter(|package| !package.is_empty()) {
+            //A package with spaces would split into several terms, so it's left out
+            if !package.contains(char::is_whitespace) {
+                terms.push(format!("package:{package}"));
+            }
+        }
+        if let Some(tolerance) = part.tolerance.as_deref().and_then(parse_tolerance) {
+            terms.push(format!("tol:<={}", format_tolerance(tolerance)));
+        }
+        Some(terms.join(" "))
+    }
+
+    /// Sorted, non-overlapping byte ranges of `text` matched by the terms that apply to `field`.
+    /// A cell matched by quantity is highlighted whole.
+    pub fn highlights(&self, field: SearchField, text: &str) -> Vec<(usize, usize)> {
+        let terms = self.terms.iter().filter(|term| term.applies_to(field));
+        highlight_terms(terms, text, |term, text| term.compare_quantity(field, text))
+    }
+
+    /// `highlights` for the attribute column `name`.
+    pub fn attribute_highlights(&self, name: &str, text: &str) -> Vec<(usize, usize)> {
+        let terms = self.terms.iter().filter(|term| term.attribute.as_deref() == Some(name));
+        highlight_terms(terms, text, SearchTerm::compare_values)
+    }
+}
+
+fn highlight_terms<'a>(terms: impl Iterator<Item = &'a SearchTerm>, text: &str,
+                       compare: impl Fn(&SearchTerm, &str) -> Option<bool>) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = terms
+        .flat_map(|term| match compare(term, text) {
+            Some(true) if !text.is_empty() => vec![(0, text.len())],
+            _ if term.comparison.is_some() => Vec::new(),
+            _ => find_all(text, &term.text.to_ascii_lowercase()),
+        })
+        .collect();
+    ranges.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// The other parts that can replace `part`, see `PartQuery::equivalent_to`.
+pub fn equivalent_parts(part: &Part, parts: &[Part]) -> Vec<Part> {
+    let Some(query) = PartQuery::equivalent_to(part) else { return Vec::new() };
+    let query = PartQuery::parse(&query);
+    parts.iter()
+        .filter(|other| other.part_number != part.part_number && query.matches(other))
+        .cloned()
+        .collect()
+}
+
+//ASCII lowercasing keeps byte offsets the same, so ranges found in the lowercase copy fit `text`
+fn contains(text: &str, needle: &str) -> bool {
+    text.to_ascii_lowercase().contains(needle)
+}
+
+fn find_all(text: &str, needle: &str) -> Vec<(usize, usize)> {
+    text.to_ascii_lowercase()
+        .match_indices(needle)
+        .map(|(start, matched)| (start, start + matched.len()))
+        .collect()
+}
+
+#[cfg(test)]
+fn part(pn: &str, manufacturer: &str, package: &str, value: &str) -> Part {
+    let mut part = Part::new();
+    part.part_number = pn.to_string();
+    part.manufacturer = Some(manufacturer.to_string());
+    part.package = Some(package.to_string());
+    part.value = Some(value.to_string());
+    part
+}
+
+#[test]
+fn test_part_query() {
+    let resistor = part("RC0603FR-0710KL", "Yageo", "0603", "10k");
+    let cap = part("GRM188R71C104KA01D", "Murata", "0603", "100n");
+    let big_resistor = part("RC1206FR-0710KL", "Yageo", "1206", "10k");
+
+    let query = PartQuery::parse("10K package:0603");
+    assert_eq!(query.terms.len(), 2);
+    assert!(query.matches(&resistor));
+    assert!(!query.matches(&cap));
+    assert!(!query.matches(&big_resistor));
+    assert!(PartQuery::parse("mfg:murata").matches(&cap));
+    assert!(!PartQuery::parse("mfg:0603").matches(&cap));
+    //Unknown prefixes and empty filters
+    let unknown = SearchTerm { field: None, attribute: Some("foo".to_string()), text: "bar".to_string(), comparison: None };
+    assert_eq!(PartQuery::parse("Foo:bar").terms[0], unknown);
+    let mut odd_pn = part("FOO:BAR-1", "Acme", "0603", "");
+    assert!(PartQuery::parse("foo:bar").matches(&odd_pn));
+    odd_pn.part_number = "FOO-1".to_string();
+    assert!(!PartQuery::parse("foo:bar").matches(&odd_pn));
+    assert!(PartQuery::parse("value: ").is_empty());
+    assert!(PartQuery::parse("").matches(&cap));
+
+    assert_eq!(query.highlights(SearchField::PartNumber, &resistor.part_number), vec![(11, 14)]);
+    assert_eq!(query.highlights(SearchField::Package, "0603"), vec![(0, 4)]);
+    assert!(query.highlights(SearchField::Tolerance, "10k").is_empty());
+    assert_eq!(PartQuery::parse("06 603").highlights(SearchField::Package, "0603"), vec![(0, 4)]);
+}
+
+#[test]
+fn test_value_search() {
+    let resistor = part("RC0603FR-0710KL", "Yageo", "0603", "10k");
+    let ohms = part("ERJ-3EKF1002V", "Panasonic", "0603", "10000Ω");
+    let big = part("RC0603FR-071ML", "Yageo", "0603", "1M");
+    let cap = part("GRM188R71C104KA01D", "Murata", "0603", "0.1uF");
+
+    assert!(PartQuery::parse("10K").matches(&ohms));
+    assert!(PartQuery::parse("value:10kΩ").matches(&resistor));
+    assert!(PartQuery::parse("100nF").matches(&cap));
+    assert!(!PartQuery::parse("value:10kF").matches(&ohms));
+    let at_least = PartQuery::parse("value:>=10k");
+    assert_eq!(at_least.terms[0].comparison, Some(Comparison::AtLeast));
+    assert!(at_least.matches(&ohms) && at_least.matches(&big));
+    assert!(!PartQuery::parse("value:<1M").matches(&big));
+    assert!(!PartQuery::parse("value:>abc").matches(&resistor));
+    assert!(PartQuery::parse("value:<=").is_empty());
+    assert_eq!(PartQuery::parse("10k").highlights(SearchField::Value, "10000Ω"), vec![(0, 7)]);
+    assert!(PartQuery::parse("value:>1M").highlights(SearchField::Value, "10k").is_empty());
+
+    let mut loose = resistor.clone();
+    loose.part_number = "RC0603JR-0710KL".to_string();
+    loose.tolerance = Some("5%".to_string());
+    let mut tight = ohms.clone();
+    tight.tolerance = Some("±1%".to_string());
+    assert!(PartQuery::parse("tol:<2").matches(&tight));
+    assert!(!PartQuery::parse("tol:<2%").matches(&loose));
+
+    assert_eq!(PartQuery::equivalent_to(&loose).as_deref(), Some("value:=10k package:0603 tol:<=5%"));
+    assert_eq!(PartQuery::equivalent_to(&part("2N7002", "Nexperia", "SOT-23", "")), None);
+    let parts = vec![resistor.clone(), ohms.clone(), big, cap, loose.clone(), tight];
+    let equivalents: Vec<String> = equivalent_parts(&loose, &parts).into_iter().map(|part| part.part_number).collect();
+    assert_eq!(equivalents, vec!["ERJ-3EKF1002V"]);
+}
+
+#[test]
+fn test_category_and_attribute_search() {
+    let mut cap = part("GRM188R71H104KA93D", "Murata", "0603", "100n");
+    cap.category = Some("Passives/Capacitors/Ceramic".to_string());
+    cap.attributes.insert("voltage".to_string(), "50V".to_string());
+    cap.attributes.insert("dielectric".to_string(), "X7R".to_string());
+    let mut resistor = part("RC0603FR-0710KL", "Yageo", "0603", "10k");
+    resistor.category = Some("Passives/Resistors/Chip".to_string());
+
+    assert!(PartQuery::parse("cat:passives").matches(&cap) && PartQuery::parse("cat:passives").matches(&resistor));
+    assert!(!PartQuery::parse("category:resistors").matches(&cap));
+    assert!(PartQuery::parse("ceramic").matches(&cap));
+    let query = PartQuery::parse("Voltage:>=25 dielectric:x7");
+    assert_eq!(query.terms[0].attribute.as_deref(), Some("voltage"));
+    assert_eq!(query.terms[0].comparison, Some(Comparison::AtLeast));
+    assert!(query.matches(&cap));
+    assert!(!query.matches(&resistor));
+    assert!(!PartQuery::parse("voltage:>50V").matches(&cap));
+    assert!(!PartQuery::parse("voltage:25V").matches(&cap));
+    assert!(PartQuery::parse("voltage:>=").is_empty());
+
+    assert_eq!(query.attribute_highlights("voltage", "50V"), vec![(0, 3)]);
+    assert_eq!(query.attribute_highlights("dielectric", "X7R"), vec![(0, 2)]);
+    assert!(query.highlights(SearchField::Value, "50V").is_empty());
+    assert_eq!(PartQuery::parse("cat:cap").highlights(SearchField::Category, "Passives/Capacitors"), vec![(9, 12)]);
+}
@@ -1,7 +1,18 @@
-use crate::db::postgres_init;
+#[cfg(test)]
+use crate::config::DatabaseConfig;
+#[cfg(test)]
+use crate::memory_repository::MemoryRepository;
+#[cfg(test)]
+use crate::repository::BomRepository;
+use postgres::{Client, Transaction};
+use crate::categories::{fetch_categories, validate_part_category};
+use crate::db::Db;
+use crate::eng_value::{normalize_tolerance, normalize_value, validate_tolerance, validate_value};
+use crate::errors::{ShikaError, ShikaResult};
+use std::collections::{BTreeMap, HashMap};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Part {
     pub part_number: String,
     pub total_qty: Option<i64>,
@@ -11,6 +22,12 @@ pub struct Part {
     pub package: Option<String>,
     pub value: Option<String>,
     pub tolerance: Option<String>,
+    //A path like Passives/Resistors/Chip, see `categories`
+    #[serde(default)]
+    pub category: Option<String>,
+    //Checked against the schema of the category
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
 }
 
 impl Part {
@@ -24,23 +41,22 @@ impl Part {
             package: None,
             value: None,
             tolerance: None,
+            category: None,
+            attributes: BTreeMap::new(),
         }
     }
-
-    pub fn new_from_pn(pn: &str) -> Part {
-        let part = fetch_single_part(pn);
-        part
-    }
 }
 
+#[derive(Serialize, Clone)]
 pub struct PartStorage {
+    #[allow(dead_code)]
     pub part_number: String,
     pub location: String,
     pub quantity: i32
 }
 
-fn new_part_from_sql(row: postgres::Row) -> Part {
-    let new_part = Part {
+pub(crate) fn new_part_from_sql(row: &postgres::Row) -> Part {
+    Part {
         part_number: row.try_get("partnumber").unwrap_or("".to_string()), //this cannot be null
         total_qty: Some(row.try_get("total_qty").unwrap_or(0)),
         manufacturer: Some(row.try_get("manufacturer").unwrap_or("".to_string())),
@@ -48,55 +64,111 @@ fn new_part_from_sql(row: postgres::Row) -> Part {
         label: Some(row.try_get("label").unwrap_or("".to_string())),
         package: Some(row.try_get("package").unwrap_or("".to_string())),
         value: Some(row.try_get("value").unwrap_or("".to_string())),
-        tolerance: Some(row.try_get("tolerance").unwrap_or("".to_string()))
+        tolerance: Some(row.try_get("tolerance").unwrap_or("".to_string())),
+        category: row.try_get("category").unwrap_or(None),
+        //Filled in by `attach_attributes` where they are needed
+        attributes: BTreeMap::new(),
+    }
+}
+
+//One query for the attributes of every part in `parts`
+fn attach_attributes(client: &mut Client, parts: &mut [Part]) -> ShikaResult<()> {
+    let rows = match parts {
+        [part] => client.query("SELECT * FROM part_attributes WHERE partnumber = $1", &[&part.part_number])?,
+        _ => client.query("SELECT * FROM part_attributes", &[])?,
     };
-    return new_part;
+    let mut by_pn: HashMap<String, &mut Part> = parts.iter_mut().map(|part| (part.part_number.clone(), part)).collect();
+    for row in rows {
+        let pn: String = row.get("partnumber");
+        if let Some(part) = by_pn.get_mut(&pn) {
+            part.attributes.insert(row.get("name"), row.get("value"));
+        }
+    }
+    Ok(())
+}
+
+//The attributes are replaced as a whole
+fn save_attributes(transaction: &mut Transaction, part: &Part) -> ShikaResult<()> {
+    transaction.execute("DELETE FROM part_attributes WHERE partnumber = $1", &[&part.part_number])?;
+    for (name, value) in &part.attributes {
+        transaction.execute("INSERT INTO part_attributes (partnumber, name, value) VALUES ($1, $2, $3)",
+                            &[&part.part_number, name, value])?;
+    }
+    Ok(())
 }
 
-pub fn fetch_all_parts() -> Vec<Part> {
-    let mut client = postgres_init();
-    let rows = client.query("select * from big_part_view", &[]).unwrap();
+pub fn fetch_all_parts(db: &mut Db) -> ShikaResult<Vec<Part>> {
+    let client = db.client()?;
+    let rows = client.query("select * from big_part_view", &[])?;
     let mut parts: Vec<Part> = Vec::new();
     for row in rows {
-        let part = new_part_from_sql(row);
+        let part = new_part_from_sql(&row);
         parts.push(part);
     };
-    return parts;
+    attach_attributes(client, &mut parts)?;
+    Ok(parts)
 }
 
-pub fn fetch_single_part(pn: &str) -> Part {
-    let mut client = postgres_init();
-    let row = client.query_one("select * from big_part_view where partnumber = $1", &[&pn]).unwrap();
-    let part = new_part_from_sql(row);
-    part
+pub fn fetch_single_part(db: &mut Db, pn: &str) -> ShikaResult<Part> {
+    let client = db.client()?;
+    let row = client.query_opt("select * from big_part_view where partnumber = $1", &[&pn])?
+        .ok_or_else(|| ShikaError::NotFound(format!("Part number {pn} not found")))?;
+    let mut part = new_part_from_sql(&row);
+    attach_attributes(client, std::slice::from_mut(&mut part))?;
+    Ok(part)
 }
 
-//Does not include quantity, this is just part information
-pub fn add_new_part(new_part: &Part) {
-    if new_part.part_number == "".to_string() {
-        return;
+/// Checks a part before it is written, shared by every repository backend.
+pub fn validate_part(part: &Part) -> ShikaResult<()> {
+    if part.part_number.trim().is_empty() {
+        return Err(ShikaError::Validation("Part number cannot be empty".to_string()));
+    }
+    if let Some(value) = &part.value {
+        validate_value(value)?;
+    }
+    if let Some(tolerance) = &part.tolerance {
+        validate_tolerance(tolerance)?;
     }
-    let mut client = postgres_init();
-    client.execute("INSERT INTO parts (partnumber, manufacturer, label, package, value, tolerance) VALUES ($1, $2, $3, $4, $5, $6)",
+    Ok(())
+}
+
+/// Rewrites the value and tolerance the way they're stored, `4k7` becomes `4.7k` and `±5 %`
+/// becomes `5%`. Done where parts are entered so imports keep the text they were given.
+pub fn normalize_part(part: &mut Part) {
+    part.value = part.value.as_deref().map(normalize_value);
+    part.tolerance = part.tolerance.as_deref().map(normalize_tolerance);
+}
+
+//Does not include quantity, this is just part information
+pub fn add_new_part(db: &mut Db, new_part: &Part) -> ShikaResult<()> {
+    validate_part(new_part)?;
+    validate_part_category(new_part, &fetch_categories(db)?)?;
+    let mut transaction = db.client()?.transaction()?;
+    transaction.execute("INSERT INTO parts (partnumber, manufacturer, description, label, package, value, tolerance, category) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
                    &[
                        &new_part.part_number,
                        &new_part.manufacturer,
-                       &new_part.package,
+                       &new_part.description,
                        &new_part.label,
+                       &new_part.package,
                        &new_part.value,
-                       &new_part.tolerance
+                       &new_part.tolerance,
+                       &new_part.category
                    ],
-    ).unwrap();
+    ).map_err(|e| ShikaError::on_unique_violation(e, || {
+        format!("Part number {} already exists", new_part.part_number)
+    }))?;
+    save_attributes(&mut transaction, new_part)?;
+    transaction.commit()?;
     //TODO: Add initial quantity to part_storage
+    Ok(())
 }
 
-pub fn modify_part(inpart: &Part) {
-    if inpart.part_number == "".to_string() {
-        println!("Part number cannot be empty!");
-        return;
-    }
-    let mut client = postgres_init();
-    client.execute("UPDATE parts SET manufacturer = $1, description = $2, label = $3, package = $4, value = $5, tolerance = $6 WHERE partnumber = $7",
+pub fn modify_part(db: &mut Db, inpart: &Part) -> ShikaResult<()> {
+    validate_part(inpart)?;
+    validate_part_category(inpart, &fetch_categories(db)?)?;
+    let mut transaction = db.client()?.transaction()?;
+    let updated = transaction.execute("UPDATE parts SET manufacturer = $1, description = $2, label = $3, package = $4, value = $5, tolerance = $6, category = $7 WHERE partnumber = $8",
                    &[
                        &inpart.manufacturer,
                        &inpart.description,
@@ -104,16 +176,21 @@ pub fn modify_part(inpart: &Part) {
                        &inpart.package,
                        &inpart.value,
                        &inpart.tolerance,
+                       &inpart.category,
                        &inpart.part_number
                    ],
-    ).unwrap();
-    let _ = client.close();
-    return;
+    )?;
+    if updated == 0 {
+        return Err(ShikaError::NotFound(format!("Part number {} not found", inpart.part_number)));
+    }
+    save_attributes(&mut transaction, inpart)?;
+    transaction.commit()?;
+    Ok(())
 }
 
-pub fn fetch_part_storage_data(pn: &str) -> Vec<PartStorage> {
-    let mut client = postgres_init();
-    let rows = client.query("select * from part_storage_view where partnumber = $1", &[&pn]).unwrap();
+pub fn fetch_part_storage_data(db: &mut Db, pn: &str) -> ShikaResult<Vec<PartStorage>> {
+    let client = db.client()?;
+    let rows = client.query("select * from part_storage_view where partnumber = $1", &[&pn])?;
     let mut part_stores: Vec<PartStorage> = Vec::new();
     for row in rows {
         let part = PartStorage {
@@ -123,28 +200,69 @@ pub fn fetch_part_storage_data(pn: &str) -> Vec<PartStorage> {
         };
         part_stores.push(part);
     };
-    return part_stores;
+    Ok(part_stores)
 }
 
 #[test]
 fn test_fetch_all_parts() {
-    let parts = fetch_all_parts();
+    let mut repo = MemoryRepository::with_sample_data();
+    let parts = repo.fetch_all_parts().unwrap();
     assert!(!parts.is_empty());
 }
 
 #[test]
 fn test_fetch_single_part() {
-    let part = fetch_single_part("HFW1V2210H4R7K");
+    let mut repo = MemoryRepository::with_sample_data();
+    let part = repo.fetch_single_part("HFW1V2210H4R7K").unwrap();
     assert_eq!(part.part_number, "HFW1V2210H4R7K");
     assert!(part.total_qty.unwrap() > 0);
-
+    assert!(matches!(repo.fetch_single_part("NOPE"), Err(ShikaError::NotFound(_))));
 }
 
 #[test]
 fn test_fetch_part_storage_data() {
-    let mut part_stores = fetch_part_storage_data("25SVPF47M");
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut part_stores = repo.fetch_part_storage_data("25SVPF47M").unwrap();
     assert!(part_stores.is_empty());
-    part_stores = fetch_part_storage_data("HFW1V2210H4R7K");
+    part_stores = repo.fetch_part_storage_data("HFW1V2210H4R7K").unwrap();
     assert!(!part_stores.is_empty());
     assert!(part_stores[0].quantity > 0);
 }
+
+#[test]
+fn test_add_new_part_rejects_empty_part_number() {
+    //Validation happens before connecting, so this doesn't need a database
+    let mut db = Db::new(DatabaseConfig::default());
+    let result = add_new_part(&mut db, &Part::new());
+    assert!(matches!(result, Err(ShikaError::Validation(_))));
+}
+
+#[test]
+fn test_add_duplicate_part() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut part = Part::new();
+    part.part_number = "25SVPF47M".to_string();
+    match repo.add_new_part(&part) {
+        Err(ShikaError::ConstraintViolation(msg)) => assert_eq!(msg, "Part number 25SVPF47M already exists"),
+        _ => panic!("expected a constraint violation"),
+    }
+}
+
+#[test]
+fn test_validate_and_normalize_part() {
+    let mut part = Part::new();
+    part.part_number = "RC0603FR-074K7L".to_string();
+    part.value = Some("4k7".to_string());
+    part.tolerance = Some("±1 %".to_string());
+    assert!(validate_part(&part).is_ok());
+    normalize_part(&mut part);
+    assert_eq!(part.value.as_deref(), Some("4.7k"));
+    assert_eq!(part.tolerance.as_deref(), Some("1%"));
+
+    let mut repo = MemoryRepository::with_sample_data();
+    part.value = Some("4.7.k".to_string());
+    assert!(matches!(repo.add_new_part(&part), Err(ShikaError::Validation(_))));
+    part.value = Some("4.7k".to_string());
+    part.tolerance = Some("one percent".to_string());
+    assert!(matches!(repo.add_new_part(&part), Err(ShikaError::Validation(_))));
+}
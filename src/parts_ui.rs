@@ -4,19 +4,38 @@ use ratatui::{
     prelude::{Color, Line, Modifier, Span, Style, Stylize},
     style::palette::tailwind,
     widgets::{
-        Block, Borders, Clear, Paragraph, Row, Scrollbar,
+        Block, Borders, Cell, Clear, Paragraph, Row, Scrollbar,
         ScrollbarOrientation, ScrollbarState, Table, Wrap
     }
 };
 use crate::app::App;
+use crate::categories::display_path;
+use crate::part_search::{PartQuery, SearchField};
 use crate::parts_view;
 //TODO: this should go into like a utils file or something
 use crate::ui::centered_rect;
 
 pub fn render_main_parts_panel(f: &mut Frame, app: &App, rect: Rect) {
-    let rows = create_parts_table_rows(&app);
+    let view = &app.parts_view;
+    let searching = view.parts_sub_state == parts_view::PartsSubState::Search;
+    //The search bar stays up while a search narrows the table
+    let rect = if searching || !view.search_text.is_empty() {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Fill(1)])
+            .split(rect);
+        render_search_bar(f, app, chunks[0]);
+        chunks[1]
+    } else {
+        rect
+    };
+    let title = match view.search_query.is_empty() {
+        true => "Table".to_string(),
+        false => format!("Table: {} of {} parts", view.part_data.len(), view.all_part_data.len()),
+    };
+    let rows = create_parts_table_rows(app);
 // Columns widths are constrained in the same way as Layout...
-    let widths = [
+    let mut widths = vec![
         Constraint::Length(20),
         Constraint::Length(11),
         Constraint::Length(20),
@@ -26,6 +45,12 @@ pub fn render_main_parts_panel(f: &mut Frame, app: &App, rect: Rect) {
         Constraint::Length(20),
 
     ];
+    if view.show_attributes {
+        widths.push(Constraint::Length(28));
+        widths.extend(view.attribute_columns.iter().map(|_| Constraint::Length(12)));
+    }
+    let columns = view.columns();
+    let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
     let header_style = Style::default()
         .fg(tailwind::SLATE.c200)
         .bg(tailwind::BLUE.c900);
@@ -36,8 +61,7 @@ pub fn render_main_parts_panel(f: &mut Frame, app: &App, rect: Rect) {
         .column_spacing(1)
         .style(Style::new().blue())
         .header(
-            //TODO: Bring this in from parts.rs
-            Row::new(vec!["Part Number", "Total Qty", "Manufacturer", "Package", "Label", "Value", "Tolerance"])
+            Row::new(view.part_sort.headers(&columns))
                 .style(header_style)
                 // To add space between the header and the rest of the rows, specify the margin
                 .bottom_margin(1),
@@ -45,7 +69,7 @@ pub fn render_main_parts_panel(f: &mut Frame, app: &App, rect: Rect) {
         // It has an optional footer, which is simply a Row always visible at the bottom.
         .footer(Row::new(vec!["Refreshed last at: 2021-09-01 12:34:56"]))
         // As any other widget, a Table can be wrapped in a Block.
-        .block(Block::default().title("Table"))
+        .block(Block::default().title(title))
         // The selected row and its content can also be styled.
         .row_highlight_style(selected_style)
         // ...and potentially show a symbol in front of the selection.
@@ -55,9 +79,26 @@ pub fn render_main_parts_panel(f: &mut Frame, app: &App, rect: Rect) {
     f.render_stateful_widget(table, rect, &mut app.parts_view.part_table_state.clone());
 }
 
+fn render_search_bar(f: &mut Frame, app: &App, rect: Rect) {
+    let searching = app.parts_view.parts_sub_state == parts_view::PartsSubState::Search;
+    let search_b = Block::default()
+        .title("Search (/) - filter a column with pn: mfg: value: package: label: desc: tol: cat: or an attribute like voltage:>=50V")
+        .title_bottom(match searching {
+            true => "(enter) keep / (esc) clear",
+            false => "(/) edit / (esc) clear",
+        })
+        .borders(Borders::ALL)
+        .border_style(match searching {
+            true => Style::default().fg(tailwind::EMERALD.c400),
+            false => Style::default().fg(tailwind::SLATE.c500),
+        });
+    let search_t = Paragraph::new(app.parts_view.search_text.clone()).block(search_b);
+    f.render_widget(search_t, rect);
+}
+
 //TODO: This panel is missing the label field
 pub fn render_details_panel(f: &mut Frame, app: &App, panel: Rect) {
-    let clear = Clear::default();
+    let clear = Clear;
     let parent_block = Block::default()
         .title("Details")
         .borders(Borders::ALL);
@@ -164,16 +205,21 @@ pub fn render_new_part_popup(f: &mut Frame, app: &App) {
         .borders(Borders::ALL)
         .style(Style::default().bg(Color::Black));
 
-    let area = centered_rect(35, 60, f.area());
-    let clear = Clear::default();
+    let area = centered_rect(60, 60, f.area());
+    let clear = Clear;
     f.render_widget(clear, area);
     f.render_widget(popup_block, area);
     // ANCHOR_END: editing_popup
 
+    //The part fields on the left, its category and attributes on the right
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(1)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(area);
     // ANCHOR: popup_layout
     let popup_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .margin(1)
         .constraints([
             Constraint::Percentage(16),
             Constraint::Percentage(16),
@@ -183,7 +229,8 @@ pub fn render_new_part_popup(f: &mut Frame, app: &App) {
             Constraint::Percentage(16),
             Constraint::Percentage(4),
         ])
-        .split(area);
+        .split(columns[0]);
+    render_category_fields(f, app, columns[1]);
     let mut pn_b = Block::default().title("Part Number").borders(Borders::ALL);
     let mut mfg_b = Block::default().title("Manufacturer").borders(Borders::ALL);
     let mut pkg_b = Block::default().title("Package").borders(Borders::ALL);
@@ -214,6 +261,8 @@ pub fn render_new_part_popup(f: &mut Frame, app: &App) {
         parts_view::CurrentlyEditingPart::Tolerance => {
             tol_b = tol_b.style(highlighted_style);
         }
+        //Drawn by `render_category_fields`
+        parts_view::CurrentlyEditingPart::Category | parts_view::CurrentlyEditingPart::Attribute(_) => {}
     }
     let pn_t = Paragraph::new(app.parts_view.part_text.part_number.clone()).block(pn_b);
     let mfg_t = Paragraph::new(app.parts_view.part_text.manufacturer.clone()).block(mfg_b);
@@ -234,6 +283,34 @@ pub fn render_new_part_popup(f: &mut Frame, app: &App) {
     f.render_widget(foot, popup_chunks[6]);
 }
 
+fn render_category_fields(f: &mut Frame, app: &App, rect: Rect) {
+    let highlighted_style = Style::default().fg(Color::White).bg(Color::Blue);
+    let text = &app.parts_view.part_text;
+    let mut constraints = vec![Constraint::Length(3); text.attributes.len() + 1];
+    constraints.push(Constraint::Fill(1));
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(rect);
+
+    let mut category_b = Block::default().title("Category (←/→ to pick)").borders(Borders::ALL);
+    if matches!(app.parts_view.currently_editing_part, parts_view::CurrentlyEditingPart::Category) {
+        category_b = category_b.style(highlighted_style);
+    }
+    let category = match text.category.is_empty() {
+        true => "None".to_string(),
+        false => display_path(&text.category),
+    };
+    f.render_widget(Paragraph::new(category).block(category_b), chunks[0]);
+    for (idx, (name, value)) in text.attributes.iter().enumerate() {
+        let mut attribute_b = Block::default().title(name.as_str()).borders(Borders::ALL);
+        if matches!(app.parts_view.currently_editing_part, parts_view::CurrentlyEditingPart::Attribute(editing) if editing == idx) {
+            attribute_b = attribute_b.style(highlighted_style);
+        }
+        f.render_widget(Paragraph::new(value.as_str()).block(attribute_b), chunks[idx + 1]);
+    }
+}
+
 pub(crate) fn side_panel_rect(f: &mut Frame) -> Rect {
     let layouts = Layout::default()
         .direction(Direction::Horizontal)
@@ -242,12 +319,12 @@ pub(crate) fn side_panel_rect(f: &mut Frame) -> Rect {
             Constraint::Percentage(30),
         ])
         .split(f.area());
-    return layouts[1]
+    layouts[1]
 }
 
 fn create_part_storage_table<'a>(app: &App, block: Block<'a>) -> Table<'a> {
     //Storage table
-    let rows = create_storage_table_rows(&app);
+    let rows = create_storage_table_rows(app);
 // Columns widths are constrained in the same way as Layout...
     let widths = [
         Constraint::Percentage(50),
@@ -266,7 +343,7 @@ fn create_part_storage_table<'a>(app: &App, block: Block<'a>) -> Table<'a> {
                 .bottom_margin(1),
         )
         .block(block);
-    return storage_ta;
+    storage_ta
 }
 
 fn create_storage_table_rows(app: &App) -> Vec<Row<'static>> {
@@ -279,23 +356,49 @@ fn create_storage_table_rows(app: &App) -> Vec<Row<'static>> {
         ]);
         rows.push(row);
     }
-    return rows;
+    rows
 }
 
 pub fn create_parts_table_rows(app: &App) -> Vec<Row<'static>> {
-    let part_data = &app.parts_view.part_data;
+    let view = &app.parts_view;
+    let query = &view.search_query;
     let mut rows: Vec<Row> = Vec::new();
-    for part in part_data {
-        let row = Row::new(vec![
-            part.part_number.clone(),
-            part.total_qty.unwrap_or(0).to_string(),
-            part.manufacturer.clone().unwrap_or("".to_string()),
-            part.package.clone().unwrap_or("".to_string()),
-            part.label.clone().unwrap_or("".to_string()),
-            part.value.clone().unwrap_or("".to_string()),
-            part.tolerance.clone().unwrap_or("".to_string()),
-        ]);
-        rows.push(row);
+    for part in &view.part_data {
+        let mut cells = vec![
+            highlighted_cell(query, SearchField::PartNumber, &part.part_number),
+            Cell::from(part.total_qty.unwrap_or(0).to_string()),
+            highlighted_cell(query, SearchField::Manufacturer, SearchField::Manufacturer.text(part)),
+            highlighted_cell(query, SearchField::Package, SearchField::Package.text(part)),
+            highlighted_cell(query, SearchField::Label, SearchField::Label.text(part)),
+            highlighted_cell(query, SearchField::Value, SearchField::Value.text(part)),
+            highlighted_cell(query, SearchField::Tolerance, SearchField::Tolerance.text(part)),
+        ];
+        if view.show_attributes {
+            cells.push(highlighted_cell(query, SearchField::Category, SearchField::Category.text(part)));
+            for name in &view.attribute_columns {
+                let text = part.attributes.get(name).map(String::as_str).unwrap_or("");
+                cells.push(styled_cell(text, query.attribute_highlights(name, text)));
+            }
+        }
+        rows.push(Row::new(cells));
+    }
+    rows
+}
+
+fn highlighted_cell(query: &PartQuery, field: SearchField, text: &str) -> Cell<'static> {
+    styled_cell(text, query.highlights(field, text))
+}
+
+//The parts of `text` the search matched are drawn in bold amber
+fn styled_cell(text: &str, highlights: Vec<(usize, usize)>) -> Cell<'static> {
+    let match_style = Style::default().fg(tailwind::AMBER.c400).add_modifier(Modifier::BOLD);
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for (start, end) in highlights {
+        spans.push(Span::raw(text[last..start].to_string()));
+        spans.push(Span::styled(text[start..end].to_string(), match_style));
+        last = end;
     }
-    return rows;
+    spans.push(Span::raw(text[last..].to_string()));
+    Cell::from(Line::from(spans))
 }
\ No newline at end of file
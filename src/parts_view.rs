@@ -1,23 +1,23 @@
 use crossterm::event::KeyCode;
 use ratatui::widgets::TableState;
+use crate::repository::BomRepository;
+use crate::categories::{self, normalize_part_attributes, Category};
+use crate::errors::{ShikaError, ShikaResult};
+#[cfg(test)]
+use crate::memory_repository::MemoryRepository;
 use crate::parts;
-use crate::parts::Part;
+use crate::parts::{normalize_part, Part};
+use crate::part_search::PartQuery;
+use crate::table_sort::SortState;
 use crate::utils::ScrollBarInfo;
 
+#[derive(PartialEq)]
 pub enum PartsSubState {
     Main,
     NewPart,
     EditPart,
-}
-impl PartialEq for PartsSubState {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (PartsSubState::Main, PartsSubState::Main) => true,
-            (PartsSubState::NewPart, PartsSubState::NewPart) => true,
-            (PartsSubState::EditPart, PartsSubState::EditPart) => true,
-            _ => false,
-        }
-    }
+    //Typing into the search bar opened with `/`
+    Search,
 }
 
 pub struct PartText {
@@ -29,6 +29,10 @@ pub struct PartText {
     pub value: String,
     pub tolerance: String,
     pub description: String,
+    //Empty for no category
+    pub category: String,
+    //The attributes of the category as (name, text), see `apply_schema`
+    pub attributes: Vec<(String, String)>,
 }
 
 impl PartText {
@@ -42,7 +46,9 @@ impl PartText {
             label: "".parse().unwrap(),
             value: "".parse().unwrap(),
             tolerance: "".parse().unwrap(),
-            description: "".parse().unwrap()
+            description: "".parse().unwrap(),
+            category: String::new(),
+            attributes: Vec::new(),
         }
     }
     pub(crate) fn clear(&mut self) {
@@ -54,6 +60,8 @@ impl PartText {
         self.value.clear();
         self.tolerance.clear();
         self.description.clear();
+        self.category.clear();
+        self.attributes.clear();
     }
 
     pub(crate) fn copy_from_db_part(&mut self, part: &Part) {
@@ -65,6 +73,8 @@ impl PartText {
         self.value = part.value.clone().unwrap_or("".to_string());
         self.tolerance = part.tolerance.clone().unwrap_or("".to_string());
         self.description = part.description.clone().unwrap_or("".to_string());
+        self.category = part.category.clone().unwrap_or_default();
+        self.attributes = part.attributes.iter().map(|(name, text)| (name.clone(), text.clone())).collect();
     }
 
     pub(crate) fn copy_to_db_part(&self, part: &mut Part) {
@@ -76,9 +86,50 @@ impl PartText {
         part.value = Some(self.value.clone());
         part.tolerance = Some(self.tolerance.clone());
         part.description = Some(self.description.clone());
+        part.category = Some(self.category.clone()).filter(|category| !category.is_empty());
+        part.attributes = self.attributes.iter().cloned().collect();
+    }
+
+    /// Lays the attribute fields out the way the category's schema lists them. Text typed so
+    /// far is kept, attributes the schema doesn't have are kept at the end so they can be cleared.
+    pub(crate) fn apply_schema(&mut self, categories: &[Category]) {
+        let mut attributes: Vec<(String, String)> = categories::schema(categories, &self.category).into_iter()
+            .map(|attribute| {
+                let text = self.attributes.iter().find(|(name, _)| *name == attribute.name).map(|(_, text)| text.clone());
+                (attribute.name, text.unwrap_or_default())
+            })
+            .collect();
+        for (name, text) in &self.attributes {
+            if !text.is_empty() && !attributes.iter().any(|(other, _)| other == name) {
+                attributes.push((name.clone(), text.clone()));
+            }
+        }
+        self.attributes = attributes;
     }
 }
 
+/// The parts table columns, `part_cell` gives their text. Showing attributes adds a category
+/// column and one for each attribute after these.
+pub const PART_COLUMNS: [&str; 7] = ["Part Number", "Total Qty", "Manufacturer", "Package", "Label", "Value", "Tolerance"];
+
+pub fn part_cell(part: &Part, column: usize, attribute_columns: &[String]) -> String {
+    let text = match column {
+        0 => return part.part_number.clone(),
+        1 => return part.total_qty.unwrap_or(0).to_string(),
+        2 => &part.manufacturer,
+        3 => &part.package,
+        4 => &part.label,
+        5 => &part.value,
+        6 => &part.tolerance,
+        7 => &part.category,
+        _ => return attribute_columns.get(column - PART_COLUMNS.len() - 1)
+            .and_then(|name| part.attributes.get(name))
+            .cloned()
+            .unwrap_or_default(),
+    };
+    text.clone().unwrap_or_default()
+}
+
 pub enum CurrentlyEditingPart {
     PartNumber,
     Manufacturer,
@@ -86,13 +137,27 @@ pub enum CurrentlyEditingPart {
     Label,
     Value,
     Tolerance,
+    //Picked with Left/Right from the known categories
+    Category,
+    //An index into `PartText::attributes`
+    Attribute(usize),
 }
 
 pub struct PartsView {
     pub parts_sub_state: PartsSubState,
     pub currently_editing_part: CurrentlyEditingPart,
     pub part_text: PartText,
+    //The rows shown in the table, `all_part_data` narrowed down by the search
     pub part_data: Vec<Part>,
+    pub all_part_data: Vec<Part>,
+    pub search_text: String,
+    pub search_query: PartQuery,
+    pub part_sort: SortState,
+    pub categories: Vec<Category>,
+    //Adds the category and attribute columns to the table, toggled with `a`
+    pub show_attributes: bool,
+    //The attributes the shown parts have, in schema order
+    pub attribute_columns: Vec<String>,
     pub part_storage_data: Vec<parts::PartStorage>,
     pub show_details: bool,
     pub part_scroll_info: ScrollBarInfo,
@@ -107,86 +172,156 @@ impl PartsView {
             currently_editing_part: CurrentlyEditingPart::PartNumber,
             part_text: PartText::new(),
             part_data: Vec::new(),
+            all_part_data: Vec::new(),
+            search_text: String::new(),
+            search_query: PartQuery::default(),
+            part_sort: SortState::default(),
+            categories: Vec::new(),
+            show_attributes: false,
+            attribute_columns: Vec::new(),
             part_storage_data: Vec::new(),
             show_details: false,
             part_scroll_info: ScrollBarInfo::new(),
             part_table_state: TableState::default(),
         }
     }
-    pub fn refresh_part_data(&mut self) {
-        self.part_data = parts::fetch_all_parts();
+    pub fn refresh_part_data(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        self.all_part_data = repo.fetch_all_parts()?;
+        self.categories = repo.fetch_categories()?;
+        self.apply_search();
+        Ok(())
+    }
+
+    /// The table headers, with the category and attribute columns when they are shown.
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns: Vec<String> = PART_COLUMNS.iter().map(|column| column.to_string()).collect();
+        if self.show_attributes {
+            columns.push("Category".to_string());
+            columns.extend(self.attribute_columns.iter().cloned());
+        }
+        columns
+    }
+
+    /// Narrows the table down to the parts matching `search_text` and sorts it, keeping the
+    /// selected part selected when it is still shown.
+    pub fn apply_search(&mut self) {
+        let selected_pn = self.part_table_state.selected()
+            .and_then(|selected| self.part_data.get(selected))
+            .map(|part| part.part_number.clone());
+        self.search_query = PartQuery::parse(&self.search_text);
+        self.part_data = self.all_part_data.iter()
+            .filter(|part| self.search_query.matches(part))
+            .cloned()
+            .collect();
+        self.attribute_columns = match self.show_attributes {
+            true => categories::attribute_columns(&self.categories, &self.part_data),
+            false => Vec::new(),
+        };
+        //The sorted column can go away with the attribute columns
+        if self.part_sort.column.is_some_and(|column| column >= self.columns().len()) {
+            self.part_sort = SortState::default();
+        }
+        let attribute_columns = &self.attribute_columns;
+        self.part_sort.sort(&mut self.part_data, |part, column| part_cell(part, column, attribute_columns));
+        //A selection that was filtered out moves to the first row
+        let selected = match selected_pn {
+            _ if self.part_data.is_empty() => None,
+            Some(pn) => Some(self.part_data.iter().position(|part| part.part_number == pn).unwrap_or(0)),
+            None => None,
+        };
+        self.part_table_state.select(selected);
     }
     pub fn show_details(&mut self) {
         self.show_details = !self.show_details;
     }
 
-    pub fn update_selected_part(&mut self) {
-        match self.part_table_state.selected() {
-            Some(selected) => {
-                let selected_pn = self.part_data[selected].part_number.clone();
-                let fetched_part = parts::fetch_single_part(&selected_pn);
-                self.part_text.copy_from_db_part(&fetched_part);
-            }
-            None => {}
+    pub fn update_selected_part(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        if let Some(part) = self.part_table_state.selected().and_then(|selected| self.part_data.get(selected)) {
+            let fetched_part = repo.fetch_single_part(&part.part_number)?;
+            self.part_text.copy_from_db_part(&fetched_part);
         }
+        Ok(())
     }
 
-    pub fn handle_main_keys(&mut self, key: KeyCode) {
+    pub fn handle_main_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
+            KeyCode::Char('/') => {
+                self.parts_sub_state = PartsSubState::Search;
+            }
+            KeyCode::Esc => {
+                self.search_text.clear();
+                self.apply_search();
+            }
+            KeyCode::Char('o') => {
+                self.part_sort.cycle(self.columns().len());
+                self.apply_search();
+            }
+            KeyCode::Char('a') => {
+                self.show_attributes = !self.show_attributes;
+                self.apply_search();
+            }
+            KeyCode::Char('f') => {
+                //Searches for stand-ins for the selected part, the part itself stays in the list
+                if let Some(part) = self.part_table_state.selected().and_then(|selected| self.part_data.get(selected)) {
+                    self.search_text = PartQuery::equivalent_to(part).ok_or_else(|| {
+                        ShikaError::Validation(format!("{} has no value to find equivalents for", part.part_number))
+                    })?;
+                    self.apply_search();
+                }
+            }
             KeyCode::Char('n') => {
+                self.categories = repo.fetch_categories()?;
                 self.parts_sub_state = PartsSubState::NewPart;
                 self.part_text.clear();
                 self.currently_editing_part = CurrentlyEditingPart::PartNumber;
             }
             KeyCode::Char('r') => {
-                self.refresh_part_data()
+                self.refresh_part_data(repo)?;
             }
             KeyCode::Char('e') => {
-                match self.part_table_state.selected() {
-                    Some(selected) => {
-                        self.part_text.clear();
-                        //Fill in part info
-                        let selected_pn = self.part_data[selected].part_number.clone();
-                        let fetched_part = parts::fetch_single_part(&selected_pn);
-                        self.part_text.copy_from_db_part(&fetched_part);
-
-                        self.parts_sub_state = PartsSubState::EditPart;
-                        //Can't edit part number
-                        self.currently_editing_part = CurrentlyEditingPart::Manufacturer;
-                    }
-                    None => {}
+                if let Some(selected) = self.part_table_state.selected() {
+                    self.part_text.clear();
+                    //Fill in part info
+                    let selected_pn = self.part_data[selected].part_number.clone();
+                    let fetched_part = repo.fetch_single_part(&selected_pn)?;
+                    self.part_text.copy_from_db_part(&fetched_part);
+                    self.categories = repo.fetch_categories()?;
+                    self.part_text.apply_schema(&self.categories);
+
+                    self.parts_sub_state = PartsSubState::EditPart;
+                    //Can't edit part number
+                    self.currently_editing_part = CurrentlyEditingPart::Manufacturer;
                 }
             }
             KeyCode::Char('d') => {
-                match self.part_table_state.selected() {
-                    Some(selected) => {
-                        self.part_text.clear();
-                        //Fill in part info for side panel
-                        //TODO: Make part text a big boi
-                        let selected_pn = self.part_data[selected].part_number.clone();
-                        let fetched_part = parts::fetch_single_part(&selected_pn);
-                        self.part_text.copy_from_db_part(&fetched_part);
-                        self.part_storage_data = parts::fetch_part_storage_data(&selected_pn);
-                        self.part_scroll_info.clear();
-                        //Only show if we have data to display
-                        self.show_details();
-                    }
-                    None => {}
+                if let Some(selected) = self.part_table_state.selected() {
+                    self.part_text.clear();
+                    //Fill in part info for side panel
+                    //TODO: Make part text a big boi
+                    let selected_pn = self.part_data[selected].part_number.clone();
+                    let fetched_part = repo.fetch_single_part(&selected_pn)?;
+                    self.part_text.copy_from_db_part(&fetched_part);
+                    self.part_storage_data = repo.fetch_part_storage_data(&selected_pn)?;
+                    self.part_scroll_info.clear();
+                    //Only show if we have data to display
+                    self.show_details();
                 }
             }
             KeyCode::Down => {
                 if !self.show_details {
                     match self.part_table_state.selected() {
                         Some(selected) => {
-                            if selected < self.part_data.len() - 1 {
+                            if selected + 1 < self.part_data.len() {
                                 self.part_table_state.select(Some(selected + 1));
                             }
                         }
                         None => {
-                            self.part_table_state.select(Some(0));
+                            if !self.part_data.is_empty() {
+                                self.part_table_state.select(Some(0));
+                            }
                         }
                     }
-                    self.update_selected_part();
+                    self.update_selected_part(repo)?;
                 }
                 else {
                     //TODO: scroll logic here
@@ -206,7 +341,7 @@ impl PartsView {
                             self.part_table_state.select(Some(0));
                         }
                     }
-                    self.update_selected_part();
+                    self.update_selected_part(repo)?;
                 }
                 else {
                     if self.part_scroll_info.scroll_position > 0 {
@@ -217,14 +352,66 @@ impl PartsView {
             }
             _ => {}
         }
+        Ok(())
     } //end handle_main_keys
 
-    pub fn handle_new_part_keys(&mut self, key: KeyCode) {
+    pub fn handle_search_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            //Enter keeps the search, Esc drops it
+            KeyCode::Enter => {
+                self.parts_sub_state = PartsSubState::Main;
+            }
+            KeyCode::Esc => {
+                self.search_text.clear();
+                self.parts_sub_state = PartsSubState::Main;
+            }
+            KeyCode::Char(character) => {
+                self.search_text.push(character);
+            }
+            KeyCode::Backspace => {
+                self.search_text.pop();
+            }
+            KeyCode::Up | KeyCode::Down => {
+                return self.handle_main_keys(repo, key);
+            }
+            _ => {}
+        }
+        self.apply_search();
+        self.update_selected_part(repo)
+    }
+
+    //Tab order after the tolerance: the category, then each of its attributes
+    fn next_category_field(&self) -> CurrentlyEditingPart {
+        let next = match self.currently_editing_part {
+            CurrentlyEditingPart::Category => 0,
+            CurrentlyEditingPart::Attribute(idx) => idx + 1,
+            _ => return CurrentlyEditingPart::Category,
+        };
+        match next < self.part_text.attributes.len() {
+            true => CurrentlyEditingPart::Attribute(next),
+            false => CurrentlyEditingPart::Manufacturer,
+        }
+    }
+
+    //Steps through no category and then every category, parents before their children
+    fn cycle_category(&mut self, forward: bool) {
+        let current = self.categories.iter().position(|category| category.path == self.part_text.category);
+        let next = match (current, forward) {
+            (None, true) => Some(0),
+            (None, false) => self.categories.len().checked_sub(1),
+            (Some(idx), true) => Some(idx + 1).filter(|next| *next < self.categories.len()),
+            (Some(idx), false) => idx.checked_sub(1),
+        };
+        self.part_text.category = next.and_then(|idx| self.categories.get(idx)).map(|category| category.path.clone()).unwrap_or_default();
+        self.part_text.apply_schema(&self.categories);
+    }
+
+    pub fn handle_new_part_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Esc => {
                 //TODO: handle state transitions better
                 self.parts_sub_state = PartsSubState::Main;
-                self.refresh_part_data();
+                self.refresh_part_data(repo)?;
 
             }
             KeyCode::Char(value) => {
@@ -247,6 +434,12 @@ impl PartsView {
                     CurrentlyEditingPart::Tolerance => {
                         self.part_text.tolerance.push(value);
                     },
+                    CurrentlyEditingPart::Category => {},
+                    CurrentlyEditingPart::Attribute(idx) => {
+                        if let Some((_, text)) = self.part_text.attributes.get_mut(idx) {
+                            text.push(value);
+                        }
+                    },
                 }
             },
             KeyCode::Tab => {
@@ -266,11 +459,14 @@ impl PartsView {
                     CurrentlyEditingPart::Value => {
                         self.currently_editing_part = CurrentlyEditingPart::Tolerance;
                     },
-                    CurrentlyEditingPart::Tolerance => {
-                        self.currently_editing_part = CurrentlyEditingPart::Manufacturer;
+                    CurrentlyEditingPart::Tolerance | CurrentlyEditingPart::Category | CurrentlyEditingPart::Attribute(_) => {
+                        self.currently_editing_part = self.next_category_field();
                     },
                 }
             },
+            KeyCode::Left | KeyCode::Right if matches!(self.currently_editing_part, CurrentlyEditingPart::Category) => {
+                self.cycle_category(key == KeyCode::Right);
+            },
             KeyCode::Backspace => {
                 match self.currently_editing_part {
                     CurrentlyEditingPart::PartNumber => {
@@ -291,27 +487,40 @@ impl PartsView {
                     CurrentlyEditingPart::Tolerance => {
                         self.part_text.tolerance.pop();
                     },
+                    //Backspace takes the part out of its category
+                    CurrentlyEditingPart::Category => {
+                        self.part_text.category.clear();
+                        self.part_text.apply_schema(&self.categories);
+                    },
+                    CurrentlyEditingPart::Attribute(idx) => {
+                        if let Some((_, text)) = self.part_text.attributes.get_mut(idx) {
+                            text.pop();
+                        }
+                    },
                 }
             },
             KeyCode::Enter => {
                 //TODO: fix!!!! | now I'm not sure what to fix lol
                 let mut new_part = Part::new();
                 self.part_text.copy_to_db_part(&mut new_part);
-                parts::add_new_part(&new_part);
+                normalize_part_attributes(&mut new_part, &self.categories);
+                normalize_part(&mut new_part);
+                repo.add_new_part(&new_part)?;
                 self.parts_sub_state = PartsSubState::Main;
-                self.refresh_part_data();
+                self.refresh_part_data(repo)?;
 
             },
             _ => {}
         }
+        Ok(())
     } //end handle_new_keys
     
-    pub fn handle_edit_part_keys(&mut self, key: KeyCode) {
+    pub fn handle_edit_part_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Esc => {
                 //TODO: handle state transitions better
                 self.parts_sub_state = PartsSubState::Main;
-                self.refresh_part_data();
+                self.refresh_part_data(repo)?;
             }
             KeyCode::Char(value) => {
                 match self.currently_editing_part {
@@ -330,6 +539,11 @@ impl PartsView {
                     CurrentlyEditingPart::Tolerance => {
                         self.part_text.tolerance.push(value);
                     },
+                    CurrentlyEditingPart::Attribute(idx) => {
+                        if let Some((_, text)) = self.part_text.attributes.get_mut(idx) {
+                            text.push(value);
+                        }
+                    },
                     _ => {}
                 }
             },
@@ -351,11 +565,14 @@ impl PartsView {
                     CurrentlyEditingPart::Value => {
                         self.currently_editing_part = CurrentlyEditingPart::Tolerance;
                     },
-                    CurrentlyEditingPart::Tolerance => {
-                        self.currently_editing_part = CurrentlyEditingPart::Manufacturer;
+                    CurrentlyEditingPart::Tolerance | CurrentlyEditingPart::Category | CurrentlyEditingPart::Attribute(_) => {
+                        self.currently_editing_part = self.next_category_field();
                     },
                 }
             },
+            KeyCode::Left | KeyCode::Right if matches!(self.currently_editing_part, CurrentlyEditingPart::Category) => {
+                self.cycle_category(key == KeyCode::Right);
+            },
             KeyCode::Backspace => {
                 match self.currently_editing_part {
                     CurrentlyEditingPart::Manufacturer => {
@@ -373,6 +590,16 @@ impl PartsView {
                     CurrentlyEditingPart::Tolerance => {
                         self.part_text.tolerance.pop();
                     },
+                    //Backspace takes the part out of its category
+                    CurrentlyEditingPart::Category => {
+                        self.part_text.category.clear();
+                        self.part_text.apply_schema(&self.categories);
+                    },
+                    CurrentlyEditingPart::Attribute(idx) => {
+                        if let Some((_, text)) = self.part_text.attributes.get_mut(idx) {
+                            text.pop();
+                        }
+                    },
                     _ => {}
                 }
             },
@@ -380,12 +607,159 @@ impl PartsView {
                 //update the part in SQL
                 let mut part = Part::new();
                 self.part_text.copy_to_db_part(&mut part);
-                parts::modify_part(&part);
+                normalize_part_attributes(&mut part, &self.categories);
+                normalize_part(&mut part);
+                repo.modify_part(&part)?;
                 self.parts_sub_state = PartsSubState::Main;
-                self.refresh_part_data();
+                self.refresh_part_data(repo)?;
             },
             _ => {}
         }
-
+        Ok(())
     } //end handle_edit_keys
 }
+
+#[cfg(test)]
+fn type_text(view: &mut PartsView, repo: &mut dyn BomRepository, text: &str) {
+    for c in text.chars() {
+        view.handle_new_part_keys(repo, KeyCode::Char(c)).unwrap();
+    }
+}
+
+#[test]
+fn test_new_part_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = PartsView::new();
+    view.handle_main_keys(&mut repo, KeyCode::Char('n')).unwrap();
+    assert!(view.parts_sub_state == PartsSubState::NewPart);
+    type_text(&mut view, &mut repo, "NEW-PN");
+    view.handle_new_part_keys(&mut repo, KeyCode::Tab).unwrap();
+    type_text(&mut view, &mut repo, "Acme");
+    view.handle_new_part_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.parts_sub_state == PartsSubState::Main);
+    let part = view.part_data.iter().find(|part| part.part_number == "NEW-PN").unwrap();
+    assert_eq!(part.manufacturer.as_deref(), Some("Acme"));
+}
+
+#[test]
+fn test_duplicate_part_keeps_popup_open() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = PartsView::new();
+    view.handle_main_keys(&mut repo, KeyCode::Char('n')).unwrap();
+    type_text(&mut view, &mut repo, "25SVPF47M");
+    let result = view.handle_new_part_keys(&mut repo, KeyCode::Enter);
+    assert!(matches!(result, Err(ShikaError::ConstraintViolation(_))));
+    assert!(view.parts_sub_state == PartsSubState::NewPart);
+    assert_eq!(view.part_text.part_number, "25SVPF47M");
+}
+
+#[test]
+fn test_edit_part_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = PartsView::new();
+    view.refresh_part_data(&mut repo).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Char('e')).unwrap();
+    assert!(view.parts_sub_state == PartsSubState::EditPart);
+    //Editing starts on the manufacturer, the part number can't change
+    for _ in 0.."Panasonic".len() {
+        view.handle_edit_part_keys(&mut repo, KeyCode::Backspace).unwrap();
+    }
+    for c in "TDK".chars() {
+        view.handle_edit_part_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_edit_part_keys(&mut repo, KeyCode::Enter).unwrap();
+    let pn = view.part_data[0].part_number.clone();
+    assert_eq!(repo.fetch_single_part(&pn).unwrap().manufacturer.as_deref(), Some("TDK"));
+}
+
+#[test]
+fn test_search_parts() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = PartsView::new();
+    view.refresh_part_data(&mut repo).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Char('/')).unwrap();
+    assert!(view.parts_sub_state == PartsSubState::Search);
+    for c in "panasonic".chars() {
+        view.handle_search_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    assert_eq!(view.part_data.len(), 2);
+    for c in " pkg:1210".chars() {
+        view.handle_search_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    assert_eq!(view.part_data.len(), 1);
+    view.handle_search_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_search_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.parts_sub_state == PartsSubState::Main);
+    assert_eq!(view.part_text.part_number, "HFW1V2210H4R7K");
+
+    //The search survives a refresh and applies to new parts
+    let mut part = Part::new();
+    part.part_number = "ETQ-P5M4R7YFC".to_string();
+    part.manufacturer = Some("Panasonic".to_string());
+    part.package = Some("1210".to_string());
+    repo.add_new_part(&part).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Char('r')).unwrap();
+    assert_eq!(view.part_data.len(), 2);
+    assert_eq!(view.part_table_state.selected(), view.part_data.iter().position(|part| part.part_number == "HFW1V2210H4R7K"));
+
+    //Nothing matches, then Esc brings every part back
+    view.handle_main_keys(&mut repo, KeyCode::Char('/')).unwrap();
+    view.handle_search_keys(&mut repo, KeyCode::Char('x')).unwrap();
+    assert!(view.part_data.is_empty());
+    assert_eq!(view.part_table_state.selected(), None);
+    view.handle_search_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_search_keys(&mut repo, KeyCode::Esc).unwrap();
+    assert_eq!(view.part_data.len(), 5);
+}
+
+#[test]
+fn test_find_equivalent_parts() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut part = Part::new();
+    part.part_number = "ERJ-3EKF1002V".to_string();
+    part.package = Some("0603".to_string());
+    part.value = Some("10000Ω".to_string());
+    part.tolerance = Some("0.5%".to_string());
+    repo.add_new_part(&part).unwrap();
+    let mut view = PartsView::new();
+    view.refresh_part_data(&mut repo).unwrap();
+    let selected = view.part_data.iter().position(|part| part.part_number == "RC0603FR-0710KL");
+    view.part_table_state.select(selected);
+    view.handle_main_keys(&mut repo, KeyCode::Char('f')).unwrap();
+    assert_eq!(view.search_text, "value:=10k package:0603 tol:<=1%");
+    let found: Vec<&str> = view.part_data.iter().map(|part| part.part_number.as_str()).collect();
+    assert_eq!(found.len(), 2);
+    assert!(found.contains(&"ERJ-3EKF1002V"));
+    assert_eq!(view.part_data[view.part_table_state.selected().unwrap()].part_number, "RC0603FR-0710KL");
+}
+
+#[test]
+fn test_new_part_with_category() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = PartsView::new();
+    view.handle_main_keys(&mut repo, KeyCode::Char('n')).unwrap();
+    type_text(&mut view, &mut repo, "CL10A106KP8NNNC");
+    for _ in 0..6 {
+        view.handle_new_part_keys(&mut repo, KeyCode::Tab).unwrap();
+    }
+    assert!(matches!(view.currently_editing_part, CurrentlyEditingPart::Category));
+    //Categories come sorted by path: Connectors, Crystals, ICs, Passives, Passives/Capacitors
+    for _ in 0..5 {
+        view.handle_new_part_keys(&mut repo, KeyCode::Right).unwrap();
+    }
+    assert_eq!(view.part_text.category, "Passives/Capacitors");
+    view.handle_new_part_keys(&mut repo, KeyCode::Tab).unwrap();
+    type_text(&mut view, &mut repo, "10");
+    view.handle_new_part_keys(&mut repo, KeyCode::Enter).unwrap();
+    let part = repo.fetch_single_part("CL10A106KP8NNNC").unwrap();
+    assert_eq!(part.category.as_deref(), Some("Passives/Capacitors"));
+    assert_eq!(part.attributes.get("voltage").map(String::as_str), Some("10V"));
+
+    view.handle_main_keys(&mut repo, KeyCode::Char('a')).unwrap();
+    assert!(view.columns().contains(&"voltage".to_string()));
+    view.search_text = "cat:capacitors voltage:<=25V".to_string();
+    view.apply_search();
+    let found: Vec<&str> = view.part_data.iter().map(|part| part.part_number.as_str()).collect();
+    assert_eq!(found, ["25SVPF47M", "CL10A106KP8NNNC"]);
+}
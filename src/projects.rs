@@ -1,11 +1,81 @@
-use postgres::Row;
-use tracing::{error, info};
-use crate::db;
-use crate::parts::Part;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use postgres::{Row, Transaction};
+use serde::Serialize;
+use crate::db::Db;
+use crate::errors::{ShikaError, ShikaResult};
+use crate::parts::{new_part_from_sql, validate_part, Part};
+use crate::stock;
 
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectStatus {
+    Active,
+    //Hidden from the project list unless archived projects are shown
+    Archived,
+}
+
+impl ProjectStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProjectStatus::Active => "active",
+            ProjectStatus::Archived => "archived",
+        }
+    }
+}
+
+impl fmt::Display for ProjectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ProjectStatus {
+    type Err = ShikaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(ProjectStatus::Active),
+            "archived" => Ok(ProjectStatus::Archived),
+            _ => Err(ShikaError::Validation(format!("Unknown project status {s}"))),
+        }
+    }
+}
+
+/// The editable descriptive fields of a project, empty when not filled in.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct ProjectMetadata {
+    pub revision: String,
+    pub description: String,
+    //Part number the board is ordered under at the PCB fab
+    pub pcb_fab_pn: String,
+    pub assembly_notes: String,
+    pub owner: String,
+}
+
+impl ProjectMetadata {
+    pub fn new() -> ProjectMetadata {
+        ProjectMetadata {
+            revision: "".to_string(),
+            description: "".to_string(),
+            pcb_fab_pn: "".to_string(),
+            assembly_notes: "".to_string(),
+            owner: "".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
 pub struct Project {
     //Nothing in this struct can be null, so no optional types needed.
     pub name: String,
+    pub status: ProjectStatus,
+    pub metadata: ProjectMetadata,
+    //Set by the database, "YYYY-MM-DD HH:MM:SS"
+    pub created_at: String,
+    //Bumped whenever the project or its BOM changes
+    pub modified_at: String,
     pub parts: Vec<ProjectPart>
 }
 
@@ -13,11 +83,16 @@ impl Project {
     pub fn new() -> Project {
         Project {
             name: "".to_string(),
+            status: ProjectStatus::Active,
+            metadata: ProjectMetadata::new(),
+            created_at: "".to_string(),
+            modified_at: "".to_string(),
             parts: vec![]
         }
     }
 }
 
+#[derive(Serialize, Clone)]
 pub struct ProjectPart {
     //Values specific to project part info
     pub partnumber: String,
@@ -27,48 +102,156 @@ pub struct ProjectPart {
     pub part_info: Part
 }
 
-pub fn fetch_project_list() -> Vec<Project> {
+pub fn fetch_project_list(db: &mut Db) -> ShikaResult<Vec<Project>> {
     let mut project_list = Vec::new();
 
-    let query = "SELECT * FROM projects";
-    let mut client = db::postgres_init();
-    let rows = client.query(query, &[]).unwrap();
+    let query = "SELECT project_name, status, revision, description, pcb_fab_pn, assembly_notes, owner,
+                        COALESCE(SUBSTR(CAST(created_at AS TEXT), 1, 19), '') AS created_at,
+                        COALESCE(SUBSTR(CAST(modified_at AS TEXT), 1, 19), '') AS modified_at
+                        FROM projects";
+    let client = db.client()?;
+    let rows = client.query(query, &[])?;
     for row in rows {
         let mut project = Project::new();
         project.name = row.try_get("project_name").unwrap_or("".to_string());
+        project.status = row.try_get::<_, String>("status")?.parse()?;
+        project.metadata = ProjectMetadata {
+            revision: row.try_get("revision")?,
+            description: row.try_get("description")?,
+            pcb_fab_pn: row.try_get("pcb_fab_pn")?,
+            assembly_notes: row.try_get("assembly_notes")?,
+            owner: row.try_get("owner")?,
+        };
+        project.created_at = row.try_get("created_at")?;
+        project.modified_at = row.try_get("modified_at")?;
         project_list.push(project);
     }
-    project_list
+    Ok(project_list)
 }
 
-pub fn fetch_project_details(project: &mut Project) {
-    let query = "SELECT * FROM project_components WHERE project_name = $1;";
-    let mut client = db::postgres_init();
-    let rows = client.query(query, &[&project.name]).unwrap();
+/// Loads the BOM lines of a project together with their part info in a single query.
+pub fn fetch_project_details(db: &mut Db, project: &mut Project) -> ShikaResult<()> {
+    let query = "SELECT pc.designators, pc.qty, bpv.*
+                        FROM project_components pc
+                        JOIN big_part_view bpv ON bpv.partnumber = pc.partnumber
+                        WHERE pc.project_name = $1
+                        ORDER BY pc.partnumber;";
+    let client = db.client()?;
+    let rows = client.query(query, &[&project.name])?;
     project.parts.clear();
     for row in rows {
-        project.parts.push(project_part_from_row(row));
+        project.parts.push(project_part_from_row(&row));
+    }
+    Ok(())
+}
+
+pub fn validate_project_name(name: &str) -> ShikaResult<()> {
+    if name.trim().is_empty() {
+        return Err(ShikaError::Validation("Project name cannot be empty".to_string()));
     }
+    Ok(())
 }
 
-pub fn create_new_project_name(name: String) {
-    let query = "INSERT INTO projects (project_name) VALUES ($1)";
-    let mut client = db::postgres_init();
-    client.execute(query, &[&name]).unwrap();
+pub fn create_new_project_name(db: &mut Db, name: String) -> ShikaResult<()> {
+    validate_project_name(&name)?;
+    let query = "INSERT INTO projects (project_name, owner, created_at, modified_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)";
+    let client = db.client()?;
+    client.execute(query, &[&name, &stock::current_user()])
+        .map_err(|e| ShikaError::on_unique_violation(e, || format!("Project {name} already exists")))?;
+    Ok(())
 }
 
-fn project_part_from_row(row: Row) -> ProjectPart {
-    let pn = row.try_get("partnumber").unwrap_or("".to_string());
-    let new_part = ProjectPart {
-        partnumber: pn.clone(),
-        qty: row.try_get("total_qty").unwrap_or(0),
+pub fn rename_project(db: &mut Db, old_name: &str, new_name: &str) -> ShikaResult<()> {
+    validate_project_name(new_name)?;
+    //The BOM lines follow through ON UPDATE CASCADE
+    let query = "UPDATE projects SET project_name = $1, modified_at = CURRENT_TIMESTAMP WHERE project_name = $2";
+    let client = db.client()?;
+    let updated = client.execute(query, &[&new_name, &old_name])
+        .map_err(|e| ShikaError::on_unique_violation(e, || format!("Project {new_name} already exists")))?;
+    if updated == 0 {
+        return Err(ShikaError::NotFound(format!("Project {old_name} does not exist")));
+    }
+    Ok(())
+}
+
+/// Creates `new_name` with the metadata and a copy of every BOM line of `source`, e.g. for the
+/// next board revision.
+pub fn duplicate_project(db: &mut Db, source: &str, new_name: &str) -> ShikaResult<()> {
+    validate_project_name(new_name)?;
+    let mut transaction = db.client()?.transaction()?;
+    let inserted = transaction.execute("INSERT INTO projects (project_name, revision, description, pcb_fab_pn, assembly_notes, owner,
+                            created_at, modified_at)
+                            SELECT $1, revision, description, pcb_fab_pn, assembly_notes, owner, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                            FROM projects
+                            WHERE project_name = $2", &[&new_name, &source])
+        .map_err(|e| ShikaError::on_unique_violation(e, || format!("Project {new_name} already exists")))?;
+    if inserted == 0 {
+        return Err(ShikaError::NotFound(format!("Project {source} does not exist")));
+    }
+    transaction.execute("INSERT INTO project_components (project_name, partnumber, designators, qty)
+                            SELECT $1, partnumber, designators, qty
+                            FROM project_components
+                            WHERE project_name = $2", &[&new_name, &source])?;
+    transaction.commit()?;
+    Ok(())
+}
+
+pub fn update_project_metadata(db: &mut Db, name: &str, metadata: &ProjectMetadata) -> ShikaResult<()> {
+    let query = "UPDATE projects SET revision = $2, description = $3, pcb_fab_pn = $4, assembly_notes = $5, owner = $6,
+        modified_at = CURRENT_TIMESTAMP
+        WHERE project_name = $1";
+    let client = db.client()?;
+    let updated = client.execute(query, &[&name, &metadata.revision, &metadata.description, &metadata.pcb_fab_pn,
+                                          &metadata.assembly_notes, &metadata.owner])?;
+    if updated == 0 {
+        return Err(ShikaError::NotFound(format!("Project {name} does not exist")));
+    }
+    Ok(())
+}
+
+pub fn set_project_status(db: &mut Db, name: &str, status: ProjectStatus) -> ShikaResult<()> {
+    let query = "UPDATE projects SET status = $1 WHERE project_name = $2";
+    let client = db.client()?;
+    let updated = client.execute(query, &[&status.as_str(), &name])?;
+    if updated == 0 {
+        return Err(ShikaError::NotFound(format!("Project {name} does not exist")));
+    }
+    Ok(())
+}
+
+/// Deletes the project for good, its BOM lines go with it through ON DELETE CASCADE. Refused
+/// once the project has builds, see `project_has_builds`.
+pub fn delete_project(db: &mut Db, name: &str) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    //Locking the project row holds off a build being started for it until the delete is done
+    if transaction.query_opt("SELECT 1 FROM projects WHERE project_name = $1 FOR UPDATE", &[&name])?.is_none() {
+        return Err(ShikaError::NotFound(format!("Project {name} does not exist")));
+    }
+    if transaction.query_opt("SELECT 1 FROM builds WHERE project_name = $1 LIMIT 1", &[&name])?.is_some() {
+        return Err(project_has_builds(name));
+    }
+    transaction.execute("DELETE FROM projects WHERE project_name = $1", &[&name])?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Builds keep their project, so projects that were built are archived rather than deleted.
+pub fn project_has_builds(name: &str) -> ShikaError {
+    ShikaError::ConstraintViolation(format!("{name} has builds, archive it instead"))
+}
+
+fn project_part_from_row(row: &Row) -> ProjectPart {
+    let part_info = new_part_from_sql(row);
+    ProjectPart {
+        partnumber: part_info.part_number.clone(),
+        qty: row.try_get("qty").unwrap_or(0),
         designators: row.try_get("designators").unwrap_or("".to_string()),
-        part_info: Part::new_from_pn(&*pn)
-    };
-    new_part
+        part_info
+    }
 }
 
-pub fn fetch_pns_not_in_project(project: &Project) -> Vec<String> {
+pub fn fetch_pns_not_in_project(db: &mut Db, project: &Project) -> ShikaResult<Vec<String>> {
     let query = "SELECT p.partnumber
                         FROM parts p
                         WHERE p.partnumber NOT IN (
@@ -76,25 +259,229 @@ pub fn fetch_pns_not_in_project(project: &Project) -> Vec<String> {
                             FROM project_components pc
                             WHERE pc.project_name = $1
                         );";
-    let mut client = db::postgres_init();
-    let row_result = client.query(query, &[&project.name]);
-    let rows = row_result.unwrap_or_else(|e| {
-        error!("Error fetching data: {:?}", e);
-        Vec::new()
-    });
+    let client = db.client()?;
+    let rows = client.query(query, &[&project.name])?;
     let mut ret_vec = vec![];
     for row in rows {
-        match row.try_get("partnumber") {
-            Ok(val) => ret_vec.push(val),
-            Err(_) => ()
+        if let Ok(val) = row.try_get("partnumber") {
+            ret_vec.push(val);
         }
     }
-    ret_vec
+    Ok(ret_vec)
 }
 
-pub fn add_pn_to_project(project: &Project, ppart: &ProjectPart) {
+pub fn add_pn_to_project(db: &mut Db, project: &Project, ppart: &ProjectPart) -> ShikaResult<()> {
     let query = "insert into project_components (project_name, partnumber, designators, qty)
         values ($1, $2, $3, $4)";
-    let mut client = db::postgres_init();
-    client.execute(query, &[&project.name, &ppart.partnumber, &ppart.designators, &ppart.qty]).unwrap();
-}
\ No newline at end of file
+    let mut transaction = db.client()?.transaction()?;
+    transaction.execute(query, &[&project.name, &ppart.partnumber, &ppart.designators, &ppart.qty])
+        .map_err(|e| ShikaError::on_unique_violation(e, || {
+            format!("{} is already in the BOM for {}", ppart.partnumber, project.name)
+        }))?;
+    touch_project(&mut transaction, &project.name)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Adds the lines of an imported BOM in one go, creating `new_parts` first. Nothing is written
+/// when any of it fails.
+pub fn import_project_parts(db: &mut Db, project: &Project, new_parts: &[Part], pparts: &[ProjectPart]) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    for part in new_parts {
+        validate_part(part)?;
+        transaction.execute("INSERT INTO parts (partnumber, manufacturer, description, label, package, value, tolerance)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                            &[&part.part_number, &part.manufacturer, &part.description, &part.label,
+                              &part.package, &part.value, &part.tolerance])
+            .map_err(|e| ShikaError::on_unique_violation(e, || format!("Part number {} already exists", part.part_number)))?;
+    }
+    for ppart in pparts {
+        transaction.execute("INSERT INTO project_components (project_name, partnumber, designators, qty) VALUES ($1, $2, $3, $4)",
+                            &[&project.name, &ppart.partnumber, &ppart.designators, &ppart.qty])
+            .map_err(|e| ShikaError::on_unique_violation(e, || {
+                format!("{} is already in the BOM for {}", ppart.partnumber, project.name)
+            }))?;
+    }
+    touch_project(&mut transaction, &project.name)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Rewrites the BOM line for `old_pn`. A different `ppart.partnumber` swaps in a substitute part.
+pub fn update_project_part(db: &mut Db, project: &Project, old_pn: &str, ppart: &ProjectPart) -> ShikaResult<()> {
+    let query = "UPDATE project_components SET partnumber = $1, designators = $2, qty = $3
+        WHERE project_name = $4 AND partnumber = $5";
+    let mut transaction = db.client()?.transaction()?;
+    let updated = transaction.execute(query, &[&ppart.partnumber, &ppart.designators, &ppart.qty, &project.name, &old_pn])
+        .map_err(|e| ShikaError::on_unique_violation(e, || {
+            format!("{} is already in the BOM for {}", ppart.partnumber, project.name)
+        }))?;
+    if updated == 0 {
+        return Err(ShikaError::NotFound(format!("{old_pn} is not in the BOM for {}", project.name)));
+    }
+    touch_project(&mut transaction, &project.name)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+pub fn remove_pn_from_project(db: &mut Db, project: &Project, pn: &str) -> ShikaResult<()> {
+    let query = "DELETE FROM project_components WHERE project_name = $1 AND partnumber = $2";
+    let mut transaction = db.client()?.transaction()?;
+    let deleted = transaction.execute(query, &[&project.name, &pn])?;
+    if deleted == 0 {
+        return Err(ShikaError::NotFound(format!("{pn} is not in the BOM for {}", project.name)));
+    }
+    touch_project(&mut transaction, &project.name)?;
+    transaction.commit()?;
+    Ok(())
+}
+
+//Bumps modified_at after a change to the project's BOM
+fn touch_project(transaction: &mut Transaction, name: &str) -> ShikaResult<()> {
+    transaction.execute("UPDATE projects SET modified_at = CURRENT_TIMESTAMP WHERE project_name = $1", &[&name])?;
+    Ok(())
+}
+
+//No board has more parts than this, a wider range is a typo or a broken import
+const MAX_DESIGNATORS: usize = 10_000;
+
+/// Expands a designator list like `R1-R4, R7` into the single designators it names.
+pub fn parse_designators(text: &str) -> ShikaResult<Vec<String>> {
+    let mut designators: Vec<String> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    for token in text.split([',', ' ']).map(str::trim).filter(|token| !token.is_empty()) {
+        let expanded = match token.split_once('-') {
+            Some((first, last)) => expand_designator_range(first, last, MAX_DESIGNATORS - designators.len())?
+                .ok_or_else(|| ShikaError::Validation(format!("{token} is not a valid designator range")))?,
+            None => {
+                split_designator(token)
+                    .ok_or_else(|| ShikaError::Validation(format!("{token} is not a valid designator")))?;
+                vec![token.to_string()]
+            }
+        };
+        for designator in expanded {
+            if !seen.insert(designator.clone()) {
+                return Err(ShikaError::Validation(format!("{designator} is listed more than once")));
+            }
+            designators.push(designator);
+        }
+        if designators.len() > MAX_DESIGNATORS {
+            return Err(ShikaError::Validation(format!("More than {MAX_DESIGNATORS} designators were entered")));
+        }
+    }
+    Ok(designators)
+}
+
+/// The inverse of `parse_designators`, sorts the designators and writes runs of three or more
+/// as a range. Designators that aren't a prefix and a number are kept at the end as they are.
+pub fn compress_designators(designators: &[String]) -> String {
+    let mut numbered: Vec<(&str, u32)> = designators.iter().filter_map(|designator| split_designator(designator)).collect();
+    numbered.sort();
+    numbered.dedup();
+    let mut groups: Vec<String> = Vec::new();
+    let mut idx = 0;
+    while idx < numbered.len() {
+        let (prefix, start) = numbered[idx];
+        let mut end = idx;
+        while end + 1 < numbered.len() && numbered[end + 1] == (prefix, numbered[end].1 + 1) {
+            end += 1;
+        }
+        if end - idx >= 2 {
+            groups.push(format!("{prefix}{start}-{prefix}{}", numbered[end].1));
+        } else {
+            groups.extend(numbered[idx..=end].iter().map(|(prefix, number)| format!("{prefix}{number}")));
+        }
+        idx = end + 1;
+    }
+    groups.extend(designators.iter().filter(|designator| split_designator(designator).is_none()).cloned());
+    groups.join(", ")
+}
+
+//Splits R12 into ("R", 12)
+fn split_designator(designator: &str) -> Option<(&str, u32)> {
+    let idx = designator.find(|c: char| c.is_ascii_digit())?;
+    let (prefix, number) = designator.split_at(idx);
+    if prefix.is_empty() || !prefix.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((prefix, number.parse().ok()?))
+}
+
+//Accepts both R1-R4 and R1-4, None when it isn't a range. Ranges naming more than `limit`
+//designators are refused before anything is built.
+fn expand_designator_range(first: &str, last: &str, limit: usize) -> ShikaResult<Option<Vec<String>>> {
+    let Some((prefix, start)) = split_designator(first) else {
+        return Ok(None);
+    };
+    let end = match last.parse::<u32>() {
+        Ok(end) => end,
+        Err(_) => match split_designator(last) {
+            Some((last_prefix, end)) if last_prefix == prefix => end,
+            _ => return Ok(None),
+        },
+    };
+    if end < start {
+        return Ok(None);
+    }
+    if (end - start) as usize >= limit {
+        return Err(ShikaError::Validation(format!("{first}-{last} makes more than {MAX_DESIGNATORS} designators in total")));
+    }
+    Ok(Some((start..=end).map(|number| format!("{prefix}{number}")).collect()))
+}
+
+/// The qty of a BOM line, a whole number above zero.
+pub fn parse_bom_qty(qty: &str) -> ShikaResult<i32> {
+    match qty.trim().parse::<i32>() {
+        Ok(qty) if qty > 0 => Ok(qty),
+        _ => Err(ShikaError::Validation("Qty must be a whole number above zero".to_string())),
+    }
+}
+
+/// Checks the qty and designators entered for a BOM line and returns the qty. There has to be
+/// one designator per part.
+pub fn validate_bom_line(qty: &str, designators: &str) -> ShikaResult<i32> {
+    let qty = parse_bom_qty(qty)?;
+    let count = parse_designators(designators)?.len();
+    if count == 0 {
+        return Err(ShikaError::Validation(format!("Enter the designators of the {qty} parts")));
+    }
+    if count != qty as usize {
+        return Err(ShikaError::Validation(
+            format!("{count} designators were entered for a qty of {qty}")));
+    }
+    Ok(qty)
+}
+
+#[test]
+fn test_parse_designators() {
+    assert_eq!(parse_designators("R1-R4, R7").unwrap(), vec!["R1", "R2", "R3", "R4", "R7"]);
+    assert_eq!(parse_designators("C10-12 C3").unwrap(), vec!["C10", "C11", "C12", "C3"]);
+    assert!(parse_designators("").unwrap().is_empty());
+    assert!(parse_designators("R4-R1").is_err());
+    assert!(parse_designators("R1-C4").is_err());
+    assert!(parse_designators("12").is_err());
+    assert!(parse_designators("R1, R1").is_err());
+    assert!(parse_designators("R1-R4000000000").is_err());
+    assert!(parse_designators("R1-R6000, C1-C6000").is_err());
+    assert_eq!(parse_designators("R1-R10000").unwrap().len(), 10_000);
+}
+
+#[test]
+fn test_compress_designators() {
+    let designators: Vec<String> = ["R7", "R2", "R1", "R3", "C10", "R4", "R9", "R10", "TP_A"]
+        .iter().map(|designator| designator.to_string()).collect();
+    assert_eq!(compress_designators(&designators), "C10, R1-R4, R7, R9, R10, TP_A");
+    assert_eq!(parse_designators("R1-R4, R7").map(|parsed| compress_designators(&parsed)).unwrap(), "R1-R4, R7");
+    assert_eq!(compress_designators(&[]), "");
+}
+
+#[test]
+fn test_validate_bom_line() {
+    assert_eq!(validate_bom_line("5", "R1-R4, R7").unwrap(), 5);
+    assert!(validate_bom_line("2", "").is_err());
+    assert_eq!(parse_bom_qty(" 2 ").unwrap(), 2);
+    assert!(validate_bom_line("4", "R1-R4, R7").is_err());
+    assert!(validate_bom_line("0", "").is_err());
+    assert!(validate_bom_line("-1", "").is_err());
+    assert!(validate_bom_line("two", "R1, R2").is_err());
+}
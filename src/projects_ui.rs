@@ -2,19 +2,23 @@ use std::str::FromStr;
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Layout, Margin, Rect},
-    prelude::{Color, Line, Modifier, Span, Style, Stylize},
+    prelude::{Color, Modifier, Style, Stylize},
     style::palette::tailwind,
     widgets::{
-        Block, Borders, Clear, Paragraph, Row, Scrollbar,
-        ScrollbarOrientation, ScrollbarState, Table, Wrap
+        Block, Borders, Paragraph, Row, Scrollbar,
+        ScrollbarOrientation, ScrollbarState, Table
     }
 };
-use ratatui::style::Styled;
-use ratatui::text::Text;
-use ratatui::widgets::{List, ListDirection, ListState};
-use tracing::info;
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{List, ListDirection};
 use crate::app::App;
-use crate::projects_view::{ATBFormField, ProjectSubState};
+use crate::build::BuildStatus;
+use crate::projects::ProjectStatus;
+use crate::revisions::DiffKind;
+use crate::kicad_import::LineResolution;
+use crate::bom_import::BomField;
+use crate::bom_export::ExportFormat;
+use crate::projects_view::{self, ATBFormField, ExportField, MetadataField, ProjectSubState};
 use crate::ui::centered_rect;
 use crate::utils;
 
@@ -29,15 +33,30 @@ pub fn render_main_panel(f: &mut Frame, app: &App, rect: Rect) {
     render_projects_list_panel(f, app, layout[0]);
     render_project_detail_panel(f, app, layout[1]);
     render_new_project_popup(f, app, rect);
+    render_delete_project_popup(f, app, rect);
     render_add_part_to_bom_popup(f, app, rect);
+    render_edit_bom_line_popup(f, app, rect);
+    render_remove_bom_line_popup(f, app, rect);
+    render_substitute_pn_popup(f, app, rect);
+    render_build_planner_popup(f, app, rect);
+    render_edit_metadata_popup(f, app, rect);
+    render_revisions_popup(f, app, rect);
+    render_snapshot_revision_popup(f, app, rect);
+    render_import_path_popup(f, app, rect);
+    render_import_review_popup(f, app, rect);
+    render_wizard_file_popup(f, app, rect);
+    render_wizard_mapping_popup(f, app, rect);
+    render_wizard_dry_run_popup(f, app, rect);
+    render_export_bom_popup(f, app, rect);
 }
 
 fn render_projects_list_panel(f: &mut Frame, app: &App, rect: Rect) {
     let is_select = app.projects_view.sub_state == ProjectSubState::ListMode;
+    let title = if app.projects_view.show_archived {"Projects (incl. archived)"} else {"Projects"};
     let b = Block::default()
         .borders(Borders::ALL)
         .border_style(get_block_border_style(is_select))
-        .title("Projects")
+        .title(title)
         .style(get_block_style());
     let items2 = create_project_list(app);
     let list = List::new(items2)
@@ -69,12 +88,16 @@ fn render_project_detail_panel(f: &mut Frame, app: &App, rect: Rect) {
     let layout = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
+            Constraint::Length(5),
             Constraint::Fill(1),
+            Constraint::Length(8),
             Constraint::Length(1),
         ])
         .split(rect);
-    let table_rect = layout[0];
-    let refreshed_rect = layout[1];
+    let metadata_rect = layout[0];
+    let table_rect = layout[1];
+    let builds_rect = layout[2];
+    let refreshed_rect = layout[3];
     let b = Block::default()
         .borders(Borders::ALL)
         .border_style(get_block_border_style(app.projects_view.sub_state == ProjectSubState::BOMMode))
@@ -103,8 +126,7 @@ fn render_project_detail_panel(f: &mut Frame, app: &App, rect: Rect) {
         .column_spacing(1)
         .style(Style::new().blue())
         .header(
-            //TODO: Bring this in from parts.rs
-            Row::new(vec!["Part Number", "Designator(s)", "Qty", "Value", "Tolerance", "Package", "Label", "MFG"])
+            Row::new(app.projects_view.bom_sort.headers(&projects_view::BOM_COLUMNS))
                 .style(header_style)
                 // To add space between the header and the rest of the rows, specify the margin
                 .bottom_margin(1),
@@ -117,17 +139,86 @@ fn render_project_detail_panel(f: &mut Frame, app: &App, rect: Rect) {
     //.borders(Borders::ALL);
     let refreshed_text = Text::from("Refreshed last at 2025-01-01 12:46")
         .style(Style::default().fg(Color::White));
+    render_metadata_panel(f, app, metadata_rect);
     f.render_stateful_widget(table, table_rect, &mut app.projects_view.bom_table_state.clone());
+    render_builds_panel(f, app, builds_rect);
     f.render_widget(refreshed_text, refreshed_rect);
 
 
 }
 
+fn render_metadata_panel(f: &mut Frame, app: &App, rect: Rect) {
+    let view = &app.projects_view;
+    let b = Block::default()
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(false))
+        .title("Project")
+        .title_bottom("(m) edit metadata")
+        .style(Style::default().fg(Color::White));
+    let Some(project) = view.project_data.get(view.selected_project_idx) else {
+        f.render_widget(Paragraph::new("No project selected.").block(b), rect);
+        return;
+    };
+    let metadata = &project.metadata;
+    let text = Text::from(vec![
+        format!("Rev: {}   Owner: {}   PCB: {}   Created: {}   Modified: {}",
+                metadata.revision, metadata.owner, metadata.pcb_fab_pn,
+                project.created_at, project.modified_at).into(),
+        format!("Description: {}", metadata.description).into(),
+        format!("Assembly notes: {}", metadata.assembly_notes).into(),
+    ]);
+    f.render_widget(Paragraph::new(text).block(b), rect);
+}
+
+fn render_builds_panel(f: &mut Frame, app: &App, rect: Rect) {
+    let view = &app.projects_view;
+    let b = Block::default()
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(view.sub_state == ProjectSubState::BuildsMode))
+        .title(format!("Builds ({} unit(s) in progress)", view.units_in_progress()))
+        .title_bottom("(c)omplete / (x) cancel")
+        .style(Style::default().fg(Color::White));
+    let rows: Vec<Row> = view.builds.iter().map(|build| {
+        let style = match build.status {
+            BuildStatus::InProgress => Style::default().fg(tailwind::AMBER.c400),
+            BuildStatus::Completed => Style::default().fg(tailwind::EMERALD.c400),
+            BuildStatus::Cancelled => Style::default().fg(tailwind::SLATE.c500),
+        };
+        Row::new(vec![
+            format!("#{}", build.id),
+            build.quantity.to_string(),
+            build.status.to_string(),
+            build.started_at.clone(),
+            build.finished_at.clone().unwrap_or_default(),
+        ]).style(style)
+    }).collect();
+    let widths = [
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(12),
+        Constraint::Length(20),
+        Constraint::Length(20),
+    ];
+    let header_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::BLUE.c900);
+    let table = Table::new(rows, widths)
+        .block(b)
+        .column_spacing(1)
+        .header(Row::new(vec!["Build", "Units", "Status", "Started", "Finished"]).style(header_style))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>");
+    f.render_stateful_widget(table, rect, &mut view.builds_table_state.clone());
+}
+
 fn create_project_list(app: &App) -> Vec<String> {
     let projects = &app.projects_view.project_data;
     let mut rows: Vec<String> = Vec::new();
     for project in projects {
-        rows.push(project.name.clone());
+        match project.status {
+            ProjectStatus::Archived => rows.push(format!("{} [archived]", project.name)),
+            ProjectStatus::Active => rows.push(project.name.clone()),
+        }
     }
     rows
 }
@@ -141,14 +232,15 @@ fn get_block_border_style(is_selected: bool) -> Style {
 }
 
 fn get_block_style() -> Style {
-    let retval = Style::default()
-        .fg(Color::White);
-    retval
+    Style::default()
+        .fg(Color::White)
 }
 
-fn create_project_table_rows(app: &App) -> Vec<Row> {
-    let project = &app.projects_view.project_data.get(app.projects_view.selected_project_idx).unwrap();
+fn create_project_table_rows(app: &App) -> Vec<Row<'_>> {
     let mut rows: Vec<Row> = Vec::new();
+    let Some(project) = app.projects_view.project_data.get(app.projects_view.selected_project_idx) else {
+        return rows;
+    };
     for part in &project.parts {
         let row = Row::new(vec![
             part.partnumber.clone(),
@@ -166,10 +258,15 @@ fn create_project_table_rows(app: &App) -> Vec<Row> {
 }
 
 fn render_new_project_popup(f: &mut Frame, app: &App, rect: Rect) {
-    if app.projects_view.sub_state != ProjectSubState::CreateNewProject {return};
+    let title = match app.projects_view.sub_state {
+        ProjectSubState::CreateNewProject => "Create new project:",
+        ProjectSubState::RenameProject => "Rename project:",
+        ProjectSubState::DuplicateProject => "Duplicate project as:",
+        _ => return,
+    };
 
     let popup_block = Block::default()
-        .title("Create new project:")
+        .title(title)
         .borders(Borders::ALL)
         .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
 
@@ -182,6 +279,22 @@ fn render_new_project_popup(f: &mut Frame, app: &App, rect: Rect) {
     f.render_widget(txt_t, area);
 }
 
+fn render_delete_project_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::DeleteProject {return};
+    let Some(project) = app.projects_view.project_list_state.selected()
+        .and_then(|idx| app.projects_view.project_data.get(idx)) else {return};
+
+    let popup_block = Block::default()
+        .title("Delete project:")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::RED.c400));
+    let area = centered_rect(30, 15, rect);
+    utils::render_popup_block(f, area, popup_block.clone());
+    let text = format!("Delete {} and its {} BOM line(s)? This can't be undone.\n(y)es / (n)o",
+                       project.name, project.parts.len());
+    f.render_widget(Paragraph::new(text).block(popup_block), area);
+}
+
 fn render_add_part_to_bom_popup(f: &mut Frame, app: &App, rect: Rect) {
     if app.projects_view.sub_state != ProjectSubState::AddToBOM {return};
 
@@ -248,3 +361,562 @@ fn render_add_part_to_bom_popup(f: &mut Frame, app: &App, rect: Rect) {
 
 }
 
+
+fn render_edit_bom_line_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::EditBOMLine {return};
+
+    let line = &app.projects_view.bom_line_edit;
+    let popup_block = Block::default()
+        .title(format!("Edit {}:", line.partnumber))
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(true));
+    let area = centered_rect(40, 30, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let form_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Percentage(50),
+            Constraint::Percentage(50)
+        ])
+        .split(area);
+    let mut desig_b = Block::default().title("Designators").borders(Borders::ALL);
+    let mut qty_b = Block::default().title("Qty").borders(Borders::ALL);
+    match line.currently_editing {
+        ATBFormField::Qty => {
+            qty_b = qty_b.border_style(get_block_border_style(true));
+        }
+        _ => {
+            desig_b = desig_b.border_style(get_block_border_style(true));
+        }
+    }
+    let desig_t = Paragraph::new(line.designators.clone()).block(desig_b);
+    let qty_t = Paragraph::new(line.qty.clone()).block(qty_b);
+    f.render_widget(desig_t, form_chunks[0]);
+    f.render_widget(qty_t, form_chunks[1]);
+}
+
+fn render_remove_bom_line_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::RemoveBOMLine {return};
+
+    let popup_block = Block::default()
+        .title("Remove BOM line:")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::RED.c400));
+    let area = centered_rect(30, 15, rect);
+    utils::render_popup_block(f, area, popup_block.clone());
+    let project = &app.projects_view.project_data[app.projects_view.selected_project_idx];
+    let text = format!("Remove {} from {}?\n(y)es / (n)o", app.projects_view.bom_line_edit.partnumber, project.name);
+    f.render_widget(Paragraph::new(text).block(popup_block), area);
+}
+
+fn render_substitute_pn_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::SubstitutePN {return};
+
+    let line = &app.projects_view.bom_line_edit;
+    let popup_block = Block::default()
+        .title(format!("Substitute for {} ({}):", line.partnumber, line.designators))
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(true));
+    let area = centered_rect(30, 50, rect);
+    utils::render_popup_block(f, area, popup_block.clone());
+    let highlighted_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::EMERALD.c900);
+    let list = List::new(line.substitutes.clone())
+        .block(popup_block)
+        .highlight_style(highlighted_style.add_modifier(Modifier::ITALIC))
+        .highlight_symbol(">>")
+        .repeat_highlight_symbol(true)
+        .direction(ListDirection::TopToBottom);
+    f.render_stateful_widget(list, area, &mut line.substitute_list_state.clone());
+}
+
+fn render_build_planner_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::BuildPlanner {return};
+
+    let view = &app.projects_view;
+    let project_name = view.project_data.get(view.selected_project_idx).map(|p| p.name.clone()).unwrap_or_default();
+    let popup_block = Block::default()
+        .title(format!("Build planner: {project_name}"))
+        .title_bottom("(0-9) units to build / (enter) start build / (r) reload stock / (esc) close")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(70, 70, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Fill(1),
+        ])
+        .split(area);
+    let units_t = Paragraph::new(view.build_units.clone())
+        .block(Block::default().title("Units to build").borders(Borders::ALL).border_style(get_block_border_style(true)));
+    f.render_widget(units_t, chunks[0]);
+
+    let Some(plan) = &view.build_plan else {
+        f.render_widget(Text::from("Enter how many units to build."), chunks[1]);
+        return;
+    };
+    let max_buildable = match plan.max_buildable {
+        Some(max) => max.to_string(),
+        None => "no limit".to_string(),
+    };
+    let verdict = if plan.can_build() {
+        Text::from(format!("{} unit(s) can be built. Max buildable: {max_buildable}", plan.units))
+            .style(Style::default().fg(tailwind::EMERALD.c400))
+    } else {
+        Text::from(format!("Short on {} part(s) for {} unit(s). Max buildable: {max_buildable}",
+                           plan.short_lines(), plan.units))
+            .style(Style::default().fg(tailwind::RED.c400))
+    };
+    f.render_widget(verdict, chunks[1]);
+
+    let rows: Vec<Row> = plan.lines.iter().map(|line| {
+        let style = if line.shortfall > 0 {
+            Style::default().fg(tailwind::RED.c400)
+        } else {
+            Style::default()
+        };
+        Row::new(vec![
+            line.partnumber.clone(),
+            line.designators.clone(),
+            line.per_unit.to_string(),
+            line.required.to_string(),
+            line.available.to_string(),
+            line.shortfall.to_string(),
+        ]).style(style)
+    }).collect();
+    let widths = [
+        Constraint::Percentage(25),
+        Constraint::Percentage(25),
+        Constraint::Percentage(10),
+        Constraint::Percentage(13),
+        Constraint::Percentage(13),
+        Constraint::Percentage(14),
+    ];
+    let header_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::EMERALD.c900);
+    let table = Table::new(rows, widths)
+        .column_spacing(1)
+        .header(Row::new(vec!["Part Number", "Designator(s)", "Per unit", "Required", "Available", "Short"])
+            .style(header_style));
+    f.render_widget(table, chunks[2]);
+}
+
+fn render_edit_metadata_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::EditMetadata {return};
+
+    let editing = &app.projects_view.editing_metadata;
+    let popup_block = Block::default()
+        .title(format!("Edit {} metadata:", editing.project_name))
+        .title_bottom("(tab) next field / (enter) save / (esc) cancel")
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(true));
+    let area = centered_rect(50, 60, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let form_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([Constraint::Length(3); 5])
+        .split(area);
+    let metadata = &editing.metadata;
+    let fields = [
+        (MetadataField::Revision, "Revision", &metadata.revision),
+        (MetadataField::Description, "Description", &metadata.description),
+        (MetadataField::PcbFabPn, "PCB fab PN", &metadata.pcb_fab_pn),
+        (MetadataField::AssemblyNotes, "Assembly notes", &metadata.assembly_notes),
+        (MetadataField::Owner, "Owner", &metadata.owner),
+    ];
+    for (chunk, (field, title, text)) in form_chunks.iter().zip(fields) {
+        let b = Block::default()
+            .title(title)
+            .borders(Borders::ALL)
+            .border_style(get_block_border_style(editing.active_field == field));
+        f.render_widget(Paragraph::new(text.clone()).block(b), *chunk);
+    }
+}
+
+fn render_revisions_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if !matches!(app.projects_view.sub_state, ProjectSubState::Revisions | ProjectSubState::SnapshotRevision) {return};
+
+    let view = &app.projects_view;
+    let project_name = view.project_data.get(view.selected_project_idx).map(|p| p.name.clone()).unwrap_or_default();
+    let popup_block = Block::default()
+        .title(format!("Revisions: {project_name}"))
+        .title_bottom("(enter) pick two to compare / (n) snapshot the working BOM / (esc) back")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(80, 70, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(1)
+        .constraints([
+            Constraint::Percentage(30),
+            Constraint::Fill(1),
+        ])
+        .split(area);
+    let header_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::BLUE.c900);
+
+    let rows: Vec<Row> = (0..=view.revisions.len()).map(|idx| {
+        let (label, lines) = view.revision_row(idx);
+        let created_at = idx.checked_sub(1)
+            .and_then(|idx| view.revisions.get(idx))
+            .map(|revision| revision.created_at.clone())
+            .unwrap_or_default();
+        let marker = if view.diff_base == Some(idx) { "*" } else { "" };
+        Row::new(vec![marker.to_string(), label, lines.len().to_string(), created_at])
+    }).collect();
+    let widths = [
+        Constraint::Length(1),
+        Constraint::Fill(1),
+        Constraint::Length(5),
+        Constraint::Length(20),
+    ];
+    let table = Table::new(rows, widths)
+        .block(Block::default().title("Revisions").borders(Borders::ALL).border_style(get_block_border_style(true)))
+        .column_spacing(1)
+        .header(Row::new(vec!["", "Revision", "Lines", "Created"]).style(header_style))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>");
+    f.render_stateful_widget(table, chunks[0], &mut view.revisions_table_state.clone());
+
+    let Some(diff) = &view.bom_diff else {
+        let hint = match view.diff_base {
+            Some(_) => "Pick the revision to compare against.",
+            None => "Pick two revisions to compare them.",
+        };
+        f.render_widget(Paragraph::new(hint).block(Block::default().title("Diff").borders(Borders::ALL)), chunks[1]);
+        return;
+    };
+    let diff_b = Block::default()
+        .title(format!("Diff {} -> {}", diff.from, diff.to))
+        .borders(Borders::ALL);
+    if diff.lines.is_empty() {
+        f.render_widget(Paragraph::new("No differences.").block(diff_b), chunks[1]);
+        return;
+    }
+    let rows: Vec<Row> = diff.lines.iter().map(|line| {
+        let color = match line.kind {
+            DiffKind::Added => tailwind::EMERALD.c400,
+            DiffKind::Removed => tailwind::RED.c400,
+            DiffKind::Changed | DiffKind::Substituted => tailwind::AMBER.c400,
+        };
+        Row::new(vec![
+            line.symbol().to_string(),
+            line.partnumber(),
+            line.designators(),
+            line.qty(),
+        ]).style(Style::default().fg(color))
+    }).collect();
+    let widths = [
+        Constraint::Length(1),
+        Constraint::Percentage(35),
+        Constraint::Fill(1),
+        Constraint::Length(10),
+    ];
+    let table = Table::new(rows, widths)
+        .block(diff_b)
+        .column_spacing(1)
+        .header(Row::new(vec!["", "Part Number", "Designator(s)", "Qty"]).style(header_style));
+    f.render_widget(table, chunks[1]);
+}
+
+fn render_snapshot_revision_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::SnapshotRevision {return};
+
+    let popup_block = Block::default()
+        .title("Snapshot the working BOM as:")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(30, 15, rect);
+    utils::render_popup_block(f, area, popup_block);
+    let txt_b = Block::default().title("Revision: ")
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(true));
+    let txt_t = Paragraph::new(app.projects_view.revision_name_text.clone()).block(txt_b);
+    f.render_widget(txt_t, area);
+}
+
+fn render_import_path_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::ImportPath {return};
+
+    let popup_block = Block::default()
+        .title("Import a KiCad BOM (.csv or .xml):")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(50, 15, rect);
+    utils::render_popup_block(f, area, popup_block);
+    let txt_b = Block::default().title("File path: ")
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(true));
+    let txt_t = Paragraph::new(app.projects_view.import_path_text.clone()).block(txt_b);
+    f.render_widget(txt_t, area);
+}
+
+fn render_import_review_popup(f: &mut Frame, app: &App, rect: Rect) {
+    let view = &app.projects_view;
+    if !matches!(view.sub_state, ProjectSubState::ImportReview | ProjectSubState::ImportPartNumber) {return};
+
+    let unmatched = view.import_lines.iter().filter(|line| line.resolution == LineResolution::Unmatched).count();
+    let popup_block = Block::default()
+        .title(format!("Review import: {} line(s), {unmatched} unmatched", view.import_lines.len()))
+        .title_bottom("(left/right) pick a match / (e) enter part number / (x) skip / (enter) import / (esc) cancel")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(85, 80, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let rows: Vec<Row> = view.import_lines.iter().map(|line| {
+        let color = match line.resolution {
+            LineResolution::Exact(_) => tailwind::EMERALD.c400,
+            LineResolution::Fuzzy(_) | LineResolution::Mapped(_) => tailwind::AMBER.c400,
+            LineResolution::NewPart(_) => tailwind::BLUE.c400,
+            LineResolution::Skip => tailwind::SLATE.c500,
+            LineResolution::Unmatched => tailwind::RED.c400,
+        };
+        Row::new(vec![
+            line.designators.clone(),
+            line.qty.to_string(),
+            line.value.clone(),
+            line.footprint.clone(),
+            line.partnumber.clone().unwrap_or_default(),
+            line.resolution.label().to_string(),
+            line.resolution.partnumber().unwrap_or("").to_string(),
+            line.candidates.len().to_string(),
+        ]).style(Style::default().fg(color))
+    }).collect();
+    let widths = [
+        Constraint::Percentage(16),
+        Constraint::Length(4),
+        Constraint::Percentage(12),
+        Constraint::Percentage(22),
+        Constraint::Percentage(14),
+        Constraint::Length(10),
+        Constraint::Percentage(16),
+        Constraint::Length(6),
+    ];
+    let header_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::BLUE.c900);
+    let table = Table::new(rows, widths)
+        .column_spacing(1)
+        .header(Row::new(vec!["Designator(s)", "Qty", "Value", "Footprint", "KiCad PN", "Match", "Part Number", "Cands"])
+            .style(header_style))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">>");
+    f.render_stateful_widget(table, area.inner(Margin { vertical: 1, horizontal: 1 }),
+                             &mut view.import_table_state.clone());
+
+    if view.sub_state == ProjectSubState::ImportPartNumber {
+        let area = centered_rect(30, 15, rect);
+        utils::render_popup_block(f, area, Block::default().style(Style::default().bg(Color::Black)));
+        let txt_b = Block::default().title("Part number (new ones are created on import): ")
+            .borders(Borders::ALL)
+            .border_style(get_block_border_style(true));
+        f.render_widget(Paragraph::new(view.import_pn_text.clone()).block(txt_b), area);
+    }
+}
+
+fn render_wizard_file_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::WizardFile {return};
+
+    let popup_block = Block::default()
+        .title("Import a spreadsheet BOM (.csv, .xlsx, .xls or .ods):")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(50, 15, rect);
+    utils::render_popup_block(f, area, popup_block);
+    let txt_b = Block::default().title("File path: ")
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(true));
+    let txt_t = Paragraph::new(app.projects_view.import_wizard.path_text.clone()).block(txt_b);
+    f.render_widget(txt_t, area);
+}
+
+fn render_wizard_mapping_popup(f: &mut Frame, app: &App, rect: Rect) {
+    let wizard = &app.projects_view.import_wizard;
+    if !matches!(app.projects_view.sub_state, ProjectSubState::WizardMapping | ProjectSubState::WizardProfileName) {return};
+    let Some(table) = &wizard.table else {return};
+
+    let profile = wizard.profile_name.clone().unwrap_or("no profile".to_string());
+    let popup_block = Block::default()
+        .title(format!("Map columns ({profile})"))
+        .title_bottom("(left/right) pick column / (p) next profile / (s) save profile / (enter) dry run / (esc) cancel")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(85, 80, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(1)
+        .constraints([
+            Constraint::Length(45),
+            Constraint::Fill(1),
+        ])
+        .split(area);
+    let field_rows: Vec<Row> = BomField::ALL.iter().enumerate().map(|(idx, field)| {
+        let style = match idx == wizard.selected_field {
+            true => Style::default().add_modifier(Modifier::REVERSED),
+            false => Style::default(),
+        };
+        Row::new(vec![field.label().to_string(), wizard.mapping.get(*field).unwrap_or("-").to_string()]).style(style)
+    }).collect();
+    let fields = Table::new(field_rows, [Constraint::Length(14), Constraint::Fill(1)])
+        .block(Block::default().title("Fields").borders(Borders::ALL).border_style(get_block_border_style(true)))
+        .column_spacing(1);
+    f.render_widget(fields, chunks[0]);
+
+    let header_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::BLUE.c900);
+    //Mapped columns stand out in the preview
+    let header_cells: Vec<Text> = table.headers.iter().map(|header| {
+        let mapped = BomField::ALL.iter().any(|field| wizard.mapping.get(*field) == Some(header.as_str()));
+        let style = match mapped {
+            true => Style::default().fg(tailwind::EMERALD.c400).add_modifier(Modifier::BOLD),
+            false => Style::default(),
+        };
+        Text::from(header.clone()).style(style)
+    }).collect();
+    let preview_rows: Vec<Row> = table.rows.iter().take(chunks[1].height as usize)
+        .map(|row| Row::new(row.clone()))
+        .collect();
+    let widths = vec![Constraint::Fill(1); table.headers.len().max(1)];
+    let preview = Table::new(preview_rows, widths)
+        .block(Block::default().title(format!("Preview ({} rows)", table.rows.len())).borders(Borders::ALL))
+        .column_spacing(1)
+        .header(Row::new(header_cells).style(header_style));
+    f.render_widget(preview, chunks[1]);
+
+    if app.projects_view.sub_state == ProjectSubState::WizardProfileName {
+        let area = centered_rect(30, 15, rect);
+        utils::render_popup_block(f, area, Block::default().style(Style::default().bg(Color::Black)));
+        let txt_b = Block::default().title("Save mapping as profile: ")
+            .borders(Borders::ALL)
+            .border_style(get_block_border_style(true));
+        f.render_widget(Paragraph::new(wizard.profile_name_text.clone()).block(txt_b), area);
+    }
+}
+
+fn render_wizard_dry_run_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::WizardDryRun {return};
+    let Some(run) = &app.projects_view.import_wizard.dry_run else {return};
+
+    let popup_block = Block::default()
+        .title("Dry run")
+        .title_bottom("(enter) import / (esc) back to the mapping")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+    let area = centered_rect(70, 70, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(2),
+            Constraint::Fill(1),
+        ])
+        .split(area);
+    let summary_style = match run.can_commit() {
+        true => Style::default().fg(tailwind::EMERALD.c400),
+        false => Style::default().fg(tailwind::RED.c400),
+    };
+    let summary = Text::from(vec![
+        format!("{} BOM line(s) to add, {} new part(s) to create, {} row(s) without a part number skipped",
+                run.lines.len(), run.new_parts.len(), run.skipped_rows).into(),
+        format!("{} problem(s)", run.problems.len()).into(),
+    ]).style(summary_style);
+    f.render_widget(summary, chunks[0]);
+
+    let mut rows: Vec<Row> = run.problems.iter()
+        .map(|problem| Row::new(vec!["!".to_string(), problem.clone(), "".to_string(), "".to_string()])
+            .style(Style::default().fg(tailwind::RED.c400)))
+        .collect();
+    rows.extend(run.lines.iter().map(|line| {
+        let is_new = run.new_parts.iter().any(|part| part.part_number == line.partnumber);
+        Row::new(vec![
+            "+".to_string(),
+            line.partnumber.clone(),
+            line.designators.clone(),
+            line.qty.to_string(),
+        ]).style(Style::default().fg(match is_new {
+            true => tailwind::BLUE.c400,
+            false => tailwind::EMERALD.c400,
+        }))
+    }));
+    let widths = [
+        Constraint::Length(1),
+        Constraint::Percentage(35),
+        Constraint::Fill(1),
+        Constraint::Length(6),
+    ];
+    let header_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::BLUE.c900);
+    let table = Table::new(rows, widths)
+        .block(Block::default().title("Lines (new parts in blue)").borders(Borders::ALL))
+        .column_spacing(1)
+        .header(Row::new(vec!["", "Part Number", "Designator(s)", "Qty"]).style(header_style));
+    f.render_widget(table, chunks[1]);
+}
+
+fn render_export_bom_popup(f: &mut Frame, app: &App, rect: Rect) {
+    if app.projects_view.sub_state != ProjectSubState::ExportBOM {return};
+
+    let view = &app.projects_view;
+    let export = &view.bom_export;
+    let project_name = view.project_data.get(view.selected_project_idx).map(|p| p.name.clone()).unwrap_or_default();
+    let popup_block = Block::default()
+        .title(format!("Export {project_name} BOM:"))
+        .title_bottom("(left/right) format / (tab) next field / (enter) export / (esc) close")
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(true));
+    let area = centered_rect(50, 40, rect);
+    utils::render_popup_block(f, area, popup_block);
+
+    let form_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .split(area);
+    let formats: Vec<Span> = ExportFormat::ALL.iter()
+        .map(|format| match *format == export.format {
+            true => Span::styled(format!(" {format} "), Style::default().fg(tailwind::SLATE.c200).bg(tailwind::BLUE.c900)),
+            false => Span::styled(format!(" {format} "), Style::default().fg(tailwind::SLATE.c500)),
+        })
+        .collect();
+    let format_b = Block::default().title("Format").borders(Borders::ALL);
+    f.render_widget(Paragraph::new(Line::from(formats)).block(format_b), form_chunks[0]);
+    let multiplier_b = Block::default()
+        .title("Build multiplier")
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(export.active_field == ExportField::Multiplier));
+    f.render_widget(Paragraph::new(export.multiplier.clone()).block(multiplier_b), form_chunks[1]);
+    let path_b = Block::default()
+        .title("File path")
+        .borders(Borders::ALL)
+        .border_style(get_block_border_style(export.active_field == ExportField::Path));
+    f.render_widget(Paragraph::new(export.path_text.clone()).block(path_b), form_chunks[2]);
+    if let Some(written) = &export.written {
+        f.render_widget(Paragraph::new(written.clone()).fg(tailwind::EMERALD.c400), form_chunks[3]);
+    }
+}
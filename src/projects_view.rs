@@ -1,28 +1,53 @@
+use std::path::Path;
 use crossterm::event::KeyCode;
 use ratatui::widgets::{ListState, TableState};
-use crate::{projects, utils};
-use crate::projects::{fetch_project_list, Project};
-use crate::projects_view::ProjectSubState::{BOMMode, CreateNewProject, ListMode, Main, AddToBOM};
+use crate::repository::BomRepository;
+use crate::build::{self, Build, BuildPlan, BuildStatus};
+use crate::errors::{ShikaError, ShikaResult};
+use crate::kicad_import::{self, ImportLine, LineResolution};
+use crate::bom_export::{self, ExportFormat};
+use crate::bom_import::{self, BomField, ColumnMapping, DryRun, ImportProfile, ImportTable};
+use crate::parts::Part;
+use crate::stock::StockInfo;
+#[cfg(test)]
+use crate::memory_repository::MemoryRepository;
+use crate::utils;
+use crate::projects::{self, Project, ProjectMetadata, ProjectPart, ProjectStatus};
+use crate::revisions::{self, BomDiffLine, ProjectRevision, RevisionLine};
+use crate::table_sort::SortState;
+use crate::projects_view::ProjectSubState::{
+    BOMMode, CreateNewProject, ListMode, Main, AddToBOM, EditBOMLine, RemoveBOMLine, SubstitutePN, RenameProject,
+    DuplicateProject, DeleteProject, BuildPlanner, BuildsMode, EditMetadata, Revisions, SnapshotRevision, ImportPath,
+    ImportReview, ImportPartNumber, WizardFile, WizardMapping, WizardProfileName, WizardDryRun, ExportBOM
+};
 use crate::utils::{ListMvmtDir, ScrollBarInfo};
 
+#[derive(PartialEq)]
 pub enum ProjectSubState {
     Main,
     ListMode,
     CreateNewProject,
+    RenameProject,
+    DuplicateProject,
+    DeleteProject,
     BOMMode,
     AddToBOM,
-}
-impl PartialEq for ProjectSubState {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Main, Main) => true,
-            (ListMode, ListMode) => true,
-            (BOMMode, BOMMode) => true,
-            (CreateNewProject, CreateNewProject) => true,
-            (AddToBOM, AddToBOM) => true,
-            _ => false,
-        }
-    }
+    EditBOMLine,
+    RemoveBOMLine,
+    SubstitutePN,
+    BuildPlanner,
+    BuildsMode,
+    EditMetadata,
+    Revisions,
+    SnapshotRevision,
+    ImportPath,
+    ImportReview,
+    ImportPartNumber,
+    WizardFile,
+    WizardMapping,
+    WizardProfileName,
+    WizardDryRun,
+    ExportBOM,
 }
 
 pub enum ATBFormField {
@@ -54,65 +79,326 @@ impl AddToBOMFormData {
     }
 }
 
+/// The BOM table columns, `bom_cell` gives their text.
+pub const BOM_COLUMNS: [&str; 8] = ["Part Number", "Designator(s)", "Qty", "Value", "Tolerance", "Package", "Label", "MFG"];
+
+pub fn bom_cell(ppart: &ProjectPart, column: usize) -> String {
+    let text = match column {
+        0 => return ppart.partnumber.clone(),
+        1 => return ppart.designators.clone(),
+        2 => return ppart.qty.to_string(),
+        3 => &ppart.part_info.value,
+        4 => &ppart.part_info.tolerance,
+        5 => &ppart.part_info.package,
+        6 => &ppart.part_info.label,
+        _ => &ppart.part_info.manufacturer,
+    };
+    text.clone().unwrap_or_default()
+}
+
+//Loads the BOM lines in the order the BOM table is sorted on
+fn fetch_bom(repo: &mut dyn BomRepository, project: &mut Project, sort: SortState) -> ShikaResult<()> {
+    repo.fetch_project_details(project)?;
+    sort.sort(&mut project.parts, bom_cell);
+    Ok(())
+}
+
+/// The BOM line being edited, removed or swapped for a substitute.
+pub struct BOMLineEdit {
+    pub partnumber: String,
+    pub designators: String,
+    pub qty: String,
+    //Only Designators and Qty are used, the part number is changed through a substitute
+    pub currently_editing: ATBFormField,
+    pub substitutes: Vec<String>,
+    pub substitute_list_state: ListState,
+}
+
+impl BOMLineEdit {
+    pub fn new() -> BOMLineEdit {
+        Self {
+            partnumber: "".to_string(),
+            designators: "".to_string(),
+            qty: "".to_string(),
+            currently_editing: ATBFormField::Designators,
+            substitutes: vec![],
+            substitute_list_state: ListState::default(),
+        }
+    }
+
+    fn from_project_part(ppart: &ProjectPart) -> BOMLineEdit {
+        let mut line = BOMLineEdit::new();
+        line.partnumber = ppart.partnumber.clone();
+        line.designators = ppart.designators.clone();
+        line.qty = ppart.qty.to_string();
+        line
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MetadataField {
+    Revision,
+    Description,
+    PcbFabPn,
+    AssemblyNotes,
+    Owner,
+}
+
+impl MetadataField {
+    pub fn next(&self) -> MetadataField {
+        match self {
+            MetadataField::Revision => MetadataField::Description,
+            MetadataField::Description => MetadataField::PcbFabPn,
+            MetadataField::PcbFabPn => MetadataField::AssemblyNotes,
+            MetadataField::AssemblyNotes => MetadataField::Owner,
+            MetadataField::Owner => MetadataField::Revision,
+        }
+    }
+
+    pub fn previous(&self) -> MetadataField {
+        match self {
+            MetadataField::Revision => MetadataField::Owner,
+            MetadataField::Description => MetadataField::Revision,
+            MetadataField::PcbFabPn => MetadataField::Description,
+            MetadataField::AssemblyNotes => MetadataField::PcbFabPn,
+            MetadataField::Owner => MetadataField::AssemblyNotes,
+        }
+    }
+}
+
+/// The metadata popup opened with `m`.
+pub struct EditingMetadata {
+    pub project_name: String,
+    pub metadata: ProjectMetadata,
+    pub active_field: MetadataField,
+}
+
+impl EditingMetadata {
+    pub fn new() -> EditingMetadata {
+        EditingMetadata {
+            project_name: "".to_string(),
+            metadata: ProjectMetadata::new(),
+            active_field: MetadataField::Revision,
+        }
+    }
+
+    fn active_text(&mut self) -> &mut String {
+        match self.active_field {
+            MetadataField::Revision => &mut self.metadata.revision,
+            MetadataField::Description => &mut self.metadata.description,
+            MetadataField::PcbFabPn => &mut self.metadata.pcb_fab_pn,
+            MetadataField::AssemblyNotes => &mut self.metadata.assembly_notes,
+            MetadataField::Owner => &mut self.metadata.owner,
+        }
+    }
+}
+
+/// The spreadsheet import wizard: pick a file, map its columns, check the dry run, import.
+pub struct ImportWizard {
+    pub path_text: String,
+    pub table: Option<ImportTable>,
+    pub mapping: ColumnMapping,
+    //Index into BomField::ALL
+    pub selected_field: usize,
+    pub profiles: Vec<ImportProfile>,
+    //The profile the mapping was last loaded from or saved as
+    pub profile_name: Option<String>,
+    pub profile_name_text: String,
+    pub dry_run: Option<DryRun>,
+}
+
+impl ImportWizard {
+    pub fn new() -> ImportWizard {
+        ImportWizard {
+            path_text: "".to_string(),
+            table: None,
+            mapping: ColumnMapping::default(),
+            selected_field: 0,
+            profiles: Vec::new(),
+            profile_name: None,
+            profile_name_text: "".to_string(),
+            dry_run: None,
+        }
+    }
+
+    pub fn selected_field(&self) -> BomField {
+        BomField::ALL[self.selected_field]
+    }
+
+    //Steps the selected field through no column and then every header
+    fn cycle_column(&mut self, forward: bool) {
+        let Some(table) = &self.table else { return };
+        let field = self.selected_field();
+        let count = table.headers.len() + 1;
+        let current = self.mapping.get(field)
+            .and_then(|header| table.column(header))
+            .map_or(0, |idx| idx + 1);
+        let next = match forward {
+            true => (current + 1) % count,
+            false => (current + count - 1) % count,
+        };
+        let header = next.checked_sub(1).map(|idx| table.headers[idx].clone());
+        self.mapping.set(field, header);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportField {
+    Multiplier,
+    Path,
+}
+
+/// The export popup opened with `x` in the BOM.
+pub struct BomExport {
+    pub format: ExportFormat,
+    pub multiplier: String,
+    pub path_text: String,
+    //The path follows the format and multiplier until it is typed in
+    pub path_edited: bool,
+    pub active_field: ExportField,
+    //Where the last export went, shown until the popup is closed
+    pub written: Option<String>,
+}
+
+impl BomExport {
+    pub fn new() -> BomExport {
+        BomExport {
+            format: ExportFormat::Csv,
+            multiplier: "1".to_string(),
+            path_text: "".to_string(),
+            path_edited: false,
+            active_field: ExportField::Multiplier,
+            written: None,
+        }
+    }
+
+    fn multiplier(&self) -> ShikaResult<i64> {
+        let multiplier = self.multiplier.trim().parse::<i64>()
+            .map_err(|_| ShikaError::Validation("The build multiplier must be a whole number".to_string()))?;
+        bom_export::validate_multiplier(multiplier)?;
+        Ok(multiplier)
+    }
+
+    fn update_default_path(&mut self, project: &Project) {
+        if !self.path_edited {
+            let multiplier = self.multiplier().unwrap_or(1);
+            self.path_text = bom_export::default_file_name(project, self.format, multiplier);
+        }
+    }
+}
+
+/// Two BOMs compared in the revisions popup, `from` is the older side.
+pub struct BomDiff {
+    pub from: String,
+    pub to: String,
+    pub lines: Vec<BomDiffLine>,
+}
+
 pub struct ProjectsView {
     pub sub_state: ProjectSubState,
     pub project_data: Vec<Project>,
     pub project_list_state: ListState,
     pub selected_project_idx: usize,
+    //Shared by the create, rename and duplicate popups
     pub new_project_name_text: String,
+    pub show_archived: bool,
     pub prj_lst_sbar_state: ScrollBarInfo,
     pub bom_table_state: TableState,
-    pub atb_form_data: AddToBOMFormData
+    pub bom_sort: SortState,
+    pub atb_form_data: AddToBOMFormData,
+    pub bom_line_edit: BOMLineEdit,
+    //Build planner popup, the stock is loaded once when it opens
+    pub build_units: String,
+    pub build_stock: Vec<StockInfo>,
+    pub build_plan: Option<BuildPlan>,
+    //Builds of the selected project, newest first
+    pub builds: Vec<Build>,
+    pub builds_table_state: TableState,
+    pub editing_metadata: EditingMetadata,
+    //Revisions popup, row 0 is the working BOM and the rest are `revisions`
+    pub revisions: Vec<ProjectRevision>,
+    pub revisions_table_state: TableState,
+    pub revision_name_text: String,
+    //Row picked as the older side of the next diff
+    pub diff_base: Option<usize>,
+    pub bom_diff: Option<BomDiff>,
+    //KiCad BOM import, the parts are loaded once for matching when the file is read
+    pub import_path_text: String,
+    pub import_lines: Vec<ImportLine>,
+    pub import_parts: Vec<Part>,
+    pub import_table_state: TableState,
+    pub import_pn_text: String,
+    pub import_wizard: ImportWizard,
+    pub bom_export: BomExport,
 }
 
 impl ProjectsView {
     pub fn new() -> Self {
         Self {
             sub_state: Main,
-            project_data: fetch_project_list(),
+            project_data: Vec::new(),
             project_list_state: ListState::default(),
             selected_project_idx: 0,
             new_project_name_text: String::from(""),
+            show_archived: false,
             prj_lst_sbar_state: ScrollBarInfo::new(),
             bom_table_state: TableState::default(),
-            atb_form_data: AddToBOMFormData::new()
-
+            bom_sort: SortState::default(),
+            atb_form_data: AddToBOMFormData::new(),
+            bom_line_edit: BOMLineEdit::new(),
+            build_units: "1".to_string(),
+            build_stock: Vec::new(),
+            build_plan: None,
+            builds: Vec::new(),
+            builds_table_state: TableState::default(),
+            editing_metadata: EditingMetadata::new(),
+            revisions: Vec::new(),
+            revisions_table_state: TableState::default(),
+            revision_name_text: String::from(""),
+            diff_base: None,
+            bom_diff: None,
+            import_path_text: String::from(""),
+            import_lines: Vec::new(),
+            import_parts: Vec::new(),
+            import_table_state: TableState::default(),
+            import_pn_text: String::from(""),
+            import_wizard: ImportWizard::new(),
+            bom_export: BomExport::new(),
         }
     }
 
-    pub fn refresh_list(&mut self) {
-        self.project_data = fetch_project_list();
+    pub fn refresh_list(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        self.project_data = repo.fetch_project_list()?;
+        if !self.show_archived {
+            self.project_data.retain(|project| project.status != ProjectStatus::Archived);
+        }
+        Ok(())
     }
 
     pub fn handle_main_keys(&mut self, key: KeyCode) {
-        match key {
-            KeyCode::Tab => {
-                self.sub_state = ListMode;
-            }
-            _ => {}
+        if key == KeyCode::Tab {
+            self.sub_state = ListMode;
         }
     }
 
-    pub fn handle_list_mode_keys(&mut self, key: KeyCode) {
+    pub fn handle_list_mode_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Esc => {
                 self.sub_state = Main;
             }
             KeyCode::Tab => {
+                if self.project_data.is_empty() {
+                    return Ok(());
+                }
                 if self.selected_project_idx != self.project_list_state.selected().unwrap_or(0) {
                     self.project_list_state.select(Some(self.selected_project_idx))
                 }
                 self.sub_state = BOMMode;
             }
             KeyCode::Enter => {
-                match self.project_list_state.selected() {
-                    Some(selected) => {
-                        let project = &mut self.project_data[selected];
-                        projects::fetch_project_details(project);
-                        self.selected_project_idx = selected;
-                        self.sub_state = BOMMode;
-                    }
-                    None => {}
+                if let Some(selected) = self.project_list_state.selected() {
+                    self.load_project(repo, selected)?;
+                    self.sub_state = BOMMode;
                 }
             }
             KeyCode::Down => {
@@ -129,57 +415,141 @@ impl ProjectsView {
                 self.prj_lst_sbar_state.scroll_position += 1
             }
             KeyCode::Up => {
-                match self.project_list_state.selected() {
-                    Some(selected) => {
-                        if selected > 0 {
-                            self.project_list_state.select(Some(selected - 1));
-                        }
-                        else { self.project_list_state.select(Some(0)); }
-                    }
-                    None => {
-                        self.project_list_state.select(Some(0));
-                    }
-                }
+                utils::exec_list_mvmt(ListMvmtDir::Less, &mut self.project_list_state, self.project_data.len());
                 if self.prj_lst_sbar_state.scroll_position > 0 {
                     self.prj_lst_sbar_state.scroll_position -= 1;
                 }
             }
             KeyCode::Char('r') => {
-                self.refresh_list()
+                self.refresh_list(repo)?;
             }
             KeyCode::Char('c') => {
                 //Create new project
+                self.new_project_name_text.clear();
                 self.sub_state = CreateNewProject;
             }
+            KeyCode::Char('e') => {
+                self.new_project_name_text = self.selected_project("rename")?.name.clone();
+                self.sub_state = RenameProject;
+            }
+            KeyCode::Char('m') => {
+                let project = self.selected_project("edit")?.clone();
+                self.start_editing_metadata(&project);
+            }
+            KeyCode::Char('D') => {
+                //Start from the old name, usually only the revision changes
+                self.new_project_name_text = self.selected_project("duplicate")?.name.clone();
+                self.sub_state = DuplicateProject;
+            }
+            KeyCode::Char('a') => {
+                let project = self.selected_project("archive")?;
+                let status = match project.status {
+                    ProjectStatus::Active => ProjectStatus::Archived,
+                    ProjectStatus::Archived => ProjectStatus::Active,
+                };
+                let name = project.name.clone();
+                repo.set_project_status(&name, status)?;
+                self.refresh_after_removal(repo)?;
+            }
+            KeyCode::Char('h') => {
+                self.show_archived = !self.show_archived;
+                self.refresh_list(repo)?;
+                self.select_clamped(repo, self.project_list_state.selected().unwrap_or(0))?;
+            }
             KeyCode::Char('d') => {
-                //Delete project??
+                let idx = self.project_list_state.selected()
+                    .filter(|idx| *idx < self.project_data.len())
+                    .ok_or_else(|| ShikaError::Validation("Select a project to delete".to_string()))?;
+                //Loaded so the confirmation can say how many BOM lines go with it
+                repo.fetch_project_details(&mut self.project_data[idx])?;
+                self.sub_state = DeleteProject;
             }
             _ => {}
         }
+        Ok(())
     }
-    pub fn handle_bom_mode_keys(&mut self, key: KeyCode) {
+    pub fn handle_bom_mode_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Esc => {
                 self.sub_state = Main;
             }
             KeyCode::Tab => {
-                self.sub_state = ListMode;
+                self.sub_state = BuildsMode;
             }
             KeyCode::Char('c') => {
-                self.atb_form_data.pns_not_in_project = projects::fetch_pns_not_in_project(
-                    &self.project_data[self.selected_project_idx]);
+                self.atb_form_data = AddToBOMFormData::new();
+                self.atb_form_data.pns_not_in_project = repo.fetch_pns_not_in_project(
+                    &self.project_data[self.selected_project_idx])?;
                 self.sub_state = AddToBOM;
             }
+            KeyCode::Char('e') => {
+                self.bom_line_edit = BOMLineEdit::from_project_part(self.selected_bom_line("edit")?);
+                self.sub_state = EditBOMLine;
+            }
+            KeyCode::Char('d') => {
+                self.bom_line_edit = BOMLineEdit::from_project_part(self.selected_bom_line("remove")?);
+                self.sub_state = RemoveBOMLine;
+            }
+            KeyCode::Char('s') => {
+                let line = BOMLineEdit::from_project_part(self.selected_bom_line("substitute")?);
+                self.bom_line_edit = line;
+                self.bom_line_edit.substitutes = repo.fetch_pns_not_in_project(
+                    &self.project_data[self.selected_project_idx])?;
+                self.sub_state = SubstitutePN;
+            }
+            KeyCode::Char('m') => {
+                if let Some(project) = self.project_data.get(self.selected_project_idx).cloned() {
+                    self.start_editing_metadata(&project);
+                }
+            }
+            KeyCode::Char('i') => {
+                self.sub_state = ImportPath;
+            }
+            KeyCode::Char('I') => {
+                self.sub_state = WizardFile;
+            }
+            KeyCode::Char('x') => {
+                if let Some(project) = self.project_data.get(self.selected_project_idx) {
+                    self.bom_export.written = None;
+                    self.bom_export.path_edited = false;
+                    self.bom_export.update_default_path(project);
+                    self.sub_state = ExportBOM;
+                }
+            }
+            KeyCode::Char('v') => {
+                let project_name = self.selected_project_name()?;
+                self.revisions = repo.fetch_revisions(&project_name)?;
+                self.revisions_table_state.select(Some(0));
+                self.diff_base = None;
+                self.bom_diff = None;
+                self.sub_state = Revisions;
+            }
+            KeyCode::Char('o') => {
+                let project = &mut self.project_data[self.selected_project_idx];
+                let selected_pn = self.bom_table_state.selected()
+                    .and_then(|idx| project.parts.get(idx))
+                    .map(|ppart| ppart.partnumber.clone());
+                self.bom_sort.cycle(BOM_COLUMNS.len());
+                fetch_bom(repo, project, self.bom_sort)?;
+                if let Some(pn) = selected_pn {
+                    self.bom_table_state.select(project.parts.iter().position(|ppart| ppart.partnumber == pn));
+                }
+            }
+            KeyCode::Char('b') => {
+                self.build_stock = repo.fetch_stock_info()?;
+                self.update_build_plan();
+                self.sub_state = BuildPlanner;
+            }
             KeyCode::Up => {
                 let parts_list = &self.project_data[self.selected_project_idx].parts;
                 match self.bom_table_state.selected() {
                     Some(selected) => {
-                        if selected > 0 && parts_list.len() > 0 {
+                        if selected > 0 && !parts_list.is_empty() {
                             self.bom_table_state.select(Some(selected - 1));
                         }
                     }
                     None => {
-                        if parts_list.len() > 0 {
+                        if !parts_list.is_empty() {
                             self.bom_table_state.select(Some(0));
                         }
                     }
@@ -194,7 +564,7 @@ impl ProjectsView {
                         }
                     }
                     None => {
-                        if parts_list.len() > 0 {
+                        if !parts_list.is_empty() {
                             self.bom_table_state.select(Some(0));
                         }
                     }
@@ -202,10 +572,13 @@ impl ProjectsView {
             }
             _ => {}
         }
+        Ok(())
     }
-    pub fn handle_create_project_keys(&mut self, key: KeyCode) {
+    /// Name input of the create, rename and duplicate popups.
+    pub fn handle_project_name_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Esc => {
+                self.new_project_name_text.clear();
                 self.sub_state = ListMode;
             }
             KeyCode::Char(character) => {
@@ -215,20 +588,37 @@ impl ProjectsView {
                 self.new_project_name_text.pop();
             }
             KeyCode::Enter => {
-                projects::create_new_project_name(self.new_project_name_text.clone());
+                let name = self.new_project_name_text.trim().to_string();
+                match self.sub_state {
+                    RenameProject => {
+                        let old_name = self.selected_project("rename")?.name.clone();
+                        repo.rename_project(&old_name, &name)?;
+                    }
+                    DuplicateProject => {
+                        let source = self.selected_project("duplicate")?.name.clone();
+                        repo.duplicate_project(&source, &name)?;
+                    }
+                    _ => {
+                        repo.create_new_project_name(name.clone())?;
+                    }
+                }
                 self.new_project_name_text.clear();
-                self.refresh_list();
-                self.select_last_idx();
+                self.refresh_list(repo)?;
+                self.select_project(repo, &name)?;
                 self.sub_state = BOMMode;
             }
             _ => {}
         }
+        Ok(())
     }
-    pub fn handle_add_to_bom_keys(&mut self, key: KeyCode) {
+    pub fn handle_add_to_bom_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Esc => {
                 self.sub_state = BOMMode;
             }
+            KeyCode::Enter => {
+                self.add_to_bom(repo)?;
+            }
             KeyCode::Tab => {
                 match self.atb_form_data.currently_editing {
                     ATBFormField::PN => {
@@ -291,15 +681,1205 @@ impl ProjectsView {
             }
             _ => {}
         }
+        Ok(())
+    }
+
+    fn add_to_bom(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        let form = &mut self.atb_form_data;
+        let pn = form.pnip_list_state.selected()
+            .and_then(|idx| form.pns_not_in_project.get(idx))
+            .ok_or_else(|| ShikaError::Validation("Select a part number to add".to_string()))?
+            .clone();
+        let qty = projects::validate_bom_line(&form.qty, &form.designators)?;
+        form.selected_pn = pn.clone();
+        let ppart = ProjectPart {
+            partnumber: pn.clone(),
+            designators: form.designators.trim().to_string(),
+            qty,
+            part_info: Part::new(),
+        };
+        let project = &mut self.project_data[self.selected_project_idx];
+        repo.add_pn_to_project(project, &ppart)?;
+        fetch_bom(repo, project, self.bom_sort)?;
+        let new_idx = project.parts.iter().position(|part| part.partnumber == pn);
+        self.bom_table_state.select(new_idx);
+        self.atb_form_data = AddToBOMFormData::new();
+        self.sub_state = BOMMode;
+        Ok(())
+    }
+
+    pub fn handle_edit_bom_line_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let line = &mut self.bom_line_edit;
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Tab | KeyCode::Up | KeyCode::Down => {
+                line.currently_editing = match line.currently_editing {
+                    ATBFormField::Designators => ATBFormField::Qty,
+                    _ => ATBFormField::Designators,
+                };
+            }
+            KeyCode::Char(character) => {
+                match line.currently_editing {
+                    ATBFormField::Qty => line.qty.push(character),
+                    _ => line.designators.push(character),
+                }
+            }
+            KeyCode::Backspace => {
+                match line.currently_editing {
+                    ATBFormField::Qty => line.qty.pop(),
+                    _ => line.designators.pop(),
+                };
+            }
+            KeyCode::Enter => {
+                let qty = projects::validate_bom_line(&line.qty, &line.designators)?;
+                let designators = line.designators.trim().to_string();
+                let pn = line.partnumber.clone();
+                self.update_bom_line(repo, &pn, pn.clone(), designators, qty)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_remove_bom_line_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc | KeyCode::Char('n') => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Enter | KeyCode::Char('y') => {
+                let project = &mut self.project_data[self.selected_project_idx];
+                repo.remove_pn_from_project(project, &self.bom_line_edit.partnumber)?;
+                fetch_bom(repo, project, self.bom_sort)?;
+                //Keep the cursor on the line that moved up into the removed one's place
+                let selected = self.bom_table_state.selected().unwrap_or(0);
+                self.bom_table_state.select(match project.parts.len() {
+                    0 => None,
+                    len => Some(selected.min(len - 1)),
+                });
+                self.sub_state = BOMMode;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_substitute_pn_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let line = &mut self.bom_line_edit;
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Up => {
+                utils::exec_list_mvmt(ListMvmtDir::Less, &mut line.substitute_list_state, line.substitutes.len());
+            }
+            KeyCode::Down => {
+                utils::exec_list_mvmt(ListMvmtDir::Greater, &mut line.substitute_list_state, line.substitutes.len());
+            }
+            KeyCode::Enter => {
+                let substitute = line.substitute_list_state.selected()
+                    .and_then(|idx| line.substitutes.get(idx))
+                    .ok_or_else(|| ShikaError::Validation("Select the substitute part number".to_string()))?
+                    .clone();
+                //The designators and qty carry over to the substitute as they are
+                let qty = line.qty.parse().unwrap_or(0);
+                let designators = line.designators.clone();
+                let pn = line.partnumber.clone();
+                self.update_bom_line(repo, &pn, substitute, designators, qty)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_build_planner_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Char(character) if character.is_ascii_digit() => {
+                self.build_units.push(character);
+                self.update_build_plan();
+            }
+            KeyCode::Backspace => {
+                self.build_units.pop();
+                self.update_build_plan();
+            }
+            KeyCode::Char('r') => {
+                self.build_stock = repo.fetch_stock_info()?;
+                self.update_build_plan();
+            }
+            KeyCode::Enter => {
+                let units = self.build_units.parse::<i32>().unwrap_or(0);
+                let name = self.project_data[self.selected_project_idx].name.clone();
+                repo.start_build(&name, units)?;
+                self.builds = repo.fetch_builds(&name)?;
+                //Newest first, so the build just started is on top
+                self.builds_table_state.select(Some(0));
+                self.sub_state = BuildsMode;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_edit_metadata_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let editing = &mut self.editing_metadata;
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Tab | KeyCode::Down => {
+                editing.active_field = editing.active_field.next();
+            }
+            KeyCode::BackTab | KeyCode::Up => {
+                editing.active_field = editing.active_field.previous();
+            }
+            KeyCode::Char(character) => {
+                editing.active_text().push(character);
+            }
+            KeyCode::Backspace => {
+                editing.active_text().pop();
+            }
+            KeyCode::Enter => {
+                let name = editing.project_name.clone();
+                let mut metadata = editing.metadata.clone();
+                for text in [&mut metadata.revision, &mut metadata.description, &mut metadata.pcb_fab_pn,
+                             &mut metadata.assembly_notes, &mut metadata.owner] {
+                    *text = text.trim().to_string();
+                }
+                repo.update_project_metadata(&name, &metadata)?;
+                self.refresh_list(repo)?;
+                self.select_project(repo, &name)?;
+                self.sub_state = BOMMode;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn start_editing_metadata(&mut self, project: &Project) {
+        self.editing_metadata = EditingMetadata::new();
+        self.editing_metadata.project_name = project.name.clone();
+        self.editing_metadata.metadata = project.metadata.clone();
+        self.sub_state = EditMetadata;
+    }
+
+    pub fn handle_revisions_keys(&mut self, key: KeyCode) -> ShikaResult<()> {
+        let row_count = self.revisions.len() + 1;
+        match key {
+            KeyCode::Esc => {
+                if self.bom_diff.is_some() || self.diff_base.is_some() {
+                    self.bom_diff = None;
+                    self.diff_base = None;
+                } else {
+                    self.sub_state = BOMMode;
+                }
+            }
+            KeyCode::Up => {
+                let selected = self.revisions_table_state.selected().unwrap_or(0);
+                self.revisions_table_state.select(Some(selected.saturating_sub(1)));
+            }
+            KeyCode::Down => {
+                let selected = self.revisions_table_state.selected().unwrap_or(0);
+                self.revisions_table_state.select(Some((selected + 1).min(row_count - 1)));
+            }
+            KeyCode::Char('n') => {
+                self.revision_name_text = self.project_data.get(self.selected_project_idx)
+                    .map(|project| project.metadata.revision.clone())
+                    .unwrap_or_default();
+                self.sub_state = SnapshotRevision;
+            }
+            KeyCode::Enter => {
+                let selected = self.revisions_table_state.selected().unwrap_or(0);
+                match self.diff_base {
+                    None => {
+                        self.diff_base = Some(selected);
+                        self.bom_diff = None;
+                    }
+                    Some(base) if base == selected => {
+                        return Err(ShikaError::Validation("Pick a different revision to compare against".to_string()));
+                    }
+                    Some(base) => {
+                        let (from, old) = self.revision_row(base);
+                        let (to, new) = self.revision_row(selected);
+                        self.bom_diff = Some(BomDiff {
+                            from,
+                            to,
+                            lines: revisions::diff_boms(&old, &new),
+                        });
+                        self.diff_base = None;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
     }
 
-    fn select_last_idx(&mut self) {
-        if self.project_data.len() == 0 {return};
+    pub fn handle_snapshot_revision_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = Revisions;
+            }
+            KeyCode::Char(character) => {
+                self.revision_name_text.push(character);
+            }
+            KeyCode::Backspace => {
+                self.revision_name_text.pop();
+            }
+            KeyCode::Enter => {
+                let project_name = self.selected_project_name()?;
+                repo.create_revision(&project_name, self.revision_name_text.trim())?;
+                self.revisions = repo.fetch_revisions(&project_name)?;
+                self.revisions_table_state.select(Some(1));
+                self.sub_state = Revisions;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 
-        let idx = self.project_data.len() - 1;
+    /// The label and BOM lines of a row in the revisions popup, row 0 is the working BOM.
+    pub fn revision_row(&self, idx: usize) -> (String, Vec<RevisionLine>) {
+        match idx.checked_sub(1).and_then(|idx| self.revisions.get(idx)) {
+            Some(revision) => (format!("Rev {}", revision.revision), revision.lines.clone()),
+            None => {
+                let parts = self.project_data.get(self.selected_project_idx)
+                    .map(|project| project.parts.as_slice())
+                    .unwrap_or_default();
+                ("Working BOM".to_string(), RevisionLine::from_parts(parts))
+            }
+        }
+    }
+
+    fn selected_project_name(&self) -> ShikaResult<String> {
+        self.project_data.get(self.selected_project_idx)
+            .map(|project| project.name.clone())
+            .ok_or_else(|| ShikaError::Validation("Open a project first".to_string()))
+    }
+
+    pub fn handle_import_path_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Char(character) => {
+                self.import_path_text.push(character);
+            }
+            KeyCode::Backspace => {
+                self.import_path_text.pop();
+            }
+            KeyCode::Enter => {
+                let components = kicad_import::read_kicad_bom(Path::new(self.import_path_text.trim()))?;
+                if components.is_empty() {
+                    return Err(ShikaError::Validation("The file has no components to import".to_string()));
+                }
+                self.import_parts = repo.fetch_all_parts()?;
+                self.import_lines = kicad_import::group_components(&components);
+                kicad_import::match_lines(&mut self.import_lines, &self.import_parts);
+                self.import_table_state.select(Some(0));
+                self.sub_state = ImportReview;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_import_review_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let selected = self.import_table_state.selected().unwrap_or(0);
+        match key {
+            KeyCode::Esc => {
+                self.import_lines.clear();
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Up => {
+                self.import_table_state.select(Some(selected.saturating_sub(1)));
+            }
+            KeyCode::Down if selected + 1 < self.import_lines.len() => {
+                self.import_table_state.select(Some(selected + 1));
+            }
+            KeyCode::Left | KeyCode::Right => {
+                let line = self.selected_import_line()?;
+                if line.candidates.is_empty() {
+                    return Err(ShikaError::Validation(
+                        format!("No parts have the value {}, use (e) to enter a part number", line.value)));
+                }
+                let count = line.candidates.len();
+                let current = line.resolution.partnumber()
+                    .and_then(|pn| line.candidates.iter().position(|candidate| candidate == pn));
+                let next = match (current, key) {
+                    (Some(idx), KeyCode::Right) => (idx + 1) % count,
+                    (Some(idx), _) => (idx + count - 1) % count,
+                    (None, _) => 0,
+                };
+                line.resolution = LineResolution::Mapped(line.candidates[next].clone());
+            }
+            KeyCode::Char('e') => {
+                let line = self.selected_import_line()?;
+                self.import_pn_text = line.resolution.partnumber()
+                    .or(line.partnumber.as_deref())
+                    .unwrap_or("")
+                    .to_string();
+                self.sub_state = ImportPartNumber;
+            }
+            KeyCode::Char('x') => {
+                let parts = &self.import_parts;
+                let line = self.import_lines.get_mut(selected)
+                    .ok_or_else(|| ShikaError::Validation("Select a line first".to_string()))?;
+                if line.resolution == LineResolution::Skip {
+                    kicad_import::match_line(line, parts);
+                } else {
+                    line.resolution = LineResolution::Skip;
+                }
+            }
+            KeyCode::Enter => {
+                let (new_parts, pparts) = kicad_import::to_project_parts(&self.import_lines)?;
+                let project = &mut self.project_data[self.selected_project_idx];
+                repo.import_project_parts(project, &new_parts, &pparts)?;
+                fetch_bom(repo, project, self.bom_sort)?;
+                self.import_lines.clear();
+                self.import_path_text.clear();
+                self.sub_state = BOMMode;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_import_part_number_keys(&mut self, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = ImportReview;
+            }
+            KeyCode::Char(character) => {
+                self.import_pn_text.push(character);
+            }
+            KeyCode::Backspace => {
+                self.import_pn_text.pop();
+            }
+            KeyCode::Enter => {
+                let pn = self.import_pn_text.trim().to_string();
+                if pn.is_empty() {
+                    return Err(ShikaError::Validation("Part number cannot be empty".to_string()));
+                }
+                let known = self.import_parts.iter().any(|part| part.part_number == pn);
+                self.selected_import_line()?.resolution = match known {
+                    true => LineResolution::Mapped(pn),
+                    false => LineResolution::NewPart(pn),
+                };
+                self.sub_state = ImportReview;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_export_bom_keys(&mut self, key: KeyCode) -> ShikaResult<()> {
+        let export = &mut self.bom_export;
+        let project = self.project_data.get(self.selected_project_idx)
+            .ok_or_else(|| ShikaError::Validation("Open a project first".to_string()))?;
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Tab => {
+                export.active_field = match export.active_field {
+                    ExportField::Multiplier => ExportField::Path,
+                    ExportField::Path => ExportField::Multiplier,
+                };
+            }
+            KeyCode::Left | KeyCode::Right => {
+                export.format = match key {
+                    KeyCode::Right => export.format.next(),
+                    _ => export.format.previous(),
+                };
+                export.update_default_path(project);
+            }
+            KeyCode::Char(character) => {
+                match export.active_field {
+                    ExportField::Multiplier if character.is_ascii_digit() => {
+                        export.multiplier.push(character);
+                        export.update_default_path(project);
+                    }
+                    ExportField::Multiplier => {}
+                    ExportField::Path => {
+                        export.path_text.push(character);
+                        export.path_edited = true;
+                    }
+                }
+            }
+            KeyCode::Backspace => {
+                match export.active_field {
+                    ExportField::Multiplier => {
+                        export.multiplier.pop();
+                        export.update_default_path(project);
+                    }
+                    ExportField::Path => {
+                        export.path_text.pop();
+                        export.path_edited = true;
+                    }
+                }
+            }
+            KeyCode::Enter => {
+                let path = export.path_text.trim().to_string();
+                if path.is_empty() {
+                    return Err(ShikaError::Validation("File path cannot be empty".to_string()));
+                }
+                bom_export::write_bom(project, export.format, export.multiplier()?, Path::new(&path))?;
+                export.written = Some(format!("Wrote {} line(s) to {path}", project.parts.len()));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_wizard_file_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let wizard = &mut self.import_wizard;
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Char(character) => {
+                wizard.path_text.push(character);
+            }
+            KeyCode::Backspace => {
+                wizard.path_text.pop();
+            }
+            KeyCode::Enter => {
+                let table = bom_import::read_table(Path::new(wizard.path_text.trim()))?;
+                wizard.profiles = repo.fetch_import_profiles()?;
+                //The first saved profile that fits the headers, otherwise a guess from the header names
+                match wizard.profiles.iter().find(|profile| profile.mapping.fits(&table.headers)) {
+                    Some(profile) => {
+                        wizard.mapping = profile.mapping.clone();
+                        wizard.profile_name = Some(profile.name.clone());
+                    }
+                    None => {
+                        wizard.mapping = ColumnMapping::auto(&table.headers);
+                        wizard.profile_name = None;
+                    }
+                }
+                wizard.table = Some(table);
+                wizard.selected_field = 0;
+                wizard.dry_run = None;
+                self.sub_state = WizardMapping;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_wizard_mapping_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let wizard = &mut self.import_wizard;
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = BOMMode;
+            }
+            KeyCode::Up => {
+                wizard.selected_field = wizard.selected_field.saturating_sub(1);
+            }
+            KeyCode::Down => {
+                wizard.selected_field = (wizard.selected_field + 1).min(BomField::ALL.len() - 1);
+            }
+            KeyCode::Left => {
+                wizard.cycle_column(false);
+            }
+            KeyCode::Right => {
+                wizard.cycle_column(true);
+            }
+            KeyCode::Char('p') => {
+                if wizard.profiles.is_empty() {
+                    return Err(ShikaError::Validation("No profiles have been saved yet".to_string()));
+                }
+                let next = wizard.profile_name.as_ref()
+                    .and_then(|name| wizard.profiles.iter().position(|profile| &profile.name == name))
+                    .map_or(0, |idx| (idx + 1) % wizard.profiles.len());
+                let profile = &wizard.profiles[next];
+                wizard.mapping = profile.mapping.clone();
+                wizard.profile_name = Some(profile.name.clone());
+            }
+            KeyCode::Char('s') => {
+                wizard.profile_name_text = wizard.profile_name.clone().unwrap_or_default();
+                self.sub_state = WizardProfileName;
+            }
+            KeyCode::Enter => {
+                let table = wizard.table.as_ref()
+                    .ok_or_else(|| ShikaError::Validation("Open a file first".to_string()))?;
+                let project = &self.project_data[self.selected_project_idx];
+                let parts = repo.fetch_all_parts()?;
+                wizard.dry_run = Some(bom_import::dry_run(table, &wizard.mapping, &parts, project)?);
+                self.sub_state = WizardDryRun;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_wizard_profile_name_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let wizard = &mut self.import_wizard;
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = WizardMapping;
+            }
+            KeyCode::Char(character) => {
+                wizard.profile_name_text.push(character);
+            }
+            KeyCode::Backspace => {
+                wizard.profile_name_text.pop();
+            }
+            KeyCode::Enter => {
+                let profile = ImportProfile {
+                    name: wizard.profile_name_text.trim().to_string(),
+                    mapping: wizard.mapping.clone(),
+                };
+                repo.save_import_profile(&profile)?;
+                wizard.profiles = repo.fetch_import_profiles()?;
+                wizard.profile_name = Some(profile.name);
+                self.sub_state = WizardMapping;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_wizard_dry_run_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = WizardMapping;
+            }
+            KeyCode::Enter => {
+                let run = self.import_wizard.dry_run.as_ref()
+                    .ok_or_else(|| ShikaError::Validation("Run the dry run first".to_string()))?;
+                if !run.can_commit() {
+                    return Err(ShikaError::Validation(match run.lines.is_empty() {
+                        true => "There are no lines to import".to_string(),
+                        false => format!("Fix the {} problem(s) before importing", run.problems.len()),
+                    }));
+                }
+                let project = &mut self.project_data[self.selected_project_idx];
+                repo.import_project_parts(project, &run.new_parts, &run.lines)?;
+                fetch_bom(repo, project, self.bom_sort)?;
+                self.import_wizard = ImportWizard::new();
+                self.sub_state = BOMMode;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn selected_import_line(&mut self) -> ShikaResult<&mut ImportLine> {
+        self.import_table_state.selected()
+            .and_then(|idx| self.import_lines.get_mut(idx))
+            .ok_or_else(|| ShikaError::Validation("Select a line first".to_string()))
+    }
+
+    pub fn handle_builds_mode_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc => {
+                self.sub_state = Main;
+            }
+            KeyCode::Tab => {
+                self.sub_state = ListMode;
+            }
+            KeyCode::Up => {
+                if let Some(selected) = self.builds_table_state.selected() {
+                    self.builds_table_state.select(Some(selected.saturating_sub(1)));
+                } else if !self.builds.is_empty() {
+                    self.builds_table_state.select(Some(0));
+                }
+            }
+            KeyCode::Down => {
+                match self.builds_table_state.selected() {
+                    Some(selected) if selected + 1 < self.builds.len() => {
+                        self.builds_table_state.select(Some(selected + 1));
+                    }
+                    None if !self.builds.is_empty() => {
+                        self.builds_table_state.select(Some(0));
+                    }
+                    _ => {}
+                }
+            }
+            KeyCode::Char('c') => {
+                let id = self.selected_build("complete")?.id;
+                repo.complete_build(id)?;
+                self.reload_builds(repo)?;
+            }
+            KeyCode::Char('x') => {
+                let id = self.selected_build("cancel")?.id;
+                repo.cancel_build(id)?;
+                self.reload_builds(repo)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn selected_build(&self, action: &str) -> ShikaResult<&Build> {
+        let build = self.builds_table_state.selected()
+            .and_then(|idx| self.builds.get(idx))
+            .ok_or_else(|| ShikaError::Validation(format!("Select a build to {action}")))?;
+        build::ensure_in_progress(build.id, build.status)?;
+        Ok(build)
+    }
+
+    fn reload_builds(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        if let Some(project) = self.project_data.get(self.selected_project_idx) {
+            self.builds = repo.fetch_builds(&project.name)?;
+        }
+        Ok(())
+    }
+
+    /// How many units are reserved by builds still in progress.
+    pub fn units_in_progress(&self) -> i32 {
+        self.builds.iter()
+            .filter(|build| build.status == BuildStatus::InProgress)
+            .map(|build| build.quantity)
+            .sum()
+    }
+
+    //No plan while the unit count is empty or zero
+    fn update_build_plan(&mut self) {
+        self.build_plan = match (self.build_units.parse::<i32>(), self.project_data.get(self.selected_project_idx)) {
+            (Ok(units), Some(project)) if units > 0 => Some(build::plan_build(project, &self.build_stock, units)),
+            _ => None,
+        };
+    }
+
+    fn selected_bom_line(&self, action: &str) -> ShikaResult<&ProjectPart> {
+        self.project_data.get(self.selected_project_idx)
+            .and_then(|project| project.parts.get(self.bom_table_state.selected()?))
+            .ok_or_else(|| ShikaError::Validation(format!("Select a BOM line to {action}")))
+    }
+
+    fn update_bom_line(&mut self, repo: &mut dyn BomRepository, old_pn: &str, pn: String, designators: String,
+                       qty: i32) -> ShikaResult<()> {
+        let ppart = ProjectPart {
+            partnumber: pn,
+            designators,
+            qty,
+            part_info: Part::new(),
+        };
+        let project = &mut self.project_data[self.selected_project_idx];
+        repo.update_project_part(project, old_pn, &ppart)?;
+        fetch_bom(repo, project, self.bom_sort)?;
+        let idx = project.parts.iter().position(|part| part.partnumber == ppart.partnumber);
+        self.bom_table_state.select(idx);
+        self.sub_state = BOMMode;
+        Ok(())
+    }
+
+    pub fn handle_delete_project_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc | KeyCode::Char('n') => {
+                self.sub_state = ListMode;
+            }
+            KeyCode::Char('y') => {
+                let name = self.selected_project("delete")?.name.clone();
+                repo.delete_project(&name)?;
+                self.refresh_after_removal(repo)?;
+                self.sub_state = ListMode;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn selected_project(&self, action: &str) -> ShikaResult<&Project> {
+        self.project_list_state.selected()
+            .and_then(|idx| self.project_data.get(idx))
+            .ok_or_else(|| ShikaError::Validation(format!("Select a project to {action}")))
+    }
+
+    fn select_project(&mut self, repo: &mut dyn BomRepository, name: &str) -> ShikaResult<()> {
+        match self.project_data.iter().position(|project| project.name == name) {
+            Some(idx) => self.select_clamped(repo, idx),
+            None => Ok(()),
+        }
+    }
+
+    //Reloads the list after the selected project was deleted or hidden by archiving it
+    fn refresh_after_removal(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        let idx = self.project_list_state.selected().unwrap_or(0);
+        self.refresh_list(repo)?;
+        self.select_clamped(repo, idx)
+    }
+
+    fn select_clamped(&mut self, repo: &mut dyn BomRepository, idx: usize) -> ShikaResult<()> {
+        self.bom_table_state.select(None);
+        if self.project_data.is_empty() {
+            self.project_list_state.select(None);
+            self.selected_project_idx = 0;
+            return Ok(());
+        }
+        let idx = idx.min(self.project_data.len() - 1);
         self.project_list_state.select(Some(idx));
+        self.load_project(repo, idx)
+    }
+
+    //Loads the BOM lines and builds of the project shown in the detail panel
+    fn load_project(&mut self, repo: &mut dyn BomRepository, idx: usize) -> ShikaResult<()> {
         let project = &mut self.project_data[idx];
-        projects::fetch_project_details(project);
+        fetch_bom(repo, project, self.bom_sort)?;
+        self.builds = repo.fetch_builds(&project.name)?;
+        self.builds_table_state.select(None);
         self.selected_project_idx = idx;
+        Ok(())
+    }
+}
+#[test]
+fn test_open_project_bom() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    assert!(view.sub_state == ListMode);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BOMMode);
+    let project = &view.project_data[view.selected_project_idx];
+    assert_eq!(project.parts.len(), 1);
+    assert_eq!(project.parts[0].part_info.value.as_deref(), Some("10k"));
+}
+
+#[test]
+fn test_create_project_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Char('c')).unwrap();
+    for c in "Rev B".chars() {
+        view.handle_project_name_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_project_name_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BOMMode);
+    assert_eq!(view.project_data[view.selected_project_idx].name, "Rev B");
+}
+
+#[test]
+fn test_add_to_bom_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('c')).unwrap();
+    assert!(view.sub_state == AddToBOM);
+    let new_pn = view.atb_form_data.pns_not_in_project[0].clone();
+    //Nothing selected yet
+    assert!(view.handle_add_to_bom_keys(&mut repo, KeyCode::Enter).is_err());
+    view.handle_add_to_bom_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_add_to_bom_keys(&mut repo, KeyCode::Tab).unwrap();
+    for c in "C1-C3".chars() {
+        view.handle_add_to_bom_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_add_to_bom_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_add_to_bom_keys(&mut repo, KeyCode::Char('2')).unwrap();
+    //Three designators for a qty of two
+    assert!(view.handle_add_to_bom_keys(&mut repo, KeyCode::Enter).is_err());
+    assert!(view.sub_state == AddToBOM);
+    view.handle_add_to_bom_keys(&mut repo, KeyCode::Backspace).unwrap();
+    view.handle_add_to_bom_keys(&mut repo, KeyCode::Char('3')).unwrap();
+    view.handle_add_to_bom_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BOMMode);
+    let project = &view.project_data[view.selected_project_idx];
+    assert_eq!(project.parts.len(), 2);
+    let selected = &project.parts[view.bom_table_state.selected().unwrap()];
+    assert_eq!(selected.partnumber, new_pn);
+    assert_eq!(selected.qty, 3);
+    assert_eq!(selected.designators, "C1-C3");
+}
+
+#[test]
+fn test_edit_substitute_and_remove_bom_line() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    //Nothing selected yet
+    assert!(view.handle_bom_mode_keys(&mut repo, KeyCode::Char('e')).is_err());
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Down).unwrap();
+
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('e')).unwrap();
+    assert!(view.sub_state == EditBOMLine);
+    view.bom_line_edit.designators.clear();
+    for c in "R1-R3".chars() {
+        view.handle_edit_bom_line_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_edit_bom_line_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.bom_line_edit.qty.clear();
+    view.handle_edit_bom_line_keys(&mut repo, KeyCode::Char('4')).unwrap();
+    assert!(view.handle_edit_bom_line_keys(&mut repo, KeyCode::Enter).is_err());
+    view.handle_edit_bom_line_keys(&mut repo, KeyCode::Backspace).unwrap();
+    view.handle_edit_bom_line_keys(&mut repo, KeyCode::Char('3')).unwrap();
+    view.handle_edit_bom_line_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BOMMode);
+    let line = &view.project_data[view.selected_project_idx].parts[0];
+    assert_eq!((line.designators.as_str(), line.qty), ("R1-R3", 3));
+
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('s')).unwrap();
+    assert!(view.sub_state == SubstitutePN);
+    let substitute = view.bom_line_edit.substitutes[0].clone();
+    view.handle_substitute_pn_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_substitute_pn_keys(&mut repo, KeyCode::Enter).unwrap();
+    let line = &view.project_data[view.selected_project_idx].parts[0];
+    assert_eq!(line.partnumber, substitute);
+    assert_eq!((line.designators.as_str(), line.qty), ("R1-R3", 3));
+
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('d')).unwrap();
+    assert!(view.sub_state == RemoveBOMLine);
+    view.handle_remove_bom_line_keys(&mut repo, KeyCode::Char('n')).unwrap();
+    assert_eq!(view.project_data[view.selected_project_idx].parts.len(), 1);
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('d')).unwrap();
+    view.handle_remove_bom_line_keys(&mut repo, KeyCode::Char('y')).unwrap();
+    assert!(view.project_data[view.selected_project_idx].parts.is_empty());
+    assert_eq!(view.bom_table_state.selected(), None);
+}
+
+#[test]
+fn test_project_lifecycle() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+
+    view.handle_list_mode_keys(&mut repo, KeyCode::Char('D')).unwrap();
+    assert!(view.sub_state == DuplicateProject);
+    for c in " Rev B".chars() {
+        view.handle_project_name_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_project_name_keys(&mut repo, KeyCode::Enter).unwrap();
+    let copy = &view.project_data[view.selected_project_idx];
+    assert_eq!(copy.name, "Sample Board Rev B");
+    assert_eq!(copy.parts.len(), 1);
+
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_builds_mode_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Char('e')).unwrap();
+    view.new_project_name_text = "Sample Board Rev C".to_string();
+    view.handle_project_name_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert_eq!(view.project_data[view.selected_project_idx].name, "Sample Board Rev C");
+    assert_eq!(view.project_data[view.selected_project_idx].parts.len(), 1);
+
+    //Archived projects drop out of the list until they are shown again
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_builds_mode_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Char('a')).unwrap();
+    assert_eq!(view.project_data.len(), 1);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Char('h')).unwrap();
+    assert_eq!(view.project_data.len(), 2);
+    assert_eq!(view.project_data[1].status, ProjectStatus::Archived);
+
+    view.project_list_state.select(Some(1));
+    view.handle_list_mode_keys(&mut repo, KeyCode::Char('d')).unwrap();
+    assert!(view.sub_state == DeleteProject);
+    view.handle_delete_project_keys(&mut repo, KeyCode::Char('y')).unwrap();
+    assert_eq!(view.project_data.len(), 1);
+    assert_eq!(view.project_list_state.selected(), Some(0));
+    assert_eq!(repo.fetch_project_list().unwrap().len(), 1);
+}
+
+#[test]
+fn test_build_planner() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('b')).unwrap();
+    assert!(view.sub_state == BuildPlanner);
+    let per_unit = view.project_data[view.selected_project_idx].parts[0].qty as i64;
+    let plan = view.build_plan.as_ref().unwrap();
+    assert_eq!(plan.units, 1);
+    let max = plan.max_buildable.unwrap();
+    assert!(plan.can_build());
+
+    //One more than the stock allows
+    view.handle_build_planner_keys(&mut repo, KeyCode::Backspace).unwrap();
+    assert!(view.build_plan.is_none());
+    for c in (max + 1).to_string().chars() {
+        view.handle_build_planner_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    let plan = view.build_plan.as_ref().unwrap();
+    assert!(!plan.can_build());
+    assert_eq!(plan.lines[0].required, per_unit * (max + 1));
+    assert!(plan.lines[0].shortfall > 0);
+    view.handle_build_planner_keys(&mut repo, KeyCode::Esc).unwrap();
+    assert!(view.sub_state == BOMMode);
+}
+
+#[test]
+fn test_build_execution() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    let in_prod = |repo: &mut MemoryRepository| {
+        repo.fetch_stock_info().unwrap().iter().find(|s| s.partnumber == "RC0603FR-0710KL").unwrap().in_prod
+    };
+
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('b')).unwrap();
+    view.handle_build_planner_keys(&mut repo, KeyCode::Char('0')).unwrap();
+    view.handle_build_planner_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BuildsMode);
+    assert_eq!(view.units_in_progress(), 10);
+    assert_eq!(in_prod(&mut repo), 40);
+
+    view.handle_builds_mode_keys(&mut repo, KeyCode::Char('x')).unwrap();
+    assert_eq!(view.builds[0].status, BuildStatus::Cancelled);
+    assert_eq!(in_prod(&mut repo), 0);
+    assert!(view.handle_builds_mode_keys(&mut repo, KeyCode::Char('c')).is_err());
+
+    view.handle_builds_mode_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('b')).unwrap();
+    view.handle_build_planner_keys(&mut repo, KeyCode::Backspace).unwrap();
+    view.handle_build_planner_keys(&mut repo, KeyCode::Enter).unwrap();
+    view.handle_builds_mode_keys(&mut repo, KeyCode::Char('c')).unwrap();
+    assert_eq!(view.builds.len(), 2);
+    assert_eq!(view.builds[0].status, BuildStatus::Completed);
+    let stock = repo.fetch_stock_info().unwrap();
+    let resistors = stock.iter().find(|s| s.partnumber == "RC0603FR-0710KL").unwrap();
+    assert_eq!((resistors.on_hand, resistors.in_prod), (4496, 0));
+    assert_eq!(repo.fetch_stock_history("RC0603FR-0710KL").unwrap()[0].kind, "build-consume");
+}
+
+#[test]
+fn test_edit_project_metadata() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Char('m')).unwrap();
+    assert!(view.sub_state == EditMetadata);
+    assert_eq!(view.editing_metadata.metadata.revision, "A");
+    view.handle_edit_metadata_keys(&mut repo, KeyCode::Backspace).unwrap();
+    view.handle_edit_metadata_keys(&mut repo, KeyCode::Char('B')).unwrap();
+    view.handle_edit_metadata_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_edit_metadata_keys(&mut repo, KeyCode::Tab).unwrap();
+    for c in "PCB-0042 ".chars() {
+        view.handle_edit_metadata_keys(&mut repo, KeyCode::Char(c)).unwrap();
     }
-}
\ No newline at end of file
+    view.handle_edit_metadata_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BOMMode);
+    let project = &view.project_data[view.selected_project_idx];
+    assert_eq!(project.metadata.revision, "B");
+    assert_eq!(project.metadata.pcb_fab_pn, "PCB-0042");
+    assert_eq!(project.metadata.description, "Buck converter evaluation board");
+    assert_eq!(project.parts.len(), 1);
+}
+
+#[test]
+fn test_revision_diff() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('v')).unwrap();
+    assert!(view.sub_state == Revisions);
+    view.handle_revisions_keys(KeyCode::Char('n')).unwrap();
+    assert_eq!(view.revision_name_text, "A");
+    view.handle_snapshot_revision_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert_eq!(view.revisions.len(), 1);
+    assert!(view.handle_revisions_keys(KeyCode::Char('n')).is_ok());
+    assert!(view.handle_snapshot_revision_keys(&mut repo, KeyCode::Enter).is_err());
+    view.handle_snapshot_revision_keys(&mut repo, KeyCode::Esc).unwrap();
+    view.handle_revisions_keys(KeyCode::Esc).unwrap();
+    assert!(view.sub_state == BOMMode);
+
+    //Bump the quantity in the working BOM, then diff Rev A against it
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('e')).unwrap();
+    view.bom_line_edit.designators.clear();
+    view.bom_line_edit.qty = "7".to_string();
+    //Every part needs its designator
+    assert!(view.handle_edit_bom_line_keys(&mut repo, KeyCode::Enter).is_err());
+    view.bom_line_edit.designators = "R1-R7".to_string();
+    view.handle_edit_bom_line_keys(&mut repo, KeyCode::Enter).unwrap();
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('v')).unwrap();
+    view.handle_revisions_keys(KeyCode::Down).unwrap();
+    view.handle_revisions_keys(KeyCode::Enter).unwrap();
+    assert!(view.handle_revisions_keys(KeyCode::Enter).is_err());
+    view.handle_revisions_keys(KeyCode::Up).unwrap();
+    view.handle_revisions_keys(KeyCode::Enter).unwrap();
+    let diff = view.bom_diff.as_ref().unwrap();
+    assert_eq!((diff.from.as_str(), diff.to.as_str()), ("Rev A", "Working BOM"));
+    assert_eq!(diff.lines.len(), 1);
+    assert_eq!(diff.lines[0].qty(), format!("{} -> 7", view.revisions[0].lines[0].qty));
+}
+
+#[test]
+fn test_kicad_import() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    let bom_lines = view.project_data[view.selected_project_idx].parts.len();
+    let path = std::env::temp_dir().join(format!("shikabom-test-kicad-{}.csv", std::process::id()));
+    std::fs::write(&path, "Reference,Value,Footprint\n\
+                           \"J1,J2\",Conn_01x02,Connector:PinHeader_1x02\n\
+                           TP1,TestPoint,TestPoint:TestPoint_Pad_D1.0mm\n").unwrap();
+
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('i')).unwrap();
+    assert!(view.handle_import_path_keys(&mut repo, KeyCode::Enter).is_err());
+    view.import_path_text = path.display().to_string();
+    view.handle_import_path_keys(&mut repo, KeyCode::Enter).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(view.sub_state == ImportReview);
+    assert_eq!(view.import_lines.len(), 2);
+    assert!(view.handle_import_review_keys(&mut repo, KeyCode::Enter).is_err());
+
+    view.handle_import_review_keys(&mut repo, KeyCode::Char('e')).unwrap();
+    for c in "PH-1x02".chars() {
+        view.handle_import_part_number_keys(KeyCode::Char(c)).unwrap();
+    }
+    view.handle_import_part_number_keys(KeyCode::Enter).unwrap();
+    assert_eq!(view.import_lines[0].resolution, LineResolution::NewPart("PH-1x02".to_string()));
+    view.handle_import_review_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_import_review_keys(&mut repo, KeyCode::Char('x')).unwrap();
+    view.handle_import_review_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BOMMode);
+    let project = &view.project_data[view.selected_project_idx];
+    assert_eq!(project.parts.len(), bom_lines + 1);
+    let line = project.parts.iter().find(|ppart| ppart.partnumber == "PH-1x02").unwrap();
+    assert_eq!((line.designators.as_str(), line.qty), ("J1, J2", 2));
+    assert!(repo.fetch_single_part("PH-1x02").is_ok());
+}
+
+#[test]
+fn test_import_wizard() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    let bom_lines = view.project_data[view.selected_project_idx].parts.len();
+    let path = std::env::temp_dir().join(format!("shikabom-test-wizard-{}.csv", std::process::id()));
+    std::fs::write(&path, "Part,Refs,Count\nPH-1x02,\"J1, J2\",2\n").unwrap();
+
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('I')).unwrap();
+    view.import_wizard.path_text = path.display().to_string();
+    view.handle_wizard_file_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == WizardMapping);
+    //"Part" isn't guessed, map it by hand
+    assert_eq!(view.import_wizard.mapping.part_number, None);
+    assert!(view.handle_wizard_mapping_keys(&mut repo, KeyCode::Enter).is_err());
+    view.handle_wizard_mapping_keys(&mut repo, KeyCode::Right).unwrap();
+    assert_eq!(view.import_wizard.mapping.part_number.as_deref(), Some("Part"));
+    view.handle_wizard_mapping_keys(&mut repo, KeyCode::Down).unwrap();
+    assert_eq!(view.import_wizard.mapping.designators.as_deref(), Some("Refs"));
+    assert_eq!(view.import_wizard.mapping.qty.as_deref(), Some("Count"));
+    view.handle_wizard_mapping_keys(&mut repo, KeyCode::Left).unwrap();
+    assert_eq!(view.import_wizard.mapping.designators.as_deref(), Some("Part"));
+    view.handle_wizard_mapping_keys(&mut repo, KeyCode::Right).unwrap();
+
+    view.handle_wizard_mapping_keys(&mut repo, KeyCode::Char('s')).unwrap();
+    for c in "Customer".chars() {
+        view.handle_wizard_profile_name_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_wizard_profile_name_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert_eq!(repo.fetch_import_profiles().unwrap().len(), 1);
+
+    view.handle_wizard_mapping_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == WizardDryRun);
+    let run = view.import_wizard.dry_run.as_ref().unwrap();
+    assert_eq!((run.lines.len(), run.new_parts.len()), (1, 1));
+    view.handle_wizard_dry_run_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(view.sub_state == BOMMode);
+    assert_eq!(view.project_data[view.selected_project_idx].parts.len(), bom_lines + 1);
+
+    //The saved profile is picked up for the next file with these headers
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('I')).unwrap();
+    view.import_wizard.path_text = path.display().to_string();
+    view.handle_wizard_file_keys(&mut repo, KeyCode::Enter).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(view.import_wizard.profile_name.as_deref(), Some("Customer"));
+    view.handle_wizard_mapping_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(!view.import_wizard.dry_run.as_ref().unwrap().can_commit());
+    assert!(view.handle_wizard_dry_run_keys(&mut repo, KeyCode::Enter).is_err());
+}
+
+#[test]
+fn test_export_bom_popup() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Char('x')).unwrap();
+    assert!(view.sub_state == ExportBOM);
+    assert_eq!(view.bom_export.path_text, "Sample_Board_revA_csv.csv");
+    view.handle_export_bom_keys(KeyCode::Right).unwrap();
+    view.handle_export_bom_keys(KeyCode::Right).unwrap();
+    view.handle_export_bom_keys(KeyCode::Char('0')).unwrap();
+    assert_eq!(view.bom_export.path_text, "Sample_Board_revA_x10_jlcpcb.csv");
+
+    let path = std::env::temp_dir().join(format!("shikabom-test-export-{}.csv", std::process::id()));
+    view.handle_export_bom_keys(KeyCode::Tab).unwrap();
+    view.bom_export.path_text = path.display().to_string();
+    view.handle_export_bom_keys(KeyCode::Enter).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(text.starts_with("Comment,Designator,Footprint,Manufacturer Part Number,Quantity"));
+    let qty = view.project_data[view.selected_project_idx].parts[0].qty * 10;
+    assert!(text.lines().nth(1).unwrap().ends_with(&format!(",{qty}")));
+    assert!(view.bom_export.written.is_some());
+
+    view.handle_export_bom_keys(KeyCode::Tab).unwrap();
+    view.bom_export.multiplier.clear();
+    assert!(view.handle_export_bom_keys(KeyCode::Enter).is_err());
+    view.handle_export_bom_keys(KeyCode::Esc).unwrap();
+    assert!(view.sub_state == BOMMode);
+}
+
+#[test]
+fn test_sort_bom() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = ProjectsView::new();
+    view.refresh_list(&mut repo).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    let project = view.project_data[view.selected_project_idx].clone();
+    for (pn, designators) in [("HFW1V2210H4R7K", "L1"), ("25SVPF47M", "C1"), ("GRM188R71H104KA93D", "C2")] {
+        let ppart = ProjectPart {
+            partnumber: pn.to_string(),
+            designators: designators.to_string(),
+            qty: 1,
+            part_info: Part::new(),
+        };
+        repo.add_pn_to_project(&project, &ppart).unwrap();
+    }
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    view.bom_table_state.select(Some(0));
+    //Part number ascending and descending, then designators and qty, then value
+    for _ in 0..7 {
+        view.handle_bom_mode_keys(&mut repo, KeyCode::Char('o')).unwrap();
+    }
+    assert_eq!(view.bom_sort, SortState { column: Some(3), descending: false });
+    let values: Vec<String> = view.project_data[view.selected_project_idx].parts.iter().map(|ppart| bom_cell(ppart, 3)).collect();
+    assert_eq!(values, vec!["100n", "4.7u", "47u", "10k"]);
+    //The cursor followed the line it was on
+    assert_eq!(view.selected_bom_line("edit").unwrap().partnumber, "25SVPF47M");
+    //The sort is kept when the BOM is reloaded
+    view.handle_bom_mode_keys(&mut repo, KeyCode::Esc).unwrap();
+    view.handle_main_keys(KeyCode::Tab);
+    view.handle_list_mode_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert_eq!(bom_cell(&view.project_data[view.selected_project_idx].parts[0], 3), "100n");
+}
@@ -0,0 +1,238 @@
+use crate::bom_import::{self, ImportProfile};
+use crate::build::{self, Build};
+use crate::categories::{self, Category};
+use crate::config::{Backend, DatabaseConfig};
+use crate::db::Db;
+use crate::memory_repository::MemoryRepository;
+use crate::sqlite_repository::SqliteRepository;
+use crate::errors::ShikaResult;
+use crate::parts::{self, Part, PartStorage};
+use crate::projects::{self, Project, ProjectMetadata, ProjectPart, ProjectStatus};
+use crate::revisions::{self, ProjectRevision};
+use crate::stock::{self, StockEdit, StockInfo, StockReceipt, StockSpend, StockTransaction, StockTransfer};
+
+/// Everything the views need from storage. `Db` is the Postgres implementation, `SqliteRepository`
+/// stores everything in a local file and `MemoryRepository` keeps everything in memory for tests.
+pub trait BomRepository {
+    fn check_connection(&mut self) -> ShikaResult<()>;
+
+    //Parts
+    fn fetch_all_parts(&mut self) -> ShikaResult<Vec<Part>>;
+    fn fetch_single_part(&mut self, pn: &str) -> ShikaResult<Part>;
+    fn add_new_part(&mut self, new_part: &Part) -> ShikaResult<()>;
+    fn modify_part(&mut self, part: &Part) -> ShikaResult<()>;
+    fn fetch_part_storage_data(&mut self, pn: &str) -> ShikaResult<Vec<PartStorage>>;
+
+    //Stock
+    fn fetch_stock_info(&mut self) -> ShikaResult<Vec<StockInfo>>;
+    fn fetch_nonstocked_partnumbers(&mut self) -> ShikaResult<Vec<String>>;
+    fn create_new_stock(&mut self, stock: StockInfo) -> ShikaResult<()>;
+    fn receive_stock(&mut self, receipt: &StockReceipt) -> ShikaResult<()>;
+    fn spend_stock(&mut self, spend: &StockSpend) -> ShikaResult<()>;
+    fn edit_stock(&mut self, edit: &StockEdit) -> ShikaResult<()>;
+    fn transfer_stock(&mut self, transfer: &StockTransfer) -> ShikaResult<()>;
+    fn fetch_stock_history(&mut self, pn: &str) -> ShikaResult<Vec<StockTransaction>>;
+    fn reconcile_stock(&mut self, pn: &str, expected: i32) -> ShikaResult<()>;
+
+    //Projects
+    fn fetch_project_list(&mut self) -> ShikaResult<Vec<Project>>;
+    fn fetch_project_details(&mut self, project: &mut Project) -> ShikaResult<()>;
+    fn create_new_project_name(&mut self, name: String) -> ShikaResult<()>;
+    fn rename_project(&mut self, old_name: &str, new_name: &str) -> ShikaResult<()>;
+    fn duplicate_project(&mut self, source: &str, new_name: &str) -> ShikaResult<()>;
+    fn set_project_status(&mut self, name: &str, status: ProjectStatus) -> ShikaResult<()>;
+    fn update_project_metadata(&mut self, name: &str, metadata: &ProjectMetadata) -> ShikaResult<()>;
+    fn delete_project(&mut self, name: &str) -> ShikaResult<()>;
+    fn fetch_pns_not_in_project(&mut self, project: &Project) -> ShikaResult<Vec<String>>;
+    fn add_pn_to_project(&mut self, project: &Project, ppart: &ProjectPart) -> ShikaResult<()>;
+    fn update_project_part(&mut self, project: &Project, old_pn: &str, ppart: &ProjectPart) -> ShikaResult<()>;
+    fn remove_pn_from_project(&mut self, project: &Project, pn: &str) -> ShikaResult<()>;
+    fn import_project_parts(&mut self, project: &Project, new_parts: &[Part], pparts: &[ProjectPart]) -> ShikaResult<()>;
+
+    //Builds
+    fn fetch_builds(&mut self, project_name: &str) -> ShikaResult<Vec<Build>>;
+    fn start_build(&mut self, project_name: &str, units: i32) -> ShikaResult<i64>;
+    fn complete_build(&mut self, id: i64) -> ShikaResult<()>;
+    fn cancel_build(&mut self, id: i64) -> ShikaResult<()>;
+
+    //Revisions
+    fn fetch_revisions(&mut self, project_name: &str) -> ShikaResult<Vec<ProjectRevision>>;
+    fn create_revision(&mut self, project_name: &str, revision: &str) -> ShikaResult<()>;
+
+    //Import profiles
+    fn fetch_import_profiles(&mut self) -> ShikaResult<Vec<ImportProfile>>;
+    fn save_import_profile(&mut self, profile: &ImportProfile) -> ShikaResult<()>;
+
+    //Part categories
+    fn fetch_categories(&mut self) -> ShikaResult<Vec<Category>>;
+    /// Creates the category or replaces its attributes.
+    fn save_category(&mut self, category: &Category) -> ShikaResult<()>;
+    fn delete_category(&mut self, path: &str) -> ShikaResult<()>;
+}
+
+/// Creates the repository for the configured backend.
+pub fn open_repository(config: DatabaseConfig) -> Box<dyn BomRepository> {
+    match config.backend {
+        Backend::Postgres => Box::new(Db::new(config)),
+        Backend::Sqlite => Box::new(SqliteRepository::new(&config)),
+        Backend::Memory => Box::new(MemoryRepository::with_sample_data()),
+    }
+}
+
+impl BomRepository for Db {
+    fn check_connection(&mut self) -> ShikaResult<()> {
+        Db::check_connection(self)
+    }
+
+    fn fetch_all_parts(&mut self) -> ShikaResult<Vec<Part>> {
+        parts::fetch_all_parts(self)
+    }
+
+    fn fetch_single_part(&mut self, pn: &str) -> ShikaResult<Part> {
+        parts::fetch_single_part(self, pn)
+    }
+
+    fn add_new_part(&mut self, new_part: &Part) -> ShikaResult<()> {
+        parts::add_new_part(self, new_part)
+    }
+
+    fn modify_part(&mut self, part: &Part) -> ShikaResult<()> {
+        parts::modify_part(self, part)
+    }
+
+    fn fetch_part_storage_data(&mut self, pn: &str) -> ShikaResult<Vec<PartStorage>> {
+        parts::fetch_part_storage_data(self, pn)
+    }
+
+    fn fetch_stock_info(&mut self) -> ShikaResult<Vec<StockInfo>> {
+        stock::fetch_stock_info(self)
+    }
+
+    fn fetch_nonstocked_partnumbers(&mut self) -> ShikaResult<Vec<String>> {
+        stock::fetch_nonstocked_partnumbers(self)
+    }
+
+    fn create_new_stock(&mut self, new_stock: StockInfo) -> ShikaResult<()> {
+        stock::create_new_stock(self, new_stock)
+    }
+
+    fn receive_stock(&mut self, receipt: &StockReceipt) -> ShikaResult<()> {
+        stock::receive_stock(self, receipt)
+    }
+
+    fn spend_stock(&mut self, spend: &StockSpend) -> ShikaResult<()> {
+        stock::spend_stock(self, spend)
+    }
+
+    fn edit_stock(&mut self, edit: &StockEdit) -> ShikaResult<()> {
+        stock::edit_stock(self, edit)
+    }
+
+    fn transfer_stock(&mut self, transfer: &StockTransfer) -> ShikaResult<()> {
+        stock::transfer_stock(self, transfer)
+    }
+
+    fn fetch_stock_history(&mut self, pn: &str) -> ShikaResult<Vec<StockTransaction>> {
+        stock::fetch_stock_history(self, pn)
+    }
+
+    fn reconcile_stock(&mut self, pn: &str, expected: i32) -> ShikaResult<()> {
+        stock::reconcile_stock(self, pn, expected)
+    }
+
+    fn fetch_project_list(&mut self) -> ShikaResult<Vec<Project>> {
+        projects::fetch_project_list(self)
+    }
+
+    fn fetch_project_details(&mut self, project: &mut Project) -> ShikaResult<()> {
+        projects::fetch_project_details(self, project)
+    }
+
+    fn create_new_project_name(&mut self, name: String) -> ShikaResult<()> {
+        projects::create_new_project_name(self, name)
+    }
+
+    fn rename_project(&mut self, old_name: &str, new_name: &str) -> ShikaResult<()> {
+        projects::rename_project(self, old_name, new_name)
+    }
+
+    fn duplicate_project(&mut self, source: &str, new_name: &str) -> ShikaResult<()> {
+        projects::duplicate_project(self, source, new_name)
+    }
+
+    fn set_project_status(&mut self, name: &str, status: ProjectStatus) -> ShikaResult<()> {
+        projects::set_project_status(self, name, status)
+    }
+
+    fn update_project_metadata(&mut self, name: &str, metadata: &ProjectMetadata) -> ShikaResult<()> {
+        projects::update_project_metadata(self, name, metadata)
+    }
+
+    fn delete_project(&mut self, name: &str) -> ShikaResult<()> {
+        projects::delete_project(self, name)
+    }
+
+    fn fetch_pns_not_in_project(&mut self, project: &Project) -> ShikaResult<Vec<String>> {
+        projects::fetch_pns_not_in_project(self, project)
+    }
+
+    fn add_pn_to_project(&mut self, project: &Project, ppart: &ProjectPart) -> ShikaResult<()> {
+        projects::add_pn_to_project(self, project, ppart)
+    }
+
+    fn update_project_part(&mut self, project: &Project, old_pn: &str, ppart: &ProjectPart) -> ShikaResult<()> {
+        projects::update_project_part(self, project, old_pn, ppart)
+    }
+
+    fn remove_pn_from_project(&mut self, project: &Project, pn: &str) -> ShikaResult<()> {
+        projects::remove_pn_from_project(self, project, pn)
+    }
+
+    fn import_project_parts(&mut self, project: &Project, new_parts: &[Part], pparts: &[ProjectPart]) -> ShikaResult<()> {
+        projects::import_project_parts(self, project, new_parts, pparts)
+    }
+
+    fn fetch_builds(&mut self, project_name: &str) -> ShikaResult<Vec<Build>> {
+        build::fetch_builds(self, project_name)
+    }
+
+    fn start_build(&mut self, project_name: &str, units: i32) -> ShikaResult<i64> {
+        build::start_build(self, project_name, units)
+    }
+
+    fn complete_build(&mut self, id: i64) -> ShikaResult<()> {
+        build::complete_build(self, id)
+    }
+
+    fn cancel_build(&mut self, id: i64) -> ShikaResult<()> {
+        build::cancel_build(self, id)
+    }
+
+    fn fetch_revisions(&mut self, project_name: &str) -> ShikaResult<Vec<ProjectRevision>> {
+        revisions::fetch_revisions(self, project_name)
+    }
+
+    fn create_revision(&mut self, project_name: &str, revision: &str) -> ShikaResult<()> {
+        revisions::create_revision(self, project_name, revision)
+    }
+
+    fn fetch_import_profiles(&mut self) -> ShikaResult<Vec<ImportProfile>> {
+        bom_import::fetch_import_profiles(self)
+    }
+
+    fn save_import_profile(&mut self, profile: &ImportProfile) -> ShikaResult<()> {
+        bom_import::save_import_profile(self, profile)
+    }
+
+    fn fetch_categories(&mut self) -> ShikaResult<Vec<Category>> {
+        categories::fetch_categories(self)
+    }
+
+    fn save_category(&mut self, category: &Category) -> ShikaResult<()> {
+        categories::save_category(self, category)
+    }
+
+    fn delete_category(&mut self, path: &str) -> ShikaResult<()> {
+        categories::delete_category(self, path)
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use postgres::Row;
+use crate::db::Db;
+use crate::errors::{ShikaError, ShikaResult};
+use crate::projects::{parse_designators, ProjectPart};
+
+/// A BOM line as it was when the revision was snapshotted.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RevisionLine {
+    pub partnumber: String,
+    pub designators: String,
+    pub qty: i32,
+}
+
+impl RevisionLine {
+    pub fn from_parts(parts: &[ProjectPart]) -> Vec<RevisionLine> {
+        parts.iter()
+            .map(|ppart| RevisionLine {
+                partnumber: ppart.partnumber.clone(),
+                designators: ppart.designators.clone(),
+                qty: ppart.qty,
+            })
+            .collect()
+    }
+}
+
+/// A frozen copy of a project's BOM, see `create_revision`.
+#[derive(Clone, Debug)]
+pub struct ProjectRevision {
+    pub id: i64,
+    pub project_name: String,
+    pub revision: String,
+    pub created_at: String,
+    pub lines: Vec<RevisionLine>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DiffKind {
+    Added,
+    Removed,
+    //Same part number, different qty or designators
+    Changed,
+    //Different part number on the same designators
+    Substituted,
+}
+
+/// One difference between two BOMs. `old` is None for added lines, `new` for removed ones.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BomDiffLine {
+    pub kind: DiffKind,
+    pub old: Option<RevisionLine>,
+    pub new: Option<RevisionLine>,
+}
+
+impl BomDiffLine {
+    pub fn symbol(&self) -> &'static str {
+        match self.kind {
+            DiffKind::Added => "+",
+            DiffKind::Removed => "-",
+            DiffKind::Changed | DiffKind::Substituted => "~",
+        }
+    }
+
+    pub fn partnumber(&self) -> String {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) if old.partnumber != new.partnumber => format!("{} -> {}", old.partnumber, new.partnumber),
+            (_, Some(line)) | (Some(line), None) => line.partnumber.clone(),
+            (None, None) => "".to_string(),
+        }
+    }
+
+    pub fn designators(&self) -> String {
+        changed_text(self.old.as_ref().map(|line| line.designators.clone()),
+                     self.new.as_ref().map(|line| line.designators.clone()))
+    }
+
+    pub fn qty(&self) -> String {
+        changed_text(self.old.as_ref().map(|line| line.qty.to_string()),
+                     self.new.as_ref().map(|line| line.qty.to_string()))
+    }
+}
+
+//"old -> new" when both sides exist and differ, otherwise whichever side there is
+fn changed_text(old: Option<String>, new: Option<String>) -> String {
+    match (old, new) {
+        (Some(old), Some(new)) if old != new => format!("{old} -> {new}"),
+        (_, Some(text)) | (Some(text), None) => text,
+        (None, None) => "".to_string(),
+    }
+}
+
+/// The lines that differ between two BOMs, ordered by part number. Lines are matched on part
+/// number first, a removed and an added line covering the same designators count as a
+/// substitution. Unchanged lines are left out.
+pub fn diff_boms(old: &[RevisionLine], new: &[RevisionLine]) -> Vec<BomDiffLine> {
+    let mut diff = Vec::new();
+    let mut removed = Vec::new();
+    for old_line in old {
+        match new.iter().find(|line| line.partnumber == old_line.partnumber) {
+            Some(new_line) if new_line != old_line => diff.push(BomDiffLine {
+                kind: DiffKind::Changed,
+                old: Some(old_line.clone()),
+                new: Some(new_line.clone()),
+            }),
+            Some(_) => {}
+            None => removed.push(old_line),
+        }
+    }
+    let mut added: Vec<&RevisionLine> = new.iter()
+        .filter(|line| !old.iter().any(|old_line| old_line.partnumber == line.partnumber))
+        .collect();
+    for old_line in removed {
+        let substitute = designator_set(&old_line.designators).and_then(|designators| {
+            added.iter().position(|line| designator_set(&line.designators).as_ref() == Some(&designators))
+        });
+        match substitute {
+            Some(idx) => diff.push(BomDiffLine {
+                kind: DiffKind::Substituted,
+                old: Some(old_line.clone()),
+                new: Some(added.remove(idx).clone()),
+            }),
+            None => diff.push(BomDiffLine {
+                kind: DiffKind::Removed,
+                old: Some(old_line.clone()),
+                new: None,
+            }),
+        }
+    }
+    for new_line in added {
+        diff.push(BomDiffLine {
+            kind: DiffKind::Added,
+            old: None,
+            new: Some(new_line.clone()),
+        });
+    }
+    diff.sort_by_key(|line| line.old.as_ref().or(line.new.as_ref()).map(|line| line.partnumber.clone()));
+    diff
+}
+
+//The sorted designators, None when there are none or they don't parse
+fn designator_set(text: &str) -> Option<Vec<String>> {
+    let mut designators = parse_designators(text).ok()?;
+    if designators.is_empty() {
+        return None;
+    }
+    designators.sort();
+    Some(designators)
+}
+
+pub fn validate_revision_name(revision: &str) -> ShikaResult<()> {
+    if revision.trim().is_empty() {
+        return Err(ShikaError::Validation("Revision name cannot be empty".to_string()));
+    }
+    Ok(())
+}
+
+pub fn revision_exists(project_name: &str, revision: &str) -> String {
+    format!("Revision {revision} already exists for {project_name}")
+}
+
+/// Snapshots the project's current BOM as `revision`.
+pub fn create_revision(db: &mut Db, project_name: &str, revision: &str) -> ShikaResult<()> {
+    validate_revision_name(revision)?;
+    let mut transaction = db.client()?.transaction()?;
+    if transaction.query_opt("SELECT 1 FROM projects WHERE project_name = $1", &[&project_name])?.is_none() {
+        return Err(ShikaError::NotFound(format!("Project {project_name} does not exist")));
+    }
+    let id: i64 = transaction.query_one("INSERT INTO project_revisions (project_name, revision) VALUES ($1, $2) RETURNING id",
+                                        &[&project_name, &revision])
+        .map_err(|e| ShikaError::on_unique_violation(e, || revision_exists(project_name, revision)))?
+        .get("id");
+    transaction.execute("INSERT INTO project_revision_parts (revision_id, partnumber, designators, qty)
+                    SELECT $1, partnumber, designators, qty FROM project_components WHERE project_name = $2",
+                        &[&id, &project_name])?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The project's revisions with their BOM lines, newest first.
+pub fn fetch_revisions(db: &mut Db, project_name: &str) -> ShikaResult<Vec<ProjectRevision>> {
+    let client = db.client()?;
+    let mut revisions: Vec<ProjectRevision> = client.query("SELECT id, project_name, revision,
+                    SUBSTR(CAST(created_at AS TEXT), 1, 19) AS created_at
+                    FROM project_revisions WHERE project_name = $1 ORDER BY id DESC", &[&project_name])?
+        .iter()
+        .map(revision_from_row)
+        .collect();
+    let rows = client.query("SELECT rp.revision_id, rp.partnumber, rp.designators, rp.qty
+                    FROM project_revision_parts rp
+                    JOIN project_revisions r ON r.id = rp.revision_id
+                    WHERE r.project_name = $1 ORDER BY rp.partnumber", &[&project_name])?;
+    let mut lines: HashMap<i64, Vec<RevisionLine>> = HashMap::new();
+    for row in rows {
+        lines.entry(row.get("revision_id")).or_default().push(RevisionLine {
+            partnumber: row.get("partnumber"),
+            designators: row.get("designators"),
+            qty: row.get("qty"),
+        });
+    }
+    for revision in revisions.iter_mut() {
+        revision.lines = lines.remove(&revision.id).unwrap_or_default();
+    }
+    Ok(revisions)
+}
+
+fn revision_from_row(row: &Row) -> ProjectRevision {
+    ProjectRevision {
+        id: row.get("id"),
+        project_name: row.get("project_name"),
+        revision: row.get("revision"),
+        created_at: row.get("created_at"),
+        lines: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+fn line(partnumber: &str, designators: &str, qty: i32) -> RevisionLine {
+    RevisionLine {
+        partnumber: partnumber.to_string(),
+        designators: designators.to_string(),
+        qty,
+    }
+}
+
+#[test]
+fn test_diff_boms() {
+    let old = vec![
+        line("C1", "C1-C2", 2),
+        line("R1", "R1-R4", 4),
+        line("U1", "U1", 1),
+        line("D1", "D1", 1),
+    ];
+    let new = vec![
+        line("C1", "C1-C2", 2),
+        line("R1", "R1-R5", 5),
+        line("U2", "U1", 1),
+        line("L1", "L1", 1),
+    ];
+    let diff = diff_boms(&old, &new);
+    let kinds: Vec<(DiffKind, String)> = diff.iter().map(|line| (line.kind, line.partnumber())).collect();
+    assert_eq!(kinds, vec![
+        (DiffKind::Removed, "D1".to_string()),
+        (DiffKind::Added, "L1".to_string()),
+        (DiffKind::Changed, "R1".to_string()),
+        (DiffKind::Substituted, "U1 -> U2".to_string()),
+    ]);
+    assert_eq!(diff[2].qty(), "4 -> 5");
+    assert_eq!(diff[2].designators(), "R1-R4 -> R1-R5");
+    assert_eq!(diff[3].designators(), "U1");
+    assert_eq!(diff[0].symbol(), "-");
+    assert!(diff_boms(&new, &new).is_empty());
+}
@@ -0,0 +1,1133 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use rusqlite::{params, Connection, OptionalExtension, Row, TransactionBehavior};
+use tracing::info;
+use crate::config::DatabaseConfig;
+use crate::errors::{ShikaError, ShikaResult};
+use crate::migrate::{self, Dialect, Migration, SchemaStore};
+use crate::parts::{validate_part, Part, PartStorage};
+use crate::bom_import::{profile_from_json, profile_to_json, validate_profile_name, ImportProfile};
+use crate::build::{ensure_in_progress, reserve_build, Build, BuildPart, BuildStatus};
+use crate::categories::{validate_category, validate_category_removal, validate_part_category, AttributeDef, Category};
+use crate::eng_value::Unit;
+use crate::projects::{project_has_builds, validate_project_name, Project, ProjectMetadata, ProjectPart, ProjectStatus};
+use crate::repository::BomRepository;
+use crate::revisions::{revision_exists, validate_revision_name, ProjectRevision, RevisionLine};
+use crate::stock::{
+    current_user, opening_balance, plan_spend, reconciliation, validate_new_stock, validate_receipt, validate_stock_edit,
+    validate_transfer, StockEdit, StockInfo, StockReceipt, StockSpend, StockTransaction, StockTransfer
+};
+
+/// A `BomRepository` backed by a single SQLite file. It runs the same migrations and queries
+/// the same views as Postgres, and applies pending migrations itself when the file is opened.
+pub struct SqliteRepository {
+    //None if no path was configured and there is no data directory to fall back to
+    path: Option<PathBuf>,
+    conn: Option<Connection>,
+    migrated: bool,
+}
+
+impl SqliteRepository {
+    pub fn new(config: &DatabaseConfig) -> SqliteRepository {
+        SqliteRepository {
+            path: config.sqlite_path().ok(),
+            conn: None,
+            migrated: false,
+        }
+    }
+
+    /// A throwaway database that lives as long as the repository, used by the tests.
+    #[cfg(test)]
+    pub fn in_memory() -> SqliteRepository {
+        SqliteRepository {
+            path: Some(PathBuf::from(":memory:")),
+            conn: None,
+            migrated: false,
+        }
+    }
+
+    /// Opens the database file if it isn't open yet, without touching the schema.
+    fn connection(&mut self) -> ShikaResult<&mut Connection> {
+        if self.conn.is_none() {
+            let path = self.path.clone().ok_or_else(|| {
+                ShikaError::Connection("No SQLite database path configured, set database.path".to_string())
+            })?;
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir).map_err(|e| {
+                    ShikaError::Connection(format!("could not create {}: {e}", dir.display()))
+                })?;
+            }
+            info!("Opening SQLite database {}", path.display());
+            let conn = Connection::open(&path)?;
+            //SQLite leaves foreign keys off unless asked, Postgres always enforces them
+            conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+            self.conn = Some(conn);
+        }
+        Ok(self.conn.as_mut().unwrap())
+    }
+
+    /// The open connection, with every migration applied.
+    fn conn(&mut self) -> ShikaResult<&mut Connection> {
+        if !self.migrated {
+            self.connection()?;
+            let applied = migrate::migrate(self)?;
+            for migration in applied {
+                info!("Applied migration {:04} {}", migration.version, migration.name);
+            }
+            self.migrated = true;
+        }
+        self.connection()
+    }
+}
+
+/// Reads a row of `big_part_view` (or anything selecting its columns) into a `Part`.
+fn part_from_row(row: &Row) -> rusqlite::Result<Part> {
+    Ok(Part {
+        part_number: row.get("partnumber")?,
+        total_qty: Some(row.get::<_, Option<i64>>("total_qty")?.unwrap_or(0)),
+        manufacturer: Some(row.get::<_, Option<String>>("manufacturer")?.unwrap_or_default()),
+        description: Some(row.get::<_, Option<String>>("description")?.unwrap_or_default()),
+        label: Some(row.get::<_, Option<String>>("label")?.unwrap_or_default()),
+        package: Some(row.get::<_, Option<String>>("package")?.unwrap_or_default()),
+        value: Some(row.get::<_, Option<String>>("value")?.unwrap_or_default()),
+        tolerance: Some(row.get::<_, Option<String>>("tolerance")?.unwrap_or_default()),
+        category: row.get("category")?,
+        //Filled in by `attach_attributes` where they are needed
+        attributes: BTreeMap::new(),
+    })
+}
+
+//One query for the attributes of every part in `parts`
+fn attach_attributes(conn: &Connection, parts: &mut [Part]) -> ShikaResult<()> {
+    let read_row = |row: &Row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?));
+    let rows = match parts {
+        [part] => conn.prepare("SELECT partnumber, name, value FROM part_attributes WHERE partnumber = ?1")?
+            .query_map([&part.part_number], read_row)?
+            .collect::<rusqlite::Result<Vec<(String, String, String)>>>()?,
+        _ => conn.prepare("SELECT partnumber, name, value FROM part_attributes")?
+            .query_map([], read_row)?
+            .collect::<rusqlite::Result<Vec<(String, String, String)>>>()?,
+    };
+    let mut by_pn: HashMap<String, &mut Part> = parts.iter_mut().map(|part| (part.part_number.clone(), part)).collect();
+    for (pn, name, value) in rows {
+        if let Some(part) = by_pn.get_mut(&pn) {
+            part.attributes.insert(name, value);
+        }
+    }
+    Ok(())
+}
+
+//The attributes are replaced as a whole
+fn save_attributes(conn: &Connection, part: &Part) -> ShikaResult<()> {
+    conn.execute("DELETE FROM part_attributes WHERE partnumber = ?1", [&part.part_number])?;
+    for (name, value) in &part.attributes {
+        conn.execute("INSERT INTO part_attributes (partnumber, name, value) VALUES (?1, ?2, ?3)",
+                     params![part.part_number, name, value])?;
+    }
+    Ok(())
+}
+
+fn fetch_categories(conn: &Connection) -> ShikaResult<Vec<Category>> {
+    let mut stmt = conn.prepare("SELECT path FROM categories ORDER BY path")?;
+    let mut categories = stmt.query_map([], |row| Ok(Category { path: row.get(0)?, attributes: Vec::new() }))?
+        .collect::<rusqlite::Result<Vec<Category>>>()?;
+    let mut stmt = conn.prepare("SELECT category, name, kind, unit FROM category_attributes ORDER BY category, position")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get::<_, String>(2)?, row.get::<_, Option<String>>(3)?)))?
+        .collect::<rusqlite::Result<Vec<(String, String, String, Option<String>)>>>()?;
+    for (path, name, kind, unit) in rows {
+        if let Some(category) = categories.iter_mut().find(|category| category.path == path) {
+            category.attributes.push(AttributeDef {
+                name,
+                kind: kind.parse()?,
+                unit: unit.as_deref().and_then(Unit::from_symbol),
+            });
+        }
+    }
+    Ok(categories)
+}
+
+fn insert_transaction(conn: &Connection, entry: &StockTransaction) -> ShikaResult<()> {
+    conn.execute("INSERT INTO stock_transactions (partnumber, kind, delta, location, reference, reason, note, username)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                 params![entry.partnumber, entry.kind, entry.delta, entry.location, entry.reference,
+                         entry.reason, entry.note, entry.username])?;
+    Ok(())
+}
+
+fn current_on_hand(conn: &Connection, pn: &str) -> ShikaResult<i32> {
+    Ok(current_stock_levels(conn, pn)?.0)
+}
+
+//on_hand and in_prod
+fn current_stock_levels(conn: &Connection, pn: &str) -> ShikaResult<(i32, i32)> {
+    conn.query_row("SELECT on_hand, in_prod FROM stock WHERE partnumber = ?1", [pn], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()?
+        .ok_or_else(|| ShikaError::NotFound(format!("Part number {pn} is not stocked")))
+}
+
+fn part_storage(conn: &Connection, pn: &str) -> ShikaResult<Vec<PartStorage>> {
+    let mut stmt = conn.prepare("SELECT * FROM part_storage_view WHERE partnumber = ?1")?;
+    let stores = stmt.query_map([pn], |row| {
+        Ok(PartStorage {
+            part_number: row.get("partnumber")?,
+            location: row.get("storage_loc_name")?,
+            quantity: row.get("quantity")?,
+        })
+    })?.collect::<rusqlite::Result<Vec<PartStorage>>>()?;
+    Ok(stores)
+}
+
+//Bumps modified_at after a change to the project's BOM
+fn touch_project(conn: &Connection, name: &str) -> ShikaResult<()> {
+    conn.execute("UPDATE projects SET modified_at = CURRENT_TIMESTAMP WHERE project_name = ?1", [name])?;
+    Ok(())
+}
+
+//Checks the build is still in progress and returns what it reserved
+fn open_build_parts(conn: &Connection, id: i64) -> ShikaResult<Vec<BuildPart>> {
+    let status: String = conn.query_row("SELECT status FROM builds WHERE id = ?1", [id], |row| row.get(0))
+        .optional()?
+        .ok_or_else(|| ShikaError::NotFound(format!("Build #{id} does not exist")))?;
+    ensure_in_progress(id, status.parse()?)?;
+    let mut stmt = conn.prepare("SELECT partnumber, quantity FROM build_parts WHERE build_id = ?1 ORDER BY partnumber")?;
+    let parts = stmt.query_map([id], |row| {
+        Ok(BuildPart {
+            partnumber: row.get("partnumber")?,
+            quantity: row.get("quantity")?,
+        })
+    })?.collect::<rusqlite::Result<Vec<BuildPart>>>()?;
+    Ok(parts)
+}
+
+impl SchemaStore for SqliteRepository {
+    fn ensure_migrations_table(&mut self) -> ShikaResult<()> {
+        self.connection()?.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )")?;
+        Ok(())
+    }
+
+    fn applied_migrations(&mut self) -> ShikaResult<Vec<(i32, String)>> {
+        let conn = self.connection()?;
+        let mut stmt = conn.prepare("SELECT version, applied_at FROM schema_migrations")?;
+        let applied = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(i32, String)>>>()?;
+        Ok(applied)
+    }
+
+    fn apply_migration(&mut self, migration: &Migration) -> ShikaResult<bool> {
+        //IMMEDIATE takes the write lock up front, so two processes can't both apply a migration
+        let transaction = self.connection()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let already_applied = transaction.query_row(
+            "SELECT 1 FROM schema_migrations WHERE version = ?1", [migration.version], |_| Ok(()))
+            .optional()?.is_some();
+        if already_applied {
+            return Ok(false);
+        }
+        transaction.execute_batch(&migration.sql_for(Dialect::Sqlite))?;
+        transaction.execute("INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+                            params![migration.version, migration.name])?;
+        transaction.commit()?;
+        Ok(true)
+    }
+}
+
+impl BomRepository for SqliteRepository {
+    fn check_connection(&mut self) -> ShikaResult<()> {
+        self.conn()?.query_row("SELECT 1", [], |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn fetch_all_parts(&mut self) -> ShikaResult<Vec<Part>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT * FROM big_part_view")?;
+        let mut parts = stmt.query_map([], part_from_row)?.collect::<rusqlite::Result<Vec<Part>>>()?;
+        attach_attributes(conn, &mut parts)?;
+        Ok(parts)
+    }
+
+    fn fetch_single_part(&mut self, pn: &str) -> ShikaResult<Part> {
+        let conn = self.conn()?;
+        let mut part = conn.query_row("SELECT * FROM big_part_view WHERE partnumber = ?1", [pn], part_from_row)
+            .optional()?
+            .ok_or_else(|| ShikaError::NotFound(format!("Part number {pn} not found")))?;
+        attach_attributes(conn, std::slice::from_mut(&mut part))?;
+        Ok(part)
+    }
+
+    fn add_new_part(&mut self, new_part: &Part) -> ShikaResult<()> {
+        validate_part(new_part)?;
+        let transaction = self.conn()?.transaction()?;
+        validate_part_category(new_part, &fetch_categories(&transaction)?)?;
+        transaction.execute("INSERT INTO parts (partnumber, manufacturer, description, label, package, value, tolerance, category) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                             params![
+                                 new_part.part_number,
+                                 new_part.manufacturer,
+                                 new_part.description,
+                                 new_part.label,
+                                 new_part.package,
+                                 new_part.value,
+                                 new_part.tolerance,
+                                 new_part.category
+                             ],
+        ).map_err(|e| ShikaError::on_sqlite_unique_violation(e, || {
+            format!("Part number {} already exists", new_part.part_number)
+        }))?;
+        save_attributes(&transaction, new_part)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn modify_part(&mut self, part: &Part) -> ShikaResult<()> {
+        validate_part(part)?;
+        let transaction = self.conn()?.transaction()?;
+        validate_part_category(part, &fetch_categories(&transaction)?)?;
+        let updated = transaction.execute("UPDATE parts SET manufacturer = ?1, description = ?2, label = ?3, package = ?4, value = ?5, tolerance = ?6, category = ?7 WHERE partnumber = ?8",
+                                           params![
+                                               part.manufacturer,
+                                               part.description,
+                                               part.label,
+                                               part.package,
+                                               part.value,
+                                               part.tolerance,
+                                               part.category,
+                                               part.part_number
+                                           ],
+        )?;
+        if updated == 0 {
+            return Err(ShikaError::NotFound(format!("Part number {} not found", part.part_number)));
+        }
+        save_attributes(&transaction, part)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fetch_part_storage_data(&mut self, pn: &str) -> ShikaResult<Vec<PartStorage>> {
+        part_storage(self.conn()?, pn)
+    }
+
+    fn fetch_stock_info(&mut self) -> ShikaResult<Vec<StockInfo>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT * FROM stock")?;
+        let stock_data = stmt.query_map([], |row| {
+            let mut stock = StockInfo::new();
+            stock.partnumber = row.get("partnumber")?;
+            stock.low_stock_threshold = row.get("low_stock_threshold")?;
+            stock.on_hand = row.get("on_hand")?;
+            stock.on_order = row.get("on_order")?;
+            stock.in_prod = row.get("in_prod")?;
+            stock.total_stock = row.get("c_stock")?;
+            stock.balance = row.get("c_balance")?;
+            stock.available = row.get("c_available")?;
+            Ok(stock)
+        })?.collect::<rusqlite::Result<Vec<StockInfo>>>()?;
+        Ok(stock_data)
+    }
+
+    fn fetch_nonstocked_partnumbers(&mut self) -> ShikaResult<Vec<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT partnumber FROM non_stocked_parts_view")?;
+        let partnumbers = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(partnumbers)
+    }
+
+    fn create_new_stock(&mut self, stock: StockInfo) -> ShikaResult<()> {
+        validate_new_stock(&stock)?;
+        let transaction = self.conn()?.transaction()?;
+        transaction.execute("INSERT INTO stock (partnumber, low_stock_threshold, on_hand, on_order, in_prod) VALUES (?1, ?2, ?3, ?4, ?5)",
+                            params![stock.partnumber, stock.low_stock_threshold, stock.on_hand, stock.on_order, stock.in_prod])
+            .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || {
+                format!("Part number {} is already stocked", stock.partnumber)
+            }))?;
+        if let Some(entry) = opening_balance(&stock) {
+            insert_transaction(&transaction, &entry)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn receive_stock(&mut self, receipt: &StockReceipt) -> ShikaResult<()> {
+        validate_receipt(receipt)?;
+        let transaction = self.conn()?.transaction()?;
+        let updated = transaction.execute("UPDATE stock SET on_hand = on_hand + ?2,
+                        on_order = CASE WHEN ?3 THEN (CASE WHEN on_order > ?2 THEN on_order - ?2 ELSE 0 END) ELSE on_order END
+                        WHERE partnumber = ?1",
+                                          params![receipt.partnumber, receipt.quantity, receipt.against_order])?;
+        if updated == 0 {
+            return Err(ShikaError::NotFound(format!("Part number {} is not stocked", receipt.partnumber)));
+        }
+        transaction.execute("INSERT INTO part_storage (partnumber, storage_loc_name, quantity) VALUES (?1, ?2, ?3)
+                        ON CONFLICT (partnumber, storage_loc_name) DO UPDATE SET quantity = part_storage.quantity + excluded.quantity",
+                            params![receipt.partnumber, receipt.location.trim(), receipt.quantity])?;
+        insert_transaction(&transaction, &receipt.to_transaction())?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn spend_stock(&mut self, spend: &StockSpend) -> ShikaResult<()> {
+        //IMMEDIATE so nobody else can write between reading the storage rows and updating them
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let (on_hand, in_prod) = current_stock_levels(&transaction, &spend.partnumber)?;
+        let storage = part_storage(&transaction, &spend.partnumber)?;
+        let plan = plan_spend(spend, on_hand, in_prod, &storage)?;
+        transaction.execute("UPDATE stock SET on_hand = on_hand - ?2 WHERE partnumber = ?1", params![spend.partnumber, spend.quantity])?;
+        for (location, quantity) in &plan {
+            transaction.execute("UPDATE part_storage SET quantity = quantity - ?3 WHERE partnumber = ?1 AND storage_loc_name = ?2",
+                                params![spend.partnumber, location, quantity])?;
+        }
+        for entry in spend.to_transactions(&plan) {
+            insert_transaction(&transaction, &entry)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn edit_stock(&mut self, edit: &StockEdit) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let delta = validate_stock_edit(edit, current_on_hand(&transaction, &edit.partnumber)?,
+                                        &part_storage(&transaction, &edit.partnumber)?)?;
+        transaction.execute("UPDATE stock SET low_stock_threshold = ?2, on_hand = ?3, on_order = ?4 WHERE partnumber = ?1",
+                            params![edit.partnumber, edit.low_stock_threshold, edit.on_hand, edit.on_order])?;
+        if let Some(location) = edit.adjusted_location().filter(|_| delta != 0) {
+            transaction.execute("UPDATE part_storage SET quantity = quantity + ?3 WHERE partnumber = ?1 AND storage_loc_name = ?2",
+                                params![edit.partnumber, location, delta])?;
+        }
+        if delta != 0 {
+            insert_transaction(&transaction, &edit.to_transaction(delta))?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn transfer_stock(&mut self, transfer: &StockTransfer) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        validate_transfer(transfer, &part_storage(&transaction, &transfer.partnumber)?)?;
+        transaction.execute("UPDATE part_storage SET quantity = quantity - ?3 WHERE partnumber = ?1 AND storage_loc_name = ?2",
+                            params![transfer.partnumber, transfer.from, transfer.quantity])?;
+        transaction.execute("INSERT INTO part_storage (partnumber, storage_loc_name, quantity) VALUES (?1, ?2, ?3)
+                        ON CONFLICT (partnumber, storage_loc_name) DO UPDATE SET quantity = part_storage.quantity + excluded.quantity",
+                            params![transfer.partnumber, transfer.to.trim(), transfer.quantity])?;
+        for entry in transfer.to_transactions() {
+            insert_transaction(&transaction, &entry)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fetch_stock_history(&mut self, pn: &str) -> ShikaResult<Vec<StockTransaction>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id, SUBSTR(CAST(created_at AS TEXT), 1, 19) AS created_at, partnumber, kind, delta,
+                                            location, reference, reason, note, username
+                                            FROM stock_transactions WHERE partnumber = ?1 ORDER BY id DESC")?;
+        let history = stmt.query_map([pn], |row| {
+            Ok(StockTransaction {
+                id: row.get("id")?,
+                created_at: row.get("created_at")?,
+                partnumber: row.get("partnumber")?,
+                kind: row.get("kind")?,
+                delta: row.get("delta")?,
+                location: row.get("location")?,
+                reference: row.get("reference")?,
+                reason: row.get("reason")?,
+                note: row.get("note")?,
+                username: row.get("username")?,
+            })
+        })?.collect::<rusqlite::Result<Vec<StockTransaction>>>()?;
+        Ok(history)
+    }
+
+    fn reconcile_stock(&mut self, pn: &str, expected: i32) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let on_hand = current_on_hand(&transaction, pn)?;
+        let ledger_total: i64 = transaction.query_row(
+            "SELECT COALESCE(SUM(delta), 0) FROM stock_transactions WHERE partnumber = ?1", [pn], |row| row.get(0))?;
+        if let Some(entry) = reconciliation(pn, on_hand, ledger_total, expected)? {
+            insert_transaction(&transaction, &entry)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fetch_project_list(&mut self) -> ShikaResult<Vec<Project>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT project_name, status, revision, description, pcb_fab_pn, assembly_notes, owner,
+                                            COALESCE(SUBSTR(CAST(created_at AS TEXT), 1, 19), '') AS created_at,
+                                            COALESCE(SUBSTR(CAST(modified_at AS TEXT), 1, 19), '') AS modified_at
+                                            FROM projects")?;
+        let rows = stmt.query_map([], |row| {
+            let mut project = Project::new();
+            project.name = row.get("project_name")?;
+            project.metadata = ProjectMetadata {
+                revision: row.get("revision")?,
+                description: row.get("description")?,
+                pcb_fab_pn: row.get("pcb_fab_pn")?,
+                assembly_notes: row.get("assembly_notes")?,
+                owner: row.get("owner")?,
+            };
+            project.created_at = row.get("created_at")?;
+            project.modified_at = row.get("modified_at")?;
+            Ok((project, row.get::<_, String>("status")?))
+        })?.collect::<rusqlite::Result<Vec<(Project, String)>>>()?;
+        let mut projects = Vec::new();
+        for (mut project, status) in rows {
+            project.status = status.parse()?;
+            projects.push(project);
+        }
+        Ok(projects)
+    }
+
+    fn fetch_project_details(&mut self, project: &mut Project) -> ShikaResult<()> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT pc.designators, pc.qty, bpv.*
+                                            FROM project_components pc
+                                            JOIN big_part_view bpv ON bpv.partnumber = pc.partnumber
+                                            WHERE pc.project_name = ?1
+                                            ORDER BY pc.partnumber")?;
+        let parts = stmt.query_map([&project.name], |row| {
+            let part_info = part_from_row(row)?;
+            Ok(ProjectPart {
+                partnumber: part_info.part_number.clone(),
+                designators: row.get("designators")?,
+                qty: row.get("qty")?,
+                part_info,
+            })
+        })?.collect::<rusqlite::Result<Vec<ProjectPart>>>()?;
+        project.parts = parts;
+        Ok(())
+    }
+
+    fn create_new_project_name(&mut self, name: String) -> ShikaResult<()> {
+        validate_project_name(&name)?;
+        self.conn()?.execute("INSERT INTO projects (project_name, owner, created_at, modified_at)
+                                 VALUES (?1, ?2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP)", params![name, current_user()])
+            .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || format!("Project {name} already exists")))?;
+        Ok(())
+    }
+
+    fn rename_project(&mut self, old_name: &str, new_name: &str) -> ShikaResult<()> {
+        validate_project_name(new_name)?;
+        let updated = self.conn()?.execute("UPDATE projects SET project_name = ?1, modified_at = CURRENT_TIMESTAMP WHERE project_name = ?2",
+                                           params![new_name, old_name])
+            .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || format!("Project {new_name} already exists")))?;
+        if updated == 0 {
+            return Err(ShikaError::NotFound(format!("Project {old_name} does not exist")));
+        }
+        Ok(())
+    }
+
+    fn duplicate_project(&mut self, source: &str, new_name: &str) -> ShikaResult<()> {
+        validate_project_name(new_name)?;
+        let transaction = self.conn()?.transaction()?;
+        let inserted = transaction.execute("INSERT INTO projects (project_name, revision, description, pcb_fab_pn, assembly_notes,
+                                                owner, created_at, modified_at)
+                                                SELECT ?1, revision, description, pcb_fab_pn, assembly_notes, owner,
+                                                CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                                                FROM projects
+                                                WHERE project_name = ?2", params![new_name, source])
+            .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || format!("Project {new_name} already exists")))?;
+        if inserted == 0 {
+            return Err(ShikaError::NotFound(format!("Project {source} does not exist")));
+        }
+        transaction.execute("INSERT INTO project_components (project_name, partnumber, designators, qty)
+                                SELECT ?1, partnumber, designators, qty
+                                FROM project_components
+                                WHERE project_name = ?2", params![new_name, source])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn set_project_status(&mut self, name: &str, status: ProjectStatus) -> ShikaResult<()> {
+        let updated = self.conn()?.execute("UPDATE projects SET status = ?1 WHERE project_name = ?2",
+                                           params![status.as_str(), name])?;
+        if updated == 0 {
+            return Err(ShikaError::NotFound(format!("Project {name} does not exist")));
+        }
+        Ok(())
+    }
+
+    fn update_project_metadata(&mut self, name: &str, metadata: &ProjectMetadata) -> ShikaResult<()> {
+        let updated = self.conn()?.execute("UPDATE projects SET revision = ?2, description = ?3, pcb_fab_pn = ?4,
+                                             assembly_notes = ?5, owner = ?6, modified_at = CURRENT_TIMESTAMP
+                                             WHERE project_name = ?1",
+                                            params![name, metadata.revision, metadata.description, metadata.pcb_fab_pn,
+                                                    metadata.assembly_notes, metadata.owner])?;
+        if updated == 0 {
+            return Err(ShikaError::NotFound(format!("Project {name} does not exist")));
+        }
+        Ok(())
+    }
+
+    fn delete_project(&mut self, name: &str) -> ShikaResult<()> {
+        //Immediate takes the write lock up front, so no build can be started between the check and the delete
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if transaction.query_row("SELECT 1 FROM builds WHERE project_name = ?1 LIMIT 1", [name], |_| Ok(())).optional()?.is_some() {
+            return Err(project_has_builds(name));
+        }
+        let deleted = transaction.execute("DELETE FROM projects WHERE project_name = ?1", [name])?;
+        if deleted == 0 {
+            return Err(ShikaError::NotFound(format!("Project {name} does not exist")));
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fetch_pns_not_in_project(&mut self, project: &Project) -> ShikaResult<Vec<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT p.partnumber
+                                            FROM parts p
+                                            WHERE p.partnumber NOT IN (
+                                                SELECT pc.partnumber
+                                                FROM project_components pc
+                                                WHERE pc.project_name = ?1
+                                            )")?;
+        let partnumbers = stmt.query_map([&project.name], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(partnumbers)
+    }
+
+    fn add_pn_to_project(&mut self, project: &Project, ppart: &ProjectPart) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction()?;
+        transaction.execute("INSERT INTO project_components (project_name, partnumber, designators, qty) VALUES (?1, ?2, ?3, ?4)",
+                            params![project.name, ppart.partnumber, ppart.designators, ppart.qty])
+            .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || {
+                format!("{} is already in the BOM for {}", ppart.partnumber, project.name)
+            }))?;
+        touch_project(&transaction, &project.name)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn update_project_part(&mut self, project: &Project, old_pn: &str, ppart: &ProjectPart) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction()?;
+        let updated = transaction.execute("UPDATE project_components SET partnumber = ?1, designators = ?2, qty = ?3
+                                             WHERE project_name = ?4 AND partnumber = ?5",
+                                            params![ppart.partnumber, ppart.designators, ppart.qty, project.name, old_pn])
+            .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || {
+                format!("{} is already in the BOM for {}", ppart.partnumber, project.name)
+            }))?;
+        if updated == 0 {
+            return Err(ShikaError::NotFound(format!("{old_pn} is not in the BOM for {}", project.name)));
+        }
+        touch_project(&transaction, &project.name)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn remove_pn_from_project(&mut self, project: &Project, pn: &str) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction()?;
+        let deleted = transaction.execute("DELETE FROM project_components WHERE project_name = ?1 AND partnumber = ?2",
+                                          params![project.name, pn])?;
+        if deleted == 0 {
+            return Err(ShikaError::NotFound(format!("{pn} is not in the BOM for {}", project.name)));
+        }
+        touch_project(&transaction, &project.name)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn import_project_parts(&mut self, project: &Project, new_parts: &[Part], pparts: &[ProjectPart]) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction()?;
+        for part in new_parts {
+            validate_part(part)?;
+            transaction.execute("INSERT INTO parts (partnumber, manufacturer, description, label, package, value, tolerance)
+                                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                                params![part.part_number, part.manufacturer, part.description, part.label,
+                                        part.package, part.value, part.tolerance])
+                .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || format!("Part number {} already exists", part.part_number)))?;
+        }
+        for ppart in pparts {
+            transaction.execute("INSERT INTO project_components (project_name, partnumber, designators, qty) VALUES (?1, ?2, ?3, ?4)",
+                                params![project.name, ppart.partnumber, ppart.designators, ppart.qty])
+                .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || {
+                    format!("{} is already in the BOM for {}", ppart.partnumber, project.name)
+                }))?;
+        }
+        touch_project(&transaction, &project.name)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fetch_builds(&mut self, project_name: &str) -> ShikaResult<Vec<Build>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id, project_name, quantity, status,
+                                            SUBSTR(CAST(started_at AS TEXT), 1, 19) AS started_at,
+                                            SUBSTR(CAST(finished_at AS TEXT), 1, 19) AS finished_at
+                                            FROM builds WHERE project_name = ?1 ORDER BY id DESC")?;
+        let rows = stmt.query_map([project_name], |row| {
+            Ok((Build {
+                id: row.get("id")?,
+                project_name: row.get("project_name")?,
+                quantity: row.get("quantity")?,
+                status: BuildStatus::InProgress,
+                started_at: row.get("started_at")?,
+                finished_at: row.get("finished_at")?,
+            }, row.get::<_, String>("status")?))
+        })?.collect::<rusqlite::Result<Vec<(Build, String)>>>()?;
+        let mut builds = Vec::new();
+        for (mut build, status) in rows {
+            build.status = status.parse()?;
+            builds.push(build);
+        }
+        Ok(builds)
+    }
+
+    fn start_build(&mut self, project_name: &str, units: i32) -> ShikaResult<i64> {
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut project = Project::new();
+        project.name = project_name.to_string();
+        project.parts = transaction.prepare("SELECT partnumber, designators, qty FROM project_components
+                                                WHERE project_name = ?1 ORDER BY partnumber")?
+            .query_map([project_name], |row| {
+                Ok(ProjectPart {
+                    partnumber: row.get("partnumber")?,
+                    designators: row.get("designators")?,
+                    qty: row.get("qty")?,
+                    part_info: Part::new(),
+                })
+            })?.collect::<rusqlite::Result<Vec<ProjectPart>>>()?;
+        let stock = transaction.prepare("SELECT partnumber, on_hand, in_prod FROM stock
+                                            WHERE partnumber IN (SELECT partnumber FROM project_components WHERE project_name = ?1)")?
+            .query_map([project_name], |row| {
+                let mut info = StockInfo::new();
+                info.partnumber = row.get("partnumber")?;
+                info.on_hand = row.get("on_hand")?;
+                info.in_prod = row.get("in_prod")?;
+                info.recompute();
+                Ok(info)
+            })?.collect::<rusqlite::Result<Vec<StockInfo>>>()?;
+        let reserved = reserve_build(&project, &stock, units)?;
+        transaction.execute("INSERT INTO builds (project_name, quantity) VALUES (?1, ?2)", params![project_name, units])?;
+        let id = transaction.last_insert_rowid();
+        for part in &reserved {
+            transaction.execute("INSERT INTO build_parts (build_id, partnumber, quantity) VALUES (?1, ?2, ?3)",
+                                params![id, part.partnumber, part.quantity])?;
+            transaction.execute("UPDATE stock SET in_prod = in_prod + ?2 WHERE partnumber = ?1",
+                                params![part.partnumber, part.quantity])?;
+        }
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    fn complete_build(&mut self, id: i64) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for part in open_build_parts(&transaction, id)? {
+            let (on_hand, in_prod) = current_stock_levels(&transaction, &part.partnumber)?;
+            let storage = part_storage(&transaction, &part.partnumber)?;
+            let plan = plan_spend(&part.to_spend(), on_hand, in_prod - part.quantity, &storage)?;
+            transaction.execute("UPDATE stock SET on_hand = on_hand - ?2, in_prod = in_prod - ?2 WHERE partnumber = ?1", params![part.partnumber, part.quantity])?;
+            for (location, quantity) in &plan {
+                transaction.execute("UPDATE part_storage SET quantity = quantity - ?3 WHERE partnumber = ?1 AND storage_loc_name = ?2",
+                                    params![part.partnumber, location, quantity])?;
+            }
+            for entry in part.to_transactions(id, &plan) {
+                insert_transaction(&transaction, &entry)?;
+            }
+        }
+        transaction.execute("UPDATE builds SET status = ?2, finished_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                            params![id, BuildStatus::Completed.as_str()])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn cancel_build(&mut self, id: i64) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for part in open_build_parts(&transaction, id)? {
+            transaction.execute("UPDATE stock SET in_prod = in_prod - ?2 WHERE partnumber = ?1", params![part.partnumber, part.quantity])?;
+        }
+        transaction.execute("UPDATE builds SET status = ?2, finished_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                            params![id, BuildStatus::Cancelled.as_str()])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fetch_revisions(&mut self, project_name: &str) -> ShikaResult<Vec<ProjectRevision>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id, project_name, revision,
+                                            SUBSTR(CAST(created_at AS TEXT), 1, 19) AS created_at
+                                            FROM project_revisions WHERE project_name = ?1 ORDER BY id DESC")?;
+        let mut revisions = stmt.query_map([project_name], |row| {
+            Ok(ProjectRevision {
+                id: row.get("id")?,
+                project_name: row.get("project_name")?,
+                revision: row.get("revision")?,
+                created_at: row.get("created_at")?,
+                lines: Vec::new(),
+            })
+        })?.collect::<rusqlite::Result<Vec<ProjectRevision>>>()?;
+        let mut stmt = conn.prepare("SELECT partnumber, designators, qty FROM project_revision_parts
+                                            WHERE revision_id = ?1 ORDER BY partnumber")?;
+        for revision in revisions.iter_mut() {
+            revision.lines = stmt.query_map([revision.id], |row| {
+                Ok(RevisionLine {
+                    partnumber: row.get("partnumber")?,
+                    designators: row.get("designators")?,
+                    qty: row.get("qty")?,
+                })
+            })?.collect::<rusqlite::Result<Vec<RevisionLine>>>()?;
+        }
+        Ok(revisions)
+    }
+
+    fn create_revision(&mut self, project_name: &str, revision: &str) -> ShikaResult<()> {
+        validate_revision_name(revision)?;
+        let transaction = self.conn()?.transaction()?;
+        if transaction.query_row("SELECT 1 FROM projects WHERE project_name = ?1", [project_name], |_| Ok(())).optional()?.is_none() {
+            return Err(ShikaError::NotFound(format!("Project {project_name} does not exist")));
+        }
+        transaction.execute("INSERT INTO project_revisions (project_name, revision) VALUES (?1, ?2)", params![project_name, revision])
+            .map_err(|e| ShikaError::on_sqlite_unique_violation(e, || revision_exists(project_name, revision)))?;
+        let id = transaction.last_insert_rowid();
+        transaction.execute("INSERT INTO project_revision_parts (revision_id, partnumber, designators, qty)
+                                SELECT ?1, partnumber, designators, qty FROM project_components WHERE project_name = ?2",
+                            params![id, project_name])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn fetch_import_profiles(&mut self) -> ShikaResult<Vec<ImportProfile>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT name, mapping FROM import_profiles ORDER BY name")?;
+        let rows = stmt.query_map([], |row| Ok((row.get("name")?, row.get("mapping")?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        rows.into_iter().map(|(name, mapping)| profile_from_json(name, mapping)).collect()
+    }
+
+    fn save_import_profile(&mut self, profile: &ImportProfile) -> ShikaResult<()> {
+        validate_profile_name(&profile.name)?;
+        self.conn()?.execute("INSERT INTO import_profiles (name, mapping) VALUES (?1, ?2)
+                                ON CONFLICT (name) DO UPDATE SET mapping = excluded.mapping",
+                             params![profile.name, profile_to_json(profile)?])?;
+        Ok(())
+    }
+
+    fn fetch_categories(&mut self) -> ShikaResult<Vec<Category>> {
+        fetch_categories(self.conn()?)
+    }
+
+    fn save_category(&mut self, category: &Category) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction()?;
+        validate_category(category, &fetch_categories(&transaction)?)?;
+        transaction.execute("INSERT INTO categories (path, parent) VALUES (?1, ?2) ON CONFLICT (path) DO NOTHING",
+                            params![category.path, category.parent()])?;
+        transaction.execute("DELETE FROM category_attributes WHERE category = ?1", [&category.path])?;
+        for (position, attribute) in category.attributes.iter().enumerate() {
+            transaction.execute("INSERT INTO category_attributes (category, name, kind, unit, position) VALUES (?1, ?2, ?3, ?4, ?5)",
+                                params![category.path, attribute.name, attribute.kind.as_str(),
+                                        attribute.unit.map(|unit| unit.symbol()), position as i32])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete_category(&mut self, path: &str) -> ShikaResult<()> {
+        let transaction = self.conn()?.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let parts: i64 = transaction.query_row("SELECT COUNT(*) FROM parts WHERE category = ?1", [path], |row| row.get(0))?;
+        validate_category_removal(path, &fetch_categories(&transaction)?, parts as usize)?;
+        transaction.execute("DELETE FROM categories WHERE path = ?1", [path])?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use crate::stock::{ledger_difference, SpendReason};
+
+#[cfg(test)]
+fn test_part(pn: &str) -> Part {
+    let mut part = Part::new();
+    part.part_number = pn.to_string();
+    part.manufacturer = Some("Yageo".to_string());
+    part
+}
+
+#[test]
+fn test_sqlite_migrates_on_open() {
+    let mut repo = SqliteRepository::in_memory();
+    repo.check_connection().unwrap();
+    assert_eq!(migrate::current_version(&mut repo).unwrap(), migrate::MIGRATIONS.len() as i32);
+    //Running it again is a no-op
+    assert!(migrate::migrate(&mut repo).unwrap().is_empty());
+}
+
+#[test]
+fn test_sqlite_parts_and_stock() {
+    let mut repo = SqliteRepository::in_memory();
+    repo.add_new_part(&test_part("RC0603FR-0710KL")).unwrap();
+    match repo.add_new_part(&test_part("RC0603FR-0710KL")) {
+        Err(ShikaError::ConstraintViolation(msg)) => assert_eq!(msg, "Part number RC0603FR-0710KL already exists"),
+        _ => panic!("expected a constraint violation"),
+    }
+    let part = repo.fetch_single_part("RC0603FR-0710KL").unwrap();
+    assert_eq!(part.total_qty, Some(0));
+    assert_eq!(part.description, Some("".to_string()));
+    assert!(matches!(repo.fetch_single_part("NOPE"), Err(ShikaError::NotFound(_))));
+
+    assert_eq!(repo.fetch_nonstocked_partnumbers().unwrap(), vec!["RC0603FR-0710KL"]);
+    let mut stock = StockInfo::new();
+    stock.partnumber = "RC0603FR-0710KL".to_string();
+    stock.on_hand = 100;
+    stock.on_order = 50;
+    repo.create_new_stock(stock).unwrap();
+    let stock = &repo.fetch_stock_info().unwrap()[0];
+    assert_eq!((stock.total_stock, stock.available, stock.balance), (150, 100, 150));
+    assert!(repo.fetch_nonstocked_partnumbers().unwrap().is_empty());
+
+    repo.receive_stock(&StockReceipt {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 30,
+        location: " Reel rack ".to_string(),
+        reference: Some("PO-1234".to_string()),
+        against_order: true,
+    }).unwrap();
+    let stock = &repo.fetch_stock_info().unwrap()[0];
+    assert_eq!((stock.on_hand, stock.on_order), (130, 20));
+    assert_eq!(repo.fetch_single_part("RC0603FR-0710KL").unwrap().total_qty, Some(30));
+    let mut spend = StockSpend {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 31,
+        location: Some("Reel rack".to_string()),
+        reason: SpendReason::Scrap,
+    };
+    assert!(matches!(repo.spend_stock(&spend), Err(ShikaError::Validation(_))));
+    spend.quantity = 10;
+    repo.spend_stock(&spend).unwrap();
+    assert_eq!(repo.fetch_stock_info().unwrap()[0].on_hand, 120);
+    assert_eq!(repo.fetch_part_storage_data("RC0603FR-0710KL").unwrap()[0].quantity, 20);
+    let mut edit = StockEdit {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        low_stock_threshold: 10,
+        on_hand: 118,
+        on_order: 20,
+        reason: "".to_string(),
+        location: None,
+    };
+    assert!(matches!(repo.edit_stock(&edit), Err(ShikaError::Validation(_))));
+    edit.reason = "recount".to_string();
+    //Only 100 aren't in the reel rack, taking off more has to be booked there
+    edit.on_hand = 10;
+    assert!(matches!(repo.edit_stock(&edit), Err(ShikaError::Validation(_))));
+    edit.on_hand = 118;
+    edit.location = Some("Reel rack".to_string());
+    repo.edit_stock(&edit).unwrap();
+    assert_eq!(repo.fetch_part_storage_data("RC0603FR-0710KL").unwrap()[0].quantity, 18);
+    let stock = &repo.fetch_stock_info().unwrap()[0];
+    assert_eq!((stock.on_hand, stock.available, stock.balance), (118, 118, 138));
+    repo.transfer_stock(&StockTransfer {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 5,
+        from: "Reel rack".to_string(),
+        to: "Drawer A1".to_string(),
+    }).unwrap();
+    assert_eq!(repo.fetch_part_storage_data("RC0603FR-0710KL").unwrap().len(), 2);
+
+    //Opening balance, receive, spend, adjust and the transfer pair, newest first
+    let history = repo.fetch_stock_history("RC0603FR-0710KL").unwrap();
+    let kinds: Vec<&str> = history.iter().map(|entry| entry.kind.as_str()).collect();
+    assert_eq!(kinds, vec!["transfer", "transfer", "adjust", "spend", "receive", "adjust"]);
+    assert_eq!(history[4].reference, Some("PO-1234".to_string()));
+    assert_eq!(history[4].location.as_deref(), Some("Reel rack"));
+    assert_eq!(history[0].created_at.len(), 19);
+    assert_eq!(ledger_difference(118, &history), 0);
+    repo.conn().unwrap().execute("UPDATE stock SET on_hand = 120", []).unwrap();
+    assert!(repo.reconcile_stock("RC0603FR-0710KL", 1).is_err());
+    repo.reconcile_stock("RC0603FR-0710KL", 2).unwrap();
+    assert_eq!(ledger_difference(120, &repo.fetch_stock_history("RC0603FR-0710KL").unwrap()), 0);
+
+    //Foreign keys are enforced like on Postgres
+    let mut orphan = StockInfo::new();
+    orphan.partnumber = "NOPE".to_string();
+    assert!(matches!(repo.create_new_stock(orphan), Err(ShikaError::ConstraintViolation(_))));
+}
+
+#[test]
+fn test_sqlite_spend_unlocated_stock() {
+    let mut repo = SqliteRepository::in_memory();
+    repo.add_new_part(&test_part("RC0603FR-0710KL")).unwrap();
+    let mut stock = StockInfo::new();
+    stock.partnumber = "RC0603FR-0710KL".to_string();
+    stock.on_hand = 12;
+    repo.create_new_stock(stock).unwrap();
+    repo.receive_stock(&StockReceipt {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 5,
+        location: "Bin 1".to_string(),
+        reference: None,
+        against_order: false,
+    }).unwrap();
+    //The opening balance isn't in a location and is spent before Bin 1
+    let mut spend = StockSpend {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 10,
+        location: None,
+        reason: SpendReason::Build,
+    };
+    repo.spend_stock(&spend).unwrap();
+    assert_eq!(repo.fetch_stock_info().unwrap()[0].on_hand, 7);
+    assert_eq!(repo.fetch_part_storage_data("RC0603FR-0710KL").unwrap()[0].quantity, 5);
+    spend.quantity = 4;
+    repo.spend_stock(&spend).unwrap();
+    assert_eq!(repo.fetch_part_storage_data("RC0603FR-0710KL").unwrap()[0].quantity, 3);
+    let history = repo.fetch_stock_history("RC0603FR-0710KL").unwrap();
+    let locations: Vec<Option<&str>> = history.iter().take(3).map(|entry| entry.location.as_deref()).collect();
+    assert_eq!(locations, vec![Some("Bin 1"), None, None]);
+    assert_eq!(ledger_difference(3, &history), 0);
+}
+
+#[test]
+fn test_sqlite_projects() {
+    let mut repo = SqliteRepository::in_memory();
+    repo.add_new_part(&test_part("RC0603FR-0710KL")).unwrap();
+    repo.add_new_part(&test_part("25SVPF47M")).unwrap();
+    repo.create_new_project_name("Sample Board".to_string()).unwrap();
+    let mut project = repo.fetch_project_list().unwrap().remove(0);
+    let ppart = ProjectPart {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        designators: "R1-R4".to_string(),
+        qty: 4,
+        part_info: Part::new(),
+    };
+    repo.add_pn_to_project(&project, &ppart).unwrap();
+    repo.fetch_project_details(&mut project).unwrap();
+    assert_eq!(project.parts.len(), 1);
+    assert_eq!(project.parts[0].qty, 4);
+    let mut metadata = project.metadata.clone();
+    metadata.revision = "B".to_string();
+    metadata.pcb_fab_pn = "PCB-0042".to_string();
+    repo.update_project_metadata("Sample Board", &metadata).unwrap();
+    let fetched = repo.fetch_project_list().unwrap().remove(0);
+    assert_eq!(fetched.metadata, metadata);
+    assert!(!fetched.created_at.is_empty());
+    assert!(repo.update_project_metadata("Missing", &metadata).is_err());
+    repo.create_revision("Sample Board", "A").unwrap();
+    assert!(repo.create_revision("Sample Board", "A").is_err());
+    assert!(repo.create_revision("Missing", "A").is_err());
+    let revisions = repo.fetch_revisions("Sample Board").unwrap();
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0].lines.len(), 1);
+    assert_eq!(revisions[0].lines[0].designators, "R1-R4");
+    assert_eq!(project.parts[0].part_info.manufacturer, Some("Yageo".to_string()));
+    assert_eq!(repo.fetch_pns_not_in_project(&project).unwrap(), vec!["25SVPF47M"]);
+
+    let mut substitute = project.parts[0].clone();
+    substitute.partnumber = "25SVPF47M".to_string();
+    substitute.qty = 2;
+    repo.update_project_part(&project, "RC0603FR-0710KL", &substitute).unwrap();
+    repo.fetch_project_details(&mut project).unwrap();
+    assert_eq!(project.parts[0].partnumber, "25SVPF47M");
+    assert_eq!(project.parts[0].qty, 2);
+    repo.remove_pn_from_project(&project, "25SVPF47M").unwrap();
+    assert!(repo.remove_pn_from_project(&project, "25SVPF47M").is_err());
+    repo.fetch_project_details(&mut project).unwrap();
+    assert!(project.parts.is_empty());
+
+    repo.add_pn_to_project(&project, &ppart).unwrap();
+    repo.duplicate_project("Sample Board", "Rev B").unwrap();
+    repo.rename_project("Rev B", "Rev C").unwrap();
+    repo.set_project_status("Sample Board", ProjectStatus::Archived).unwrap();
+    let projects = repo.fetch_project_list().unwrap();
+    assert_eq!(projects.iter().map(|p| (p.name.as_str(), p.status)).collect::<Vec<_>>(),
+               vec![("Sample Board", ProjectStatus::Archived), ("Rev C", ProjectStatus::Active)]);
+    let mut copy = projects[1].clone();
+    repo.fetch_project_details(&mut copy).unwrap();
+    assert_eq!(copy.parts.len(), 1);
+    //A build reserves into in_prod and completing it consumes on hand and storage
+    repo.create_new_stock(StockInfo { partnumber: "RC0603FR-0710KL".to_string(), on_hand: 10, ..StockInfo::new() }).unwrap();
+    repo.receive_stock(&StockReceipt {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 10,
+        location: "Drawer A1".to_string(),
+        reference: None,
+        against_order: false,
+    }).unwrap();
+    assert!(matches!(repo.start_build("Rev C", 6), Err(ShikaError::Validation(_))));
+    let first = repo.start_build("Rev C", 2).unwrap();
+    let second = repo.start_build("Rev C", 1).unwrap();
+    let stock = &repo.fetch_stock_info().unwrap()[0];
+    assert_eq!((stock.on_hand, stock.in_prod, stock.available), (20, 12, 8));
+    //Spending can't take what the builds reserved
+    let spend = StockSpend {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 9,
+        location: None,
+        reason: SpendReason::Scrap,
+    };
+    assert!(matches!(repo.spend_stock(&spend), Err(ShikaError::Validation(_))));
+    repo.complete_build(first).unwrap();
+    assert!(matches!(repo.complete_build(first), Err(ShikaError::Validation(_))));
+    repo.cancel_build(second).unwrap();
+    let stock = &repo.fetch_stock_info().unwrap()[0];
+    assert_eq!((stock.on_hand, stock.in_prod), (12, 0));
+    //The opening balance isn't in a location, so the build used that up first
+    assert_eq!(repo.fetch_part_storage_data("RC0603FR-0710KL").unwrap()[0].quantity, 10);
+    let history = repo.fetch_stock_history("RC0603FR-0710KL").unwrap();
+    assert_eq!(history[0].kind, "build-consume");
+    assert_eq!(ledger_difference(12, &history), 0);
+    let builds = repo.fetch_builds("Rev C").unwrap();
+    assert_eq!(builds.iter().map(|build| build.status).collect::<Vec<_>>(),
+               vec![BuildStatus::Cancelled, BuildStatus::Completed]);
+    assert!(builds[0].finished_at.is_some());
+    //An import that clashes with the BOM leaves nothing behind
+    let imported = ProjectPart {
+        partnumber: "PH-1x02".to_string(),
+        designators: "J1, J2".to_string(),
+        qty: 2,
+        part_info: Part::new(),
+    };
+    let clashing = ProjectPart { partnumber: "RC0603FR-0710KL".to_string(), ..imported.clone() };
+    assert!(repo.import_project_parts(&project, &[test_part("PH-1x02")], &[imported.clone(), clashing]).is_err());
+    assert!(repo.fetch_single_part("PH-1x02").is_err());
+    repo.import_project_parts(&project, &[test_part("PH-1x02")], &[imported]).unwrap();
+    repo.fetch_project_details(&mut project).unwrap();
+    assert_eq!(project.parts.len(), 2);
+
+    assert!(matches!(repo.delete_project("Rev C"), Err(ShikaError::ConstraintViolation(_))));
+    repo.delete_project("Sample Board").unwrap();
+    assert_eq!(repo.conn().unwrap().query_row(
+        "SELECT COUNT(*) FROM project_components WHERE project_name = 'Sample Board'", [], |row| row.get::<_, i64>(0)).unwrap(), 0);
+}
+
+#[test]
+fn test_sqlite_import_profiles() {
+    let mut repo = SqliteRepository::in_memory();
+    let mut profile = ImportProfile {
+        name: "Altium".to_string(),
+        mapping: Default::default(),
+    };
+    repo.save_import_profile(&profile).unwrap();
+    profile.mapping.part_number = Some("Manufacturer Part Number 1".to_string());
+    repo.save_import_profile(&profile).unwrap();
+    assert_eq!(repo.fetch_import_profiles().unwrap(), vec![profile]);
+    assert!(repo.save_import_profile(&ImportProfile { name: " ".to_string(), mapping: Default::default() }).is_err());
+}
+
+#[test]
+fn test_sqlite_categories() {
+    let mut repo = SqliteRepository::in_memory();
+    assert!(repo.fetch_categories().unwrap().iter().any(|category| category.path == "Passives/Resistors/Chip"));
+    let film = Category {
+        path: "Passives/Capacitors/Film".to_string(),
+        attributes: vec![AttributeDef::parse("ripple:value:A").unwrap()],
+    };
+    repo.save_category(&film).unwrap();
+    let mut part = test_part("ECW-FD2W104KB");
+    part.category = Some(film.path.clone());
+    part.attributes.insert("voltage".to_string(), "450V".to_string());
+    part.attributes.insert("ripple".to_string(), "1.2A".to_string());
+    repo.add_new_part(&part).unwrap();
+    let fetched = repo.fetch_single_part("ECW-FD2W104KB").unwrap();
+    assert_eq!(fetched.category.as_deref(), Some("Passives/Capacitors/Film"));
+    assert_eq!(fetched.attributes, part.attributes);
+
+    part.attributes.remove("ripple");
+    part.attributes.insert("dielectric".to_string(), "PP".to_string());
+    assert!(matches!(repo.modify_part(&part), Err(ShikaError::Validation(_))));
+    part.attributes.remove("dielectric");
+    repo.modify_part(&part).unwrap();
+    assert_eq!(repo.fetch_all_parts().unwrap()[0].attributes.len(), 1);
+    assert!(matches!(repo.delete_category("Passives/Capacitors/Film"), Err(ShikaError::ConstraintViolation(_))));
+    repo.delete_category("Crystals").unwrap();
+    assert!(repo.fetch_categories().unwrap().iter().all(|category| category.path != "Crystals"));
+}
@@ -1,4 +1,10 @@
-use crate::db;
+use std::str::FromStr;
+use postgres::Transaction;
+use serde::Serialize;
+use crate::db::Db;
+use crate::parts::PartStorage;
+use crate::errors::{ShikaError, ShikaResult};
+#[derive(Serialize, Clone)]
 pub struct StockInfo {
     //Nothing in this struct can be null, so no optional types needed.
     pub partnumber: String,
@@ -24,14 +30,342 @@ impl StockInfo {
             available: 0
         }
     }
+
+    /// Fills in the computed columns the same way the `stock` table does:
+    /// total = on hand + on order, available = on hand - in production, balance = total - in production.
+    pub fn recompute(&mut self) {
+        self.total_stock = self.on_hand + self.on_order;
+        self.available = self.on_hand - self.in_prod;
+        self.balance = self.total_stock - self.in_prod;
+    }
+}
+
+/// What a stock transaction did, stored in `stock_transactions.kind`.
+#[derive(Clone)]
+pub enum TransactionKind {
+    Receive,
+    Spend,
+    Adjust,
+    //Booked in pairs, out of one location and into another
+    Transfer,
+    //Stock taken by a completed build, see `build::complete_build`
+    BuildConsume,
+}
+
+impl TransactionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionKind::Receive => "receive",
+            TransactionKind::Spend => "spend",
+            TransactionKind::Adjust => "adjust",
+            TransactionKind::Transfer => "transfer",
+            TransactionKind::BuildConsume => "build-consume",
+        }
+    }
+}
+
+/// A row of the append-only `stock_transactions` ledger. For every part the deltas add up to `on_hand`.
+#[derive(Clone)]
+pub struct StockTransaction {
+    //0 and empty until the transaction has been stored
+    pub id: i64,
+    pub created_at: String,
+    pub partnumber: String,
+    pub kind: String,
+    pub delta: i32,
+    pub location: Option<String>,
+    pub reference: Option<String>,
+    pub reason: Option<String>,
+    pub note: Option<String>,
+    pub username: Option<String>,
+}
+
+impl StockTransaction {
+    /// A transaction booked by the current user.
+    pub fn new(partnumber: &str, kind: TransactionKind, delta: i32) -> StockTransaction {
+        StockTransaction {
+            id: 0,
+            created_at: "".to_string(),
+            partnumber: partnumber.to_string(),
+            kind: kind.as_str().to_string(),
+            delta,
+            location: None,
+            reference: None,
+            reason: None,
+            note: None,
+            username: Some(current_user()),
+        }
+    }
+}
+
+/// The login name recorded on transactions.
+pub fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or("unknown".to_string())
+}
+
+/// What a ledger is off by: how much has to be booked for it to add up to `on_hand` again.
+pub fn ledger_difference(on_hand: i32, history: &[StockTransaction]) -> i32 {
+    on_hand - history.iter().map(|transaction| transaction.delta).sum::<i32>()
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SpendReason {
+    Build,
+    Scrap,
+    Sample,
+}
+
+impl SpendReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpendReason::Build => "build",
+            SpendReason::Scrap => "scrap",
+            SpendReason::Sample => "sample",
+        }
+    }
+
+    /// The next reason, wrapping around, for cycling through them in a form.
+    pub fn next(&self) -> SpendReason {
+        match self {
+            SpendReason::Build => SpendReason::Scrap,
+            SpendReason::Scrap => SpendReason::Sample,
+            SpendReason::Sample => SpendReason::Build,
+        }
+    }
+}
+
+impl FromStr for SpendReason {
+    type Err = ShikaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "build" => Ok(SpendReason::Build),
+            "scrap" => Ok(SpendReason::Scrap),
+            "sample" => Ok(SpendReason::Sample),
+            _ => Err(ShikaError::Validation(format!("Unknown spend reason {s}"))),
+        }
+    }
+}
+
+/// Stock arriving at a storage location, optionally against an open order.
+pub struct StockReceipt {
+    pub partnumber: String,
+    pub quantity: i32,
+    pub location: String,
+    //PO number, supplier invoice, etc.
+    pub reference: Option<String>,
+    //Takes the quantity off `on_order` as well
+    pub against_order: bool,
 }
 
-pub fn fetch_stock_info() -> Vec<StockInfo> {
+/// Stock taken out of storage. Without a location it is pulled from every location in turn.
+pub struct StockSpend {
+    pub partnumber: String,
+    pub quantity: i32,
+    pub location: Option<String>,
+    pub reason: SpendReason,
+}
+
+impl StockSpend {
+    /// One transaction per location in the plan, plus one without a location for whatever the
+    /// plan takes from stock that was never put away.
+    pub fn to_transactions(&self, plan: &[(String, i32)]) -> Vec<StockTransaction> {
+        self.to_transactions_of(TransactionKind::Spend, plan)
+    }
+
+    /// Like `to_transactions`, booked as `kind`.
+    pub fn to_transactions_of(&self, kind: TransactionKind, plan: &[(String, i32)]) -> Vec<StockTransaction> {
+        let entry = |location: Option<&String>, quantity: i32| {
+            let mut entry = StockTransaction::new(&self.partnumber, kind.clone(), -quantity);
+            entry.location = location.cloned();
+            entry.reason = Some(self.reason.as_str().to_string());
+            entry
+        };
+        let located: i32 = plan.iter().map(|(_, quantity)| quantity).sum();
+        let mut entries = Vec::new();
+        if located < self.quantity {
+            entries.push(entry(None, self.quantity - located));
+        }
+        entries.extend(plan.iter().map(|(location, quantity)| entry(Some(location), *quantity)));
+        entries
+    }
+}
+
+/// Works out how much to take from each storage location, rejecting anything that would take
+/// `on_hand` or a location below zero. Whatever part of `on_hand` isn't in a location (stocked
+/// before it was put away) is spent first and left out of the plan. `reserved` is held for builds
+/// in progress and can't be spent.
+pub fn plan_spend(spend: &StockSpend, on_hand: i32, reserved: i32, storage: &[PartStorage]) -> ShikaResult<Vec<(String, i32)>> {
+    if spend.quantity <= 0 {
+        return Err(ShikaError::Validation("Spent quantity must be more than zero".to_string()));
+    }
+    if on_hand < spend.quantity {
+        return Err(ShikaError::Validation(
+            format!("Only {on_hand} of {} on hand, can't spend {}", spend.partnumber, spend.quantity)));
+    }
+    if on_hand - reserved < spend.quantity {
+        return Err(ShikaError::Validation(
+            format!("{reserved} of {} are reserved for builds, can't spend more than {}", spend.partnumber, on_hand - reserved)));
+    }
+    if let Some(location) = &spend.location {
+        let store = storage.iter().find(|store| &store.location == location)
+            .ok_or_else(|| ShikaError::Validation(format!("{} isn't stored at {location}", spend.partnumber)))?;
+        if store.quantity < spend.quantity {
+            return Err(ShikaError::Validation(
+                format!("Only {} at {location}, can't spend {}", store.quantity, spend.quantity)));
+        }
+        return Ok(vec![(location.clone(), spend.quantity)]);
+    }
+    let stored: i32 = storage.iter().map(|store| store.quantity).sum();
+    let mut remaining = spend.quantity - (on_hand - stored).max(0);
+    let mut plan = Vec::new();
+    for store in storage {
+        let take = remaining.min(store.quantity);
+        if take > 0 {
+            plan.push((store.location.clone(), take));
+            remaining -= take;
+        }
+    }
+    if remaining > 0 {
+        return Err(ShikaError::Validation(
+            format!("Only {stored} of {} in storage, can't spend {}", spend.partnumber, spend.quantity)));
+    }
+    Ok(plan)
+}
+
+/// Stock moved between two storage locations. `on_hand` doesn't change.
+pub struct StockTransfer {
+    pub partnumber: String,
+    pub quantity: i32,
+    pub from: String,
+    pub to: String,
+}
+
+impl StockTransfer {
+    /// The transfer out of `from` and into `to`, which cancel each other out in the ledger.
+    pub fn to_transactions(&self) -> Vec<StockTransaction> {
+        let mut out = StockTransaction::new(&self.partnumber, TransactionKind::Transfer, -self.quantity);
+        out.location = Some(self.from.clone());
+        let mut into = StockTransaction::new(&self.partnumber, TransactionKind::Transfer, self.quantity);
+        into.location = Some(self.to.trim().to_string());
+        vec![out, into]
+    }
+}
+
+pub fn validate_transfer(transfer: &StockTransfer, storage: &[PartStorage]) -> ShikaResult<()> {
+    if transfer.quantity <= 0 {
+        return Err(ShikaError::Validation("Transferred quantity must be more than zero".to_string()));
+    }
+    if transfer.to.trim().is_empty() {
+        return Err(ShikaError::Validation("Enter the storage location to move the stock to".to_string()));
+    }
+    if transfer.to.trim() == transfer.from {
+        return Err(ShikaError::Validation("Pick a different location to move the stock to".to_string()));
+    }
+    let available = storage.iter()
+        .find(|store| store.location == transfer.from)
+        .map(|store| store.quantity)
+        .ok_or_else(|| ShikaError::Validation(format!("{} isn't stored at {}", transfer.partnumber, transfer.from)))?;
+    if available < transfer.quantity {
+        return Err(ShikaError::Validation(
+            format!("Only {available} at {}, can't move {}", transfer.from, transfer.quantity)));
+    }
+    Ok(())
+}
+
+impl StockReceipt {
+    pub fn to_transaction(&self) -> StockTransaction {
+        let mut entry = StockTransaction::new(&self.partnumber, TransactionKind::Receive, self.quantity);
+        entry.location = Some(self.location.trim().to_string());
+        entry.reference = self.reference.clone();
+        entry
+    }
+}
+
+pub fn validate_receipt(receipt: &StockReceipt) -> ShikaResult<()> {
+    if receipt.quantity <= 0 {
+        return Err(ShikaError::Validation("Received quantity must be more than zero".to_string()));
+    }
+    if receipt.location.trim().is_empty() {
+        return Err(ShikaError::Validation("Enter the storage location the stock went to".to_string()));
+    }
+    Ok(())
+}
+
+/// New stock levels for a part. A changed `on_hand` is booked as an adjustment and needs a reason.
+/// `in_prod` isn't part of it, only starting, completing and cancelling builds change that.
+pub struct StockEdit {
+    pub partnumber: String,
+    pub low_stock_threshold: i32,
+    pub on_hand: i32,
+    pub on_order: i32,
+    pub reason: String,
+    //Where the on_hand change was counted, needed once the part has storage locations
+    pub location: Option<String>,
+}
+
+impl StockEdit {
+    pub fn to_transaction(&self, delta: i32) -> StockTransaction {
+        let mut entry = StockTransaction::new(&self.partnumber, TransactionKind::Adjust, delta);
+        entry.location = self.adjusted_location().map(str::to_string);
+        entry.note = Some(self.reason.trim().to_string());
+        entry
+    }
+
+    pub fn adjusted_location(&self) -> Option<&str> {
+        self.location.as_deref().map(str::trim).filter(|location| !location.is_empty())
+    }
+}
+
+/// Checks an edit against the current `on_hand` and returns the adjustment to book, 0 for none.
+/// Without a location the adjustment goes to the stock that isn't in a location, which can't go
+/// below zero, so the locations never add up to more than `on_hand`.
+pub fn validate_stock_edit(edit: &StockEdit, current_on_hand: i32, storage: &[PartStorage]) -> ShikaResult<i32> {
+    if edit.on_hand < 0 || edit.low_stock_threshold < 0 || edit.on_order < 0 {
+        return Err(ShikaError::Validation("Stock quantities cannot be negative".to_string()));
+    }
+    let delta = edit.on_hand - current_on_hand;
+    if delta == 0 {
+        return Ok(0);
+    }
+    if edit.reason.trim().is_empty() {
+        return Err(ShikaError::Validation("Give a reason for changing the on hand quantity".to_string()));
+    }
+    let Some(location) = edit.adjusted_location() else {
+        let unlocated = current_on_hand - storage.iter().map(|store| store.quantity).sum::<i32>();
+        if unlocated + delta < 0 {
+            return Err(ShikaError::Validation("Enter the storage location the on hand change was counted at".to_string()));
+        }
+        return Ok(delta);
+    };
+    let stored = storage.iter()
+        .find(|store| store.location == location)
+        .map(|store| store.quantity)
+        .ok_or_else(|| ShikaError::Validation(format!("{} isn't stored at {location}", edit.partnumber)))?;
+    if stored + delta < 0 {
+        return Err(ShikaError::Validation(format!("Only {stored} at {location}, can't take off {}", -delta)));
+    }
+    Ok(delta)
+}
+
+pub fn validate_new_stock(stock: &StockInfo) -> ShikaResult<()> {
+    if stock.partnumber.is_empty() {
+        return Err(ShikaError::Validation("Select a part number to stock".to_string()));
+    }
+    if stock.on_hand < 0 || stock.low_stock_threshold < 0 || stock.on_order < 0 || stock.in_prod < 0 {
+        return Err(ShikaError::Validation("Stock quantities cannot be negative".to_string()));
+    }
+    Ok(())
+}
+
+pub fn fetch_stock_info(db: &mut Db) -> ShikaResult<Vec<StockInfo>> {
     let mut stock_data = Vec::new();
 
     let query = "SELECT * FROM stock";
-    let mut client = db::postgres_init();
-    let rows = client.query(query, &[]).unwrap();
+    let client = db.client()?;
+    let rows = client.query(query, &[])?;
     for row in rows {
         let mut stock = StockInfo::new();
         stock.partnumber = row.try_get("partnumber").unwrap_or("".to_string());
@@ -44,23 +378,303 @@ pub fn fetch_stock_info() -> Vec<StockInfo> {
         stock.available = row.try_get("c_available").unwrap_or(0);
         stock_data.push(stock);
     }
-    return stock_data;
+    Ok(stock_data)
 }
 
-pub fn fetch_nonstocked_partnumbers() -> Vec<String> {
+pub fn fetch_nonstocked_partnumbers(db: &mut Db) -> ShikaResult<Vec<String>> {
     let mut partnumbers = Vec::new();
     let query = "select * from non_stocked_parts_view";
-    let mut client = db::postgres_init();
-    let rows = client.query(query, &[]).unwrap_or(Vec::new());
+    let client = db.client()?;
+    let rows = client.query(query, &[])?;
     for row in rows {
         let partnumber: String = row.try_get("partnumber").unwrap_or("".to_string());
         partnumbers.push(partnumber);
     }
-    partnumbers
+    Ok(partnumbers)
+}
+
+pub fn insert_transaction(transaction: &mut Transaction, entry: &StockTransaction) -> ShikaResult<()> {
+    transaction.execute("INSERT INTO stock_transactions (partnumber, kind, delta, location, reference, reason, note, username)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                        &[&entry.partnumber, &entry.kind, &entry.delta, &entry.location, &entry.reference,
+                          &entry.reason, &entry.note, &entry.username])?;
+    Ok(())
+}
+
+/// The part's storage rows, locked until the transaction ends.
+pub fn lock_part_storage(transaction: &mut Transaction, pn: &str) -> ShikaResult<Vec<PartStorage>> {
+    let rows = transaction.query("SELECT partnumber, storage_loc_name, quantity FROM part_storage
+                    WHERE partnumber = $1 ORDER BY storage_loc_name FOR UPDATE", &[&pn])?;
+    Ok(rows.iter()
+        .map(|row| PartStorage {
+            part_number: row.get("partnumber"),
+            location: row.get("storage_loc_name"),
+            quantity: row.get("quantity"),
+        })
+        .collect())
+}
+
+pub fn lock_on_hand(transaction: &mut Transaction, pn: &str) -> ShikaResult<i32> {
+    Ok(lock_stock_levels(transaction, pn)?.0)
+}
+
+/// The part's `on_hand` and `in_prod`, locked until the transaction ends.
+pub fn lock_stock_levels(transaction: &mut Transaction, pn: &str) -> ShikaResult<(i32, i32)> {
+    let row = transaction.query_opt("SELECT on_hand, in_prod FROM stock WHERE partnumber = $1 FOR UPDATE", &[&pn])?
+        .ok_or_else(|| ShikaError::NotFound(format!("Part number {pn} is not stocked")))?;
+    Ok((row.get("on_hand"), row.get("in_prod")))
+}
+
+/// Anything stocked with a quantity gets an opening balance, so the ledger adds up from the start.
+pub fn opening_balance(stock: &StockInfo) -> Option<StockTransaction> {
+    if stock.on_hand == 0 {
+        return None;
+    }
+    let mut entry = StockTransaction::new(&stock.partnumber, TransactionKind::Adjust, stock.on_hand);
+    entry.note = Some("Opening balance".to_string());
+    Some(entry)
 }
 
-pub fn create_new_stock(stock: StockInfo) {
-    let query = "INSERT INTO stock (partnumber, low_stock_threshold, on_hand, on_order) VALUES ($1, $2, $3, $4)";
-    let mut client = db::postgres_init();
-    client.execute(query, &[&stock.partnumber, &stock.low_stock_threshold, &stock.on_hand, &stock.on_order]).unwrap();
-}
\ No newline at end of file
+pub fn create_new_stock(db: &mut Db, stock: StockInfo) -> ShikaResult<()> {
+    validate_new_stock(&stock)?;
+    let query = "INSERT INTO stock (partnumber, low_stock_threshold, on_hand, on_order, in_prod) VALUES ($1, $2, $3, $4, $5)";
+    let mut transaction = db.client()?.transaction()?;
+    transaction.execute(query, &[&stock.partnumber, &stock.low_stock_threshold, &stock.on_hand, &stock.on_order, &stock.in_prod])
+        .map_err(|e| ShikaError::on_unique_violation(e, || {
+            format!("Part number {} is already stocked", stock.partnumber)
+        }))?;
+    if let Some(entry) = opening_balance(&stock) {
+        insert_transaction(&mut transaction, &entry)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Books received stock: bumps `on_hand`, optionally works off `on_order`, adds the quantity to
+/// the storage location and records the transaction. All of it happens or none of it does.
+pub fn receive_stock(db: &mut Db, receipt: &StockReceipt) -> ShikaResult<()> {
+    validate_receipt(receipt)?;
+    let mut transaction = db.client()?.transaction()?;
+    let updated = transaction.execute("UPDATE stock SET on_hand = on_hand + $2,
+                    on_order = CASE WHEN $3 THEN (CASE WHEN on_order > $2 THEN on_order - $2 ELSE 0 END) ELSE on_order END
+                    WHERE partnumber = $1",
+                                      &[&receipt.partnumber, &receipt.quantity, &receipt.against_order])?;
+    if updated == 0 {
+        return Err(ShikaError::NotFound(format!("Part number {} is not stocked", receipt.partnumber)));
+    }
+    transaction.execute("INSERT INTO part_storage (partnumber, storage_loc_name, quantity) VALUES ($1, $2, $3)
+                    ON CONFLICT (partnumber, storage_loc_name) DO UPDATE SET quantity = part_storage.quantity + EXCLUDED.quantity",
+                        &[&receipt.partnumber, &receipt.location.trim(), &receipt.quantity])?;
+    insert_transaction(&mut transaction, &receipt.to_transaction())?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Spends stock following `plan_spend`, taking `on_hand` and the storage locations down together
+/// and recording one transaction per location. Stock reserved in `in_prod` is left alone.
+pub fn spend_stock(db: &mut Db, spend: &StockSpend) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    //Lock the rows we are about to change so the plan can't go stale before it is applied
+    let (on_hand, in_prod) = lock_stock_levels(&mut transaction, &spend.partnumber)?;
+    let storage = lock_part_storage(&mut transaction, &spend.partnumber)?;
+    let plan = plan_spend(spend, on_hand, in_prod, &storage)?;
+    transaction.execute("UPDATE stock SET on_hand = on_hand - $2 WHERE partnumber = $1", &[&spend.partnumber, &spend.quantity])?;
+    for (location, quantity) in &plan {
+        transaction.execute("UPDATE part_storage SET quantity = quantity - $3 WHERE partnumber = $1 AND storage_loc_name = $2",
+                            &[&spend.partnumber, location, quantity])?;
+    }
+    for entry in spend.to_transactions(&plan) {
+        insert_transaction(&mut transaction, &entry)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Saves new stock levels, booking any change to `on_hand` as an adjustment transaction.
+pub fn edit_stock(db: &mut Db, edit: &StockEdit) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    let current_on_hand = lock_on_hand(&mut transaction, &edit.partnumber)?;
+    let storage = lock_part_storage(&mut transaction, &edit.partnumber)?;
+    let delta = validate_stock_edit(edit, current_on_hand, &storage)?;
+    transaction.execute("UPDATE stock SET low_stock_threshold = $2, on_hand = $3, on_order = $4 WHERE partnumber = $1",
+                        &[&edit.partnumber, &edit.low_stock_threshold, &edit.on_hand, &edit.on_order])?;
+    if let Some(location) = edit.adjusted_location().filter(|_| delta != 0) {
+        transaction.execute("UPDATE part_storage SET quantity = quantity + $3 WHERE partnumber = $1 AND storage_loc_name = $2",
+                            &[&edit.partnumber, &location, &delta])?;
+    }
+    if delta != 0 {
+        insert_transaction(&mut transaction, &edit.to_transaction(delta))?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+pub fn transfer_stock(db: &mut Db, transfer: &StockTransfer) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    let storage = lock_part_storage(&mut transaction, &transfer.partnumber)?;
+    validate_transfer(transfer, &storage)?;
+    transaction.execute("UPDATE part_storage SET quantity = quantity - $3 WHERE partnumber = $1 AND storage_loc_name = $2",
+                        &[&transfer.partnumber, &transfer.from, &transfer.quantity])?;
+    transaction.execute("INSERT INTO part_storage (partnumber, storage_loc_name, quantity) VALUES ($1, $2, $3)
+                    ON CONFLICT (partnumber, storage_loc_name) DO UPDATE SET quantity = part_storage.quantity + EXCLUDED.quantity",
+                        &[&transfer.partnumber, &transfer.to.trim(), &transfer.quantity])?;
+    for entry in transfer.to_transactions() {
+        insert_transaction(&mut transaction, &entry)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The part's ledger, newest first.
+pub fn fetch_stock_history(db: &mut Db, pn: &str) -> ShikaResult<Vec<StockTransaction>> {
+    let rows = db.client()?.query("SELECT id, SUBSTR(CAST(created_at AS TEXT), 1, 19) AS created_at, partnumber, kind, delta,
+                    location, reference, reason, note, username
+                    FROM stock_transactions WHERE partnumber = $1 ORDER BY id DESC", &[&pn])?;
+    Ok(rows.iter()
+        .map(|row| StockTransaction {
+            id: row.get("id"),
+            created_at: row.get("created_at"),
+            partnumber: row.get("partnumber"),
+            kind: row.get("kind"),
+            delta: row.get("delta"),
+            location: row.get("location"),
+            reference: row.get("reference"),
+            reason: row.get("reason"),
+            note: row.get("note"),
+            username: row.get("username"),
+        })
+        .collect())
+}
+
+/// Books whatever the ledger is missing so it adds up to `on_hand` again, as long as that is
+/// still the `expected` delta the user confirmed.
+pub fn reconcile_stock(db: &mut Db, pn: &str, expected: i32) -> ShikaResult<()> {
+    let mut transaction = db.client()?.transaction()?;
+    let on_hand = lock_on_hand(&mut transaction, pn)?;
+    let ledger_total: i64 = transaction.query_one(
+        "SELECT COALESCE(SUM(delta), 0) AS total FROM stock_transactions WHERE partnumber = $1", &[&pn])?.get("total");
+    if let Some(entry) = reconciliation(pn, on_hand, ledger_total, expected)? {
+        insert_transaction(&mut transaction, &entry)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The adjustment that brings a ledger back in line with `on_hand`, None if it already adds up.
+/// Fails when the stock moved since the user confirmed `expected`, so nothing is booked unseen.
+pub fn reconciliation(pn: &str, on_hand: i32, ledger_total: i64, expected: i32) -> ShikaResult<Option<StockTransaction>> {
+    let delta = on_hand as i64 - ledger_total;
+    if delta != expected as i64 {
+        return Err(ShikaError::Validation(
+            format!("The ledger of {pn} is now off by {delta:+}, not {expected:+}, check it again")));
+    }
+    if delta == 0 {
+        return Ok(None);
+    }
+    let mut entry = StockTransaction::new(pn, TransactionKind::Adjust, delta as i32);
+    entry.note = Some("Reconciled against on hand".to_string());
+    Ok(Some(entry))
+}
+
+#[cfg(test)]
+fn test_storage(location: &str, quantity: i32) -> PartStorage {
+    PartStorage {
+        part_number: "RC0603FR-0710KL".to_string(),
+        location: location.to_string(),
+        quantity,
+    }
+}
+
+#[test]
+fn test_plan_spend() {
+    let storage = vec![test_storage("Drawer A1", 30), test_storage("Reel rack", 100)];
+    let mut spend = StockSpend {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 50,
+        location: None,
+        reason: SpendReason::Build,
+    };
+    //Without a location the first locations are emptied first
+    assert_eq!(plan_spend(&spend, 130, 0, &storage).unwrap(),
+               vec![("Drawer A1".to_string(), 30), ("Reel rack".to_string(), 20)]);
+    //A location can't go negative
+    spend.location = Some("Drawer A1".to_string());
+    assert!(matches!(plan_spend(&spend, 130, 0, &storage), Err(ShikaError::Validation(_))));
+    //Neither can on hand
+    spend.location = None;
+    assert!(matches!(plan_spend(&spend, 40, 0, &storage), Err(ShikaError::Validation(_))));
+    //Stock that was never put away is only checked against on hand
+    assert!(plan_spend(&spend, 50, 0, &[]).unwrap().is_empty());
+    //and goes before the locations when some of it is
+    assert_eq!(plan_spend(&spend, 150, 0, &storage).unwrap(), vec![("Drawer A1".to_string(), 30)]);
+    let entries = spend.to_transactions(&[("Drawer A1".to_string(), 30)]);
+    assert_eq!(entries.iter().map(|entry| (entry.location.as_deref(), entry.delta)).collect::<Vec<_>>(),
+               vec![(None, -20), (Some("Drawer A1"), -30)]);
+    //Stock reserved for builds stays put
+    assert!(matches!(plan_spend(&spend, 130, 81, &storage), Err(ShikaError::Validation(_))));
+    assert!(plan_spend(&spend, 130, 80, &storage).is_ok());
+    spend.quantity = 0;
+    assert!(matches!(plan_spend(&spend, 130, 0, &storage), Err(ShikaError::Validation(_))));
+}
+
+#[test]
+fn test_validate_transfer() {
+    let storage = vec![test_storage("Drawer A1", 30)];
+    let mut transfer = StockTransfer {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        quantity: 20,
+        from: "Drawer A1".to_string(),
+        to: "Reel rack".to_string(),
+    };
+    assert!(validate_transfer(&transfer, &storage).is_ok());
+    let entries = transfer.to_transactions();
+    assert_eq!(entries.iter().map(|entry| entry.delta).sum::<i32>(), 0);
+    transfer.quantity = 31;
+    assert!(matches!(validate_transfer(&transfer, &storage), Err(ShikaError::Validation(_))));
+    transfer.quantity = 5;
+    transfer.to = "Drawer A1".to_string();
+    assert!(matches!(validate_transfer(&transfer, &storage), Err(ShikaError::Validation(_))));
+    transfer.to = " Drawer A1 ".to_string();
+    assert!(matches!(validate_transfer(&transfer, &storage), Err(ShikaError::Validation(_))));
+}
+
+#[test]
+fn test_reconciliation() {
+    let history = vec![
+        StockTransaction::new("X", TransactionKind::Receive, 10),
+        StockTransaction::new("X", TransactionKind::Spend, -4),
+    ];
+    assert_eq!(ledger_difference(6, &history), 0);
+    assert_eq!(ledger_difference(9, &history), 3);
+    assert!(reconciliation("X", 6, 6, 0).unwrap().is_none());
+    assert_eq!(reconciliation("X", 9, 6, 3).unwrap().unwrap().delta, 3);
+    assert!(matches!(reconciliation("X", 10, 6, 3), Err(ShikaError::Validation(_))));
+}
+
+#[test]
+fn test_validate_stock_edit() {
+    let storage = vec![test_storage("Drawer A1", 20), test_storage("Reel rack", 100)];
+    let mut edit = StockEdit {
+        partnumber: "RC0603FR-0710KL".to_string(),
+        low_stock_threshold: 0,
+        on_hand: 90,
+        on_order: 0,
+        reason: "recount".to_string(),
+        location: None,
+    };
+    assert_eq!(validate_stock_edit(&edit, 90, &storage).unwrap(), 0);
+    assert_eq!(validate_stock_edit(&edit, 120, &[]).unwrap(), -30);
+    //Stored parts need the location the count changed at, with enough there to take off
+    assert!(matches!(validate_stock_edit(&edit, 120, &storage), Err(ShikaError::Validation(_))));
+    //Unless it comes off the stock that isn't in a location
+    edit.on_hand = 140;
+    assert_eq!(validate_stock_edit(&edit, 150, &storage).unwrap(), -10);
+    edit.on_hand = 90;
+    edit.location = Some(" Drawer A1 ".to_string());
+    assert!(matches!(validate_stock_edit(&edit, 120, &storage), Err(ShikaError::Validation(_))));
+    edit.location = Some("Reel rack".to_string());
+    assert_eq!(validate_stock_edit(&edit, 120, &storage).unwrap(), -30);
+    assert_eq!(edit.to_transaction(-30).location.as_deref(), Some("Reel rack"));
+    edit.location = Some("Shelf 3".to_string());
+    assert!(matches!(validate_stock_edit(&edit, 120, &storage), Err(ShikaError::Validation(_))));
+}
@@ -1,7 +1,7 @@
 use ratatui::{
     Frame,
     layout::{Constraint, Rect},
-    prelude::{Color, Direction, Modifier, Layout, Style, Stylize},
+    prelude::{Color, Direction, Modifier, Layout, Style},
     style::palette::tailwind,
     widgets::{
         Block, Row, Paragraph, Clear,
@@ -10,12 +10,12 @@ use ratatui::{
     }
 };
 use crate::app::App;
-use crate::stock_view::CreateStockPartField;
+use crate::stock_view::{self, CreateStockPartField, ReceiveStockField, SpendStockField, TransferStockField};
 //TODO: this should go into like a utils file or something
 use crate::ui::centered_rect;
 
 pub fn render_main_stock_panel(f: &mut Frame, app: &App, rect: Rect) {
-    let rows = create_stock_table_rows(&app);
+    let rows = create_stock_table_rows(app);
 // Columns widths are constrained in the same way as Layout...
     let widths = [
         Constraint::Length(20),
@@ -38,8 +38,7 @@ pub fn render_main_stock_panel(f: &mut Frame, app: &App, rect: Rect) {
         .column_spacing(1)
         .style(Style::new().bg(Color::Black).fg(tailwind::EMERALD.c400))
         .header(
-            //TODO: Bring this in from parts.rs
-            Row::new(vec!["Part Number", "Total Stock", "On Hand", "Available", "In Production", "Balance", "Low Stock Threshold", "On Order"])
+            Row::new(app.stock_view.stock_sort.headers(&stock_view::STOCK_COLUMNS))
                 .style(header_style)
                 // To add space between the header and the rest of the rows, specify the margin
                 .bottom_margin(1),
@@ -55,7 +54,7 @@ pub fn render_main_stock_panel(f: &mut Frame, app: &App, rect: Rect) {
     f.render_stateful_widget(table, rect, & mut app.stock_view.stock_table_state.clone());
 }
 
-pub fn create_stock_table_rows(app: &App) -> Vec<Row> {
+pub fn create_stock_table_rows(app: &App) -> Vec<Row<'_>> {
     let mut rows = Vec::new();
     for stock in &app.stock_view.stock_data {
         rows.push(Row::new(vec![
@@ -76,10 +75,6 @@ pub fn render_create_stock_popup(f: &mut Frame, app: &App) {
     let highlighted_style = Style::default()
         .fg(tailwind::SLATE.c200)
         .bg(tailwind::EMERALD.c900);
-    let selected_style = Style::default()
-        //.add_modifier(Modifier::REVERSED)
-        .fg(tailwind::EMERALD.c400)
-        .bg(tailwind::SLATE.c200);
 
     let popup_block = Block::default()
         .title("Create stock for an existing part:")
@@ -87,7 +82,7 @@ pub fn render_create_stock_popup(f: &mut Frame, app: &App) {
         .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
 
     let area = centered_rect(60, 35, f.area());
-    let clear = Clear::default();
+    let clear = Clear;
     f.render_widget(clear, area);
     f.render_widget(popup_block, area);
 
@@ -105,6 +100,8 @@ pub fn render_create_stock_popup(f: &mut Frame, app: &App) {
     let mut table_b = Block::default().title("Part Number").borders(Borders::ALL);
     let mut lst_b = Block::default().title("Low Stock Threshold").borders(Borders::ALL);
     let mut onhand_b = Block::default().title("On Hand").borders(Borders::ALL);
+    let mut onorder_b = Block::default().title("On Order").borders(Borders::ALL);
+    let mut inprod_b = Block::default().title("In Production").borders(Borders::ALL);
 
     match app.stock_view.currently_editing_stock.active_field {
         CreateStockPartField::PartNumber => {
@@ -116,6 +113,14 @@ pub fn render_create_stock_popup(f: &mut Frame, app: &App) {
         CreateStockPartField::OnHand => {
             onhand_b = onhand_b.style(highlighted_style);
         }
+        CreateStockPartField::OnOrder => {
+            onorder_b = onorder_b.style(highlighted_style);
+        }
+        CreateStockPartField::InProd => {
+            inprod_b = inprod_b.style(highlighted_style);
+        }
+        //Not part of the create form
+        CreateStockPartField::AdjustReason | CreateStockPartField::AdjustLocation => {}
     }
 
     let items = app.stock_view.nonstocked_pns.clone();
@@ -132,14 +137,320 @@ pub fn render_create_stock_popup(f: &mut Frame, app: &App) {
     let form_chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Percentage(50),
-            Constraint::Percentage(50)
+            Constraint::Percentage(25),
+            Constraint::Percentage(25),
+            Constraint::Percentage(25),
+            Constraint::Percentage(25)
             ])
         .split(form_chunk);
 
     let lts_t = Paragraph::new(app.stock_view.currently_editing_stock.low_stock_threshold.clone()).block(lst_b);
     let onhand_t = Paragraph::new(app.stock_view.currently_editing_stock.on_hand.clone()).block(onhand_b);
+    let onorder_t = Paragraph::new(app.stock_view.currently_editing_stock.on_order.clone()).block(onorder_b);
+    let inprod_t = Paragraph::new(app.stock_view.currently_editing_stock.in_prod.clone()).block(inprod_b);
 
     f.render_widget(lts_t, form_chunks[0]);
     f.render_widget(onhand_t, form_chunks[1]);
-}
\ No newline at end of file
+    f.render_widget(onorder_t, form_chunks[2]);
+    f.render_widget(inprod_t, form_chunks[3]);
+}
+pub fn render_receive_stock_popup(f: &mut Frame, app: &App) {
+    let highlighted_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::EMERALD.c900);
+    let receiving = &app.stock_view.receiving_stock;
+
+    let popup_block = Block::default()
+        .title(format!("Receive stock for {}:", receiving.partnumber))
+        .title_bottom("(tab) next field / (space) toggle / (enter) receive / (esc) cancel")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+
+    let area = centered_rect(50, 50, f.area());
+    f.render_widget(Clear, area);
+    f.render_widget(popup_block, area);
+
+    let form_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(0)
+        ])
+        .split(area);
+
+    let mut qty_b = Block::default().title("Quantity").borders(Borders::ALL);
+    let mut location_b = Block::default().title("Storage Location").borders(Borders::ALL);
+    let mut reference_b = Block::default().title("PO / Reference (optional)").borders(Borders::ALL);
+    let mut order_b = Block::default().title("Received Against Order").borders(Borders::ALL);
+
+    match receiving.active_field {
+        ReceiveStockField::Quantity => qty_b = qty_b.style(highlighted_style),
+        ReceiveStockField::Location => location_b = location_b.style(highlighted_style),
+        ReceiveStockField::Reference => reference_b = reference_b.style(highlighted_style),
+        ReceiveStockField::AgainstOrder => order_b = order_b.style(highlighted_style),
+    }
+
+    let against_order = if receiving.against_order { "[x] take off on order" } else { "[ ] take off on order" };
+    f.render_widget(Paragraph::new(receiving.quantity.clone()).block(qty_b), form_chunks[0]);
+    f.render_widget(Paragraph::new(receiving.location.clone()).block(location_b), form_chunks[1]);
+    f.render_widget(Paragraph::new(receiving.reference.clone()).block(reference_b), form_chunks[2]);
+    f.render_widget(Paragraph::new(against_order).block(order_b), form_chunks[3]);
+
+    //Show what the part has now, so the user can sanity check the receipt
+    let current = app.stock_view.stock_data.iter().find(|stock| stock.partnumber == receiving.partnumber);
+    if let Some(stock) = current {
+        let summary = format!("On hand: {} / On order: {}", stock.on_hand, stock.on_order);
+        f.render_widget(Paragraph::new(summary), form_chunks[4]);
+    }
+}
+
+pub fn render_spend_stock_popup(f: &mut Frame, app: &App) {
+    let highlighted_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::EMERALD.c900);
+    let spending = &app.stock_view.spending_stock;
+
+    let popup_block = Block::default()
+        .title(format!("Spend stock of {}:", spending.partnumber))
+        .title_bottom("(tab) next field / (space) change reason / (enter) spend / (esc) cancel")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+
+    let area = centered_rect(60, 50, f.area());
+    f.render_widget(Clear, area);
+    f.render_widget(popup_block, area);
+
+    let hori_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(1)
+        .constraints([
+            Constraint::Percentage(50),
+            Constraint::Percentage(50)
+        ])
+        .split(area);
+    let form_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(0)
+        ])
+        .split(hori_chunks[1]);
+
+    let mut location_b = Block::default().title("Take From").borders(Borders::ALL);
+    let mut qty_b = Block::default().title("Quantity").borders(Borders::ALL);
+    let mut reason_b = Block::default().title("Reason").borders(Borders::ALL);
+
+    match spending.active_field {
+        SpendStockField::Quantity => qty_b = qty_b.style(highlighted_style),
+        SpendStockField::Location => location_b = location_b.style(highlighted_style),
+        SpendStockField::Reason => reason_b = reason_b.style(highlighted_style),
+    }
+
+    let stored: i32 = spending.locations.iter().map(|store| store.quantity).sum();
+    let mut items = vec![format!("Any location ({stored})")];
+    for store in &spending.locations {
+        items.push(format!("{} ({})", store.location, store.quantity));
+    }
+    let list = List::new(items)
+        .block(location_b)
+        .highlight_style(highlighted_style.add_modifier(Modifier::ITALIC))
+        .highlight_symbol(">>")
+        .direction(ListDirection::TopToBottom);
+    f.render_stateful_widget(list, hori_chunks[0], &mut spending.location_list_state.clone());
+
+    f.render_widget(Paragraph::new(spending.quantity.clone()).block(qty_b), form_chunks[0]);
+    f.render_widget(Paragraph::new(spending.reason.as_str()).block(reason_b), form_chunks[1]);
+
+    let current = app.stock_view.stock_data.iter().find(|stock| stock.partnumber == spending.partnumber);
+    if let Some(stock) = current {
+        let summary = format!("On hand: {} / Available: {}", stock.on_hand, stock.available);
+        f.render_widget(Paragraph::new(summary), form_chunks[2]);
+    }
+}
+
+pub fn render_edit_stock_popup(f: &mut Frame, app: &App) {
+    let highlighted_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::EMERALD.c900);
+    let editing = &app.stock_view.currently_editing_stock;
+
+    let popup_block = Block::default()
+        .title(format!("Edit stock of {}:", editing.partnumber))
+        .title_bottom("(tab) next field / (enter) save / (esc) cancel")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+
+    let area = centered_rect(50, 70, f.area());
+    f.render_widget(Clear, area);
+    f.render_widget(popup_block, area);
+
+    let form_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(0)
+        ])
+        .split(area);
+
+    let mut lst_b = Block::default().title("Low Stock Threshold").borders(Borders::ALL);
+    let mut onorder_b = Block::default().title("On Order").borders(Borders::ALL);
+    //Builds own in_prod, so it is only shown here
+    let inprod_b = Block::default().title("In Production (set by builds)").borders(Borders::ALL);
+    let mut onhand_b = Block::default().title("On Hand (booked as an adjustment)").borders(Borders::ALL);
+    let mut reason_b = Block::default().title("Adjustment Reason").borders(Borders::ALL);
+    let mut location_b = Block::default().title("Adjusted At (storage location)").borders(Borders::ALL);
+
+    match editing.active_field {
+        CreateStockPartField::LowStockThreshold => lst_b = lst_b.style(highlighted_style),
+        CreateStockPartField::OnOrder => onorder_b = onorder_b.style(highlighted_style),
+        CreateStockPartField::OnHand => onhand_b = onhand_b.style(highlighted_style),
+        CreateStockPartField::AdjustReason => reason_b = reason_b.style(highlighted_style),
+        CreateStockPartField::AdjustLocation => location_b = location_b.style(highlighted_style),
+        CreateStockPartField::PartNumber | CreateStockPartField::InProd => {}
+    }
+
+    f.render_widget(Paragraph::new(editing.low_stock_threshold.clone()).block(lst_b), form_chunks[0]);
+    f.render_widget(Paragraph::new(editing.on_order.clone()).block(onorder_b), form_chunks[1]);
+    f.render_widget(Paragraph::new(editing.in_prod.clone()).block(inprod_b), form_chunks[2]);
+    f.render_widget(Paragraph::new(editing.on_hand.clone()).block(onhand_b), form_chunks[3]);
+    f.render_widget(Paragraph::new(editing.adjust_reason.clone()).block(reason_b), form_chunks[4]);
+    f.render_widget(Paragraph::new(editing.adjust_location.clone()).block(location_b), form_chunks[5]);
+
+    //The computed columns as they will be once saved
+    let preview = editing.copy_to_stock_info();
+    let summary = format!("Total stock: {} / Available: {} / Balance: {}",
+                          preview.total_stock, preview.available, preview.balance);
+    f.render_widget(Paragraph::new(summary), form_chunks[6]);
+}
+
+/// The ledger of the selected stock row, newest first.
+pub fn render_stock_history_panel(f: &mut Frame, app: &App, rect: Rect) {
+    let header_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::EMERALD.c900);
+    let rows: Vec<Row> = app.stock_view.history.iter().map(|entry| {
+        //Whatever explains the transaction best: the spend reason, a note or the reference
+        let detail = entry.reason.clone()
+            .or(entry.note.clone())
+            .or(entry.reference.clone())
+            .unwrap_or_default();
+        Row::new(vec![
+            entry.created_at.clone(),
+            entry.kind.clone(),
+            format!("{:+}", entry.delta),
+            entry.location.clone().unwrap_or_default(),
+            entry.reference.clone().unwrap_or_default(),
+            detail,
+            entry.username.clone().unwrap_or_default(),
+        ])
+    }).collect();
+    let widths = [
+        Constraint::Length(20),
+        Constraint::Length(14),
+        Constraint::Length(8),
+        Constraint::Length(15),
+        Constraint::Length(15),
+        Constraint::Min(20),
+        Constraint::Length(12)
+    ];
+    //A ledger that doesn't add up to on hand means something changed the stock table directly
+    let footer = match app.stock_view.history_difference() {
+        Some(0) => "Ledger matches on hand.".to_string(),
+        Some(difference) => format!("Ledger is off from on hand by {difference:+}, (R) to reconcile."),
+        None => "Select a stock row to see its history.".to_string(),
+    };
+    let table = Table::new(rows, widths)
+        .column_spacing(1)
+        .style(Style::new().bg(Color::Black).fg(tailwind::EMERALD.c400))
+        .header(
+            Row::new(vec!["When", "Kind", "Qty", "Location", "Reference", "Reason / Note", "User"])
+                .style(header_style)
+        )
+        .footer(Row::new(vec![footer]))
+        .block(Block::default().title("History").borders(Borders::TOP));
+    f.render_widget(table, rect);
+}
+
+pub fn render_transfer_stock_popup(f: &mut Frame, app: &App) {
+    let highlighted_style = Style::default()
+        .fg(tailwind::SLATE.c200)
+        .bg(tailwind::EMERALD.c900);
+    let transferring = &app.stock_view.transferring_stock;
+
+    let popup_block = Block::default()
+        .title(format!("Move stock of {}:", transferring.partnumber))
+        .title_bottom("(tab) next field / (enter) move / (esc) cancel")
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::EMERALD.c400));
+
+    let area = centered_rect(60, 45, f.area());
+    f.render_widget(Clear, area);
+    f.render_widget(popup_block, area);
+
+    let hori_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(1)
+        .constraints([
+            Constraint::Percentage(50),
+            Constraint::Percentage(50)
+        ])
+        .split(area);
+    let form_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Min(0)
+        ])
+        .split(hori_chunks[1]);
+
+    let mut from_b = Block::default().title("From").borders(Borders::ALL);
+    let mut qty_b = Block::default().title("Quantity").borders(Borders::ALL);
+    let mut to_b = Block::default().title("To Location").borders(Borders::ALL);
+
+    match transferring.active_field {
+        TransferStockField::Quantity => qty_b = qty_b.style(highlighted_style),
+        TransferStockField::From => from_b = from_b.style(highlighted_style),
+        TransferStockField::To => to_b = to_b.style(highlighted_style),
+    }
+
+    let items: Vec<String> = transferring.locations.iter()
+        .map(|store| format!("{} ({})", store.location, store.quantity))
+        .collect();
+    let list = List::new(items)
+        .block(from_b)
+        .highlight_style(highlighted_style.add_modifier(Modifier::ITALIC))
+        .highlight_symbol(">>")
+        .direction(ListDirection::TopToBottom);
+    f.render_stateful_widget(list, hori_chunks[0], &mut transferring.from_list_state.clone());
+
+    f.render_widget(Paragraph::new(transferring.quantity.clone()).block(qty_b), form_chunks[0]);
+    f.render_widget(Paragraph::new(transferring.to_location.clone()).block(to_b), form_chunks[1]);
+}
+
+pub fn render_reconcile_stock_popup(f: &mut Frame, app: &App) {
+    let view = &app.stock_view;
+    let Some(stock) = view.stock_table_state.selected().and_then(|idx| view.stock_data.get(idx)) else {return};
+
+    let popup_block = Block::default()
+        .title(format!("Reconcile {}:", stock.partnumber))
+        .borders(Borders::ALL)
+        .style(Style::default().bg(Color::Black).fg(tailwind::AMBER.c400));
+    let area = centered_rect(40, 20, f.area());
+    f.render_widget(Clear, area);
+    //The delta is booked as an adjustment, anything it hides won't show up in the ledger again
+    let text = format!("The ledger is off from on hand ({}) by {:+}.\nBook an adjustment of {:+} to match it?\n(y)es / (n)o",
+                       stock.on_hand, view.reconcile_delta, view.reconcile_delta);
+    f.render_widget(Paragraph::new(text).block(popup_block), area);
+}
@@ -1,7 +1,15 @@
 use std::cmp::PartialEq;
 use crossterm::event::KeyCode;
 use ratatui::widgets::{ListState, TableState};
+use crate::repository::BomRepository;
+use crate::errors::{ShikaError, ShikaResult};
+#[cfg(test)]
+use crate::memory_repository::MemoryRepository;
+use crate::parts::PartStorage;
 use crate::stock::*;
+use crate::table_sort::SortState;
+use crate::utils;
+use crate::utils::ListMvmtDir;
 
 
 pub enum StockSubState {
@@ -9,20 +17,34 @@ pub enum StockSubState {
     CreateStock,
     AddStock,
     SpendStock,
-    EditStock
+    EditStock,
+    TransferStock,
+    ReconcileStock
 }
 
+/// The fields of the create and edit stock popups. Creating starts at the part number, editing
+/// never shows it and instead asks for a reason when `on_hand` changes.
+#[derive(PartialEq)]
 pub enum CreateStockPartField {
     PartNumber,
     LowStockThreshold,
     OnHand,
-    //TODO: include on_order and in_prod
+    OnOrder,
+    InProd,
+    AdjustReason,
+    AdjustLocation
 }
 
 pub struct CurrentlyEditingStock {
     pub partnumber: String,
     pub low_stock_threshold: String,
     pub on_hand: String,
+    pub on_order: String,
+    pub in_prod: String,
+    //Only used when editing, required if on_hand was changed
+    pub adjust_reason: String,
+    //Storage location the on_hand change is booked at
+    pub adjust_location: String,
     pub active_field: CreateStockPartField
 }
 
@@ -32,42 +54,248 @@ impl CurrentlyEditingStock {
             partnumber: "".to_string(),
             low_stock_threshold: "".to_string(),
             on_hand: "".to_string(),
+            on_order: "".to_string(),
+            in_prod: "".to_string(),
+            adjust_reason: "".to_string(),
+            adjust_location: "".to_string(),
             active_field: CreateStockPartField::PartNumber
         }
     }
 
+    /// Prefills the edit popup from an existing stock row.
+    pub fn from_stock_info(stock: &StockInfo) -> CurrentlyEditingStock {
+        CurrentlyEditingStock {
+            partnumber: stock.partnumber.clone(),
+            low_stock_threshold: stock.low_stock_threshold.to_string(),
+            on_hand: stock.on_hand.to_string(),
+            on_order: stock.on_order.to_string(),
+            in_prod: stock.in_prod.to_string(),
+            adjust_reason: "".to_string(),
+            adjust_location: "".to_string(),
+            active_field: CreateStockPartField::LowStockThreshold
+        }
+    }
+
+    /// The form as a `StockInfo`, with the computed columns filled in so they can be previewed.
     pub fn copy_to_stock_info(&self) -> StockInfo {
-        StockInfo {
+        let mut stock = StockInfo {
             partnumber: self.partnumber.clone(),
             low_stock_threshold: self.low_stock_threshold.parse().unwrap_or(0),
             on_hand: self.on_hand.parse().unwrap_or(0),
-            on_order: 0,
-            in_prod: 0,
+            on_order: self.on_order.parse().unwrap_or(0),
+            in_prod: self.in_prod.parse().unwrap_or(0),
             total_stock: 0,
             balance: 0,
             available: 0
+        };
+        stock.recompute();
+        stock
+    }
+
+    pub fn to_stock_edit(&self) -> StockEdit {
+        let stock = self.copy_to_stock_info();
+        StockEdit {
+            partnumber: stock.partnumber,
+            low_stock_threshold: stock.low_stock_threshold,
+            on_hand: stock.on_hand,
+            on_order: stock.on_order,
+            reason: self.adjust_reason.trim().to_string(),
+            location: Some(self.adjust_location.trim().to_string()).filter(|location| !location.is_empty())
+        }
+    }
+
+    fn active_text(&mut self) -> Option<&mut String> {
+        match self.active_field {
+            CreateStockPartField::PartNumber => None,
+            CreateStockPartField::LowStockThreshold => Some(&mut self.low_stock_threshold),
+            CreateStockPartField::OnHand => Some(&mut self.on_hand),
+            CreateStockPartField::OnOrder => Some(&mut self.on_order),
+            CreateStockPartField::InProd => Some(&mut self.in_prod),
+            CreateStockPartField::AdjustReason => Some(&mut self.adjust_reason),
+            CreateStockPartField::AdjustLocation => Some(&mut self.adjust_location),
+        }
+    }
+
+    fn push_char(&mut self, value: char) {
+        //Quantities are whole numbers, only the reason and location take free text
+        let free_text = matches!(self.active_field, CreateStockPartField::AdjustReason | CreateStockPartField::AdjustLocation);
+        if !free_text && !value.is_ascii_digit() {
+            return;
+        }
+        if let Some(text) = self.active_text() {
+            text.push(value);
+        }
+    }
+
+    fn pop_char(&mut self) {
+        if let Some(text) = self.active_text() {
+            text.pop();
         }
     }
 }
+
+#[derive(PartialEq)]
+pub enum ReceiveStockField {
+    Quantity,
+    Location,
+    Reference,
+    AgainstOrder,
+}
+
+/// The receiving popup opened with `a`, always for the selected stock row.
+pub struct ReceivingStock {
+    pub partnumber: String,
+    pub quantity: String,
+    pub location: String,
+    pub reference: String,
+    pub against_order: bool,
+    pub active_field: ReceiveStockField
+}
+
+impl ReceivingStock {
+    pub fn new() -> ReceivingStock {
+        ReceivingStock {
+            partnumber: "".to_string(),
+            quantity: "".to_string(),
+            location: "".to_string(),
+            reference: "".to_string(),
+            against_order: false,
+            active_field: ReceiveStockField::Quantity
+        }
+    }
+
+    pub fn to_receipt(&self) -> StockReceipt {
+        let reference = self.reference.trim();
+        StockReceipt {
+            partnumber: self.partnumber.clone(),
+            quantity: self.quantity.parse().unwrap_or(0),
+            location: self.location.trim().to_string(),
+            reference: if reference.is_empty() { None } else { Some(reference.to_string()) },
+            against_order: self.against_order
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum SpendStockField {
+    Quantity,
+    Location,
+    Reason,
+}
+
+/// The spend popup opened with `s`. `locations` are the part's storage locations, the first
+/// entry of the location list means "any location".
+pub struct SpendingStock {
+    pub partnumber: String,
+    pub quantity: String,
+    pub locations: Vec<PartStorage>,
+    pub location_list_state: ListState,
+    pub reason: SpendReason,
+    pub active_field: SpendStockField
+}
+
+impl SpendingStock {
+    pub fn new() -> SpendingStock {
+        SpendingStock {
+            partnumber: "".to_string(),
+            quantity: "".to_string(),
+            locations: Vec::new(),
+            location_list_state: ListState::default().with_selected(Some(0)),
+            reason: SpendReason::Build,
+            active_field: SpendStockField::Quantity
+        }
+    }
+
+    pub fn to_spend(&self) -> StockSpend {
+        let location = match self.location_list_state.selected() {
+            Some(idx) if idx > 0 => self.locations.get(idx - 1).map(|store| store.location.clone()),
+            _ => None,
+        };
+        StockSpend {
+            partnumber: self.partnumber.clone(),
+            quantity: self.quantity.parse().unwrap_or(0),
+            location,
+            reason: self.reason
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum TransferStockField {
+    Quantity,
+    From,
+    To,
+}
+
+/// The transfer popup opened with `t`, moves stock between two storage locations.
+pub struct TransferringStock {
+    pub partnumber: String,
+    pub quantity: String,
+    pub locations: Vec<PartStorage>,
+    pub from_list_state: ListState,
+    pub to_location: String,
+    pub active_field: TransferStockField
+}
+
+impl TransferringStock {
+    pub fn new() -> TransferringStock {
+        TransferringStock {
+            partnumber: "".to_string(),
+            quantity: "".to_string(),
+            locations: Vec::new(),
+            from_list_state: ListState::default().with_selected(Some(0)),
+            to_location: "".to_string(),
+            active_field: TransferStockField::Quantity
+        }
+    }
+
+    pub fn to_transfer(&self) -> StockTransfer {
+        let from = self.from_list_state.selected()
+            .and_then(|idx| self.locations.get(idx))
+            .map(|store| store.location.clone())
+            .unwrap_or_default();
+        StockTransfer {
+            partnumber: self.partnumber.clone(),
+            quantity: self.quantity.parse().unwrap_or(0),
+            from,
+            to: self.to_location.trim().to_string()
+        }
+    }
+}
+
+/// The stock table columns, `stock_cell` gives their text.
+pub const STOCK_COLUMNS: [&str; 8] = ["Part Number", "Total Stock", "On Hand", "Available", "In Production", "Balance",
+    "Low Stock Threshold", "On Order"];
+
+pub fn stock_cell(stock: &StockInfo, column: usize) -> String {
+    match column {
+        0 => stock.partnumber.clone(),
+        1 => stock.total_stock.to_string(),
+        2 => stock.on_hand.to_string(),
+        3 => stock.available.to_string(),
+        4 => stock.in_prod.to_string(),
+        5 => stock.balance.to_string(),
+        6 => stock.low_stock_threshold.to_string(),
+        _ => stock.on_order.to_string(),
+    }
+}
+
 pub struct StockView {
     pub stock_sub_state: StockSubState,
     pub stock_data: Vec<StockInfo>,
+    //Shows the ledger of the selected row under the table
     pub show_details: bool,
+    pub history: Vec<StockTransaction>,
     pub stock_table_state: TableState,
+    pub stock_sort: SortState,
     pub nonstocked_pns: Vec<String>,
     pub nonstocked_pn_list_state: ListState,
-    pub currently_editing_stock: CurrentlyEditingStock
-}
-
-impl PartialEq for CreateStockPartField {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (CreateStockPartField::PartNumber, CreateStockPartField::PartNumber) => true,
-            (CreateStockPartField::LowStockThreshold, CreateStockPartField::LowStockThreshold) => true,
-            (CreateStockPartField::OnHand, CreateStockPartField::OnHand) => true,
-            _ => false
-        }
-    }
+    pub currently_editing_stock: CurrentlyEditingStock,
+    pub receiving_stock: ReceivingStock,
+    pub spending_stock: SpendingStock,
+    pub transferring_stock: TransferringStock,
+    //What reconciling the selected row would book, shown for confirmation first
+    pub reconcile_delta: i32
 }
 
 impl StockView {
@@ -76,33 +304,91 @@ impl StockView {
             stock_sub_state: StockSubState::StockMain,
             stock_data: Vec::new(),
             show_details: false,
+            history: Vec::new(),
             stock_table_state: TableState::default(),
+            stock_sort: SortState::default(),
             nonstocked_pns: Vec::new(),
             nonstocked_pn_list_state: ListState::default(),
-            currently_editing_stock: CurrentlyEditingStock::new()
+            currently_editing_stock: CurrentlyEditingStock::new(),
+            receiving_stock: ReceivingStock::new(),
+            spending_stock: SpendingStock::new(),
+            transferring_stock: TransferringStock::new(),
+            reconcile_delta: 0
+        }
+    }
+
+    pub fn fetch_stock_data(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        self.stock_data = repo.fetch_stock_info()?;
+        self.stock_sort.sort(&mut self.stock_data, stock_cell);
+        self.fetch_history(repo)
+    }
+
+    /// Loads the ledger of the selected row, only while the history panel is open.
+    fn fetch_history(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        self.history.clear();
+        if !self.show_details {
+            return Ok(());
+        }
+        if let Some(stock) = self.stock_table_state.selected().and_then(|idx| self.stock_data.get(idx)) {
+            self.history = repo.fetch_stock_history(&stock.partnumber)?;
         }
+        Ok(())
     }
 
-    pub fn fetch_stock_data(&mut self) {
-        self.stock_data = fetch_stock_info();
+    /// How far the ledger of the selected row is off from its `on_hand`.
+    pub fn history_difference(&self) -> Option<i32> {
+        let stock = self.stock_table_state.selected().and_then(|idx| self.stock_data.get(idx))?;
+        Some(ledger_difference(stock.on_hand, &self.history))
     }
 
-    pub fn handle_main_keys(&mut self, key: KeyCode) {
+    pub fn handle_main_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Char('d') => {
                 self.show_details = !self.show_details;
+                self.fetch_history(repo)?;
+            },
+            KeyCode::Char('t') => {
+                self.start_transfer(repo)?;
+            },
+            KeyCode::Char('o') => {
+                let selected_pn = self.stock_table_state.selected()
+                    .and_then(|idx| self.stock_data.get(idx))
+                    .map(|stock| stock.partnumber.clone());
+                self.stock_sort.cycle(STOCK_COLUMNS.len());
+                self.fetch_stock_data(repo)?;
+                //The cursor stays on the same part wherever it moved to
+                if let Some(pn) = selected_pn {
+                    self.stock_table_state.select(self.stock_data.iter().position(|stock| stock.partnumber == pn));
+                }
+            },
+            KeyCode::Char('R') => {
+                let stock = self.selected_stock("reconcile")?;
+                let delta = ledger_difference(stock.on_hand, &repo.fetch_stock_history(&stock.partnumber)?);
+                if delta == 0 {
+                    return Err(ShikaError::Validation(format!("The ledger of {} already matches on hand", stock.partnumber)));
+                }
+                self.reconcile_delta = delta;
+                self.stock_sub_state = StockSubState::ReconcileStock;
             },
             KeyCode::Char('c') => {
-                self.nonstocked_pns = fetch_nonstocked_partnumbers();
+                self.nonstocked_pns = repo.fetch_nonstocked_partnumbers()?;
+                self.currently_editing_stock = CurrentlyEditingStock::new();
                 self.stock_sub_state = StockSubState::CreateStock;
             },
             KeyCode::Char('a') => {
-                self.stock_sub_state = StockSubState::AddStock;
+                self.start_receiving(repo)?;
             },
             KeyCode::Char('s') => {
-                self.stock_sub_state = StockSubState::SpendStock;
+                self.start_spending(repo)?;
             },
             KeyCode::Char('e') => {
+                let stock = self.selected_stock("edit")?;
+                let mut editing = CurrentlyEditingStock::from_stock_info(stock);
+                //Adjustments go where the part is kept unless another location is entered
+                if let Some(store) = repo.fetch_part_storage_data(&stock.partnumber)?.first() {
+                    editing.adjust_location = store.location.clone();
+                }
+                self.currently_editing_stock = editing;
                 self.stock_sub_state = StockSubState::EditStock;
             },
             KeyCode::Down => {
@@ -116,6 +402,7 @@ impl StockView {
                         self.stock_table_state.select(Some(0));
                     }
                 }
+                self.fetch_history(repo)?;
             },
             KeyCode::Up => {
                 match self.stock_table_state.selected() {
@@ -128,82 +415,41 @@ impl StockView {
                         self.stock_table_state.select(Some(0));
                     }
                 }
+                self.fetch_history(repo)?;
             },
             _ => {}
         }
+        Ok(())
     }
 
-    pub fn handle_create_stock_keys(&mut self, key: KeyCode) {
+    pub fn handle_create_stock_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
         match key {
             KeyCode::Esc => {
                 self.stock_sub_state = StockSubState::StockMain;
             },
-            KeyCode::Down => {
-                if self.currently_editing_stock.active_field == CreateStockPartField::PartNumber {
-                    match self.nonstocked_pn_list_state.selected() {
-                        Some(selected) => {
-                            if selected < self.nonstocked_pns.len() - 1 {
-                                self.nonstocked_pn_list_state.select(Some(selected + 1));
-                            }
-                        }
-                        None => {
-                            self.nonstocked_pn_list_state.select(Some(0));
-                        }
-                    }
-                }
+            KeyCode::Down if self.currently_editing_stock.active_field == CreateStockPartField::PartNumber => {
+                let list_len = self.nonstocked_pns.len();
+                utils::exec_list_mvmt(ListMvmtDir::Greater, &mut self.nonstocked_pn_list_state, list_len);
             },
-            KeyCode::Up => {
-                if self.currently_editing_stock.active_field == CreateStockPartField::PartNumber {
-                    match self.nonstocked_pn_list_state.selected() {
-                        Some(selected) => {
-                            if selected > 0 {
-                                self.nonstocked_pn_list_state.select(Some(selected - 1));
-                            }
-                        }
-                        None => {
-                            self.nonstocked_pn_list_state.select(Some(0));
-                        }
-                    }
-                }
+            KeyCode::Up if self.currently_editing_stock.active_field == CreateStockPartField::PartNumber => {
+                let list_len = self.nonstocked_pns.len();
+                utils::exec_list_mvmt(ListMvmtDir::Less, &mut self.nonstocked_pn_list_state, list_len);
             },
             KeyCode::Tab => {
-                match self.currently_editing_stock.active_field {
-                    CreateStockPartField::PartNumber => {
-                        self.currently_editing_stock.active_field = CreateStockPartField::LowStockThreshold;
-                    },
-                    CreateStockPartField::LowStockThreshold => {
-                        self.currently_editing_stock.active_field = CreateStockPartField::OnHand;
-                    },
-                    CreateStockPartField::OnHand => {
-                        self.currently_editing_stock.active_field = CreateStockPartField::PartNumber;
-                    }
-                }
+                self.currently_editing_stock.active_field = match self.currently_editing_stock.active_field {
+                    CreateStockPartField::PartNumber => CreateStockPartField::LowStockThreshold,
+                    CreateStockPartField::LowStockThreshold => CreateStockPartField::OnHand,
+                    CreateStockPartField::OnHand => CreateStockPartField::OnOrder,
+                    CreateStockPartField::OnOrder => CreateStockPartField::InProd,
+                    _ => CreateStockPartField::PartNumber,
+                };
             },
             KeyCode::Char(value) => {
                 //Non-number values aren't allowed in this form
-                if !value.is_ascii_digit() {
-                    return;
-                }
-                match self.currently_editing_stock.active_field {
-                    CreateStockPartField::LowStockThreshold => {
-                        self.currently_editing_stock.low_stock_threshold.push(value);
-                    },
-                    CreateStockPartField::OnHand => {
-                        self.currently_editing_stock.on_hand.push(value);
-                    },
-                    _ => {}
-                }
+                self.currently_editing_stock.push_char(value);
             }
             KeyCode::Backspace => {
-                match self.currently_editing_stock.active_field {
-                    CreateStockPartField::LowStockThreshold => {
-                        self.currently_editing_stock.low_stock_threshold.pop();
-                    },
-                    CreateStockPartField::OnHand => {
-                        self.currently_editing_stock.on_hand.pop();
-                    },
-                    _ => {}
-                }
+                self.currently_editing_stock.pop_char();
             },
             KeyCode::Enter => {
                 match self.nonstocked_pn_list_state.selected() {
@@ -212,42 +458,438 @@ impl StockView {
                             self.nonstocked_pns[selected].clone();
                     },
                     //Don't do DB operations if a PN isn't selected.
-                    None => { return; }
+                    None => { return Ok(()); }
                 }
                 let new_stock = self.currently_editing_stock.copy_to_stock_info();
-                create_new_stock(new_stock);
+                repo.create_new_stock(new_stock)?;
                 //Reload the table after creating a new item
-                self.fetch_stock_data();
+                self.fetch_stock_data(repo)?;
                 self.stock_sub_state = StockSubState::StockMain;
             },
             _ => {}
         }
+        Ok(())
+    }
+
+    fn selected_stock(&self, action: &str) -> ShikaResult<&StockInfo> {
+        self.stock_table_state.selected()
+            .and_then(|idx| self.stock_data.get(idx))
+            .ok_or_else(|| ShikaError::Validation(format!("Select a stock row to {action}")))
     }
 
-    pub fn handle_add_stock_keys(&mut self, key: KeyCode) {
+    /// Opens the receiving popup for the selected row. The location defaults to where the part
+    /// is already kept and stock on order is assumed to be what's arriving.
+    fn start_receiving(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        let stock = self.selected_stock("receive stock for")?;
+        let mut receiving = ReceivingStock::new();
+        receiving.partnumber = stock.partnumber.clone();
+        receiving.against_order = stock.on_order > 0;
+        if let Some(store) = repo.fetch_part_storage_data(&stock.partnumber)?.first() {
+            receiving.location = store.location.clone();
+        }
+        self.receiving_stock = receiving;
+        self.stock_sub_state = StockSubState::AddStock;
+        Ok(())
+    }
+
+    pub fn handle_add_stock_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let receiving = &mut self.receiving_stock;
         match key {
             KeyCode::Esc => {
                 self.stock_sub_state = StockSubState::StockMain;
             },
+            KeyCode::Tab => {
+                receiving.active_field = match receiving.active_field {
+                    ReceiveStockField::Quantity => ReceiveStockField::Location,
+                    ReceiveStockField::Location => ReceiveStockField::Reference,
+                    ReceiveStockField::Reference => ReceiveStockField::AgainstOrder,
+                    ReceiveStockField::AgainstOrder => ReceiveStockField::Quantity,
+                };
+            },
+            KeyCode::Char(' ') if receiving.active_field == ReceiveStockField::AgainstOrder => {
+                receiving.against_order = !receiving.against_order;
+            },
+            KeyCode::Char(value) => {
+                match receiving.active_field {
+                    //Only whole numbers make sense as a quantity
+                    ReceiveStockField::Quantity if value.is_ascii_digit() => receiving.quantity.push(value),
+                    ReceiveStockField::Location => receiving.location.push(value),
+                    ReceiveStockField::Reference => receiving.reference.push(value),
+                    _ => {}
+                }
+            },
+            KeyCode::Backspace => {
+                match receiving.active_field {
+                    ReceiveStockField::Quantity => { receiving.quantity.pop(); },
+                    ReceiveStockField::Location => { receiving.location.pop(); },
+                    ReceiveStockField::Reference => { receiving.reference.pop(); },
+                    ReceiveStockField::AgainstOrder => {}
+                }
+            },
+            KeyCode::Enter => {
+                repo.receive_stock(&receiving.to_receipt())?;
+                self.fetch_stock_data(repo)?;
+                self.stock_sub_state = StockSubState::StockMain;
+            },
             _ => {}
         }
+        Ok(())
     }
 
-    pub fn handle_spend_stock_keys(&mut self, key: KeyCode) {
+    fn start_spending(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        let stock = self.selected_stock("spend stock from")?;
+        let mut spending = SpendingStock::new();
+        spending.partnumber = stock.partnumber.clone();
+        spending.locations = repo.fetch_part_storage_data(&stock.partnumber)?;
+        self.spending_stock = spending;
+        self.stock_sub_state = StockSubState::SpendStock;
+        Ok(())
+    }
+
+    pub fn handle_spend_stock_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let spending = &mut self.spending_stock;
         match key {
             KeyCode::Esc => {
                 self.stock_sub_state = StockSubState::StockMain;
             },
+            KeyCode::Tab => {
+                spending.active_field = match spending.active_field {
+                    SpendStockField::Quantity => SpendStockField::Location,
+                    SpendStockField::Location => SpendStockField::Reason,
+                    SpendStockField::Reason => SpendStockField::Quantity,
+                };
+            },
+            KeyCode::Down if spending.active_field == SpendStockField::Location => {
+                //+1 for the "any location" entry
+                let list_len = spending.locations.len() + 1;
+                utils::exec_list_mvmt(ListMvmtDir::Greater, &mut spending.location_list_state, list_len);
+            },
+            KeyCode::Up if spending.active_field == SpendStockField::Location => {
+                let list_len = spending.locations.len() + 1;
+                utils::exec_list_mvmt(ListMvmtDir::Less, &mut spending.location_list_state, list_len);
+            },
+            KeyCode::Char(' ') | KeyCode::Right if spending.active_field == SpendStockField::Reason => {
+                spending.reason = spending.reason.next();
+            },
+            //Only whole numbers make sense as a quantity
+            KeyCode::Char(value) if spending.active_field == SpendStockField::Quantity && value.is_ascii_digit() => {
+                spending.quantity.push(value);
+            },
+            KeyCode::Backspace if spending.active_field == SpendStockField::Quantity => {
+                spending.quantity.pop();
+            },
+            KeyCode::Enter => {
+                repo.spend_stock(&spending.to_spend())?;
+                self.fetch_stock_data(repo)?;
+                self.stock_sub_state = StockSubState::StockMain;
+            },
             _ => {}
         }
+        Ok(())
     }
 
-    pub fn handle_edit_stock_keys(&mut self, key: KeyCode) {
+    fn start_transfer(&mut self, repo: &mut dyn BomRepository) -> ShikaResult<()> {
+        let stock = self.selected_stock("transfer stock of")?;
+        let mut transferring = TransferringStock::new();
+        transferring.partnumber = stock.partnumber.clone();
+        transferring.locations = repo.fetch_part_storage_data(&stock.partnumber)?;
+        if transferring.locations.is_empty() {
+            return Err(ShikaError::Validation(format!("{} isn't in any storage location yet", stock.partnumber)));
+        }
+        self.transferring_stock = transferring;
+        self.stock_sub_state = StockSubState::TransferStock;
+        Ok(())
+    }
+
+    pub fn handle_reconcile_stock_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        match key {
+            KeyCode::Esc | KeyCode::Char('n') => {
+                self.stock_sub_state = StockSubState::StockMain;
+            },
+            KeyCode::Char('y') => {
+                let partnumber = self.selected_stock("reconcile")?.partnumber.clone();
+                self.stock_sub_state = StockSubState::StockMain;
+                repo.reconcile_stock(&partnumber, self.reconcile_delta)?;
+                self.fetch_stock_data(repo)?;
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn handle_transfer_stock_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let transferring = &mut self.transferring_stock;
         match key {
             KeyCode::Esc => {
                 self.stock_sub_state = StockSubState::StockMain;
             },
+            KeyCode::Tab => {
+                transferring.active_field = match transferring.active_field {
+                    TransferStockField::Quantity => TransferStockField::From,
+                    TransferStockField::From => TransferStockField::To,
+                    TransferStockField::To => TransferStockField::Quantity,
+                };
+            },
+            KeyCode::Down if transferring.active_field == TransferStockField::From => {
+                let list_len = transferring.locations.len();
+                utils::exec_list_mvmt(ListMvmtDir::Greater, &mut transferring.from_list_state, list_len);
+            },
+            KeyCode::Up if transferring.active_field == TransferStockField::From => {
+                let list_len = transferring.locations.len();
+                utils::exec_list_mvmt(ListMvmtDir::Less, &mut transferring.from_list_state, list_len);
+            },
+            KeyCode::Char(value) if transferring.active_field == TransferStockField::Quantity && value.is_ascii_digit() => {
+                transferring.quantity.push(value);
+            },
+            KeyCode::Char(value) if transferring.active_field == TransferStockField::To => {
+                transferring.to_location.push(value);
+            },
+            KeyCode::Backspace => {
+                match transferring.active_field {
+                    TransferStockField::Quantity => { transferring.quantity.pop(); },
+                    TransferStockField::To => { transferring.to_location.pop(); },
+                    TransferStockField::From => {}
+                }
+            },
+            KeyCode::Enter => {
+                repo.transfer_stock(&transferring.to_transfer())?;
+                self.fetch_stock_data(repo)?;
+                self.stock_sub_state = StockSubState::StockMain;
+            },
             _ => {}
         }
+        Ok(())
+    }
+
+    pub fn handle_edit_stock_keys(&mut self, repo: &mut dyn BomRepository, key: KeyCode) -> ShikaResult<()> {
+        let editing = &mut self.currently_editing_stock;
+        match key {
+            KeyCode::Esc => {
+                self.stock_sub_state = StockSubState::StockMain;
+            },
+            KeyCode::Tab => {
+                editing.active_field = match editing.active_field {
+                    CreateStockPartField::LowStockThreshold => CreateStockPartField::OnOrder,
+                    CreateStockPartField::OnOrder => CreateStockPartField::OnHand,
+                    CreateStockPartField::OnHand => CreateStockPartField::AdjustReason,
+                    CreateStockPartField::AdjustReason => CreateStockPartField::AdjustLocation,
+                    _ => CreateStockPartField::LowStockThreshold,
+                };
+            },
+            KeyCode::Char(value) => {
+                editing.push_char(value);
+            },
+            KeyCode::Backspace => {
+                editing.pop_char();
+            },
+            KeyCode::Enter => {
+                repo.edit_stock(&editing.to_stock_edit())?;
+                //Reload so the table shows the recomputed totals straight away
+                self.fetch_stock_data(repo)?;
+                self.stock_sub_state = StockSubState::StockMain;
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+}
+#[test]
+fn test_create_stock_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.handle_main_keys(&mut repo, KeyCode::Char('c')).unwrap();
+    assert_eq!(view.nonstocked_pns, vec!["25SVPF47M", "GRM188R71H104KA93D"]);
+    view.handle_create_stock_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_create_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_create_stock_keys(&mut repo, KeyCode::Char('5')).unwrap();
+    view.handle_create_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    //Letters are ignored in the quantity fields
+    for c in "1x2".chars() {
+        view.handle_create_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_create_stock_keys(&mut repo, KeyCode::Enter).unwrap();
+    let stock = view.stock_data.iter().find(|stock| stock.partnumber == "25SVPF47M").unwrap();
+    assert_eq!(stock.low_stock_threshold, 5);
+    assert_eq!(stock.on_hand, 12);
+    assert_eq!(stock.available, 12);
+}
+
+#[test]
+fn test_create_stock_needs_a_part_number() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.handle_main_keys(&mut repo, KeyCode::Char('c')).unwrap();
+    view.handle_create_stock_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(matches!(view.stock_sub_state, StockSubState::CreateStock));
+    assert_eq!(repo.fetch_stock_info().unwrap().len(), 2);
+}
+
+#[test]
+fn test_receive_stock_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.fetch_stock_data(&mut repo).unwrap();
+    //Nothing selected yet
+    assert!(matches!(view.handle_main_keys(&mut repo, KeyCode::Char('a')), Err(ShikaError::Validation(_))));
+    //RC0603FR-0710KL has 5000 on order and lives on the reel rack
+    view.handle_main_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Char('a')).unwrap();
+    assert_eq!(view.receiving_stock.partnumber, "RC0603FR-0710KL");
+    assert_eq!(view.receiving_stock.location, "Reel rack");
+    assert!(view.receiving_stock.against_order);
+    for c in "2x000".chars() {
+        view.handle_add_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_add_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_add_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    for c in "PO-77".chars() {
+        view.handle_add_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    assert_eq!(view.receiving_stock.reference, "PO-77");
+    view.handle_add_stock_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(matches!(view.stock_sub_state, StockSubState::StockMain));
+    let stock = view.stock_data.iter().find(|stock| stock.partnumber == "RC0603FR-0710KL").unwrap();
+    assert_eq!((stock.on_hand, stock.on_order), (6500, 3000));
+    assert_eq!(repo.fetch_part_storage_data("RC0603FR-0710KL").unwrap()[0].quantity, 6500);
+}
+
+#[test]
+fn test_receive_stock_needs_a_quantity() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.fetch_stock_data(&mut repo).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Char('a')).unwrap();
+    assert!(matches!(view.handle_add_stock_keys(&mut repo, KeyCode::Enter), Err(ShikaError::Validation(_))));
+    assert!(matches!(view.stock_sub_state, StockSubState::AddStock));
+}
+
+#[test]
+fn test_spend_stock_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.fetch_stock_data(&mut repo).unwrap();
+    //HFW1V2210H4R7K has 40 on hand, all in Drawer A1
+    view.handle_main_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Char('s')).unwrap();
+    assert_eq!(view.spending_stock.locations.len(), 1);
+    for c in "41".chars() {
+        view.handle_spend_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    //Pick the drawer explicitly and scrap them
+    view.handle_spend_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_spend_stock_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_spend_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_spend_stock_keys(&mut repo, KeyCode::Char(' ')).unwrap();
+    assert_eq!(view.spending_stock.to_spend().location, Some("Drawer A1".to_string()));
+    assert_eq!(view.spending_stock.reason, SpendReason::Scrap);
+    //More than the drawer holds
+    assert!(matches!(view.handle_spend_stock_keys(&mut repo, KeyCode::Enter), Err(ShikaError::Validation(_))));
+    assert!(matches!(view.stock_sub_state, StockSubState::SpendStock));
+    view.spending_stock.quantity = "15".to_string();
+    view.handle_spend_stock_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(matches!(view.stock_sub_state, StockSubState::StockMain));
+    assert_eq!(view.stock_data[0].on_hand, 25);
+    assert_eq!(repo.fetch_part_storage_data("HFW1V2210H4R7K").unwrap()[0].quantity, 25);
+}
+
+#[test]
+fn test_edit_stock_flow() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.fetch_stock_data(&mut repo).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Down).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Char('e')).unwrap();
+    assert_eq!(view.currently_editing_stock.on_hand, "40");
+    //On order 0 -> 25
+    view.handle_edit_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_edit_stock_keys(&mut repo, KeyCode::Backspace).unwrap();
+    for c in "25".chars() {
+        view.handle_edit_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    let preview = view.currently_editing_stock.copy_to_stock_info();
+    assert_eq!((preview.total_stock, preview.available, preview.balance), (65, 40, 65));
+    //In production belongs to the builds, tab goes past it. Counting found two missing,
+    //which needs a reason
+    view.handle_edit_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    assert!(view.currently_editing_stock.active_field == CreateStockPartField::OnHand);
+    view.handle_edit_stock_keys(&mut repo, KeyCode::Backspace).unwrap();
+    view.handle_edit_stock_keys(&mut repo, KeyCode::Backspace).unwrap();
+    for c in "38".chars() {
+        view.handle_edit_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
     }
-}
\ No newline at end of file
+    assert!(matches!(view.handle_edit_stock_keys(&mut repo, KeyCode::Enter), Err(ShikaError::Validation(_))));
+    view.handle_edit_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    for c in "cycle count".chars() {
+        view.handle_edit_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_edit_stock_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(matches!(view.stock_sub_state, StockSubState::StockMain));
+    let stock = &view.stock_data[0];
+    assert_eq!((stock.on_hand, stock.on_order, stock.in_prod), (38, 25, 0));
+    assert_eq!((stock.total_stock, stock.available, stock.balance), (63, 38, 63));
+    //Booked at the part's only location, which was filled in
+    assert_eq!(repo.fetch_part_storage_data("HFW1V2210H4R7K").unwrap()[0].quantity, 38);
+}
+
+#[test]
+fn test_stock_history_panel() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.fetch_stock_data(&mut repo).unwrap();
+    view.handle_main_keys(&mut repo, KeyCode::Down).unwrap();
+    //Nothing is loaded while the panel is closed
+    assert!(view.history.is_empty());
+    view.handle_main_keys(&mut repo, KeyCode::Char('d')).unwrap();
+    assert_eq!(view.history.len(), 1);
+    assert_eq!(view.history_difference(), Some(0));
+
+    //Move half the drawer to a new bin, the ledger still adds up
+    view.handle_main_keys(&mut repo, KeyCode::Char('t')).unwrap();
+    for c in "20".chars() {
+        view.handle_transfer_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_transfer_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    view.handle_transfer_stock_keys(&mut repo, KeyCode::Tab).unwrap();
+    for c in "Bin 7".chars() {
+        view.handle_transfer_stock_keys(&mut repo, KeyCode::Char(c)).unwrap();
+    }
+    view.handle_transfer_stock_keys(&mut repo, KeyCode::Enter).unwrap();
+    assert!(matches!(view.stock_sub_state, StockSubState::StockMain));
+    assert_eq!(view.history.len(), 3);
+    assert_eq!(view.history[0].location, Some("Bin 7".to_string()));
+    assert_eq!(view.history_difference(), Some(0));
+    assert_eq!(repo.fetch_part_storage_data("HFW1V2210H4R7K").unwrap().len(), 2);
+}
+
+#[test]
+fn test_sort_stock() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.fetch_stock_data(&mut repo).unwrap();
+    view.stock_table_state.select(Some(0));
+    let selected_pn = view.stock_data[0].partnumber.clone();
+    //On hand descending
+    for _ in 0..6 {
+        view.handle_main_keys(&mut repo, KeyCode::Char('o')).unwrap();
+    }
+    assert_eq!(view.stock_sort.headers(&STOCK_COLUMNS)[2], "On Hand ▼");
+    let on_hand: Vec<i32> = view.stock_data.iter().map(|stock| stock.on_hand).collect();
+    assert!(on_hand.windows(2).all(|pair| pair[0] >= pair[1]));
+    let selected = view.stock_table_state.selected().unwrap();
+    assert_eq!(view.stock_data[selected].partnumber, selected_pn);
+}
+
+#[test]
+fn test_reconcile_needs_a_difference() {
+    let mut repo = MemoryRepository::with_sample_data();
+    let mut view = StockView::new();
+    view.fetch_stock_data(&mut repo).unwrap();
+    view.stock_table_state.select(Some(0));
+    //The sample ledgers add up, there's nothing to confirm
+    let result = view.handle_main_keys(&mut repo, KeyCode::Char('R'));
+    assert!(matches!(result, Err(ShikaError::Validation(_))));
+    assert!(matches!(view.stock_sub_state, StockSubState::StockMain));
+    assert_eq!(repo.fetch_stock_history(&view.stock_data[0].partnumber).unwrap().len(), 1);
+}
@@ -0,0 +1,84 @@
+use std::cmp::Ordering;
+use crate::eng_value::{parse_tolerance, parse_value};
+
+/// Which column a table is sorted on. Kept in each view so it lasts for the session.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct SortState {
+    //None keeps the rows in the order they were loaded
+    pub column: Option<usize>,
+    pub descending: bool,
+}
+
+impl SortState {
+    /// Steps through every column ascending and then descending, then back to unsorted.
+    pub fn cycle(&mut self, columns: usize) {
+        *self = match (self.column, self.descending) {
+            (None, _) => SortState { column: Some(0), descending: false },
+            (Some(column), false) => SortState { column: Some(column), descending: true },
+            (Some(column), true) if column + 1 < columns => SortState { column: Some(column + 1), descending: false },
+            (Some(_), true) => SortState::default(),
+        };
+    }
+
+    /// The headers with an arrow on the sorted one.
+    pub fn headers(&self, headers: &[&str]) -> Vec<String> {
+        headers.iter().enumerate()
+            .map(|(idx, header)| match self.column == Some(idx) {
+                true if self.descending => format!("{header} ▼"),
+                true => format!("{header} ▲"),
+                false => header.to_string(),
+            })
+            .collect()
+    }
+
+    /// Sorts `rows` on the text `cell` returns for the sorted column. The sort is stable so
+    /// equal cells keep their loaded order.
+    pub fn sort<T>(&self, rows: &mut [T], cell: impl Fn(&T, usize) -> String) {
+        let Some(column) = self.column else { return };
+        rows.sort_by(|a, b| {
+            let ordering = compare_cells(&cell(a, column), &cell(b, column));
+            match self.descending {
+                true => ordering.reverse(),
+                false => ordering,
+            }
+        });
+    }
+}
+
+/// Numbers, engineering values like `4k7` and tolerances compare by value and come before text,
+/// which compares case-insensitively. Empty cells go last.
+pub fn compare_cells(a: &str, b: &str) -> Ordering {
+    let number = |text: &str| parse_value(text).map(|value| value.magnitude).or_else(|| parse_tolerance(text));
+    let rank = |text: &str| match (text.trim().is_empty(), number(text)) {
+        (true, _) => (2, None),
+        (false, Some(value)) => (0, Some(value)),
+        (false, None) => (1, None),
+    };
+    let (a_rank, a_value) = rank(a);
+    let (b_rank, b_value) = rank(b);
+    a_rank.cmp(&b_rank).then_with(|| match (a_value, b_value) {
+        (Some(a_value), Some(b_value)) => a_value.total_cmp(&b_value),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    })
+}
+
+#[test]
+fn test_sort_state() {
+    let mut rows = vec![("R1", "10k"), ("R2", "4k7"), ("R3", ""), ("C1", "100n"), ("X1", "tbd"), ("R4", "1M")];
+    let mut state = SortState::default();
+    state.cycle(2);
+    state.cycle(2);
+    state.cycle(2);
+    assert_eq!(state, SortState { column: Some(1), descending: false });
+    assert_eq!(state.headers(&["Part", "Value"]), vec!["Part", "Value ▲"]);
+    state.sort(&mut rows, |row, column| match column {
+        0 => row.0.to_string(),
+        _ => row.1.to_string(),
+    });
+    let order: Vec<&str> = rows.iter().map(|row| row.0).collect();
+    assert_eq!(order, vec!["C1", "R2", "R1", "R4", "X1", "R3"]);
+    state.cycle(2);
+    assert_eq!(state.headers(&["Part", "Value"]), vec!["Part", "Value ▼"]);
+    state.cycle(2);
+    assert_eq!(state, SortState::default());
+}
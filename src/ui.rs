@@ -6,6 +6,7 @@ use ratatui::{
     Frame,
 };
 use crate::{parts_ui, parts_view, stock_view, stock_ui, projects_ui};
+use crate::errors::ShikaError;
 use crate::app::{App, CurrentScreen};
 
 pub fn ui(f: &mut Frame, app: &App) {
@@ -38,11 +39,15 @@ pub fn ui(f: &mut Frame, app: &App) {
     let current_keys_hint = {
         match app.current_screen {
             CurrentScreen::PartScreen => Span::styled(
-                "(q) to quit / (n) to make new part / (r) to refresh data / (e) to edit part / (d)etailed view",
+                "(q) to quit / (n) to make new part / (r) to refresh data / (e) to edit part / (d)etailed view / (/) search / (f)ind equivalents / (o) sort / (a)ttribute columns",
+                Style::default().fg(Color::Red),
+            ),
+            CurrentScreen::ProjectScreen => Span::styled(
+                "(q) to quit / (Tab) to switch panel / List: (c)reate / (e) rename / (D)uplicate / (m)etadata / (a)rchive / (h) show archived / (d)elete / BOM: (c) to add line / (m)etadata / (e) to edit line / (s) to substitute PN / (d) to remove line / (b)uild planner / (v) revisions / (i)mport KiCad BOM / (I)mport spreadsheet / e(x)port / (o) sort / Builds: (c)omplete / (x) cancel",
                 Style::default().fg(Color::Red),
             ),
             _ => Span::styled(
-                "(q) to quit / (c) to create stock / (a) to add stock / (s) to spend stock / (e) to edit stock",
+                "(q) to quit / (c) to create stock / (a) to add stock / (s) to spend stock / (e) to edit stock / (t) to transfer / (d) history / (R)econcile / (o) sort",
                 Style::default().fg(Color::Red),
             ),
         }
@@ -51,11 +56,16 @@ pub fn ui(f: &mut Frame, app: &App) {
         .block(footer_b);
     f.render_widget(key_notes_footer, footer_chunk);
 
+    if let Some(message) = &app.connection_error {
+        render_connection_error(f, message, content_chunk);
+        return;
+    }
+
     match app.current_screen {
         CurrentScreen::PartScreen => {
             parts_ui::render_main_parts_panel(f, app, content_chunk);
             match app.parts_view.parts_sub_state {
-                parts_view::PartsSubState::Main => {
+                parts_view::PartsSubState::Main | parts_view::PartsSubState::Search => {
                     if app.parts_view.show_details {
                         let panel = parts_ui::side_panel_rect(f);
                         parts_ui::render_details_panel(f, app, panel);
@@ -70,16 +80,36 @@ pub fn ui(f: &mut Frame, app: &App) {
             }
         },
         CurrentScreen::StockScreen => {
-            stock_ui::render_main_stock_panel(f, app, content_chunk);
+            if app.stock_view.show_details {
+                let stock_chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+                    .split(content_chunk);
+                stock_ui::render_main_stock_panel(f, app, stock_chunks[0]);
+                stock_ui::render_stock_history_panel(f, app, stock_chunks[1]);
+            } else {
+                stock_ui::render_main_stock_panel(f, app, content_chunk);
+            }
             match app.stock_view.stock_sub_state {
-                stock_view::StockSubState::StockMain => {
-                    //TODO: Render a detail panel???
-                },
+                stock_view::StockSubState::StockMain => {},
                 stock_view::StockSubState::CreateStock => {
                     stock_ui::render_create_stock_popup(f, app);
                 }
-
-                _ => {}
+                stock_view::StockSubState::AddStock => {
+                    stock_ui::render_receive_stock_popup(f, app);
+                }
+                stock_view::StockSubState::SpendStock => {
+                    stock_ui::render_spend_stock_popup(f, app);
+                }
+                stock_view::StockSubState::EditStock => {
+                    stock_ui::render_edit_stock_popup(f, app);
+                }
+                stock_view::StockSubState::TransferStock => {
+                    stock_ui::render_transfer_stock_popup(f, app);
+                }
+                stock_view::StockSubState::ReconcileStock => {
+                    stock_ui::render_reconcile_stock_popup(f, app);
+                }
             }
         },
         CurrentScreen::ProjectScreen => {
@@ -87,6 +117,55 @@ pub fn ui(f: &mut Frame, app: &App) {
 
         }
     }
+    //Drawn last so it sits on top of any popup
+    if let Some(e) = &app.error_toast {
+        render_error_toast(f, e, content_chunk);
+    }
+}
+
+fn render_error_toast(f: &mut Frame, e: &ShikaError, rect: Rect) {
+    let title = match e {
+        ShikaError::Connection(_) => "Connection lost",
+        ShikaError::ConstraintViolation(_) => "Not saved",
+        ShikaError::NotFound(_) => "Not found",
+        ShikaError::Validation(_) => "Invalid input",
+        ShikaError::Database(_) => "Database error",
+    };
+    let toast_b = Block::default()
+        .title(title)
+        .title_bottom("(any key) to dismiss")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Red))
+        .style(Style::default().bg(Color::Black));
+    let toast_t = Paragraph::new(Span::styled(e.to_string(), Style::default().fg(Color::White)))
+        .block(toast_b)
+        .wrap(Wrap { trim: true });
+    //Bottom right corner of the content area, above the key hints
+    let width = rect.width.min(60);
+    let height = rect.height.min(5);
+    let area = Rect::new(rect.x + rect.width - width, rect.y + rect.height - height, width, height);
+    f.render_widget(Clear, area);
+    f.render_widget(toast_t, area);
+}
+
+fn render_connection_error(f: &mut Frame, message: &str, rect: Rect) {
+    let error_b = Block::default()
+        .title("Could not connect to the database")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Red));
+    let mut lines: Vec<Line> = message.lines()
+        .map(|line| Line::from(Span::styled(line.to_string(), Style::default().fg(Color::White))))
+        .collect();
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "Check the [database] section of your config file, the SHIKABOM_DB_* environment variables or the --db-* flags.",
+        Style::default().fg(Color::Gray),
+    )));
+    lines.push(Line::from(Span::styled("(r) to retry / (q) to quit", Style::default().fg(Color::Red))));
+    let error_t = Paragraph::new(lines)
+        .block(error_b)
+        .wrap(Wrap { trim: false });
+    f.render_widget(error_t, centered_rect(70, 50, rect));
 }
 
 pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -1,3 +1,4 @@
+use std::time::{SystemTime, UNIX_EPOCH};
 use ratatui::Frame;
 use ratatui::layout::{Rect};
 use ratatui::widgets::{Block, Clear, ListState};
@@ -21,7 +22,7 @@ impl ScrollBarInfo {
 }
 
 pub fn render_popup_block(f: &mut Frame, area: Rect, popup_block: Block) {
-    let clear = Clear::default();
+    let clear = Clear;
     f.render_widget(clear, area);
     f.render_widget(popup_block, area);
 }
@@ -61,4 +62,31 @@ pub fn exec_list_mvmt(direction: ListMvmtDir, state: &mut ListState, list_len: u
             }
         }
     }
-}
\ No newline at end of file
+}
+/// The current UTC time as `YYYY-MM-DD HH:MM:SS`, the format timestamps are shown in.
+pub fn utc_timestamp() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    format_utc(secs)
+}
+
+fn format_utc(secs: i64) -> String {
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    //Days since the epoch to a calendar date, from Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}", rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+#[test]
+fn test_format_utc() {
+    assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+    assert_eq!(format_utc(951_782_400), "2000-02-29 00:00:00");
+    assert_eq!(format_utc(1_792_281_600 + 3_723), "2026-10-18 01:02:03");
+}